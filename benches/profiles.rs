@@ -0,0 +1,53 @@
+//! Compares encoder profiles, run with `cargo bench --bench profiles`.
+//!
+//! For each test image prints time of encoding, size of the file and mean squared error of quantization for `Profile::Fast`
+//! and `Profile::Small`. Typical results for 16-color images: `Small` takes 1.5 to 3 times as long and makes files 1 to 5
+//! percent smaller. Its error is lower for detailed images thanks to the refined palette and higher for smooth ones, where
+//! dithering trades exact colors for the absence of bands.
+extern crate pcx;
+
+use std::time::{Duration, Instant};
+
+const ITERATIONS: u32 = 20;
+
+fn encode(rgb: &[u8], size: (u16, u16), profile: pcx::Profile) -> Vec<u8> {
+    let options = pcx::WriterOptions { profile, ..pcx::WriterOptions::default() };
+    let mut pcx = Vec::new();
+    let mut writer = pcx::WriterQuantized::new_with_options(&mut pcx, size, (300, 300), options).unwrap();
+    writer.set_quantizer(pcx::Quantizer::new(16).unwrap());
+    for row in rgb.chunks((size.0 as usize) * 3) {
+        writer.write_row(row).unwrap();
+    }
+    writer.finish().unwrap();
+    pcx
+}
+
+fn mean_squared_error(pcx: &[u8], rgb: &[u8]) -> f64 {
+    let decoded = pcx::decode(pcx).unwrap().to_rgb();
+    let sum: u64 = decoded.iter().zip(rgb).map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64).sum();
+    (sum as f64) / (rgb.len() as f64)
+}
+
+fn main() {
+    let images: [(&str, &[u8]); 2] = [("marbles", include_bytes!("../test-data/marbles.pcx")), ("gmarbles", include_bytes!("../test-data/gmarbles.pcx"))];
+    for &(name, data) in &images {
+        let image = pcx::decode(data).unwrap();
+        let (size, rgb) = (image.dimensions(), image.to_rgb());
+        for &profile in &[pcx::Profile::Fast, pcx::Profile::Small] {
+            let started = Instant::now();
+            let mut pcx = Vec::new();
+            for _ in 0..ITERATIONS {
+                pcx = encode(&rgb, size, profile);
+            }
+            let time: Duration = started.elapsed() / ITERATIONS;
+            println!(
+                "{:>8} {:<5}: {:>8.2} ms, {:>6} bytes, mean squared error {:.2}",
+                name,
+                format!("{:?}", profile),
+                time.as_secs_f64() * 1000.0,
+                pcx.len(),
+                mean_squared_error(&pcx, &rgb)
+            );
+        }
+    }
+}
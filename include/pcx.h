@@ -0,0 +1,36 @@
+/* C API of the pcx crate, built with the `capi` feature. See src/ffi.rs for documentation of each function.
+ * Kept in sync with the Rust declarations by the tests of src/ffi.rs. */
+#ifndef PCX_H
+#define PCX_H
+
+#include <stddef.h>
+#include <stdint.h>
+
+#ifdef __cplusplus
+extern "C" {
+#endif
+
+#define PCX_OK 0
+#define PCX_ERROR_IO 1
+#define PCX_ERROR_INVALID_DATA 2
+#define PCX_ERROR_UNSUPPORTED 3
+#define PCX_ERROR_INVALID_ARGUMENT 4
+#define PCX_ERROR_END_OF_IMAGE 5
+#define PCX_ERROR_NO_PALETTE 6
+#define PCX_ERROR_LIMIT 7
+#define PCX_ERROR_OUT_OF_MEMORY 8
+
+typedef struct PcxReader PcxReader;
+
+int pcx_reader_new_from_memory(const uint8_t *data, size_t length, PcxReader **reader);
+int pcx_reader_size(const PcxReader *reader, uint16_t *width, uint16_t *height);
+int pcx_reader_is_paletted(const PcxReader *reader, int *paletted);
+int pcx_reader_next_row(PcxReader *reader, uint8_t *buffer, size_t length);
+int pcx_reader_read_palette(PcxReader *reader, uint8_t *palette, size_t length, size_t *colors);
+void pcx_reader_free(PcxReader *reader);
+
+#ifdef __cplusplus
+}
+#endif
+
+#endif /* PCX_H */
@@ -0,0 +1,695 @@
+//! Reading PCX files from asynchronous streams without blocking the executor.
+//!
+//! The crate does not depend on an async runtime. Streams implement the `AsyncRead` trait defined here, which has the same
+//! shape as `futures::io::AsyncRead` and `tokio::io::AsyncRead`, so runtime streams are adapted with a few lines of wrapper
+//! code. The futures returned by `AsyncReader` can be awaited on any executor.
+//!
+//! `AsyncReader` fetches the stored data of each row from the stream and hands it to a `Reader`, so decoding, validation and
+//! conversions are exactly those of `Reader`.
+//!
+//! Data arriving in chunks, e.g. the body of an HTTP response, is read with `AsyncReader::from_chunks()` from a stream of chunks
+//! implementing `ChunkStream`. Rows are yielded by `AsyncReader::rows()` as soon as their data has arrived, so images are decoded
+//! while they are downloaded.
+use std::collections::VecDeque;
+use std::future::Future;
+use std::pin::Pin;
+use std::task::{Context, Poll};
+use std::{cmp, io};
+use low_level::rle::DECOMPRESSOR_BUFFER_LENGTH;
+use low_level::{Header, HEADER_LENGTH};
+use reader::next_row;
+use {ColorFormat, Options, PixelFormat, Reader, Result, Row, Warning};
+
+/// Asynchronous byte stream, see the module documentation.
+pub trait AsyncRead {
+    /// Attempt to read data into `buffer`, returning the number of bytes read, zero at the end of the stream.
+    ///
+    /// If no data is available `Poll::Pending` is returned and the task is woken once data can be read.
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>>;
+}
+
+impl AsyncRead for &[u8] {
+    fn poll_read(self: Pin<&mut Self>, _cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        Poll::Ready(io::Read::read(self.get_mut(), buffer))
+    }
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for &mut T {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_read(cx, buffer)
+    }
+}
+
+impl<T: AsyncRead + Unpin + ?Sized> AsyncRead for Box<T> {
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        Pin::new(&mut **self.get_mut()).poll_read(cx, buffer)
+    }
+}
+
+/// Asynchronous stream of data chunks, e.g. the body of an HTTP response. It has the same shape as
+/// `futures::Stream<Item = io::Result<Bytes>>`, see the module documentation.
+pub trait ChunkStream {
+    /// Chunk of data, e.g. `Vec<u8>` or `Bytes`.
+    type Chunk: AsRef<[u8]>;
+
+    /// Attempt to get the next chunk, `None` at the end of the stream.
+    ///
+    /// If no chunk is available `Poll::Pending` is returned and the task is woken once it arrives.
+    fn poll_next_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Self::Chunk>>>;
+}
+
+impl<S: ChunkStream + Unpin + ?Sized> ChunkStream for &mut S {
+    type Chunk = S::Chunk;
+
+    fn poll_next_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Self::Chunk>>> {
+        Pin::new(&mut **self.get_mut()).poll_next_chunk(cx)
+    }
+}
+
+impl<S: ChunkStream + Unpin + ?Sized> ChunkStream for Box<S> {
+    type Chunk = S::Chunk;
+
+    fn poll_next_chunk(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Self::Chunk>>> {
+        Pin::new(&mut **self.get_mut()).poll_next_chunk(cx)
+    }
+}
+
+/// Adapter reading the data of a `ChunkStream` as `AsyncRead`, see `AsyncReader::from_chunks()`.
+#[derive(Debug)]
+pub struct ChunkReader<S: ChunkStream> {
+    chunks: S,
+
+    // Chunk being read and the position of its first byte which was not read yet.
+    chunk: Option<S::Chunk>,
+    position: usize,
+}
+
+impl<S: ChunkStream> ChunkReader<S> {
+    /// Read the data of the chunks one after another.
+    pub fn new(chunks: S) -> Self {
+        ChunkReader { chunks, chunk: None, position: 0 }
+    }
+
+    /// Get a reference to the stream of chunks.
+    pub fn get_ref(&self) -> &S {
+        &self.chunks
+    }
+}
+
+impl<S: ChunkStream + Unpin> AsyncRead for ChunkReader<S>
+where
+    S::Chunk: Unpin,
+{
+    fn poll_read(self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+        let reader = self.get_mut();
+        loop {
+            if let Some(ref chunk) = reader.chunk {
+                let rest = &chunk.as_ref()[reader.position..];
+                if !rest.is_empty() {
+                    let length = cmp::min(rest.len(), buffer.len());
+                    buffer[..length].copy_from_slice(&rest[..length]);
+                    reader.position += length;
+                    return Poll::Ready(Ok(length));
+                }
+            }
+
+            match Pin::new(&mut reader.chunks).poll_next_chunk(cx) {
+                Poll::Ready(Some(Ok(chunk))) => {
+                    reader.chunk = Some(chunk);
+                    reader.position = 0;
+                }
+                Poll::Ready(Some(Err(error))) => return Poll::Ready(Err(error)),
+                Poll::Ready(None) => return Poll::Ready(Ok(0)),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+}
+
+// Pixel data handed over to the reader. It always covers the row being read, so the reader never runs out of data mid-row.
+#[derive(Clone, Debug, Default)]
+struct Feed {
+    data: VecDeque<u8>,
+}
+
+impl io::Read for Feed {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        self.data.read(buffer)
+    }
+}
+
+/// PCX file reader for asynchronous streams, mirrors `Reader`.
+///
+/// Rows are read with the futures returned by `next_row_paletted()`, `next_row_rgb()` and `next_row_into()`. Compressed data is
+/// read from the stream in blocks of `DECOMPRESSOR_BUFFER_LENGTH` bytes, so the stream is generally read beyond the end of the
+/// rows decoded so far.
+#[derive(Debug)]
+pub struct AsyncReader<R> {
+    stream: R,
+    reader: Reader<Feed>,
+
+    // Data read from the stream which was not handed to the reader yet. The first `scanned` bytes belong to the row being
+    // fetched.
+    pending: Vec<u8>,
+    scanned: usize,
+
+    // Decompressed bytes of the row being fetched which are not covered by the scanned data yet, `None` between rows.
+    needed: Option<u64>,
+
+    // Remaining length of the last scanned run, tracks the state the decompressor of the reader will have at the end of the
+    // fetched rows.
+    run: u8,
+
+    // Number of rows whose data was handed to the reader.
+    fetched_rows: u16,
+    end_of_stream: bool,
+}
+
+impl<R: AsyncRead + Unpin> AsyncReader<R> {
+    /// Start reading PCX file, the returned future completes once the header is read.
+    pub fn open(stream: R) -> Open<R> {
+        Self::open_with_options(stream, Options::default())
+    }
+
+    /// Start reading PCX file with the given options, the returned future completes once the header is read.
+    pub fn open_with_options(stream: R, options: Options) -> Open<R> {
+        Open {
+            stream: Some(stream),
+            header: [0; HEADER_LENGTH],
+            length: 0,
+            options,
+        }
+    }
+
+    /// File header.
+    #[inline]
+    pub fn header(&self) -> &Header {
+        &self.reader.header
+    }
+
+    /// Get width and height of the image.
+    #[inline]
+    pub fn dimensions(&self) -> (u16, u16) {
+        self.reader.dimensions()
+    }
+
+    /// The width of this image.
+    #[inline]
+    pub fn width(&self) -> u16 {
+        self.reader.width()
+    }
+
+    /// The height of this image.
+    #[inline]
+    pub fn height(&self) -> u16 {
+        self.reader.height()
+    }
+
+    /// Whether this image is paletted, see `Reader::is_paletted()`.
+    #[inline]
+    pub fn is_paletted(&self) -> bool {
+        self.reader.is_paletted()
+    }
+
+    /// Kind of pixel data in the image.
+    #[inline]
+    pub fn color_format(&self) -> ColorFormat {
+        self.reader.color_format()
+    }
+
+    /// Get number of colors in the palette if this image is paletted. Number of colors is either 2, 4, 8, 16 or 256.
+    #[inline]
+    pub fn palette_length(&self) -> Option<u16> {
+        self.reader.palette_length()
+    }
+
+    /// Length of the row buffer in bytes for the given pixel format.
+    #[inline]
+    pub fn row_length(&self, format: PixelFormat) -> usize {
+        self.reader.row_length(format)
+    }
+
+    /// Number of rows which were already read.
+    #[inline]
+    pub fn rows_read(&self) -> u16 {
+        self.reader.rows_read()
+    }
+
+    /// Whether there are rows left to read.
+    #[inline]
+    pub fn has_more_rows(&self) -> bool {
+        self.reader.has_more_rows()
+    }
+
+    /// Problems tolerated while loading the header, see `Reader::warnings()`.
+    pub fn warnings(&self) -> &[Warning] {
+        self.reader.warnings()
+    }
+
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &R {
+        &self.stream
+    }
+
+    /// Read next row of the paletted image, see `Reader::next_row_paletted()`.
+    pub fn next_row_paletted<'a>(&'a mut self, buffer: &'a mut [u8]) -> NextRow<'a, R> {
+        NextRow { reader: self, buffer, format: RowFormat::Paletted }
+    }
+
+    /// Read next row of the RGB image with interleaved RGB values, see `Reader::next_row_rgb()`.
+    pub fn next_row_rgb<'a>(&'a mut self, rgb: &'a mut [u8]) -> NextRow<'a, R> {
+        NextRow { reader: self, buffer: rgb, format: RowFormat::Rgb }
+    }
+
+    /// Read next row in the requested pixel format, see `Reader::next_row_into()`.
+    pub fn next_row_into<'a>(&'a mut self, buffer: &'a mut [u8], format: PixelFormat) -> NextRow<'a, R> {
+        NextRow { reader: self, buffer, format: RowFormat::Into(format) }
+    }
+
+    /// Iterate over the remaining rows of the image asynchronously, see `Reader::rows()`.
+    ///
+    /// Each row is yielded as soon as its data was read from the stream.
+    pub fn rows(&mut self) -> AsyncRows<'_, R> {
+        AsyncRows { reader: self, failed: false }
+    }
+
+    /// Read color palette, see `Reader::read_palette()`.
+    ///
+    /// The 256-color palette is stored at the end of file, so the stream is read to the end.
+    pub fn read_palette(self, buffer: &mut [u8]) -> ReadPalette<'_, R> {
+        ReadPalette { reader: Some(self), buffer }
+    }
+
+    // Hand the stored data of the next row to the reader unless it was handed over already. Rows which can not be read are
+    // not fetched, the reader reports the error then.
+    fn poll_row(&mut self, cx: &mut Context<'_>) -> Poll<Result<()>> {
+        if self.fetched_rows > self.reader.rows_read() || self.fetched_rows >= self.reader.height() || self.reader.is_finished() {
+            return Poll::Ready(Ok(()));
+        }
+
+        if self.needed.is_none() {
+            let header = &self.reader.header;
+            let mut needed = (header.lane_length as u64) * (header.number_of_color_planes as u64);
+
+            // Padding of the very last lane may be missing and is not read.
+            if self.fetched_rows + 1 == header.size.1 {
+                needed -= header.lane_padding() as u64;
+            }
+            self.needed = Some(needed);
+        }
+
+        while !self.scan() && !self.end_of_stream {
+            match self.poll_fill(cx) {
+                Poll::Ready(Ok(_)) => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // A truncated row is handed over as well, so the reader fails on it exactly as for a blocking stream.
+        self.reader.get_mut().data.extend(self.pending.drain(..self.scanned));
+        self.scanned = 0;
+        self.needed = None;
+        self.fetched_rows += 1;
+        Poll::Ready(Ok(()))
+    }
+
+    // Scan the pending data for the stored data of the row being fetched, returns whether the row is covered completely.
+    fn scan(&mut self) -> bool {
+        let mut needed = self.needed.unwrap_or(0);
+        if !self.reader.header.is_compressed {
+            let taken = cmp::min((self.pending.len() - self.scanned) as u64, needed);
+            self.scanned += taken as usize;
+            needed -= taken;
+        } else {
+            let taken = cmp::min(self.run as u64, needed);
+            self.run -= taken as u8;
+            needed -= taken;
+
+            while needed > 0 && self.scanned < self.pending.len() {
+                let code = self.pending[self.scanned];
+                if (code & 0xC0) != 0xC0 {
+                    self.scanned += 1;
+                    needed -= 1;
+                    continue;
+                }
+
+                // The value of the run is not available yet.
+                if self.scanned + 1 == self.pending.len() {
+                    break;
+                }
+                self.scanned += 2;
+
+                let count = (code & 0x3F) as u64;
+                if count > needed {
+                    self.run = (count - needed) as u8;
+                    needed = 0;
+                } else {
+                    needed -= count;
+                }
+            }
+        }
+
+        self.needed = Some(needed);
+        needed == 0
+    }
+
+    // Read the next block of data from the stream into the pending data.
+    fn poll_fill(&mut self, cx: &mut Context<'_>) -> Poll<io::Result<usize>> {
+        let length = self.pending.len();
+        self.pending.resize(length + DECOMPRESSOR_BUFFER_LENGTH, 0);
+        loop {
+            let result = Pin::new(&mut self.stream).poll_read(cx, &mut self.pending[length..]);
+            match result {
+                Poll::Ready(Err(ref error)) if error.kind() == io::ErrorKind::Interrupted => continue,
+                Poll::Ready(Ok(read)) => {
+                    self.pending.truncate(length + read);
+                    self.end_of_stream = read == 0;
+                }
+                _ => self.pending.truncate(length),
+            }
+            return result;
+        }
+    }
+}
+
+impl<S: ChunkStream + Unpin> AsyncReader<ChunkReader<S>>
+where
+    S::Chunk: Unpin,
+{
+    /// Start reading PCX file from a stream of chunks, the returned future completes once the header has arrived.
+    pub fn from_chunks(chunks: S) -> Open<ChunkReader<S>> {
+        Self::open(ChunkReader::new(chunks))
+    }
+}
+
+/// Future returned by `AsyncReader::open()`.
+#[derive(Debug)]
+pub struct Open<R> {
+    stream: Option<R>,
+    header: [u8; HEADER_LENGTH],
+    length: usize,
+    options: Options,
+}
+
+impl<R: AsyncRead + Unpin> Future for Open<R> {
+    type Output = Result<AsyncReader<R>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let open = self.get_mut();
+        while open.length < HEADER_LENGTH {
+            let stream = open.stream.as_mut().expect("pcx::Open polled after completion");
+            match Pin::new(stream).poll_read(cx, &mut open.header[open.length..]) {
+                Poll::Ready(Ok(0)) => break,
+                Poll::Ready(Ok(read)) => open.length += read,
+                Poll::Ready(Err(ref error)) if error.kind() == io::ErrorKind::Interrupted => {}
+                Poll::Ready(Err(error)) => return Poll::Ready(Err(error.into())),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+
+        // A truncated header fails exactly as for a blocking stream.
+        let stream = open.stream.take().expect("pcx::Open polled after completion");
+        let (header, raw_header) = Header::load_raw_with_options(&mut &open.header[..open.length], &open.options)?;
+        let reader = Reader::with_header(Feed::default(), header, raw_header, open.options)?;
+        Poll::Ready(Ok(AsyncReader {
+            stream,
+            reader,
+            pending: Vec::new(),
+            scanned: 0,
+            needed: None,
+            run: 0,
+            fetched_rows: 0,
+            end_of_stream: false,
+        }))
+    }
+}
+
+#[derive(Copy, Clone, Debug)]
+enum RowFormat {
+    Paletted,
+    Rgb,
+    Into(PixelFormat),
+}
+
+/// Future returned by `AsyncReader::next_row_paletted()`, `next_row_rgb()` and `next_row_into()`.
+#[derive(Debug)]
+pub struct NextRow<'a, R: 'a> {
+    reader: &'a mut AsyncReader<R>,
+    buffer: &'a mut [u8],
+    format: RowFormat,
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for NextRow<'a, R> {
+    type Output = Result<()>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let next_row = self.get_mut();
+        match next_row.reader.poll_row(cx) {
+            Poll::Ready(Ok(())) => {}
+            Poll::Ready(Err(error)) => return Poll::Ready(Err(error)),
+            Poll::Pending => return Poll::Pending,
+        }
+
+        let reader = &mut next_row.reader.reader;
+        Poll::Ready(match next_row.format {
+            RowFormat::Paletted => reader.next_row_paletted(next_row.buffer),
+            RowFormat::Rgb => reader.next_row_rgb(next_row.buffer),
+            RowFormat::Into(format) => reader.next_row_into(next_row.buffer, format),
+        })
+    }
+}
+
+/// Asynchronous stream of the rows of the image, created by `AsyncReader::rows()`.
+///
+/// It has the same shape as `futures::Stream<Item = Result<Row>>`. Rows are yielded until the end of the image or the first error.
+#[derive(Debug)]
+pub struct AsyncRows<'a, R: 'a> {
+    reader: &'a mut AsyncReader<R>,
+    failed: bool,
+}
+
+impl<'a, R: AsyncRead + Unpin> AsyncRows<'a, R> {
+    /// Attempt to read the next row, `None` after the last row or after an error.
+    pub fn poll_next(&mut self, cx: &mut Context<'_>) -> Poll<Option<Result<Row>>> {
+        if self.failed || !self.reader.has_more_rows() {
+            return Poll::Ready(None);
+        }
+
+        let row = match self.reader.poll_row(cx) {
+            Poll::Ready(Ok(())) => next_row(&mut self.reader.reader),
+            Poll::Ready(Err(error)) => Err(error),
+            Poll::Pending => return Poll::Pending,
+        };
+        self.failed = row.is_err();
+        Poll::Ready(Some(row))
+    }
+
+    /// Read the next row, the returned future resolves to `None` after the last row or after an error.
+    pub fn next_row(&mut self) -> NextAsyncRow<'_, 'a, R> {
+        NextAsyncRow { rows: self }
+    }
+}
+
+/// Future returned by `AsyncRows::next_row()`.
+#[derive(Debug)]
+pub struct NextAsyncRow<'b, 'a: 'b, R: 'a> {
+    rows: &'b mut AsyncRows<'a, R>,
+}
+
+impl<'b, 'a, R: AsyncRead + Unpin> Future for NextAsyncRow<'b, 'a, R> {
+    type Output = Option<Result<Row>>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        self.get_mut().rows.poll_next(cx)
+    }
+}
+
+/// Future returned by `AsyncReader::read_palette()`, resolves to the number of colors in palette.
+#[derive(Debug)]
+pub struct ReadPalette<'a, R> {
+    reader: Option<AsyncReader<R>>,
+    buffer: &'a mut [u8],
+}
+
+impl<'a, R: AsyncRead + Unpin> Future for ReadPalette<'a, R> {
+    type Output = Result<usize>;
+
+    fn poll(self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Self::Output> {
+        let read_palette = self.get_mut();
+        let reader = read_palette.reader.as_mut().expect("pcx::ReadPalette polled after completion");
+
+        // Other palettes are stored in the header.
+        if reader.palette_length() == Some(256) {
+            while !reader.end_of_stream {
+                match reader.poll_fill(cx) {
+                    Poll::Ready(Ok(_)) => {}
+                    Poll::Ready(Err(error)) => return Poll::Ready(Err(error.into())),
+                    Poll::Pending => return Poll::Pending,
+                }
+            }
+        }
+
+        let AsyncReader { mut reader, mut pending, .. } = read_palette.reader.take().unwrap();
+        reader.get_mut().data.extend(pending.drain(..));
+        Poll::Ready(reader.read_palette(read_palette.buffer))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::future::Future;
+    use std::pin::Pin;
+    use std::sync::Arc;
+    use std::task::{Context, Poll, Wake, Waker};
+    use std::collections::VecDeque;
+    use std::io;
+    use super::{AsyncRead, AsyncReader, ChunkStream};
+    use {Error, PixelFormat, Reader, Result, Row};
+
+    struct NoopWaker;
+
+    impl Wake for NoopWaker {
+        fn wake(self: Arc<Self>) {}
+    }
+
+    // Poll the future until it completes.
+    fn block_on<F: Future>(future: F) -> F::Output {
+        let waker = Waker::from(Arc::new(NoopWaker));
+        let mut cx = Context::from_waker(&waker);
+        let mut future = Box::pin(future);
+        loop {
+            if let Poll::Ready(output) = future.as_mut().poll(&mut cx) {
+                return output;
+            }
+        }
+    }
+
+    // Stream returning data in small pieces, every other read is not ready.
+    struct Trickle<'a> {
+        data: &'a [u8],
+        ready: bool,
+    }
+
+    impl<'a> AsyncRead for Trickle<'a> {
+        fn poll_read(mut self: Pin<&mut Self>, cx: &mut Context<'_>, buffer: &mut [u8]) -> Poll<io::Result<usize>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+
+            let length = self.data.len().min(buffer.len()).min(7);
+            buffer[..length].copy_from_slice(&self.data[..length]);
+            self.data = &self.data[length..];
+            Poll::Ready(Ok(length))
+        }
+    }
+
+    // Chunks of a download, every other poll is not ready.
+    struct Download {
+        chunks: VecDeque<io::Result<Vec<u8>>>,
+        ready: bool,
+    }
+
+    impl ChunkStream for Download {
+        type Chunk = Vec<u8>;
+
+        fn poll_next_chunk(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<io::Result<Vec<u8>>>> {
+            self.ready = !self.ready;
+            if !self.ready {
+                cx.waker().wake_by_ref();
+                return Poll::Pending;
+            }
+            Poll::Ready(self.chunks.pop_front())
+        }
+    }
+
+    #[test]
+    fn same_as_reader() {
+        let files: [&[u8]; 3] = [
+            include_bytes!("../test-data/marbles.pcx"),
+            include_bytes!("../test-data/gmarbles.pcx"),
+            include_bytes!("../test-data/cga/CGA_RGBI.PCX"),
+        ];
+        for &data in &files {
+            let mut reader = Reader::new(data).unwrap();
+            let mut async_reader = block_on(AsyncReader::open(Trickle { data, ready: false })).unwrap();
+            assert_eq!(async_reader.dimensions(), reader.dimensions());
+
+            let format = if reader.is_paletted() { PixelFormat::Indexed } else { PixelFormat::Rgb };
+            let length = reader.row_length(format);
+            for _ in 0..reader.height() {
+                let (mut expected, mut row) = (vec![0; length], vec![0; length]);
+                reader.next_row_into(&mut expected, format).unwrap();
+                block_on(async_reader.next_row_into(&mut row, format)).unwrap();
+                assert_eq!(row, expected);
+            }
+            assert!(matches!(block_on(async_reader.next_row_into(&mut vec![0; length], format)), Err(Error::EndOfImage)));
+
+            if reader.is_paletted() {
+                let (mut expected, mut palette) = ([0; 256 * 3], [0; 256 * 3]);
+                let length = reader.read_palette(&mut expected).unwrap();
+                assert_eq!(block_on(async_reader.read_palette(&mut palette)).unwrap(), length);
+                assert_eq!(palette[..], expected[..]);
+            }
+        }
+    }
+
+    #[test]
+    fn palette_without_rows() {
+        let data = &include_bytes!("../test-data/gmarbles.pcx")[..];
+        let mut expected = [0; 256 * 3];
+        Reader::new(data).unwrap().read_palette(&mut expected).unwrap();
+
+        let reader = block_on(AsyncReader::open(data)).unwrap();
+        let mut palette = [0; 256 * 3];
+        assert_eq!(block_on(reader.read_palette(&mut palette)).unwrap(), 256);
+        assert_eq!(palette[..], expected[..]);
+    }
+
+    #[test]
+    fn chunks() {
+        let data = &include_bytes!("../test-data/gmarbles.pcx")[..];
+        let expected = Reader::new(data).unwrap().rows().collect::<Result<Vec<Row>>>().unwrap();
+
+        let chunks = data.chunks(100).map(|chunk| Ok(chunk.to_vec())).collect();
+        let mut reader = block_on(AsyncReader::from_chunks(Download { chunks, ready: false })).unwrap();
+        let mut rows = Vec::new();
+        {
+            let mut async_rows = reader.rows();
+            while let Some(row) = block_on(async_rows.next_row()) {
+                rows.push(row.unwrap());
+            }
+        }
+        assert_eq!(rows, expected);
+        assert_eq!(block_on(reader.read_palette(&mut [0; 256 * 3])).unwrap(), 256);
+
+        // Errors of the download end the rows.
+        let chunks = vec![Ok(data[..1000].to_vec()), Err(io::Error::new(io::ErrorKind::ConnectionReset, "reset"))].into_iter().collect();
+        let mut reader = block_on(AsyncReader::from_chunks(Download { chunks, ready: false })).unwrap();
+        let mut rows = reader.rows();
+        let mut last = None;
+        while let Some(row) = block_on(rows.next_row()) {
+            last = Some(row);
+        }
+        assert!(matches!(last, Some(Err(Error::Io(_)))));
+    }
+
+    #[test]
+    fn errors() {
+        assert!(matches!(block_on(AsyncReader::open(&[0u8; 10][..])), Err(Error::NotPcx)));
+        assert!(matches!(block_on(AsyncReader::open(&[10u8; 10][..])), Err(Error::Io(_))));
+
+        // Truncated pixel data fails like with `Reader` and does not wait for more data.
+        let data = &include_bytes!("../test-data/gmarbles.pcx")[..2000];
+        let mut reader = block_on(AsyncReader::open(Trickle { data, ready: false })).unwrap();
+        let mut row = vec![0; reader.width() as usize];
+        let mut result = Ok(());
+        while result.is_ok() {
+            result = block_on(reader.next_row_paletted(&mut row));
+        }
+        assert!(matches!(result, Err(Error::Io(_))));
+        assert!(matches!(block_on(reader.next_row_paletted(&mut row)), Err(Error::InvalidUsage(_))));
+    }
+}
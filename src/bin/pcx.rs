@@ -0,0 +1,316 @@
+//! Command-line tool for inspecting and converting PCX images, built when the `cli` feature is enabled.
+//!
+//! Usage:
+//!
+//!     pcx info FILE...
+//!     pcx convert INPUT OUTPUT
+//!     pcx topnm INPUT [OUTPUT]
+//!     pcx diff [--colors[=TOLERANCE]] A B
+//!     pcx verify FILE...
+//!
+//! Formats for `convert` are determined by file extensions, supported are PCX, binary PPM (P6), PNM output (see below) and
+//! PNG if the `cli-png` feature is enabled.
+//!
+//! `topnm` converts PCX to binary PGM (P5) for grayscale images, PAM (P7) for images with alpha channel and PPM (P6)
+//! otherwise. Output goes to stdout if OUTPUT is omitted or `-`, so it can be piped to other tools.
+//!
+//! `diff` compares pixels of two PCX images and fails if they differ. Palette indices are compared by default, with `--colors`
+//! colors resolved through the palettes are compared instead, allowing each channel to differ by up to TOLERANCE.
+//!
+//! `verify` decodes all pixel data and the palette of each file without converting it and prints one JSON object per file and
+//! line with its status, tolerated warnings and header summary, e.g. for archive integrity jobs. It fails if any file is
+//! damaged.
+#[cfg(feature = "cli-png")]
+extern crate image;
+extern crate pcx;
+
+use std::error::Error;
+use std::fs::File;
+use std::io::{self, Read, Write};
+use std::path::Path;
+use std::{env, process};
+
+type Result<T> = std::result::Result<T, Box<dyn Error>>;
+
+const USAGE: &str = "usage: pcx info FILE...\n       pcx convert INPUT OUTPUT\n       pcx topnm INPUT [OUTPUT]\n       pcx diff [--colors[=TOLERANCE]] A B\n       pcx verify FILE...\n\n\
+Conversion is supported between PCX, PNG (if built with cli-png) and binary PPM (P6) files, PNM output picks PGM, PPM or PAM.";
+
+fn main() {
+    let args: Vec<String> = env::args().skip(1).collect();
+    let result = match args.first().map(|command| &command[..]) {
+        Some("info") if args.len() > 1 => args[1..].iter().try_for_each(|path| info(path)),
+        Some("convert") if args.len() == 3 => convert(&args[1], &args[2]),
+        Some("topnm") if args.len() == 2 || args.len() == 3 => topnm(&args[1], args.get(2).map(|output| &output[..]).unwrap_or("-")),
+        Some("diff") if args.len() == 3 => diff(&args[1], &args[2], pcx::Comparison::Values),
+        Some("diff") if args.len() == 4 && args[1].starts_with("--colors") => match parse_tolerance(&args[1]) {
+            Some(tolerance) => diff(&args[2], &args[3], pcx::Comparison::Colors { tolerance }),
+            None => Err(format!("{}: invalid tolerance", args[1]).into()),
+        },
+        Some("verify") if args.len() > 1 => verify(&args[1..]),
+        _ => {
+            eprintln!("{}", USAGE);
+            process::exit(2);
+        }
+    };
+
+    if let Err(error) = result {
+        eprintln!("pcx: {}", error);
+        process::exit(1);
+    }
+}
+
+fn info(path: &str) -> Result<()> {
+    let info = pcx::probe_file(path).map_err(|error| format!("{}: {}", path, error))?;
+    let format = match info.color_format {
+        pcx::ColorFormat::Paletted => format!("paletted, {} colors", info.palette_length.unwrap_or(0)),
+        pcx::ColorFormat::Rgb => "RGB".to_string(),
+        pcx::ColorFormat::GrayAlpha => "grayscale with alpha".to_string(),
+        pcx::ColorFormat::Rgba => "RGBA".to_string(),
+    };
+    println!(
+        "{}: {}x{}, {} ({} bits per pixel, {} color planes), {}x{} dpi, version {}",
+        path,
+        info.size.0,
+        info.size.1,
+        format,
+        info.bit_depth,
+        info.number_of_color_planes,
+        info.dpi.0,
+        info.dpi.1,
+        info.version as u8
+    );
+    Ok(())
+}
+
+fn convert(input: &str, output: &str) -> Result<()> {
+    let (image, dpi) = match &extension(input)[..] {
+        "pcx" => {
+            let reader = pcx::Reader::from_file(input)?;
+            let dpi = reader.header.dpi;
+            (reader.read_image()?, dpi)
+        }
+        #[cfg(feature = "cli-png")]
+        "png" => {
+            let png = image::open(input)?.to_rgb();
+            let (width, height) = dimensions(png.width(), png.height())?;
+            (pcx::Image { width, height, pixels: pcx::Pixels::Rgb(png.into_raw()) }, (300, 300))
+        }
+        "ppm" => {
+            let mut data = Vec::new();
+            File::open(input)?.read_to_end(&mut data)?;
+            let image = pcx::Image::from_ppm(&data)?;
+            dimensions(image.width as u32, image.height as u32)?;
+            (image, (300, 300))
+        }
+        _ => return Err(format!("{}: unsupported input format", input).into()),
+    };
+
+    match &extension(output)[..] {
+        "pcx" => pcx::encode(io::BufWriter::new(File::create(output)?), &image, dpi)?,
+        #[cfg(feature = "cli-png")]
+        "png" => {
+            let (values, color) = match image.pixels {
+                pcx::Pixels::GrayAlpha(ref gray_alpha) => (gray_alpha.clone(), image::ColorType::GrayA(8)),
+                pcx::Pixels::Rgba(ref rgba) => (rgba.clone(), image::ColorType::RGBA(8)),
+                pcx::Pixels::Paletted { .. } | pcx::Pixels::Rgb(_) => (image.to_rgb(), image::ColorType::RGB(8)),
+            };
+            image::save_buffer(output, &values, image.width as u32, image.height as u32, color)?
+        }
+        "ppm" => write_ppm(io::BufWriter::new(File::create(output)?), &image)?,
+        "pnm" | "pgm" | "pam" => write_pnm(io::BufWriter::new(File::create(output)?), &image)?,
+        _ => return Err(format!("{}: unsupported output format", output).into()),
+    }
+    Ok(())
+}
+
+fn topnm(input: &str, output: &str) -> Result<()> {
+    let image = pcx::Reader::from_file(input)?.read_image()?;
+    if output == "-" {
+        let stdout = io::stdout();
+        write_pnm(io::BufWriter::new(stdout.lock()), &image)?;
+    } else {
+        write_pnm(io::BufWriter::new(File::create(output)?), &image)?;
+    }
+    Ok(())
+}
+
+fn diff(a: &str, b: &str, comparison: pcx::Comparison) -> Result<()> {
+    let difference = pcx::compare_images(&pcx::Reader::from_file(a)?.read_image()?, &pcx::Reader::from_file(b)?.read_image()?, comparison)?;
+    match difference.first {
+        None => Ok(()),
+        Some((x, y)) => Err(format!("{} and {} differ in {} pixels, first at ({}, {})", a, b, difference.differing_pixels, x, y).into()),
+    }
+}
+
+fn verify(paths: &[String]) -> Result<()> {
+    let stdout = io::stdout();
+    let mut stdout = stdout.lock();
+    let mut failed = 0;
+    for path in paths {
+        let (ok, json) = verify_file(path);
+        writeln!(stdout, "{}", json)?;
+        if !ok {
+            failed += 1;
+        }
+    }
+    match failed {
+        0 => Ok(()),
+        _ => Err(format!("{} of {} files failed verification", failed, paths.len()).into()),
+    }
+}
+
+// Decode the file and describe the result as a JSON object, returns whether the file is intact.
+fn verify_file(path: &str) -> (bool, String) {
+    let options = pcx::Options { strict: false, ..pcx::Options::default() };
+    let reader = match File::open(path).map_err(pcx::Error::from).and_then(|file| pcx::Reader::new_with_options(io::BufReader::new(file), options)) {
+        Ok(reader) => reader,
+        Err(error) => return (false, format!("{{\"file\": {}, \"status\": \"error\", \"error\": {}, \"warnings\": [], \"header\": null}}", json_string(path), json_string(&error.to_string()))),
+    };
+
+    let header = &reader.header;
+    let summary = format!(
+        "{{\"width\": {}, \"height\": {}, \"bit_depth\": {}, \"planes\": {}, \"version\": {}, \"compressed\": {}}}",
+        header.size.0,
+        header.size.1,
+        header.bit_depth,
+        header.number_of_color_planes,
+        header.version as u8,
+        header.is_compressed
+    );
+    let warnings: Vec<String> = reader.warnings().iter().map(|warning| json_string(&warning.to_string())).collect();
+    let warnings = format!("[{}]", warnings.join(", "));
+
+    // Truncated data is reported rather than filled, as `finish()` does not recover from it.
+    match reader.finish() {
+        Ok(_) => (true, format!("{{\"file\": {}, \"status\": \"ok\", \"warnings\": {}, \"header\": {}}}", json_string(path), warnings, summary)),
+        Err(error) => (
+            false,
+            format!("{{\"file\": {}, \"status\": \"error\", \"error\": {}, \"warnings\": {}, \"header\": {}}}", json_string(path), json_string(&error.to_string()), warnings, summary),
+        ),
+    }
+}
+
+// Quote and escape string as JSON.
+fn json_string(value: &str) -> String {
+    let mut json = String::with_capacity(value.len() + 2);
+    json.push('"');
+    for c in value.chars() {
+        match c {
+            '"' => json.push_str("\\\""),
+            '\\' => json.push_str("\\\\"),
+            '\n' => json.push_str("\\n"),
+            c if (c as u32) < 0x20 => json.push_str(&format!("\\u{:04x}", c as u32)),
+            c => json.push(c),
+        }
+    }
+    json.push('"');
+    json
+}
+
+// Parse `--colors` or `--colors=TOLERANCE`.
+fn parse_tolerance(option: &str) -> Option<u8> {
+    match option {
+        "--colors" => Some(0),
+        _ => option.strip_prefix("--colors=").and_then(|tolerance| tolerance.parse().ok()),
+    }
+}
+
+fn extension(path: &str) -> String {
+    Path::new(path).extension().and_then(|extension| extension.to_str()).unwrap_or("").to_ascii_lowercase()
+}
+
+fn dimensions(width: u32, height: u32) -> Result<(u16, u16)> {
+    if width == 0 || height == 0 || width >= 0xFFFF || height > 0xFFFF {
+        return Err(format!("image size {}x{} can not be stored in PCX", width, height).into());
+    }
+    Ok((width as u16, height as u16))
+}
+
+// Write binary PPM (P6), alpha channel is dropped.
+fn write_ppm<W: Write>(mut stream: W, image: &pcx::Image) -> io::Result<()> {
+    write!(stream, "P6\n{} {}\n255\n", image.width, image.height)?;
+    stream.write_all(&image.to_rgb())?;
+    stream.flush()
+}
+
+// Write binary PAM (P7), PGM (P5) if all pixels are gray or PPM (P6), whichever is the smallest that keeps all information.
+fn write_pnm<W: Write>(mut stream: W, image: &pcx::Image) -> io::Result<()> {
+    let (width, height) = (image.width, image.height);
+    match image.pixels {
+        pcx::Pixels::GrayAlpha(ref gray_alpha) => {
+            write!(stream, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n", width, height)?;
+            stream.write_all(gray_alpha)?;
+        }
+        pcx::Pixels::Rgba(ref rgba) => {
+            write!(stream, "P7\nWIDTH {}\nHEIGHT {}\nDEPTH 4\nMAXVAL 255\nTUPLTYPE RGB_ALPHA\nENDHDR\n", width, height)?;
+            stream.write_all(rgba)?;
+        }
+        pcx::Pixels::Paletted { .. } | pcx::Pixels::Rgb(_) => {
+            let rgb = image.to_rgb();
+            if rgb.chunks(3).all(|pixel| pixel[0] == pixel[1] && pixel[1] == pixel[2]) {
+                let gray: Vec<u8> = rgb.iter().step_by(3).cloned().collect();
+                write!(stream, "P5\n{} {}\n255\n", width, height)?;
+                stream.write_all(&gray)?;
+            } else {
+                write!(stream, "P6\n{} {}\n255\n", width, height)?;
+                stream.write_all(&rgb)?;
+            }
+        }
+    }
+    stream.flush()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{json_string, parse_tolerance, verify_file, write_pnm, write_ppm};
+
+    #[test]
+    fn ppm() {
+        let image = pcx::decode(&include_bytes!("../../test-data/marbles.pcx")[..]).unwrap();
+        let mut ppm = Vec::new();
+        write_ppm(&mut ppm, &image).unwrap();
+        assert!(ppm.starts_with(b"P6\n143 101\n255\n"));
+        assert_eq!(pcx::Image::from_ppm(&ppm).unwrap(), image);
+    }
+
+    #[test]
+    fn pnm() {
+        let mut pnm = Vec::new();
+        write_pnm(&mut pnm, &pcx::decode(&include_bytes!("../../test-data/marbles.pcx")[..]).unwrap()).unwrap();
+        assert!(pnm.starts_with(b"P6\n143 101\n255\n"));
+
+        let mut pnm = Vec::new();
+        write_pnm(&mut pnm, &pcx::decode(&include_bytes!("../../test-data/gmarbles.pcx")[..]).unwrap()).unwrap();
+        assert!(pnm.starts_with(b"P5\n141 99\n255\n"));
+        assert_eq!(pnm.len(), "P5\n141 99\n255\n".len() + 141 * 99);
+
+        let gray_alpha = pcx::Image { width: 2, height: 1, pixels: pcx::Pixels::GrayAlpha(vec![1, 2, 3, 4]) };
+        let mut pnm = Vec::new();
+        write_pnm(&mut pnm, &gray_alpha).unwrap();
+        assert_eq!(pnm, &b"P7\nWIDTH 2\nHEIGHT 1\nDEPTH 2\nMAXVAL 255\nTUPLTYPE GRAYSCALE_ALPHA\nENDHDR\n\x01\x02\x03\x04"[..]);
+    }
+
+    #[test]
+    fn tolerance() {
+        assert_eq!(parse_tolerance("--colors"), Some(0));
+        assert_eq!(parse_tolerance("--colors=12"), Some(12));
+        assert_eq!(parse_tolerance("--colors=256"), None);
+        assert_eq!(parse_tolerance("--colours"), None);
+    }
+
+    #[test]
+    fn verify() {
+        let (ok, json) = verify_file("test-data/marbles.pcx");
+        assert!(ok);
+        assert_eq!(
+            json,
+            r#"{"file": "test-data/marbles.pcx", "status": "ok", "warnings": [], "header": {"width": 143, "height": 101, "bit_depth": 8, "planes": 3, "version": 5, "compressed": true}}"#
+        );
+
+        let (ok, json) = verify_file("test-data/missing.pcx");
+        assert!(!ok);
+        assert!(json.starts_with(r#"{"file": "test-data/missing.pcx", "status": "error", "error": "#) && json.ends_with(r#""warnings": [], "header": null}"#));
+
+        assert_eq!(json_string("a \"b\"\\\n\u{1}"), r#""a \"b\"\\\n\u0001""#);
+    }
+}
@@ -0,0 +1,111 @@
+//! Export of decoded images as BMP files.
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use pixels::{Image, Pixels};
+use {Error, Result};
+
+// Length of BITMAPFILEHEADER followed by BITMAPINFOHEADER.
+const BMP_HEADERS_LENGTH: u32 = 14 + 40;
+
+// Resolution stored in the header, 72 DPI in pixels per meter.
+const PIXELS_PER_METER: u32 = 2835;
+
+impl Image {
+    /// Encode the image as an uncompressed Windows BMP file, e.g. to view decoded images without depending on the `image` crate.
+    ///
+    /// Paletted images are stored with 8 bits per pixel and a palette of 256 colors, pixels referring to colors beyond the end
+    /// of the palette are black. Other images are stored as 24-bit RGB, alpha channel is dropped.
+    ///
+    /// Returns `Error::InvalidUsage` for images without pixels and images whose file would exceed the 4 GiB limit of BMP.
+    pub fn to_bmp_bytes(&self) -> Result<Vec<u8>> {
+        let (width, height) = (self.width as usize, self.height as usize);
+        if width == 0 || height == 0 {
+            return Err(Error::InvalidUsage("BMP images must have at least one pixel"));
+        }
+        let (bits_per_pixel, palette_length) = if self.is_paletted() { (8, 256 * 4) } else { (24, 0) };
+
+        // Rows are padded to a multiple of 4 bytes.
+        let row_length = (width * (bits_per_pixel / 8) + 3) & !3;
+        let pixels_offset = BMP_HEADERS_LENGTH + palette_length as u32;
+        let sizes = (row_length as u64)
+            .checked_mul(height as u64)
+            .filter(|&length| length <= u32::MAX as u64)
+            .and_then(|length| pixels_offset.checked_add(length as u32).map(|file_length| (length as u32, file_length)));
+        let (pixels_length, file_length) = match sizes {
+            Some(sizes) => sizes,
+            None => return Err(Error::InvalidUsage("image is too large for BMP")),
+        };
+
+        let mut bmp = Vec::with_capacity(file_length as usize);
+        bmp.extend_from_slice(b"BM");
+        for &value in &[file_length, 0, pixels_offset, 40] {
+            bmp.write_u32::<LittleEndian>(value).unwrap();
+        }
+        bmp.write_i32::<LittleEndian>(width as i32).unwrap();
+        bmp.write_i32::<LittleEndian>(height as i32).unwrap();
+        bmp.write_u16::<LittleEndian>(1).unwrap();
+        bmp.write_u16::<LittleEndian>(bits_per_pixel as u16).unwrap();
+        for &value in &[0, pixels_length, PIXELS_PER_METER, PIXELS_PER_METER, (palette_length / 4) as u32, 0] {
+            bmp.write_u32::<LittleEndian>(value).unwrap();
+        }
+
+        // Rows are stored from bottom to top, colors in B, G, R order.
+        match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                for index in 0..256 {
+                    let color = palette.get((index * 3)..(index * 3 + 3)).unwrap_or(&[0, 0, 0]);
+                    bmp.extend_from_slice(&[color[2], color[1], color[0], 0]);
+                }
+                for row in indices.chunks(width).rev() {
+                    bmp.extend_from_slice(row);
+                    bmp.resize(bmp.len() + row_length - width, 0);
+                }
+            }
+            _ => {
+                let rgb = self.to_rgb();
+                for row in rgb.chunks(width * 3).rev() {
+                    for pixel in row.chunks(3) {
+                        bmp.extend_from_slice(&[pixel[2], pixel[1], pixel[0]]);
+                    }
+                    bmp.resize(bmp.len() + row_length - width * 3, 0);
+                }
+            }
+        }
+        Ok(bmp)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Error, Image, Pixels};
+
+    #[test]
+    fn bmp() {
+        let image = Image { width: 2, height: 2, pixels: Pixels::Rgb(vec![1, 2, 3, 4, 5, 6, 7, 8, 9, 10, 11, 12]) };
+        let bmp = image.to_bmp_bytes().unwrap();
+        assert_eq!(bmp.len(), 54 + 2 * 8);
+        assert_eq!(&bmp[..2], b"BM");
+        assert_eq!(&bmp[2..6], [70, 0, 0, 0]);
+        assert_eq!(&bmp[28..30], [24, 0]);
+        assert_eq!(&bmp[54..], [9, 8, 7, 12, 11, 10, 0, 0, 3, 2, 1, 6, 5, 4, 0, 0]);
+
+        let image = Image { width: 3, height: 1, pixels: Pixels::Paletted { indices: vec![0, 1, 2], palette: vec![10, 20, 30, 40, 50, 60] } };
+        let bmp = image.to_bmp_bytes().unwrap();
+        assert_eq!(bmp.len(), 54 + 1024 + 4);
+        assert_eq!(&bmp[10..14], [(54 + 1024) as u8, 4, 0, 0]);
+        assert_eq!(&bmp[28..30], [8, 0]);
+        assert_eq!(&bmp[54..66], [30, 20, 10, 0, 60, 50, 40, 0, 0, 0, 0, 0]);
+        assert_eq!(&bmp[(54 + 1024)..], [0, 1, 2, 0]);
+
+        // Alpha is dropped.
+        let image = Image { width: 1, height: 1, pixels: Pixels::GrayAlpha(vec![7, 0]) };
+        assert_eq!(&image.to_bmp_bytes().unwrap()[54..], [7, 7, 7, 0]);
+
+        let empty = Image { width: 0, height: 2, pixels: Pixels::Rgb(Vec::new()) };
+        assert!(matches!(empty.to_bmp_bytes(), Err(Error::InvalidUsage(_))));
+
+        // Pixels are not looked at before the size is checked.
+        let huge = Image { width: 0xFFFF, height: 0xFFFF, pixels: Pixels::Rgb(Vec::new()) };
+        assert!(matches!(huge.to_bmp_bytes(), Err(Error::InvalidUsage(_))));
+    }
+}
@@ -0,0 +1,60 @@
+//! Splitting images into color channels and merging them back, e.g. to restore faded planes of scans independently.
+use pixels::{Image, Pixels, RawFormat};
+use {Error, Result};
+
+impl Image {
+    /// Split the image into red, green and blue channels, each a grayscale image as given by `RawFormat::Indexed8`.
+    ///
+    /// Paletted pixels are looked up in the palette and alpha channel is dropped, as in `to_rgb()`.
+    pub fn split_channels(&self) -> [Image; 3] {
+        let rgb = self.to_rgb();
+        [0, 1, 2].map(|channel| {
+            let values: Vec<u8> = rgb.iter().skip(channel).step_by(3).cloned().collect();
+            Image::from_raw(self.width, self.height, RawFormat::Indexed8, &values).expect("one value per pixel")
+        })
+    }
+
+    /// Merge red, green and blue channels into an RGB image, the inverse of `split_channels()`.
+    ///
+    /// Values of each channel are taken from the red values of the pixels as given by `to_rgb()`, which are the gray values of
+    /// grayscale images. All channels must have the same dimensions.
+    pub fn merge_channels(red: &Image, green: &Image, blue: &Image) -> Result<Image> {
+        if green.dimensions() != red.dimensions() || blue.dimensions() != red.dimensions() {
+            return Err(Error::InvalidUsage("channels must have the same dimensions"));
+        }
+
+        let channels = [red.to_rgb(), green.to_rgb(), blue.to_rgb()];
+        let pixels = (0..channels[0].len() / 3).flat_map(|i| channels.iter().map(move |channel| channel[i * 3])).collect();
+        Ok(Image {
+            width: red.width,
+            height: red.height,
+            pixels: Pixels::Rgb(pixels),
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Error, Image, Pixels};
+
+    #[test]
+    fn channels() {
+        let image = Image { width: 2, height: 1, pixels: Pixels::Rgba(vec![1, 2, 3, 4, 5, 6, 7, 8]) };
+        let [red, green, blue] = image.split_channels();
+        assert_eq!(red.clone().into_raw_parts().0, [1, 5]);
+        assert_eq!(green.clone().into_raw_parts().0, [2, 6]);
+        assert_eq!(blue.to_rgb(), [3, 3, 3, 7, 7, 7]);
+        assert!(red.is_paletted());
+
+        let merged = Image::merge_channels(&red, &green, &blue).unwrap();
+        assert_eq!(merged.pixels, Pixels::Rgb(vec![1, 2, 3, 5, 6, 7]));
+        assert_eq!(merged.dimensions(), (2, 1));
+
+        let paletted = Image { width: 3, height: 1, pixels: Pixels::Paletted { indices: vec![1, 0, 1], palette: vec![10, 20, 30, 40, 50, 60] } };
+        let [red, green, blue] = paletted.split_channels();
+        assert_eq!(Image::merge_channels(&red, &green, &blue).unwrap().to_rgb(), paletted.to_rgb());
+
+        let small = Image { width: 1, height: 1, pixels: Pixels::Rgb(vec![0; 3]) };
+        assert!(matches!(Image::merge_channels(&red, &small, &blue), Err(Error::InvalidUsage(_))));
+    }
+}
@@ -0,0 +1,153 @@
+//! Comparison of decoded images.
+use {Error, Result};
+use pixels::{Image, Pixels};
+
+/// How pixels are compared by `compare_images()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Comparison {
+    /// Stored values must be equal: palette indices of paletted images, channel values otherwise. Palettes are not compared,
+    /// both images must have the same color format.
+    Values,
+
+    /// Colors resolved through the palettes must be equal up to `tolerance` in each of the R, G and B channels, alpha is
+    /// ignored. Images of any color format can be compared, e.g. a re-quantized or remapped file with its original.
+    Colors {
+        /// Maximal allowed difference of each channel.
+        tolerance: u8,
+    },
+}
+
+/// Result of `compare_images()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Difference {
+    /// Number of pixels which differ.
+    pub differing_pixels: usize,
+
+    /// Position of the first differing pixel in row order, `None` if the images are identical.
+    pub first: Option<(u16, u16)>,
+
+    /// Largest difference of a color channel over all pixels, only computed when comparing `Comparison::Colors`.
+    pub max_channel_difference: u8,
+}
+
+impl Difference {
+    /// Whether no pixels differ.
+    pub fn is_identical(&self) -> bool {
+        self.differing_pixels == 0
+    }
+}
+
+/// Color error of an encoded image compared with its source, see `WriterQuantized::finish_verified()`.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct ColorError {
+    /// Largest difference of a color channel over all pixels.
+    pub max: u8,
+
+    /// Mean difference of a color channel over all channels of all pixels.
+    pub mean: f64,
+}
+
+impl ColorError {
+    /// Color error between two buffers of interleaved RGB values of the same length.
+    pub fn between(source: &[u8], encoded: &[u8]) -> Self {
+        let mut error = ColorError { max: 0, mean: 0.0 };
+        let mut sum = 0u64;
+        for (&a, &b) in source.iter().zip(encoded) {
+            let difference = a.abs_diff(b);
+            error.max = error.max.max(difference);
+            sum += difference as u64;
+        }
+        if !source.is_empty() {
+            error.mean = sum as f64 / source.len() as f64;
+        }
+        error
+    }
+}
+
+/// Compare pixels of two images of the same dimensions.
+///
+/// Returns `Error::InvalidUsage` if the dimensions differ or if the color formats differ when comparing `Comparison::Values`.
+pub fn compare_images(a: &Image, b: &Image, comparison: Comparison) -> Result<Difference> {
+    if a.dimensions() != b.dimensions() {
+        return Err(Error::InvalidUsage("compared images must have the same dimensions"));
+    }
+
+    let mut difference = Difference { differing_pixels: 0, first: None, max_channel_difference: 0 };
+    let mut count = |i: usize, differs: bool| {
+        if differs {
+            difference.differing_pixels += 1;
+            if difference.first.is_none() {
+                let width = a.width as usize;
+                difference.first = Some(((i % width) as u16, (i / width) as u16));
+            }
+        }
+    };
+
+    match comparison {
+        Comparison::Values => {
+            let (a, b, channels) = match (&a.pixels, &b.pixels) {
+                (Pixels::Paletted { indices: a, .. }, Pixels::Paletted { indices: b, .. }) => (a, b, 1),
+                (Pixels::Rgb(a), Pixels::Rgb(b)) => (a, b, 3),
+                (Pixels::GrayAlpha(a), Pixels::GrayAlpha(b)) => (a, b, 2),
+                (Pixels::Rgba(a), Pixels::Rgba(b)) => (a, b, 4),
+                _ => return Err(Error::InvalidUsage("compared images must have the same color format")),
+            };
+            for (i, (a, b)) in a.chunks(channels).zip(b.chunks(channels)).enumerate() {
+                count(i, a != b);
+            }
+        }
+        Comparison::Colors { tolerance } => {
+            let mut max_channel_difference = 0;
+            for (i, (a, b)) in a.to_rgb().chunks(3).zip(b.to_rgb().chunks(3)).enumerate() {
+                let channel_difference = a.iter().zip(b).map(|(&a, &b)| a.abs_diff(b)).max().unwrap_or(0);
+                max_channel_difference = max_channel_difference.max(channel_difference);
+                count(i, channel_difference > tolerance);
+            }
+            difference.max_channel_difference = max_channel_difference;
+        }
+    }
+    Ok(difference)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{compare_images, Comparison};
+    use {decode, Error, Image, Pixels};
+
+    #[test]
+    fn remapped() {
+        let original = decode(&include_bytes!("../test-data/gmarbles.pcx")[..]).unwrap();
+        let (indices, palette) = match original.pixels {
+            Pixels::Paletted { ref indices, ref palette } => (indices, palette),
+            _ => panic!("gmarbles.pcx must be paletted"),
+        };
+
+        // Same colors with reversed palette order.
+        let reversed: Vec<u8> = palette.chunks(3).rev().flat_map(|color| color.to_vec()).collect();
+        let remapped = Image {
+            width: original.width,
+            height: original.height,
+            pixels: Pixels::Paletted { indices: indices.iter().map(|&index| 255 - index).collect(), palette: reversed },
+        };
+        assert!(compare_images(&original, &remapped, Comparison::Colors { tolerance: 0 }).unwrap().is_identical());
+        assert!(!compare_images(&original, &remapped, Comparison::Values).unwrap().is_identical());
+
+        let rgb = Image { width: original.width, height: original.height, pixels: Pixels::Rgb(original.to_rgb()) };
+        assert!(compare_images(&remapped, &rgb, Comparison::Colors { tolerance: 0 }).unwrap().is_identical());
+        assert!(matches!(compare_images(&original, &rgb, Comparison::Values), Err(Error::InvalidUsage(_))));
+    }
+
+    #[test]
+    fn tolerance() {
+        let a = Image { width: 2, height: 2, pixels: Pixels::Rgb(vec![10, 10, 10, 20, 20, 20, 30, 30, 30, 40, 40, 40]) };
+        let b = Image { width: 2, height: 2, pixels: Pixels::Paletted { indices: vec![0, 0, 1, 1], palette: vec![12, 10, 10, 30, 30, 30] } };
+
+        let difference = compare_images(&a, &b, Comparison::Colors { tolerance: 2 }).unwrap();
+        assert_eq!((difference.differing_pixels, difference.first, difference.max_channel_difference), (2, Some((1, 0)), 10));
+        let difference = compare_images(&a, &b, Comparison::Colors { tolerance: 10 }).unwrap();
+        assert!(difference.is_identical() && difference.first.is_none());
+
+        let c = Image { width: 4, height: 1, pixels: Pixels::Rgb(vec![0; 12]) };
+        assert!(matches!(compare_images(&a, &c, Comparison::Values), Err(Error::InvalidUsage(_))));
+    }
+}
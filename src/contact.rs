@@ -0,0 +1,128 @@
+//! Contact sheets: many images laid out in a grid of thumbnails, e.g. for browsing asset collections.
+use pixels::{Image, Pixels};
+use {Error, Quantizer, Result};
+
+/// Layout of a contact sheet, see `render()` and `render_paletted()`.
+///
+/// Images are scaled down with `Image::thumbnail()` to fit into cells of equal size, centered in them and placed row by row.
+/// Cells are separated by `spacing()` pixels of the background color, also around the edges of the sheet.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct ContactSheet {
+    cell_size: (u16, u16),
+    columns: u16,
+    spacing: u16,
+    background: [u8; 3],
+}
+
+impl ContactSheet {
+    /// Create layout with `columns` cells of `cell_size` pixels per row, 4 pixels of spacing and black background.
+    pub fn new(cell_size: (u16, u16), columns: u16) -> Result<Self> {
+        if cell_size.0 == 0 || cell_size.1 == 0 || columns == 0 {
+            return Err(Error::InvalidUsage("cell size and number of columns must not be zero"));
+        }
+        Ok(ContactSheet { cell_size, columns, spacing: 4, background: [0; 3] })
+    }
+
+    /// Set the number of pixels between cells and around the edges of the sheet.
+    pub fn set_spacing(&mut self, spacing: u16) {
+        self.spacing = spacing;
+    }
+
+    /// Spacing set by `set_spacing()`.
+    pub fn spacing(&self) -> u16 {
+        self.spacing
+    }
+
+    /// Set the color of the spacing and of the parts of cells not covered by images.
+    pub fn set_background(&mut self, background: [u8; 3]) {
+        self.background = background;
+    }
+
+    /// Background color set by `set_background()`.
+    pub fn background(&self) -> [u8; 3] {
+        self.background
+    }
+
+    /// Width and height of the sheet for `count` images. Returns `Error::InvalidDimensions` if it does not fit into 16 bits.
+    pub fn size(&self, count: usize) -> Result<(u16, u16)> {
+        let rows = count.div_ceil(self.columns as usize);
+        let extent = |cells: usize, cell: u16| (cells * (cell as usize + self.spacing as usize)).checked_add(self.spacing as usize);
+        match (extent(self.columns as usize, self.cell_size.0), extent(rows, self.cell_size.1)) {
+            (Some(width), Some(height)) if width <= 0xFFFF && height <= 0xFFFF => Ok((width as u16, height as u16)),
+            _ => Err(Error::InvalidDimensions),
+        }
+    }
+
+    /// Lay out `images` into an RGB contact sheet, at least one image is required. Alpha channel is dropped.
+    pub fn render(&self, images: &[Image]) -> Result<Image> {
+        if images.is_empty() {
+            return Err(Error::InvalidUsage("contact sheet requires at least one image"));
+        }
+
+        let (width, height) = self.size(images.len())?;
+        let row_length = (width as usize) * 3;
+        let mut rgb: Vec<u8> = (0..(width as usize) * (height as usize)).flat_map(|_| self.background).collect();
+        for (i, image) in images.iter().enumerate() {
+            let thumbnail = image.thumbnail(self.cell_size);
+            let (column, row) = (i % (self.columns as usize), i / (self.columns as usize));
+            let x = self.spacing as usize + column * (self.cell_size.0 as usize + self.spacing as usize) + (self.cell_size.0 - thumbnail.width) as usize / 2;
+            let y = self.spacing as usize + row * (self.cell_size.1 as usize + self.spacing as usize) + (self.cell_size.1 - thumbnail.height) as usize / 2;
+
+            let thumbnail_row_length = (thumbnail.width as usize) * 3;
+            for (ty, source) in thumbnail.to_rgb().chunks(thumbnail_row_length.max(1)).enumerate() {
+                let start = (y + ty) * row_length + x * 3;
+                rgb[start..(start + source.len())].copy_from_slice(source);
+            }
+        }
+        Ok(Image { width, height, pixels: Pixels::Rgb(rgb) })
+    }
+
+    /// Lay out `images` like `render()` and convert the sheet to a paletted image with the palette built by `quantizer`.
+    pub fn render_paletted(&self, images: &[Image], quantizer: &Quantizer) -> Result<Image> {
+        let sheet = self.render(images)?;
+        let rgb = sheet.to_rgb();
+        let palette = quantizer.palette(&rgb)?;
+        Ok(Image {
+            width: sheet.width,
+            height: sheet.height,
+            pixels: Pixels::Paletted { indices: palette.map_rgb_in(&rgb, quantizer.color_space())?, palette: palette.to_rgb() },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ContactSheet;
+    use {Error, Image, Pixels, Quantizer};
+
+    #[test]
+    fn contact_sheet() {
+        let red = Image { width: 4, height: 2, pixels: Pixels::Rgb([255, 0, 0].repeat(8)) };
+        let blue = Image { width: 1, height: 1, pixels: Pixels::Paletted { indices: vec![1], palette: vec![0, 0, 0, 0, 0, 255] } };
+
+        let mut layout = ContactSheet::new((2, 2), 2).unwrap();
+        layout.set_spacing(1);
+        layout.set_background([9, 9, 9]);
+        assert_eq!(layout.size(3).unwrap(), (7, 7));
+
+        let sheet = layout.render(&[red.clone(), blue.clone(), red]).unwrap();
+        assert_eq!(sheet.dimensions(), (7, 7));
+        let rgb = sheet.to_rgb();
+        let pixel = |x: usize, y: usize| &rgb[((y * 7 + x) * 3)..((y * 7 + x) * 3 + 3)];
+        assert_eq!(pixel(0, 0), [9, 9, 9]);
+        // Red image is shrunk to 2x1, blue pixel is placed in the top-left of the middle of its cell.
+        assert_eq!((pixel(1, 1), pixel(2, 1), pixel(1, 2)), (&[255, 0, 0][..], &[255, 0, 0][..], &[9, 9, 9][..]));
+        assert_eq!((pixel(4, 1), pixel(5, 2)), (&[0, 0, 255][..], &[9, 9, 9][..]));
+        assert_eq!(pixel(1, 4), [255, 0, 0]);
+        assert_eq!(pixel(4, 4), [9, 9, 9]);
+
+        // Sheets with few colors are converted losslessly.
+        let paletted = layout.render_paletted(::std::slice::from_ref(&blue), &Quantizer::new(4).unwrap()).unwrap();
+        assert!(paletted.is_paletted());
+        assert_eq!(paletted.to_rgb(), layout.render(&[blue]).unwrap().to_rgb());
+
+        assert!(matches!(layout.render(&[]), Err(Error::InvalidUsage(_))));
+        assert!(matches!(ContactSheet::new((0, 1), 1), Err(Error::InvalidUsage(_))));
+        assert!(matches!(ContactSheet::new((0x8000, 1), 2).unwrap().size(1), Err(Error::InvalidDimensions)));
+    }
+}
@@ -0,0 +1,89 @@
+//! Writing of DCX files, which are multi-page containers of PCX images used e.g. by fax software.
+use std::io;
+use byteorder::{LittleEndian, WriteBytesExt};
+
+use {encode, user_error, Image};
+
+// Magic number at the start of DCX files.
+const MAGIC: u32 = 987_654_321;
+
+// DCX header holds a table of this many page offsets, at most one less are used since the table is terminated by zero.
+const OFFSET_TABLE_LENGTH: usize = 1024;
+const MAX_PAGES: usize = OFFSET_TABLE_LENGTH - 1;
+
+/// Write DCX file with the given pages, each page is a complete PCX file (e.g. produced by one of the writers).
+///
+/// At most 1023 pages are supported. The header always holds the full table of 1024 offsets, zero after the last page, so the
+/// first page starts at byte 4100 as expected by readers which do not scan the table.
+pub fn write_dcx<W: io::Write>(mut stream: W, pages: &[Vec<u8>]) -> io::Result<()> {
+    if pages.is_empty() || pages.len() > MAX_PAGES {
+        return user_error("pcx::write_dcx: number of pages must be between 1 and 1023");
+    }
+
+    stream.write_u32::<LittleEndian>(MAGIC)?;
+
+    let mut offset = 4 + 4 * (OFFSET_TABLE_LENGTH as u64);
+    for page in pages {
+        if offset > u32::MAX as u64 {
+            return user_error("pcx::write_dcx: pages do not fit into 4 GiB");
+        }
+        stream.write_u32::<LittleEndian>(offset as u32)?;
+        offset += page.len() as u64;
+    }
+    for _ in pages.len()..OFFSET_TABLE_LENGTH {
+        stream.write_u32::<LittleEndian>(0)?;
+    }
+
+    for page in pages {
+        stream.write_all(page)?;
+    }
+    stream.flush()
+}
+
+/// Write DCX file with the full resolution image as the first page and its thumbnail fitting into `thumbnail_size` as the
+/// second page, the layout expected by some document management systems.
+///
+/// Pages are written with `pcx::encode`, see it for the supported formats.
+pub fn write_dcx_with_thumbnail<W: io::Write>(stream: W, image: &Image, dpi: (u16, u16), thumbnail_size: (u16, u16)) -> io::Result<()> {
+    let mut full = Vec::new();
+    encode(&mut full, image, dpi)?;
+
+    let mut thumbnail = Vec::new();
+    encode(&mut thumbnail, &image.thumbnail(thumbnail_size), dpi)?;
+
+    write_dcx(stream, &[full, thumbnail])
+}
+
+#[cfg(test)]
+mod tests {
+    use byteorder::{LittleEndian, ReadBytesExt};
+
+    use {decode, Reader};
+    use super::{write_dcx, write_dcx_with_thumbnail, MAGIC};
+
+    #[test]
+    fn thumbnail_page() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = decode(&data[..]).unwrap();
+
+        let mut dcx = Vec::new();
+        write_dcx_with_thumbnail(&mut dcx, &image, (300, 300), (32, 32)).unwrap();
+
+        let mut header = &dcx[..];
+        assert_eq!(header.read_u32::<LittleEndian>().unwrap(), MAGIC);
+        let first = header.read_u32::<LittleEndian>().unwrap() as usize;
+        let second = header.read_u32::<LittleEndian>().unwrap() as usize;
+        for _ in 2..1024 {
+            assert_eq!(header.read_u32::<LittleEndian>().unwrap(), 0);
+        }
+        assert_eq!(first, 4100);
+
+        assert_eq!(decode(&dcx[first..second]).unwrap(), image);
+
+        let thumbnail = Reader::new(&dcx[second..]).unwrap().read_image().unwrap();
+        assert_eq!(thumbnail.dimensions(), (32, 22));
+        assert!(thumbnail.is_paletted());
+
+        assert!(write_dcx(&mut Vec::new(), &[]).is_err());
+    }
+}
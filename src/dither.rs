@@ -0,0 +1,175 @@
+//! Dithering methods with reproducible results.
+use {ColorSpace, Error, Palette, Result};
+
+/// Method of dithering used by `Palette::dither_with()`.
+///
+/// All methods are computed with integer arithmetic, so the same input gives the same indices on every run and machine, e.g.
+/// for content-addressed asset stores.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Dithering {
+    /// Floyd-Steinberg error diffusion, see `Palette::dither()`.
+    Diffusion,
+
+    /// Ordered dithering with Bayer matrix of `size` by `size` pixels, `size` must be 2, 4, 8 or 16. Gives regular patterns
+    /// which compress better with RLE than the other methods.
+    Ordered {
+        /// Size of the matrix.
+        size: u8,
+    },
+
+    /// Random noise added to each pixel, generated from `seed` with a fixed pseudo-random generator.
+    Noise {
+        /// Seed of the generator, images dithered with the same seed are identical.
+        seed: u64,
+    },
+}
+
+impl Palette {
+    /// Map RGB values interleaved (R, G, B, R, G, B, ...) to indices of palette colors with the given dithering method.
+    ///
+    /// Ordered and noise dithering shift each pixel by up to half of the typical distance between neighbouring palette colors
+    /// before looking up the nearest color. Pixels form rows of `width` pixels, length of `rgb` must be a multiple of
+    /// `width * 3`. The palette must not be empty unless `rgb` is empty.
+    pub fn dither_with(&self, rgb: &[u8], width: usize, dithering: Dithering) -> Result<Vec<u8>> {
+        self.dither_rows(rgb, width, 0, dithering, 100, ColorSpace::Rgb)
+    }
+
+    // Implementation of `dither_with()` for rows starting at row `first_row` of the image, so that the pattern of ordered
+    // dithering continues across calls. Shifts are scaled to `strength` percent and nearest colors are looked up in `color_space`.
+    pub(crate) fn dither_rows(&self, rgb: &[u8], width: usize, first_row: usize, dithering: Dithering, strength: i32, color_space: ColorSpace) -> Result<Vec<u8>> {
+        let size = match dithering {
+            Dithering::Diffusion => return self.dither(rgb, width),
+            Dithering::Ordered { size } if ![2, 4, 8, 16].contains(&size) => {
+                return Err(Error::InvalidUsage("size of the Bayer matrix must be 2, 4, 8 or 16"));
+            }
+            Dithering::Ordered { size } => size as usize,
+            Dithering::Noise { .. } => 0,
+        };
+        if rgb.is_empty() {
+            return Ok(Vec::new());
+        }
+        if width == 0 || rgb.len() % (width * 3) != 0 {
+            return Err(Error::InvalidUsage("RGB buffer length must be a multiple of the row length"));
+        }
+        if self.is_empty() {
+            return Err(Error::InvalidUsage("colors can not be mapped to an empty palette"));
+        }
+
+        let spread = self.spread() * strength / 100;
+        let matrix = bayer_matrix(size);
+        let mut state = match dithering {
+            // Zero state would make the generator produce only zeros, the one seed giving it is replaced.
+            Dithering::Noise { seed } if seed == NOISE_SEED_MASK => NOISE_SEED_MASK,
+            Dithering::Noise { seed } => seed ^ NOISE_SEED_MASK,
+            _ => 0,
+        };
+        let cells = (size * size) as i32;
+
+        Ok(rgb
+            .chunks(3)
+            .enumerate()
+            .map(|(i, color)| {
+                let offset = match dithering {
+                    Dithering::Noise { .. } => (xorshift(&mut state) % (spread as u64 + 1)) as i32 - spread / 2,
+                    _ => {
+                        let (x, y) = ((i % width) % size, (first_row + i / width) % size);
+                        (2 * matrix[y * size + x] + 1) * spread / (2 * cells) - spread / 2
+                    }
+                };
+                let shifted = [0, 1, 2].map(|channel| (color[channel] as i32 + offset).clamp(0, 255) as u8);
+                self.nearest_in(shifted, color_space).unwrap()
+            })
+            .collect())
+    }
+
+    // Mean distance of colors to their nearest other color, measured as the largest difference of a channel.
+    fn spread(&self) -> i32 {
+        let colors = self.colors();
+        if colors.len() < 2 {
+            return 0;
+        }
+
+        let sum: i32 = colors
+            .iter()
+            .enumerate()
+            .map(|(i, a)| {
+                let others = colors.iter().enumerate().filter(|&(j, _)| j != i);
+                others.map(|(_, b)| (0..3).map(|channel| (a[channel] as i32 - b[channel] as i32).abs()).max().unwrap()).min().unwrap()
+            })
+            .sum();
+        sum / colors.len() as i32
+    }
+}
+
+// Mixed into seeds of noise dithering, so that small seeds give well mixed generator states.
+const NOISE_SEED_MASK: u64 = 0x9E37_79B9_7F4A_7C15;
+
+// Bayer matrix of `size` by `size` values from 0 to `size * size - 1` in row order, built by repeatedly doubling the 1x1 matrix.
+pub(crate) fn bayer_matrix(size: usize) -> Vec<i32> {
+    let mut matrix = vec![0];
+    let mut n = 1;
+    while n < size {
+        let mut doubled = vec![0; 4 * n * n];
+        for y in 0..n {
+            for x in 0..n {
+                let value = 4 * matrix[y * n + x];
+                doubled[y * 2 * n + x] = value;
+                doubled[y * 2 * n + x + n] = value + 2;
+                doubled[(y + n) * 2 * n + x] = value + 3;
+                doubled[(y + n) * 2 * n + x + n] = value + 1;
+            }
+        }
+        matrix = doubled;
+        n *= 2;
+    }
+    matrix
+}
+
+// Xorshift64 pseudo-random generator.
+fn xorshift(state: &mut u64) -> u64 {
+    *state ^= *state << 13;
+    *state ^= *state >> 7;
+    *state ^= *state << 17;
+    *state
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{bayer_matrix, Dithering};
+    use {Error, Palette};
+
+    #[test]
+    fn bayer() {
+        assert_eq!(bayer_matrix(2), [0, 2, 3, 1]);
+        assert_eq!(bayer_matrix(4), [0, 8, 2, 10, 12, 4, 14, 6, 3, 11, 1, 9, 15, 7, 13, 5]);
+        let mut values = bayer_matrix(16);
+        values.sort_unstable();
+        assert_eq!(values, (0..256).collect::<Vec<_>>());
+    }
+
+    #[test]
+    fn reproducible() {
+        let palette = Palette::standard(2).unwrap();
+        let rgb: Vec<u8> = (0..64).flat_map(|_| [128; 3]).collect();
+
+        // Mid gray becomes an even pattern of black and white.
+        let ordered = palette.dither_with(&rgb, 8, Dithering::Ordered { size: 4 }).unwrap();
+        assert_eq!(ordered.iter().filter(|&&index| index == 1).count(), 32);
+        assert_eq!(ordered[..8], [0, 1, 0, 1, 0, 1, 0, 1]);
+
+        let noise = palette.dither_with(&rgb, 8, Dithering::Noise { seed: 7 }).unwrap();
+        assert_eq!(noise, palette.dither_with(&rgb, 8, Dithering::Noise { seed: 7 }).unwrap());
+        assert_ne!(noise, palette.dither_with(&rgb, 8, Dithering::Noise { seed: 8 }).unwrap());
+        let white = noise.iter().filter(|&&index| index == 1).count();
+        assert!((16..48).contains(&white), "{}", white);
+
+        // The seed which would give the zero state of the generator still gives noise.
+        let noise = palette.dither_with(&rgb, 8, Dithering::Noise { seed: 0x9E37_79B9_7F4A_7C15 }).unwrap();
+        let white = noise.iter().filter(|&&index| index == 1).count();
+        assert!((16..48).contains(&white), "{}", white);
+
+        assert_eq!(palette.dither_with(&rgb, 8, Dithering::Diffusion).unwrap(), palette.dither(&rgb, 8).unwrap());
+        assert!(matches!(palette.dither_with(&rgb, 8, Dithering::Ordered { size: 3 }), Err(Error::InvalidUsage(_))));
+        assert!(matches!(palette.dither_with(&rgb, 7, Dithering::Noise { seed: 0 }), Err(Error::InvalidUsage(_))));
+    }
+}
@@ -0,0 +1,192 @@
+//! Error type used by the decoder.
+use std::{error, fmt, io, result};
+
+use low_level::header::Version;
+
+/// Result type of the decoding functions.
+pub type Result<T> = result::Result<T, Error>;
+
+/// Error which can happen while reading PCX image.
+#[derive(Debug)]
+pub enum Error {
+    /// Error of the underlying stream. Truncated files are reported as `io::ErrorKind::UnexpectedEof`.
+    Io(io::Error),
+
+    /// Stream does not start with the PCX magic byte.
+    NotPcx,
+
+    /// Unknown file format version.
+    UnknownVersion(u8),
+
+    /// Unknown encoding of the pixel data.
+    UnknownEncoding(u8),
+
+    /// Image end coordinates are smaller than start coordinates or image dimensions do not fit into 16 bits.
+    InvalidDimensions,
+
+    /// Combination of bit depth and number of color planes is invalid or not supported.
+    UnsupportedFormat {
+        /// Bits per pixel per color plane.
+        bit_depth: u8,
+        /// Number of color planes.
+        number_of_color_planes: u8,
+    },
+
+    /// Combination of bit depth and number of color planes is not supported by the file format version, e.g. 24-bit RGB in
+    /// version 2.8 file.
+    UnsupportedByVersion {
+        /// File format version.
+        version: Version,
+        /// Bits per pixel per color plane.
+        bit_depth: u8,
+        /// Number of color planes.
+        number_of_color_planes: u8,
+    },
+
+    /// Lane length stored in the header is too small to hold a row of pixels.
+    InvalidLaneLength,
+
+    /// Image is supposed to have 256-color palette at the end of file but it is missing.
+    NoPalette,
+
+    /// Method for reading paletted images was called on RGB image.
+    NotPaletted,
+
+    /// Method for reading RGB images was called on paletted image.
+    NotRgb,
+
+    /// Method for reading grayscale images with alpha was called on image of another kind.
+    NotGrayAlpha,
+
+    /// Buffer passed to the reading method has wrong length.
+    BufferLength {
+        /// Required buffer length.
+        expected: usize,
+        /// Length of the passed buffer.
+        actual: usize,
+    },
+
+    /// All rows declared in the header were already read.
+    EndOfImage,
+
+    /// The API was used incorrectly in a way not covered by other variants.
+    InvalidUsage(&'static str),
+
+    /// Decoding the image would exceed a limit set in `Options`.
+    LimitExceeded {
+        /// Name of the limit.
+        limit: &'static str,
+        /// Maximal allowed value.
+        maximum: u64,
+        /// Value required by the image.
+        required: u64,
+    },
+
+    /// Decoding took longer than allowed by `Options::time_budget`.
+    TimedOut,
+
+    /// No palette with this name is registered, see `PaletteRegistry`.
+    UnknownPalette(String),
+
+    /// Allocation of a buffer failed, see `Options::fallible_allocation`.
+    OutOfMemory {
+        /// Size of the failed allocation in bytes.
+        required: usize,
+    },
+}
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Error::Io(ref error) => error.fmt(f),
+            Error::NotPcx => write!(f, "not a PCX file"),
+            Error::UnknownVersion(version) => write!(f, "PCX: unknown version {}", version),
+            Error::UnknownEncoding(encoding) => write!(f, "PCX: unknown encoding {}", encoding),
+            Error::InvalidDimensions => write!(f, "PCX: invalid dimensions"),
+            Error::UnsupportedFormat { bit_depth, number_of_color_planes } => write!(
+                f,
+                "PCX: invalid or unsupported color format ({} bits per pixel, {} color planes)",
+                bit_depth,
+                number_of_color_planes
+            ),
+            Error::UnsupportedByVersion { version, bit_depth, number_of_color_planes } => write!(
+                f,
+                "PCX: color format ({} bits per pixel, {} color planes) is not supported by version {}",
+                bit_depth,
+                number_of_color_planes,
+                version as u8
+            ),
+            Error::InvalidLaneLength => write!(f, "PCX: invalid lane length"),
+            Error::NoPalette => write!(f, "PCX: no 256-color palette"),
+            Error::NotPaletted => write!(f, "PCX: image is not paletted"),
+            Error::NotRgb => write!(f, "PCX: image is paletted"),
+            Error::NotGrayAlpha => write!(f, "PCX: image is not grayscale with alpha"),
+            Error::BufferLength { expected, actual } => write!(f, "PCX: buffer length must be {} but it is {}", expected, actual),
+            Error::EndOfImage => write!(f, "PCX: all rows were already read"),
+            Error::InvalidUsage(message) => write!(f, "PCX: {}", message),
+            Error::LimitExceeded { limit, maximum, required } => write!(f, "PCX: {} limit exceeded ({} required, maximum is {})", limit, required, maximum),
+            Error::TimedOut => write!(f, "PCX: decoding timed out"),
+            Error::UnknownPalette(ref name) => write!(f, "PCX: unknown palette \"{}\"", name),
+            Error::OutOfMemory { required } => write!(f, "PCX: not enough memory to allocate {} bytes", required),
+        }
+    }
+}
+
+/// Problem in the file which was tolerated because `Options::strict` is not set, see `Reader::warnings()`.
+///
+/// In strict mode the same problems are reported as errors.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Warning {
+    /// Combination of bit depth and number of color planes is not supported by the file format version stored in the header,
+    /// e.g. 24-bit RGB in version 2.5 file. Image is decoded according to the bit depth and number of color planes.
+    UnsupportedByVersion {
+        /// File format version.
+        version: Version,
+        /// Bits per pixel per color plane.
+        bit_depth: u8,
+        /// Number of color planes.
+        number_of_color_planes: u8,
+    },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Warning::UnsupportedByVersion { version, bit_depth, number_of_color_planes } => write!(
+                f,
+                "PCX: color format ({} bits per pixel, {} color planes) is not supported by version {}",
+                bit_depth,
+                number_of_color_planes,
+                version as u8
+            ),
+        }
+    }
+}
+
+impl error::Error for Error {
+    fn source(&self) -> Option<&(dyn error::Error + 'static)> {
+        match *self {
+            Error::Io(ref error) => Some(error),
+            _ => None,
+        }
+    }
+}
+
+impl From<io::Error> for Error {
+    fn from(error: io::Error) -> Self {
+        Error::Io(error)
+    }
+}
+
+impl From<Error> for io::Error {
+    fn from(error: Error) -> Self {
+        let kind = match error {
+            Error::Io(error) => return error,
+            Error::NotPaletted | Error::NotRgb | Error::NotGrayAlpha | Error::BufferLength { .. } | Error::EndOfImage | Error::InvalidUsage(_) | Error::UnknownPalette(_) => io::ErrorKind::InvalidInput,
+            Error::TimedOut => io::ErrorKind::TimedOut,
+            Error::OutOfMemory { .. } => io::ErrorKind::OutOfMemory,
+            _ => io::ErrorKind::InvalidData,
+        };
+        io::Error::new(kind, error)
+    }
+}
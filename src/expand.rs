@@ -0,0 +1,87 @@
+//! Expansion of paletted images to RGB and RGBA on multiple threads.
+use std::thread;
+
+use pixels::{Image, Pixels};
+
+impl Image {
+    /// Convert pixels to interleaved RGB values like `to_rgb()`, looking up strips of rows of paletted images on all available
+    /// cores. Result is the same as of `to_rgb()`.
+    pub fn to_rgb_parallel(&self) -> Vec<u8> {
+        match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => expand_parallel(indices, &lookup(palette), self.width as usize, 3),
+            _ => self.to_rgb(),
+        }
+    }
+
+    /// Convert pixels to interleaved RGBA values (R, G, B, A, R, G, B, A, ...), looking up strips of rows of paletted images
+    /// on all available cores.
+    ///
+    /// Pixels referring to colors beyond the end of the palette are converted to black, images without alpha channel are opaque.
+    pub fn to_rgba_parallel(&self) -> Vec<u8> {
+        match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => expand_parallel(indices, &lookup(palette), self.width as usize, 4),
+            Pixels::Rgb(ref rgb) => rgb.chunks(3).flat_map(|pixel| [pixel[0], pixel[1], pixel[2], 0xFF]).collect(),
+            Pixels::GrayAlpha(ref gray_alpha) => gray_alpha.chunks(2).flat_map(|pixel| [pixel[0], pixel[0], pixel[0], pixel[1]]).collect(),
+            Pixels::Rgba(ref rgba) => rgba.clone(),
+        }
+    }
+}
+
+// Opaque RGBA color of each index, black for indices beyond the end of `palette`.
+fn lookup(palette: &[u8]) -> [[u8; 4]; 256] {
+    let mut lookup = [[0, 0, 0, 0xFF]; 256];
+    for (entry, color) in lookup.iter_mut().zip(palette.chunks(3)) {
+        entry[..3].copy_from_slice(color);
+    }
+    lookup
+}
+
+fn expand_parallel(indices: &[u8], lookup: &[[u8; 4]; 256], width: usize, channels: usize) -> Vec<u8> {
+    let threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    expand_strips(indices, lookup, width, channels, threads)
+}
+
+// Write the first `channels` values of the colors of `indices` split into at most `strips` strips of rows, each on its own
+// thread.
+fn expand_strips(indices: &[u8], lookup: &[[u8; 4]; 256], width: usize, channels: usize, strips: usize) -> Vec<u8> {
+    let mut values = vec![0; indices.len() * channels];
+    if indices.is_empty() {
+        return values;
+    }
+
+    let height = indices.len() / width;
+    let rows_per_strip = height.div_ceil(strips.max(1));
+    thread::scope(|scope| {
+        for (strip, indices) in values.chunks_mut(width * rows_per_strip * channels).zip(indices.chunks(width * rows_per_strip)) {
+            scope.spawn(move || {
+                for (value, &index) in strip.chunks_mut(channels).zip(indices) {
+                    value.copy_from_slice(&lookup[index as usize][..channels]);
+                }
+            });
+        }
+    });
+    values
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{expand_strips, lookup};
+    use {Image, Pixels};
+
+    #[test]
+    fn expand() {
+        let indices: Vec<u8> = (0..7 * 5).map(|i| (i % 4) as u8).collect();
+        let palette = vec![10, 20, 30, 40, 50, 60, 70, 80, 90];
+        let image = Image { width: 7, height: 5, pixels: Pixels::Paletted { indices: indices.clone(), palette: palette.clone() } };
+        let rgb = image.to_rgb();
+        assert_eq!(image.to_rgb_parallel(), rgb);
+        for strips in 1..8 {
+            assert_eq!(expand_strips(&indices, &lookup(&palette), 7, 3, strips), rgb);
+        }
+
+        let rgba = image.to_rgba_parallel();
+        assert_eq!(&rgba[..16], [10, 20, 30, 255, 40, 50, 60, 255, 70, 80, 90, 255, 0, 0, 0, 255]);
+        assert_eq!(Image { width: 1, height: 1, pixels: Pixels::GrayAlpha(vec![7, 8]) }.to_rgba_parallel(), [7, 7, 7, 8]);
+        assert!(Image { width: 0, height: 0, pixels: Pixels::Paletted { indices: vec![], palette } }.to_rgba_parallel().is_empty());
+    }
+}
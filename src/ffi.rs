@@ -0,0 +1,279 @@
+//! C API for using the decoder from other languages, enabled by the `capi` feature.
+//!
+//! Build a static or dynamic library with `cargo rustc --release --features capi --crate-type staticlib` (or `cdylib`) and
+//! include `include/pcx.h`. All functions return one of the `PCX_*` status codes, `PCX_OK` on success. The header is written
+//! by hand, a test checks that it declares every constant and function of this module with matching types.
+//!
+//! Paletted images are decoded to palette indices, all other images to interleaved RGB values.
+#![allow(unsafe_code)]
+
+use std::os::raw::c_int;
+use std::{io, ptr, slice};
+
+use {Error, Reader};
+
+/// Success.
+pub const PCX_OK: c_int = 0;
+/// Error of the underlying stream, e.g. truncated data.
+pub const PCX_ERROR_IO: c_int = 1;
+/// Data is not a valid PCX image.
+pub const PCX_ERROR_INVALID_DATA: c_int = 2;
+/// Image uses a color format which is not supported.
+pub const PCX_ERROR_UNSUPPORTED: c_int = 3;
+/// Null pointer, buffer of wrong length or other incorrect use of the API.
+pub const PCX_ERROR_INVALID_ARGUMENT: c_int = 4;
+/// All rows were already read.
+pub const PCX_ERROR_END_OF_IMAGE: c_int = 5;
+/// Image is not paletted or its palette is missing.
+pub const PCX_ERROR_NO_PALETTE: c_int = 6;
+/// Decoding would exceed a limit.
+pub const PCX_ERROR_LIMIT: c_int = 7;
+/// Allocation of a buffer failed.
+pub const PCX_ERROR_OUT_OF_MEMORY: c_int = 8;
+
+/// Opaque PCX reader, created by `pcx_reader_new_from_memory` and destroyed by `pcx_reader_free`.
+pub struct PcxReader {
+    reader: Reader<io::Cursor<Vec<u8>>>,
+}
+
+fn status(error: &Error) -> c_int {
+    match *error {
+        Error::Io(_) => PCX_ERROR_IO,
+        Error::NotPcx | Error::UnknownVersion(_) | Error::UnknownEncoding(_) | Error::InvalidDimensions | Error::InvalidLaneLength => PCX_ERROR_INVALID_DATA,
+        Error::UnsupportedFormat { .. } | Error::UnsupportedByVersion { .. } => PCX_ERROR_UNSUPPORTED,
+        Error::NoPalette | Error::NotPaletted => PCX_ERROR_NO_PALETTE,
+        Error::EndOfImage => PCX_ERROR_END_OF_IMAGE,
+        Error::LimitExceeded { .. } | Error::TimedOut => PCX_ERROR_LIMIT,
+        Error::OutOfMemory { .. } => PCX_ERROR_OUT_OF_MEMORY,
+        Error::NotRgb | Error::NotGrayAlpha | Error::BufferLength { .. } | Error::InvalidUsage(_) | Error::UnknownPalette(_) => PCX_ERROR_INVALID_ARGUMENT,
+    }
+}
+
+/// Create reader of the PCX image stored in memory. The data is copied, so it may be freed right after the call.
+///
+/// On success `*reader` is set to the new reader which must be destroyed with `pcx_reader_free`.
+///
+/// # Safety
+///
+/// `data` must point to `length` readable bytes and `reader` must point to writable storage for a pointer.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_new_from_memory(data: *const u8, length: usize, reader: *mut *mut PcxReader) -> c_int {
+    if data.is_null() || reader.is_null() {
+        return PCX_ERROR_INVALID_ARGUMENT;
+    }
+
+    let data = slice::from_raw_parts(data, length).to_vec();
+    match Reader::new(io::Cursor::new(data)) {
+        Ok(inner) => {
+            *reader = Box::into_raw(Box::new(PcxReader { reader: inner }));
+            PCX_OK
+        }
+        Err(error) => {
+            *reader = ptr::null_mut();
+            status(&error)
+        }
+    }
+}
+
+/// Get width and height of the image.
+///
+/// # Safety
+///
+/// `reader` must be a valid reader, `width` and `height` must be valid pointers.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_size(reader: *const PcxReader, width: *mut u16, height: *mut u16) -> c_int {
+    if reader.is_null() || width.is_null() || height.is_null() {
+        return PCX_ERROR_INVALID_ARGUMENT;
+    }
+
+    let (w, h) = (*reader).reader.dimensions();
+    *width = w;
+    *height = h;
+    PCX_OK
+}
+
+/// Set `*paletted` to 1 if the image is paletted and to 0 otherwise.
+///
+/// # Safety
+///
+/// `reader` must be a valid reader, `paletted` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_is_paletted(reader: *const PcxReader, paletted: *mut c_int) -> c_int {
+    if reader.is_null() || paletted.is_null() {
+        return PCX_ERROR_INVALID_ARGUMENT;
+    }
+
+    *paletted = (*reader).reader.is_paletted() as c_int;
+    PCX_OK
+}
+
+/// Read next row of the image: `width` palette indices for paletted images, `width * 3` interleaved RGB values otherwise.
+///
+/// Order of rows is from top to bottom. `length` must be exactly the length of the row.
+///
+/// # Safety
+///
+/// `reader` must be a valid reader and `buffer` must point to `length` writable bytes.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_next_row(reader: *mut PcxReader, buffer: *mut u8, length: usize) -> c_int {
+    if reader.is_null() || buffer.is_null() {
+        return PCX_ERROR_INVALID_ARGUMENT;
+    }
+
+    let reader = &mut (*reader).reader;
+    let buffer = slice::from_raw_parts_mut(buffer, length);
+    let result = if reader.is_paletted() { reader.next_row_paletted(buffer) } else { reader.next_row_rgb(buffer) };
+    match result {
+        Ok(()) => PCX_OK,
+        Err(error) => status(&error),
+    }
+}
+
+/// Read palette of the paletted image as R, G, B, R, G, B, ... and set `*colors` to the number of colors.
+///
+/// May be called at any time, `length` must be at least 768 to hold 256 colors.
+///
+/// # Safety
+///
+/// `reader` must be a valid reader, `palette` must point to `length` writable bytes and `colors` must be a valid pointer.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_read_palette(reader: *mut PcxReader, palette: *mut u8, length: usize, colors: *mut usize) -> c_int {
+    if reader.is_null() || palette.is_null() || colors.is_null() || length < 256 * 3 {
+        return PCX_ERROR_INVALID_ARGUMENT;
+    }
+
+    let reader = &mut (*reader).reader;
+    let result = if reader.palette_length() == Some(256) { reader.read_palette_early() } else { reader.palette_or_default() };
+    match result {
+        Ok(result) => {
+            let rgb = result.to_rgb();
+            slice::from_raw_parts_mut(palette, length)[..rgb.len()].copy_from_slice(&rgb);
+            *colors = rgb.len() / 3;
+            PCX_OK
+        }
+        Err(error) => status(&error),
+    }
+}
+
+/// Destroy the reader. Null pointer is ignored.
+///
+/// # Safety
+///
+/// `reader` must be null or a reader created by `pcx_reader_new_from_memory` which was not freed yet.
+#[no_mangle]
+pub unsafe extern "C" fn pcx_reader_free(reader: *mut PcxReader) {
+    if !reader.is_null() {
+        drop(Box::from_raw(reader));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::ptr;
+
+    use super::*;
+
+    #[test]
+    fn read_paletted() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = ::decode(&data[..]).unwrap();
+
+        unsafe {
+            let mut reader = ptr::null_mut();
+            assert_eq!(pcx_reader_new_from_memory(data.as_ptr(), data.len(), &mut reader), PCX_OK);
+
+            let (mut width, mut height, mut paletted) = (0, 0, 0);
+            assert_eq!(pcx_reader_size(reader, &mut width, &mut height), PCX_OK);
+            assert_eq!(pcx_reader_is_paletted(reader, &mut paletted), PCX_OK);
+            assert_eq!((width, height, paletted), (141, 99, 1));
+
+            let mut palette = [0; 768];
+            let mut colors = 0;
+            assert_eq!(pcx_reader_read_palette(reader, palette.as_mut_ptr(), palette.len(), &mut colors), PCX_OK);
+            assert_eq!(colors, 256);
+
+            let mut indices = vec![0; 141 * 99];
+            for row in indices.chunks_mut(141) {
+                assert_eq!(pcx_reader_next_row(reader, row.as_mut_ptr(), row.len()), PCX_OK);
+            }
+            assert_eq!(pcx_reader_next_row(reader, indices.as_mut_ptr(), 141), PCX_ERROR_END_OF_IMAGE);
+            assert_eq!(::Pixels::Paletted { indices, palette: palette.to_vec() }, image.pixels);
+
+            pcx_reader_free(reader);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        unsafe {
+            let mut reader = ptr::null_mut();
+            assert_eq!(pcx_reader_new_from_memory(data.as_ptr(), 10, &mut reader), PCX_ERROR_IO);
+            assert!(reader.is_null());
+            assert_eq!(pcx_reader_new_from_memory(data[1..].as_ptr(), data.len() - 1, &mut reader), PCX_ERROR_INVALID_DATA);
+            assert_eq!(pcx_reader_new_from_memory(ptr::null(), 0, &mut reader), PCX_ERROR_INVALID_ARGUMENT);
+
+            assert_eq!(pcx_reader_new_from_memory(data.as_ptr(), data.len(), &mut reader), PCX_OK);
+            let mut row = [0; 143];
+            assert_eq!(pcx_reader_next_row(reader, row.as_mut_ptr(), row.len()), PCX_ERROR_INVALID_ARGUMENT);
+            let mut palette = [0; 768];
+            let mut colors = 0;
+            assert_eq!(pcx_reader_read_palette(reader, palette.as_mut_ptr(), palette.len(), &mut colors), PCX_ERROR_NO_PALETTE);
+            pcx_reader_free(reader);
+            pcx_reader_free(ptr::null_mut());
+        }
+    }
+
+    // C type of a parameter or return type of the functions exported by this module.
+    fn c_type(rust: &str) -> String {
+        if let Some(pointee) = rust.strip_prefix("*const ") {
+            return format!("const {} *", c_type(pointee));
+        }
+        if let Some(pointee) = rust.strip_prefix("*mut ") {
+            let pointee = c_type(pointee);
+            return if pointee.ends_with('*') { format!("{}*", pointee) } else { format!("{} *", pointee) };
+        }
+        match rust {
+            "u8" => "uint8_t",
+            "u16" => "uint16_t",
+            "usize" => "size_t",
+            "c_int" => "int",
+            "PcxReader" => "PcxReader",
+            _ => panic!("no C type for {}", rust),
+        }
+        .to_string()
+    }
+
+    #[test]
+    fn header_in_sync() {
+        let header = include_str!("../include/pcx.h");
+        let source = include_str!("ffi.rs");
+
+        let mut constants = 0;
+        for line in source.lines().filter_map(|line| line.strip_prefix("pub const ")) {
+            let (name, value) = line.split_once(": c_int = ").unwrap();
+            assert!(header.contains(&format!("#define {} {}\n", name, value.trim_end_matches(';'))), "{} missing in pcx.h", name);
+            constants += 1;
+        }
+        assert_eq!(header.matches("#define PCX_OK").count() + header.matches("#define PCX_ERROR_").count(), constants);
+
+        let mut functions = 0;
+        for line in source.lines().filter_map(|line| line.strip_prefix("pub unsafe extern \"C\" fn ")) {
+            let (name, rest) = line.split_once('(').unwrap();
+            let (parameters, rest) = rest.split_once(')').unwrap();
+            let result = rest.trim_end_matches('{').trim().strip_prefix("-> ").map_or("void".to_string(), c_type);
+            let parameters: Vec<String> = parameters
+                .split(", ")
+                .map(|parameter| {
+                    let (parameter, rust) = parameter.split_once(": ").unwrap();
+                    let c = c_type(rust);
+                    if c.ends_with('*') { format!("{}{}", c, parameter) } else { format!("{} {}", c, parameter) }
+                })
+                .collect();
+            let declaration = format!("{} {}({});", result, name, parameters.join(", "));
+            assert!(header.contains(&declaration), "{} missing in pcx.h", declaration);
+            functions += 1;
+        }
+        assert_eq!(header.matches(" pcx_").count(), functions);
+    }
+}
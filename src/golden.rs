@@ -0,0 +1,226 @@
+//! Golden corpus of reference images for testing, enabled by the `golden` feature.
+//!
+//! Each case of a corpus directory consists of a reference image `NAME.ppm` (binary PPM or PGM with maximal value 255) and
+//! expected metadata `NAME.json`, a flat JSON object such as `{"width": 9, "height": 7, "bit_depth": 1, "planes": 4}` with
+//! optional `"version"` and `"compressed"` entries. If `NAME.pcx` is present it is decoded and compared with the reference,
+//! otherwise the reference image is first encoded in the format given by the metadata. New combinations of planes and bit
+//! depth can therefore be covered by adding just a reference image and its metadata.
+use std::fs;
+use std::io;
+use std::path::Path;
+
+use {Image, Quantizer, Reader, WriterOptions, WriterPaletted, WriterPlanar, WriterRgb};
+use low_level::header::Version;
+
+/// Expected properties of the image of a golden case.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Metadata {
+    /// Width and height of the image.
+    pub size: (u16, u16),
+
+    /// Number of bits per pixel in each color plane.
+    pub bit_depth: u8,
+
+    /// Number of color planes.
+    pub number_of_color_planes: u8,
+
+    /// Expected file format version, not checked if `None`.
+    pub version: Option<Version>,
+
+    /// Whether pixel data is RLE-compressed, `true` if not given.
+    pub compressed: bool,
+}
+
+/// Reference image with its expected metadata and optionally the PCX file to check.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct GoldenCase {
+    /// Name of the case, the common file stem.
+    pub name: String,
+
+    /// Expected metadata.
+    pub metadata: Metadata,
+
+    /// Reference pixels as R, G, B, R, G, B, ...
+    pub reference: Vec<u8>,
+
+    /// Contents of the PCX file, `None` if it is to be generated from the reference image.
+    pub pcx: Option<Vec<u8>>,
+}
+
+/// Load all cases of the corpus in `directory` sorted by name.
+pub fn load_corpus<P: AsRef<Path>>(directory: P) -> io::Result<Vec<GoldenCase>> {
+    let mut cases = Vec::new();
+    for entry in fs::read_dir(directory)? {
+        let path = entry?.path();
+        if path.extension().map_or(true, |extension| extension != "json") {
+            continue;
+        }
+
+        let name = path.file_stem().unwrap_or_default().to_string_lossy().into_owned();
+        let metadata = parse_metadata(&fs::read_to_string(&path)?)?;
+        let reference = Image::from_ppm(&fs::read(path.with_extension("ppm"))?)?;
+        if reference.dimensions() != metadata.size {
+            return Err(invalid_data(format!("{}: size of the reference image differs from the metadata", name)));
+        }
+        let pcx = match fs::read(path.with_extension("pcx")) {
+            Ok(pcx) => Some(pcx),
+            Err(ref error) if error.kind() == io::ErrorKind::NotFound => None,
+            Err(error) => return Err(error),
+        };
+        cases.push(GoldenCase { name, metadata, reference: reference.to_rgb(), pcx });
+    }
+    cases.sort_by(|a, b| a.name.cmp(&b.name));
+    Ok(cases)
+}
+
+impl GoldenCase {
+    /// Check the case: decode the PCX file, generating it first if needed, and compare it with the metadata and the reference
+    /// image. Returns description of the first mismatch found.
+    pub fn run(&self) -> Result<(), String> {
+        let generated;
+        let pcx = match self.pcx {
+            Some(ref pcx) => pcx,
+            None => {
+                generated = self.encode().map_err(|error| format!("{}: encoding failed: {}", self.name, error))?;
+                &generated
+            }
+        };
+
+        let reader = Reader::new(&pcx[..]).map_err(|error| format!("{}: {}", self.name, error))?;
+        let header = reader.header;
+        let actual = Metadata {
+            size: header.size,
+            bit_depth: header.bit_depth,
+            number_of_color_planes: header.number_of_color_planes,
+            version: self.metadata.version.map(|_| header.version),
+            compressed: header.is_compressed,
+        };
+        if actual != self.metadata {
+            return Err(format!("{}: expected {:?}, found {:?}", self.name, self.metadata, actual));
+        }
+
+        let rgb = reader.read_image_rgb().map_err(|error| format!("{}: {}", self.name, error))?;
+        match rgb.chunks(3).zip(self.reference.chunks(3)).position(|(actual, expected)| actual != expected) {
+            Some(i) => {
+                let width = self.metadata.size.0 as usize;
+                Err(format!("{}: pixel ({}, {}) differs from the reference", self.name, i % width, i / width))
+            }
+            None => Ok(()),
+        }
+    }
+
+    // Encode the reference image in the format given by the metadata.
+    fn encode(&self) -> io::Result<Vec<u8>> {
+        let metadata = &self.metadata;
+        let options = WriterOptions {
+            compress: metadata.compressed,
+            version: metadata.version,
+            ..WriterOptions::default()
+        };
+        let width = metadata.size.0 as usize;
+
+        let mut pcx = Vec::new();
+        match (metadata.bit_depth, metadata.number_of_color_planes) {
+            (8, 3) => {
+                let mut writer = WriterRgb::new_with_options(&mut pcx, metadata.size, (300, 300), options)?;
+                for row in self.reference.chunks(width * 3) {
+                    writer.write_row(row)?;
+                }
+                writer.finish()?;
+            }
+            (8, 1) => {
+                let (indices, mut palette) = self.quantize(256)?;
+                palette.resize(256 * 3, 0);
+                let mut writer = WriterPaletted::new_with_options(&mut pcx, metadata.size, (300, 300), options)?;
+                for row in indices.chunks(width) {
+                    writer.write_row(row)?;
+                }
+                writer.write_palette(&palette)?;
+            }
+            (1, planes @ 1..=4) => {
+                let (indices, palette) = self.quantize(1 << planes)?;
+                let mut writer = WriterPlanar::new_with_options(&mut pcx, metadata.size, (300, 300), planes, &palette, options)?;
+                for row in indices.chunks(width) {
+                    writer.write_row(row)?;
+                }
+                writer.finish()?;
+            }
+            (bit_depth, planes) => return Err(invalid_data(format!("no writer for {} bits per pixel in {} planes", bit_depth, planes))),
+        }
+        Ok(pcx)
+    }
+
+    // Palette indices and RGB palette of the reference image with at most `colors` colors.
+    fn quantize(&self, colors: u16) -> io::Result<(Vec<u8>, Vec<u8>)> {
+        let palette = Quantizer::new(colors).unwrap().palette(&self.reference).map_err(|error| invalid_data(error.to_string()))?;
+        let indices = palette.map_rgb(&self.reference).map_err(|error| invalid_data(error.to_string()))?;
+        Ok((indices, palette.to_rgb()))
+    }
+}
+
+/// Parse metadata from a flat JSON object with number and boolean values.
+pub fn parse_metadata(json: &str) -> io::Result<Metadata> {
+    let body = json.trim();
+    let body = body
+        .strip_prefix('{')
+        .and_then(|body| body.strip_suffix('}'))
+        .ok_or_else(|| invalid_data("metadata: expected JSON object".into()))?;
+
+    let mut size = (None, None);
+    let mut metadata = Metadata { size: (0, 0), bit_depth: 0, number_of_color_planes: 0, version: None, compressed: true };
+    let (mut bit_depth, mut planes) = (None, None);
+    for entry in body.split(',').filter(|entry| !entry.trim().is_empty()) {
+        let (key, value) = entry.split_once(':').ok_or_else(|| invalid_data(format!("metadata: invalid entry {}", entry.trim())))?;
+        let (key, value) = (key.trim().trim_matches('"'), value.trim());
+        let number = || value.parse::<u16>().map_err(|_| invalid_data(format!("metadata: {} must be a number", key)));
+        match key {
+            "width" => size.0 = Some(number()?),
+            "height" => size.1 = Some(number()?),
+            "bit_depth" => bit_depth = Some(number()? as u8),
+            "planes" => planes = Some(number()? as u8),
+            "version" => metadata.version = Some(Version::from_u8(number()? as u8).ok_or_else(|| invalid_data("metadata: unknown version".into()))?),
+            "compressed" => metadata.compressed = value == "true",
+            _ => return Err(invalid_data(format!("metadata: unknown key {}", key))),
+        }
+    }
+
+    match (size, bit_depth, planes) {
+        ((Some(width), Some(height)), Some(bit_depth), Some(planes)) => {
+            metadata.size = (width, height);
+            metadata.bit_depth = bit_depth;
+            metadata.number_of_color_planes = planes;
+            Ok(metadata)
+        }
+        _ => Err(invalid_data("metadata: width, height, bit_depth and planes are required".into())),
+    }
+}
+
+fn invalid_data(message: String) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{load_corpus, parse_metadata};
+    use low_level::header::Version;
+
+    #[test]
+    fn corpus() {
+        let cases = load_corpus("test-data/golden").unwrap();
+        assert!(cases.iter().any(|case| case.pcx.is_some()) && cases.iter().any(|case| case.pcx.is_none()));
+
+        let failures: Vec<String> = cases.iter().filter_map(|case| case.run().err()).collect();
+        assert!(failures.is_empty(), "{}", failures.join("\n"));
+    }
+
+    #[test]
+    fn metadata() {
+        let metadata = parse_metadata(r#"{ "width": 3, "height": 2, "bit_depth": 8, "planes": 1, "version": 2, "compressed": false }"#).unwrap();
+        assert_eq!((metadata.size, metadata.bit_depth, metadata.number_of_color_planes), ((3, 2), 8, 1));
+        assert_eq!((metadata.version, metadata.compressed), (Some(Version::V2), false));
+
+        assert!(parse_metadata(r#"{"width": 3, "height": 2, "bit_depth": 8}"#).is_err());
+        assert!(parse_metadata(r#"{"width": 3, "height": 2, "bit_depth": 8, "planes": 1, "colour": 1}"#).is_err());
+        assert!(parse_metadata("[]").is_err());
+    }
+}
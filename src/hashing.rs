@@ -0,0 +1,86 @@
+//! Digest of the source bytes computed while decoding.
+use std::hash::Hasher;
+use std::io;
+
+/// Stream adapter feeding all bytes read from the underlying stream to a hasher, e.g. a CRC-32 implementing `std::hash::Hasher`.
+///
+/// Wrap the source before passing it to `Reader` to get the digest of the file without reading it twice:
+///
+/// ```
+/// use std::collections::hash_map::DefaultHasher;
+/// use std::hash::Hasher;
+///
+/// let data = include_bytes!("../test-data/marbles.pcx");
+/// let mut reader = pcx::Reader::new(pcx::HashingReader::new(&data[..], DefaultHasher::new())).unwrap();
+/// for row in reader.rows() {
+///     row.unwrap();
+/// }
+/// let digest = reader.get_ref().hasher().finish();
+/// ```
+///
+/// The reader reads ahead, so the digest may cover data following the image if the file continues after it. Read the rest of the
+/// stream returned by `Reader::finish()` to get the digest of the whole file.
+#[derive(Clone, Debug)]
+pub struct HashingReader<R: io::Read, H: Hasher> {
+    stream: R,
+    hasher: H,
+}
+
+impl<R: io::Read, H: Hasher> HashingReader<R, H> {
+    /// Create adapter reading from `stream` and feeding the data to `hasher`.
+    pub fn new(stream: R, hasher: H) -> Self {
+        HashingReader { stream, hasher }
+    }
+
+    /// Hasher which received all bytes read so far.
+    pub fn hasher(&self) -> &H {
+        &self.hasher
+    }
+
+    /// Get the underlying stream and the hasher.
+    pub fn into_inner(self) -> (R, H) {
+        (self.stream, self.hasher)
+    }
+}
+
+impl<R: io::Read, H: Hasher> io::Read for HashingReader<R, H> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buffer)?;
+        self.hasher.write(&buffer[..read]);
+        Ok(read)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::hash::Hasher;
+    use std::io::Read;
+
+    use super::HashingReader;
+    use Reader;
+
+    // Hasher keeping all the bytes it received.
+    #[derive(Default)]
+    struct Collect(Vec<u8>);
+
+    impl Hasher for Collect {
+        fn write(&mut self, bytes: &[u8]) {
+            self.0.extend_from_slice(bytes);
+        }
+
+        fn finish(&self) -> u64 {
+            self.0.len() as u64
+        }
+    }
+
+    #[test]
+    fn whole_file() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let reader = Reader::new(HashingReader::new(&data[..], Collect::default())).unwrap();
+        let (_, mut rest) = reader.finish().unwrap();
+        rest.read_to_end(&mut Vec::new()).unwrap();
+
+        let (_, stream) = rest.into_inner();
+        assert_eq!(stream.hasher().0, &data[..]);
+    }
+}
@@ -0,0 +1,205 @@
+//! Integration with the `image` crate. Available with the `image` feature.
+use std::io;
+use image::{ColorType, DecodingResult, DynamicImage, ImageBuffer, ImageDecoder, ImageError, ImageResult};
+
+use {Error, Reader, WriterPaletted, WriterRgb};
+
+/// PCX decoder implementing `image::ImageDecoder`.
+///
+/// All images are decoded as 8-bit RGB, alpha channel is dropped. Paletted images with 256-color palette have the palette stored at the end of file,
+/// so they are decoded into memory as a whole when the first row is requested.
+pub struct PcxDecoder<R: io::Read> {
+    reader: Option<Reader<R>>,
+    dimensions: (u16, u16),
+    decoded_rgb: Option<Vec<u8>>,
+    next_row: u16,
+}
+
+impl<R: io::Read> PcxDecoder<R> {
+    /// Create new decoder. This will read the PCX header.
+    pub fn new(stream: R) -> ImageResult<Self> {
+        let reader = Reader::new(stream).map_err(image_error)?;
+        Ok(PcxDecoder {
+            dimensions: reader.dimensions(),
+            reader: Some(reader),
+            decoded_rgb: None,
+            next_row: 0,
+        })
+    }
+
+    /// Decode the whole image into `image::DynamicImage`.
+    pub fn into_dynamic_image(mut self) -> ImageResult<DynamicImage> {
+        let (width, height) = self.dimensions()?;
+        let rgb = match self.read_image()? {
+            DecodingResult::U8(rgb) => rgb,
+            DecodingResult::U16(_) => unreachable!(),
+        };
+        ImageBuffer::from_raw(width, height, rgb).map(DynamicImage::ImageRgb8).ok_or(ImageError::DimensionError)
+    }
+
+    fn row_length(&self) -> usize {
+        (self.dimensions.0 as usize) * 3
+    }
+}
+
+impl<R: io::Read> ImageDecoder for PcxDecoder<R> {
+    fn dimensions(&mut self) -> ImageResult<(u32, u32)> {
+        Ok((self.dimensions.0 as u32, self.dimensions.1 as u32))
+    }
+
+    fn colortype(&mut self) -> ImageResult<ColorType> {
+        Ok(ColorType::RGB(8))
+    }
+
+    fn row_len(&mut self) -> ImageResult<usize> {
+        Ok(self.row_length())
+    }
+
+    fn read_scanline(&mut self, buf: &mut [u8]) -> ImageResult<u32> {
+        if self.next_row == self.dimensions.1 {
+            return Err(ImageError::ImageEnd);
+        }
+
+        let row_length = self.row_length();
+        if buf.len() < row_length {
+            return Err(ImageError::DimensionError);
+        }
+
+        let paletted = self.reader.as_ref().map(|reader| reader.is_paletted()).unwrap_or(false);
+        if paletted {
+            let reader = self.reader.take().unwrap();
+            let image = reader.read_image().map_err(image_error)?;
+            self.decoded_rgb = Some(image.to_rgb());
+        }
+
+        match self.decoded_rgb {
+            Some(ref rgb) => {
+                let offset = (self.next_row as usize) * row_length;
+                buf[..row_length].copy_from_slice(&rgb[offset..(offset + row_length)]);
+            }
+            None => {
+                let reader = self.reader.as_mut().unwrap();
+                reader.next_row_rgb(&mut buf[..row_length]).map_err(image_error)?;
+            }
+        }
+
+        self.next_row += 1;
+        Ok(row_length as u32)
+    }
+
+    fn read_image(&mut self) -> ImageResult<DecodingResult> {
+        let row_length = self.row_length();
+        let mut rgb = vec![0; row_length * ((self.dimensions.1 - self.next_row) as usize)];
+        for row in rgb.chunks_mut(row_length) {
+            self.read_scanline(row)?;
+        }
+
+        Ok(DecodingResult::U8(rgb))
+    }
+}
+
+fn image_error(error: Error) -> ImageError {
+    match error {
+        Error::Io(error) => ImageError::IoError(error),
+        Error::UnsupportedFormat { .. } => ImageError::UnsupportedError(error.to_string()),
+        error => ImageError::FormatError(error.to_string()),
+    }
+}
+
+/// PCX encoder with the same interface as encoders of the `image` crate.
+pub struct PcxEncoder<W: io::Write> {
+    stream: W,
+}
+
+impl<W: io::Write> PcxEncoder<W> {
+    /// Create new encoder which writes to `stream`.
+    pub fn new(stream: W) -> Self {
+        PcxEncoder { stream }
+    }
+
+    /// Encode the image `data` that has dimensions `width` and `height` and `ColorType` `color`.
+    ///
+    /// Supported color types are `RGB(8)`, which is saved as 24-bit RGB, and `Gray(8)`, which is saved as paletted image
+    /// with grayscale palette. Image dimensions must fit into `u16`.
+    pub fn encode(self, data: &[u8], width: u32, height: u32, color: ColorType) -> io::Result<()> {
+        if width > 0xFFFF || height > 0xFFFF {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "image is too large for PCX"));
+        }
+        let size = (width as u16, height as u16);
+
+        let channels = match color {
+            ColorType::RGB(8) => 3,
+            ColorType::Gray(8) => 1,
+            _ => return Err(io::Error::new(io::ErrorKind::InvalidInput, "color type is not supported by PCX encoder")),
+        };
+
+        let row_length = (width as usize) * channels;
+        if data.len() != row_length * (height as usize) {
+            return Err(io::Error::new(io::ErrorKind::InvalidInput, "data length does not match image dimensions"));
+        }
+
+        if channels == 3 {
+            let mut writer = WriterRgb::new(self.stream, size, (300, 300))?;
+            for row in data.chunks(row_length) {
+                writer.write_row(row)?;
+            }
+            writer.finish()
+        } else {
+            let mut writer = WriterPaletted::new(self.stream, size, (300, 300))?;
+            for row in data.chunks(row_length) {
+                writer.write_row(row)?;
+            }
+
+            let palette: Vec<u8> = (0..256).flat_map(|i| vec![i as u8; 3]).collect();
+            writer.write_palette(&palette)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::fs::File;
+    use std::io;
+    use image::{self, ColorType, ImageDecoder};
+
+    use super::{PcxDecoder, PcxEncoder};
+
+    #[test]
+    fn decode_paletted() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let decoded = PcxDecoder::new(&data[..]).unwrap().into_dynamic_image().unwrap().to_rgb();
+
+        let reference = File::open(concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/gmarbles.png")).unwrap();
+        let reference = image::load(io::BufReader::new(reference), image::ImageFormat::PNG).unwrap().to_rgb();
+        assert_eq!(decoded.into_raw(), reference.into_raw());
+    }
+
+    #[test]
+    fn decode_rgb_scanlines() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut decoder = PcxDecoder::new(&data[..]).unwrap();
+        assert_eq!(decoder.dimensions().unwrap(), (143, 101));
+
+        let mut row = vec![0; decoder.row_len().unwrap()];
+        for _ in 0..101 {
+            decoder.read_scanline(&mut row).unwrap();
+        }
+        assert!(decoder.read_scanline(&mut row).is_err());
+    }
+
+    #[test]
+    fn encode_round_trip() {
+        let gray: Vec<u8> = (0..(7 * 5)).map(|v| (v * 7) as u8).collect();
+        let mut pcx = Vec::new();
+        PcxEncoder::new(&mut pcx).encode(&gray, 7, 5, ColorType::Gray(8)).unwrap();
+
+        let decoded = PcxDecoder::new(&pcx[..]).unwrap().into_dynamic_image().unwrap().to_rgb().into_raw();
+        let expected: Vec<u8> = gray.iter().flat_map(|&v| vec![v; 3]).collect();
+        assert_eq!(decoded, expected);
+
+        let mut pcx = Vec::new();
+        PcxEncoder::new(&mut pcx).encode(&expected, 7, 5, ColorType::RGB(8)).unwrap();
+        let decoded = PcxDecoder::new(&pcx[..]).unwrap().into_dynamic_image().unwrap().to_rgb().into_raw();
+        assert_eq!(decoded, expected);
+    }
+}
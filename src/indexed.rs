@@ -0,0 +1,145 @@
+//! Export of paletted images in the data model of indexed PNG and BMP files.
+use {Error, Result};
+use palette::{Palette, Transparency};
+use pixels::{Image, Pixels};
+
+/// Paletted image as indices, palette and optional alpha table which map 1:1 onto indexed PNG (`PLTE` and `tRNS` chunks) and
+/// BMP, see `Image::to_indexed()`.
+///
+/// Converters can write it without expanding it to RGB and quantizing it again, so the palette is preserved exactly.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct IndexedImage {
+    /// Width of the image.
+    pub width: u16,
+
+    /// Height of the image.
+    pub height: u16,
+
+    /// Smallest bit depth of 1, 2, 4 or 8 bits per pixel which can hold all palette indices.
+    pub bit_depth: u8,
+
+    /// One palette index per pixel, all indices refer to colors of the palette.
+    pub indices: Vec<u8>,
+
+    /// Palette colors in the same order as in the file.
+    pub palette: Palette,
+
+    /// Alpha value for each palette entry with trailing opaque entries omitted like in the `tRNS` chunk, `None` if all colors
+    /// are opaque.
+    pub alpha: Option<Vec<u8>>,
+}
+
+impl IndexedImage {
+    /// Indices packed with `bit_depth` bits per pixel, starting from the most significant bits, with each row padded to whole
+    /// bytes. This is the layout of indexed PNG rows (without filter type bytes). Empty if the image has no pixels.
+    pub fn packed_rows(&self) -> Vec<u8> {
+        if self.width == 0 {
+            return Vec::new();
+        }
+
+        let pixels_per_byte = (8 / self.bit_depth) as usize;
+        let mut packed = Vec::with_capacity((self.width as usize).div_ceil(pixels_per_byte) * (self.height as usize));
+        for row in self.indices.chunks(self.width as usize) {
+            for pixels in row.chunks(pixels_per_byte) {
+                let byte = pixels.iter().enumerate().fold(0, |byte, (i, &index)| byte | (index << (8 - self.bit_depth * (i as u8 + 1))));
+                packed.push(byte);
+            }
+        }
+        packed
+    }
+}
+
+impl Image {
+    /// Export paletted image without expanding it to RGB, returns `Error::NotPaletted` for other images.
+    ///
+    /// Palette is extended with black colors if some pixels refer to colors beyond its end, which is how `to_rgb()` treats them.
+    /// `transparency` is converted to the alpha table.
+    pub fn to_indexed(&self, transparency: &Transparency) -> Result<IndexedImage> {
+        let (indices, palette) = match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => (indices, palette),
+            Pixels::Rgb(_) | Pixels::GrayAlpha(_) | Pixels::Rgba(_) => return Err(Error::NotPaletted),
+        };
+
+        let used_colors = indices.iter().max().map_or(0, |&index| index as usize + 1);
+        let mut rgb = palette.clone();
+        if rgb.len() < used_colors * 3 {
+            rgb.resize(used_colors * 3, 0);
+        }
+        let palette = Palette::from_rgb(&rgb)?;
+
+        let mut alpha = match *transparency {
+            Transparency::Opaque => Vec::new(),
+            Transparency::Index(index) if (index as usize) < palette.len() => {
+                let mut alpha = vec![0xFF; index as usize + 1];
+                alpha[index as usize] = 0;
+                alpha
+            }
+            Transparency::Index(_) => Vec::new(),
+            Transparency::Alpha(ref alpha) => alpha.iter().take(palette.len()).cloned().collect(),
+        };
+        while alpha.last() == Some(&0xFF) {
+            alpha.pop();
+        }
+
+        let bit_depth = match palette.len() {
+            0..=2 => 1,
+            3..=4 => 2,
+            5..=16 => 4,
+            _ => 8,
+        };
+
+        Ok(IndexedImage {
+            width: self.width,
+            height: self.height,
+            bit_depth,
+            indices: indices.clone(),
+            palette,
+            alpha: if alpha.is_empty() { None } else { Some(alpha) },
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {decode, Error, Transparency};
+    use pixels::{Image, Pixels};
+
+    #[test]
+    fn to_indexed() {
+        let image = decode(&include_bytes!("../test-data/gmarbles.pcx")[..]).unwrap();
+        let indexed = image.to_indexed(&Transparency::Opaque).unwrap();
+        assert_eq!((indexed.width, indexed.height, indexed.bit_depth), (141, 99, 8));
+        assert_eq!(indexed.packed_rows(), indexed.indices);
+        assert_eq!(indexed.alpha, None);
+        match image.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                assert_eq!(&indexed.indices, indices);
+                assert_eq!(&indexed.palette.to_rgb(), palette);
+            }
+            _ => panic!("gmarbles.pcx is paletted"),
+        }
+
+        let image = Image {
+            width: 3,
+            height: 2,
+            pixels: Pixels::Paletted {
+                indices: vec![0, 1, 2, 2, 1, 0],
+                palette: vec![10, 20, 30, 40, 50, 60],
+            },
+        };
+        let indexed = image.to_indexed(&Transparency::Index(1)).unwrap();
+        assert_eq!(indexed.palette.to_rgb(), [10, 20, 30, 40, 50, 60, 0, 0, 0]);
+        assert_eq!(indexed.bit_depth, 2);
+        assert_eq!(indexed.alpha, Some(vec![0xFF, 0]));
+        assert_eq!(indexed.packed_rows(), [0b0001_1000, 0b1001_0000]);
+
+        let empty = Image { width: 0, height: 2, pixels: Pixels::Paletted { indices: Vec::new(), palette: vec![0; 6] } };
+        assert!(empty.to_indexed(&Transparency::Opaque).unwrap().packed_rows().is_empty());
+
+        assert_eq!(image.to_indexed(&Transparency::Alpha(vec![7, 0xFF, 0xFF, 3])).unwrap().alpha, Some(vec![7]));
+        assert_eq!(image.to_indexed(&Transparency::Index(9)).unwrap().alpha, None);
+
+        let rgb = Image { width: 1, height: 1, pixels: Pixels::Rgb(vec![0; 3]) };
+        assert!(matches!(rgb.to_indexed(&Transparency::Opaque), Err(Error::NotPaletted)));
+    }
+}
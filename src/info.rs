@@ -0,0 +1,146 @@
+//! Basic image properties available without decoding pixel data.
+use std::fs::File;
+use std::io;
+use std::path::Path;
+
+use Result;
+use low_level::{Header, MAGIC_BYTE};
+use low_level::header::{self, ColorFormat, Version, Window};
+
+/// Properties of PCX image stored in its header, returned by `pcx::probe`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Info {
+    /// Width and height of the image.
+    pub size: (u16, u16),
+
+    /// Bits per pixel per color plane.
+    pub bit_depth: u8,
+
+    /// Number of color planes.
+    pub number_of_color_planes: u8,
+
+    /// Position of the top-left corner of the image, see `Window`.
+    pub start: (u16, u16),
+
+    /// Dots per inch.
+    pub dpi: (u16, u16),
+
+    /// Kind of pixel data in the image.
+    pub color_format: ColorFormat,
+
+    /// Number of colors in the palette or `None` if the image is not paletted.
+    pub palette_length: Option<u16>,
+
+    /// Version of the file format.
+    pub version: Version,
+
+    /// Size of the screen in pixels the image was created on, `(0, 0)` if not stored.
+    pub screen_size: (u16, u16),
+}
+
+impl Info {
+    /// Properties described by the header.
+    pub fn from_header(header: &Header) -> Self {
+        Info {
+            size: header.size,
+            bit_depth: header.bit_depth,
+            number_of_color_planes: header.number_of_color_planes,
+            start: header.start,
+            dpi: header.dpi,
+            color_format: header.color_format(),
+            palette_length: header.palette_length(),
+            version: header.version,
+            screen_size: header.screen_size,
+        }
+    }
+
+    /// Get width and height of the image.
+    #[inline]
+    pub fn dimensions(&self) -> (u16, u16) {
+        self.size
+    }
+
+    /// Whether this image is paletted.
+    #[inline]
+    pub fn is_paletted(&self) -> bool {
+        self.color_format == ColorFormat::Paletted
+    }
+
+    /// Placement of the image: position of its top-left corner and its size.
+    pub fn window(&self) -> Window {
+        Window { start: self.start, size: self.size }
+    }
+
+    /// Physical size of the image in millimeters computed from its DPI, `None` if DPI is not set.
+    pub fn physical_size_mm(&self) -> Option<(f64, f64)> {
+        header::physical_size_mm(self.size, self.dpi)
+    }
+}
+
+/// Read only the 128-byte header from the stream and return image properties, pixel data is not touched.
+///
+/// Useful for quickly scanning many files, e.g. to lay out a grid of thumbnails before decoding them.
+pub fn probe<R: io::Read>(mut stream: R) -> Result<Info> {
+    Ok(Info::from_header(&Header::load(&mut stream)?))
+}
+
+/// Read only the header of PCX file and return image properties, see `probe`.
+pub fn probe_file<P: AsRef<Path>>(path: P) -> Result<Info> {
+    probe(File::open(path)?)
+}
+
+/// Registered MIME type of PCX images.
+pub const MIME_TYPE: &str = "image/vnd.zbrush.pcx";
+
+/// Unregistered MIME type of PCX images still expected by many older clients.
+pub const MIME_TYPE_LEGACY: &str = "image/x-pcx";
+
+/// Guess whether `data`, the first bytes of a file, is PCX image and return its MIME type, `None` if it is not.
+///
+/// Only the first 4 bytes are checked: the magic byte, a known version, the encoding and a valid bit depth. This is enough
+/// to set `Content-Type` of served files, use `probe()` to validate the whole header.
+pub fn content_type(data: &[u8]) -> Option<&'static str> {
+    match *data {
+        [MAGIC_BYTE, version, 0..=1, 1 | 2 | 4 | 8, ..] if Version::from_u8(version).is_some() => Some(MIME_TYPE),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{content_type, probe, probe_file, MIME_TYPE};
+    use low_level::header::{ColorFormat, Version};
+    use Error;
+
+    #[test]
+    fn header_only() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let info = probe(&data[..128]).unwrap();
+        assert_eq!(info.dimensions(), (141, 99));
+        assert_eq!((info.bit_depth, info.number_of_color_planes), (8, 1));
+        assert_eq!(info.version, Version::V5);
+        assert!(info.is_paletted());
+        assert_eq!(info.palette_length, Some(256));
+
+        let info = probe_file(concat!(env!("CARGO_MANIFEST_DIR"), "/test-data/marbles.pcx")).unwrap();
+        assert_eq!(info.dimensions(), (143, 101));
+        assert_eq!(info.color_format, ColorFormat::Rgb);
+        assert!(!info.is_paletted());
+        assert_eq!(info.palette_length, None);
+
+        assert!(matches!(probe(&data[1..]), Err(Error::NotPcx)));
+    }
+
+    #[test]
+    fn sniffing() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        assert_eq!(content_type(&data[..]), Some(MIME_TYPE));
+        assert_eq!(content_type(&data[..4]), Some(MIME_TYPE));
+        assert_eq!(content_type(&data[..3]), None);
+        assert_eq!(content_type(&data[1..]), None);
+        assert_eq!(content_type(&[0x0A, 1, 1, 8]), None);
+        assert_eq!(content_type(&[0x0A, 5, 2, 8]), None);
+        assert_eq!(content_type(&[0x0A, 5, 1, 3]), None);
+        assert_eq!(content_type(b"\x89PNG"), None);
+    }
+}
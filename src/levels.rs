@@ -0,0 +1,138 @@
+//! Levels adjustment of decoded images.
+use {Error, Result};
+use pixels::{Image, Pixels};
+
+/// Linear mapping of values stretching the range from `black` to `white` to the whole range from 0 to 255.
+///
+/// Values below `black` become 0 and values above `white` become 255.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Levels {
+    black: u8,
+    white: u8,
+}
+
+impl Levels {
+    /// Create levels mapping `black` to 0 and `white` to 255, `black` must be less than `white`.
+    pub fn new(black: u8, white: u8) -> Result<Self> {
+        if black >= white {
+            return Err(Error::InvalidUsage("black point must be less than white point"));
+        }
+        Ok(Levels { black, white })
+    }
+
+    /// Value mapped to 0.
+    pub fn black(&self) -> u8 {
+        self.black
+    }
+
+    /// Value mapped to 255.
+    pub fn white(&self) -> u8 {
+        self.white
+    }
+
+    /// Whether values are left unchanged.
+    pub fn is_identity(&self) -> bool {
+        self.black == 0 && self.white == 255
+    }
+
+    /// Table mapping each value to the adjusted one.
+    pub fn lookup_table(&self) -> [u8; 256] {
+        let (black, range) = (self.black as u32, (self.white - self.black) as u32);
+        let mut table = [0; 256];
+        for (value, entry) in table.iter_mut().enumerate() {
+            let value = (value as u32).clamp(black, black + range) - black;
+            *entry = ((value * 255 + range / 2) / range) as u8;
+        }
+        table
+    }
+}
+
+impl Default for Levels {
+    /// Levels leaving values unchanged.
+    fn default() -> Self {
+        Levels { black: 0, white: 255 }
+    }
+}
+
+impl Image {
+    /// Adjust each color channel with its own levels, alpha channel is left unchanged.
+    ///
+    /// `levels` must contain one entry per color channel in the same order as `Stats::colors`: R, G and B for paletted and RGB
+    /// images, gray for grayscale images. Palette of paletted images is adjusted instead of the pixels.
+    pub fn adjust_levels(&mut self, levels: &[Levels]) -> Result<()> {
+        let channels = match self.pixels {
+            Pixels::GrayAlpha(_) => 1,
+            Pixels::Paletted { .. } | Pixels::Rgb(_) | Pixels::Rgba(_) => 3,
+        };
+        if levels.len() != channels {
+            return Err(Error::InvalidUsage("number of levels must be equal to the number of color channels"));
+        }
+
+        let tables: Vec<[u8; 256]> = levels.iter().map(Levels::lookup_table).collect();
+        let (values, stride) = match self.pixels {
+            Pixels::Paletted { ref mut palette, .. } => (palette, 3),
+            Pixels::Rgb(ref mut rgb) => (rgb, 3),
+            Pixels::GrayAlpha(ref mut gray_alpha) => (gray_alpha, 2),
+            Pixels::Rgba(ref mut rgba) => (rgba, 4),
+        };
+        for pixel in values.chunks_mut(stride) {
+            for (value, table) in pixel.iter_mut().zip(&tables) {
+                *value = table[*value as usize];
+            }
+        }
+        Ok(())
+    }
+
+    /// Stretch contrast so that the color range found by `Stats::levels(clip)` spans the whole range from 0 to 255.
+    ///
+    /// All color channels get the same levels so hues are preserved. Returns the applied levels or `None` if the image has only
+    /// one color and is left unchanged. `clip` is the fraction of the darkest and the brightest pixels ignored as noise,
+    /// e.g. `0.005`.
+    pub fn auto_contrast(&mut self, clip: f64) -> Option<Levels> {
+        let stats = self.stats();
+        let (black, white) = stats.levels(clip);
+        let levels = Levels::new(black, white).ok()?;
+        if !levels.is_identity() {
+            self.adjust_levels(&vec![levels; stats.colors.len()]).expect("one level per color channel");
+        }
+        Some(levels)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::Levels;
+    use pixels::{Image, Pixels};
+    use Error;
+
+    #[test]
+    fn lookup_table() {
+        let table = Levels::new(50, 100).unwrap().lookup_table();
+        assert_eq!((table[0], table[50], table[75], table[100], table[255]), (0, 0, 128, 255, 255));
+        assert!(Levels::default().lookup_table().iter().enumerate().all(|(value, &entry)| value == entry as usize));
+        assert!(matches!(Levels::new(7, 7), Err(Error::InvalidUsage(_))));
+    }
+
+    #[test]
+    fn auto_contrast() {
+        let mut image = Image {
+            width: 3,
+            height: 1,
+            pixels: Pixels::Rgba(vec![60, 80, 100, 9, 100, 100, 100, 9, 140, 120, 100, 9]),
+        };
+        assert_eq!(image.auto_contrast(0.0), Some(Levels::new(60, 140).unwrap()));
+        assert_eq!(image.pixels, Pixels::Rgba(vec![0, 64, 128, 9, 128, 128, 128, 9, 255, 191, 128, 9]));
+
+        let mut image = Image {
+            width: 2,
+            height: 1,
+            pixels: Pixels::Paletted { indices: vec![0, 1], palette: vec![10, 10, 10, 20, 20, 20] },
+        };
+        image.adjust_levels(&[Levels::new(10, 20).unwrap(), Levels::default(), Levels::default()]).unwrap();
+        assert_eq!(image.pixels, Pixels::Paletted { indices: vec![0, 1], palette: vec![0, 10, 10, 255, 20, 20] });
+        assert!(image.adjust_levels(&[Levels::default()]).is_err());
+
+        let mut flat = Image { width: 1, height: 1, pixels: Pixels::GrayAlpha(vec![7, 0]) };
+        assert_eq!(flat.auto_contrast(0.0), None);
+    }
+}
@@ -4,7 +4,17 @@
 //!
 //! PCX does not contain any color space information. Today one will usually interpret it as containing colors in [sRGB](https://en.wikipedia.org/wiki/sRGB) color space.
 //!
-//! Example for reading PCX image:
+//! Example for reading the whole PCX image into memory:
+//!
+//!     let image = pcx::Reader::from_file("test-data/marbles.pcx").unwrap().read_image().unwrap();
+//!     println!("width = {}, height = {}, paletted = {}", image.width, image.height, image.is_paletted());
+//!
+//! Example for reading only the image properties from the header:
+//!
+//!     let info = pcx::probe_file("test-data/marbles.pcx").unwrap();
+//!     println!("width = {}, height = {}, paletted = {}", info.size.0, info.size.1, info.is_paletted());
+//!
+//! Example for reading PCX image row by row:
 //!
 //!     let mut reader = pcx::Reader::from_file("test-data/marbles.pcx").unwrap();
 //!     println!("width = {}, height = {}, paletted = {}", reader.width(), reader.height(), reader.is_paletted());
@@ -16,6 +26,18 @@
 //!         }
 //!     }
 //!
+//! Rows can also be consumed with an iterator:
+//!
+//!     let mut reader = pcx::Reader::from_file("test-data/marbles.pcx").unwrap();
+//!     for row in reader.rows() {
+//!         match row.unwrap() {
+//!             pcx::Row::Paletted(indices) => println!("{} palette indices", indices.len()),
+//!             pcx::Row::Rgb(rgb) => println!("{} RGB values", rgb.len()),
+//!             pcx::Row::GrayAlpha(gray_alpha) => println!("{} gray and alpha values", gray_alpha.len()),
+//!             pcx::Row::Rgba(rgba) => println!("{} RGBA values", rgba.len()),
+//!         }
+//!     }
+//!
 //! Example for writing PCX image:
 //!
 //!     // Create 5x5 RGB file.
@@ -25,6 +47,39 @@
 //!         writer.write_row(&[0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0, 0, 255, 0]);
 //!     }
 //!     writer.finish().unwrap();
+//!
+//! # Integration with the `image` crate
+//!
+//! When the `image` feature is enabled `PcxDecoder` implements `image::ImageDecoder` and `PcxEncoder` provides the same interface
+//! as encoders of the `image` crate.
+//!
+//! # Parallel decoding
+//!
+//! When the `parallel` feature is enabled `pcx::decode_parallel` and `pcx::decode_parallel_with_options` decode images stored in
+//! memory on multiple threads.
+//!
+//! # Asynchronous reading
+//!
+//! When the `async` feature is enabled `pcx::AsyncReader` reads images from streams implementing `pcx::AsyncRead` without
+//! blocking the executor. No async runtime is required, streams of runtimes are adapted by implementing `AsyncRead` for them.
+//! Downloads arriving in chunks are decoded while they arrive with `AsyncReader::from_chunks()` and `AsyncReader::rows()`.
+//!
+//! # Custom allocation
+//!
+//! `Image` owns its pixels in plain `Vec<u8>` buffers, there is no allocator parameter. Engines which load assets into arena
+//! or frame allocators should allocate the destination there themselves and decode into it with `Reader::read_rows_into()`
+//! (or row by row with `Reader::next_row_into()`), using `Reader::buffer_length()` to size it. The reader then only allocates
+//! small scratch buffers, which `Options::max_memory` bounds and `Options::fallible_allocation` makes fail with
+//! `Error::OutOfMemory` instead of aborting.
+//!
+//! # Unsafe code
+//!
+//! By default the crate is compiled with `#![forbid(unsafe_code)]`, so the decoder can be verified to be free of unsafe code
+//! when processing untrusted files. The C API in the `ffi` module enabled by the `capi` feature is the only place using unsafe
+//! code, the rest of the crate is still checked with `#![deny(unsafe_code)]` then.
+
+#![cfg_attr(not(feature = "capi"), forbid(unsafe_code))]
+#![cfg_attr(feature = "capi", deny(unsafe_code))]
 
 // References:
 // https://github.com/FFmpeg/FFmpeg/blob/415f907ce8dcca87c9e7cfdc954b92df399d3d80/libavcodec/pcx.c
@@ -34,21 +89,184 @@
 extern crate byteorder;
 #[cfg(test)]
 extern crate walkdir;
-#[cfg(test)]
+#[cfg(any(test, feature = "image"))]
 extern crate image;
 
 use std::io;
 
-pub use reader::Reader;
-pub use writer::{WriterRgb, WriterPaletted};
+pub use reader::{PlaneRows, Reader, Remainder, Rows, RowsRev};
+pub use writer::{write_quantized_two_pass, WriterRgb, WriterPaletted, WriterPlanar, WriterQuantized, WriterRaw, IndexPolicy};
+pub use pixels::{ChannelOrder, Image, Layout, Origin, PixelFormat, Pixels, RawFormat, Row};
+pub use palette::{Palette, PaletteMatch, PaletteRegistry, Transparency};
+pub use quantize::{ColorHistogram, ColorSpace, Quantizer};
+pub use self_test::{self_test, SelfTestCase, SelfTestReport};
+pub use error::{Error, Result, Warning};
+pub use options::{Fill, Limits, Options, Profile, WriterOptions};
+pub use low_level::header::{ColorFormat, Window};
+pub use dcx::{write_dcx, write_dcx_with_thumbnail};
+pub use info::{content_type, probe, probe_file, Info, MIME_TYPE, MIME_TYPE_LEGACY};
+pub use stats::{ChannelStats, Stats};
+pub use levels::Levels;
+pub use indexed::IndexedImage;
+pub use hashing::HashingReader;
+pub use monochrome::{encode_monochrome, encode_monochrome_with_options, Binarization};
+pub use patch::{patch_rows, patch_rows_file};
+pub use unify::unify_palettes;
+pub use compare::{compare_images, ColorError, Comparison, Difference};
+pub use report::{decode_with_report, DecodeReport, LimitCheck};
+pub use transform::RowTransform;
+pub use redundancy::Redundancy;
+pub use variant::{Variant, VariantRegistry};
+pub use dither::Dithering;
+pub use producer::{Fingerprint, ProducerDatabase};
+pub use watch::{DecoderState, Watchpoint};
+pub use contact::ContactSheet;
+pub use roundtrip::{roundtrip_check, RoundtripReport};
+#[cfg(feature = "parallel")]
+pub use reader::{decode_parallel, decode_parallel_with_options};
+#[cfg(feature = "image")]
+pub use image_codec::{PcxDecoder, PcxEncoder};
+#[cfg(feature = "async")]
+pub use async_reader::{AsyncRead, AsyncReader, AsyncRows, ChunkReader, ChunkStream, NextAsyncRow, NextRow, Open, ReadPalette};
 
 pub mod low_level;
 mod reader;
 mod writer;
+mod pixels;
+mod palette;
+mod quantize;
+mod error;
+mod options;
+mod self_test;
+mod dcx;
+mod info;
+mod stats;
+mod levels;
+mod indexed;
+mod hashing;
+mod palette_edit;
+mod monochrome;
+mod patch;
+mod unify;
+mod compare;
+mod report;
+mod transform;
+mod bmp;
+mod redundancy;
+mod variant;
+mod dither;
+mod producer;
+mod channels;
+mod watch;
+mod contact;
+mod roundtrip;
+mod ppm;
+#[cfg(feature = "async")]
+mod async_reader;
+#[cfg(feature = "parallel")]
+mod expand;
+#[cfg(feature = "capi")]
+pub mod ffi;
+#[cfg(feature = "golden")]
+pub mod golden;
+#[cfg(feature = "image")]
+mod image_codec;
 
 #[cfg(test)]
 mod test_samples;
 
+/// Decode the whole PCX image from the stream into memory.
+///
+/// This is a shortcut for `Reader::new(stream)?.read_image()`.
+pub fn decode<R: io::Read>(stream: R) -> Result<Image> {
+    Reader::new(stream)?.read_image()
+}
+
+/// Encode the whole image into the stream.
+///
+/// Paletted images are written with 256-color palette and RGB images as 24-bit RGB. There is no writer for images with alpha
+/// channel, so alpha is dropped and they are written as 24-bit RGB.
+pub fn encode<W: io::Write>(stream: W, image: &Image, dpi: (u16, u16)) -> io::Result<()> {
+    encode_with_options(stream, image, dpi, WriterOptions::default())
+}
+
+/// Encode the whole image into the stream with the given options, see `encode()`.
+pub fn encode_with_options<W: io::Write>(stream: W, image: &Image, dpi: (u16, u16), options: WriterOptions) -> io::Result<()> {
+    let size = image.dimensions();
+    let width = size.0 as usize;
+    match image.pixels {
+        Pixels::Paletted { ref indices, ref palette } => {
+            let mut writer = WriterPaletted::new_with_options(stream, size, dpi, options)?;
+            for row in indices.chunks(width) {
+                writer.write_row(row)?;
+            }
+
+            // Decoded palettes may be shorter than some of the indices.
+            let mut full_palette = palette.clone();
+            full_palette.resize(256 * 3, 0);
+            writer.write_palette(&full_palette)
+        }
+        Pixels::Rgb(ref rgb) => {
+            let mut writer = WriterRgb::new_with_options(stream, size, dpi, options)?;
+            for row in rgb.chunks(width * 3) {
+                writer.write_row(row)?;
+            }
+            writer.finish()
+        }
+        Pixels::GrayAlpha(_) | Pixels::Rgba(_) => {
+            let rgb = Image { width: size.0, height: size.1, pixels: Pixels::Rgb(image.to_rgb()) };
+            encode_with_options(stream, &rgb, dpi, options)
+        }
+    }
+}
+
+/// Length in bytes of the file written by `encode_with_options()`, which is known in advance only if pixel data is not
+/// compressed. Returns `None` if `options.compress` is set.
+pub fn encoded_length(image: &Image, options: &WriterOptions) -> Option<u64> {
+    if options.compress {
+        return None;
+    }
+
+    let proper_length = low_level::header::lane_proper_length(image.width, 8);
+    let lane_length = options.lane_length.unwrap_or(proper_length + proper_length % 2) as u64;
+    let (planes, palette_length) = if image.is_paletted() { (1, 1 + 256 * 3) } else { (3, 0) };
+    Some((low_level::HEADER_LENGTH as u64) + lane_length * planes * (image.height as u64) + palette_length)
+}
+
+/// Encode the image into the body of a response, e.g. by a server generating PCX files on the fly for legacy clients.
+///
+/// `start_body` is called with `encoded_length()` first, so that the content length can be sent before the body (or chunked
+/// transfer used if it is `None`), and returns the writer of the body. Disable `WriterOptions::compress` to know the length.
+pub fn encode_response<W, F>(image: &Image, dpi: (u16, u16), options: WriterOptions, start_body: F) -> io::Result<()>
+where
+    W: io::Write,
+    F: FnOnce(Option<u64>) -> io::Result<W>,
+{
+    let body = start_body(encoded_length(image, &options))?;
+    encode_with_options(body, image, dpi, options)
+}
+
+/// Encode 24-bit RGB pixels (R, G, B, R, G, B, ...) as 16-color planar image with the standard EGA palette, as displayed by
+/// EGA hardware.
+///
+/// Colors are reduced to the palette with Floyd-Steinberg dithering, see `Palette::dither()`. Length of `rgb` must be equal to
+/// the number of pixels multiplied by 3.
+pub fn encode_ega<W: io::Write>(stream: W, rgb: &[u8], image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<()> {
+    let width = image_size.0 as usize;
+    if rgb.len() != width * (image_size.1 as usize) * 3 {
+        return user_error("pcx::encode_ega: buffer length must be equal to the number of pixels multiplied by 3");
+    }
+
+    // Buffer length was checked, so dithering can not fail.
+    let palette = Palette::standard(16).unwrap();
+    let indices = palette.dither(rgb, width).unwrap();
+    let mut writer = WriterPlanar::new(stream, image_size, dpi, 4, &palette.to_rgb())?;
+    for row in indices.chunks(width) {
+        writer.write_row(row)?;
+    }
+    writer.finish()
+}
+
 // Error caused by incorrect use of the API.
 fn user_error<T>(error: &str) -> io::Result<T> {
     Err(io::Error::new(io::ErrorKind::InvalidInput, error))
@@ -56,8 +274,7 @@ fn user_error<T>(error: &str) -> io::Result<T> {
 
 #[cfg(test)]
 mod tests {
-    use std::iter;
-    use {Reader, WriterRgb, WriterPaletted};
+    use {probe, Reader, Window, WriterRgb, WriterPaletted, WriterPlanar, WriterQuantized, WriterRaw, WriterOptions, IndexPolicy, ChannelOrder, Palette, Pixels, Quantizer};
 
     fn round_trip_rgb_separate(width: u16, height: u16) {
         let mut pcx = Vec::new();
@@ -65,9 +282,9 @@ mod tests {
         {
             let mut writer = WriterRgb::new(&mut pcx, (width, height), (300, 300)).unwrap();
 
-            let r: Vec<u8> = iter::repeat(88).take(width as usize).collect();
+            let r: Vec<u8> = vec![88; width as usize];
             let g: Vec<u8> = (0..width).map(|v| (v & 0xFF) as u8).collect();
-            let mut b: Vec<u8> = iter::repeat(88).take(width as usize).collect();
+            let mut b: Vec<u8> = vec![88; width as usize];
             for y in 0..height {
                 for x in 0..width {
                     b[x as usize] = (y & 0xFF) as u8;
@@ -80,12 +297,12 @@ mod tests {
 
         let mut reader = Reader::new(&pcx[..]).unwrap();
         assert_eq!(reader.dimensions(), (width, height));
-        assert_eq!(reader.is_paletted(), false);
+        assert!(!reader.is_paletted());
         assert_eq!(reader.palette_length(), None);
 
-        let mut r: Vec<u8> = iter::repeat(0).take(width as usize).collect();
-        let mut g: Vec<u8> = iter::repeat(0).take(width as usize).collect();
-        let mut b: Vec<u8> = iter::repeat(0).take(width as usize).collect();
+        let mut r: Vec<u8> = vec![0; width as usize];
+        let mut g: Vec<u8> = vec![0; width as usize];
+        let mut b: Vec<u8> = vec![0; width as usize];
 
         for y in 0..height {
             reader.next_row_rgb_separate(&mut r, &mut g, &mut b).unwrap();
@@ -113,10 +330,10 @@ mod tests {
 
         let mut reader = Reader::new(&pcx[..]).unwrap();
         assert_eq!(reader.dimensions(), (width, height));
-        assert_eq!(reader.is_paletted(), false);
+        assert!(!reader.is_paletted());
         assert_eq!(reader.palette_length(), None);
 
-        let mut read_rgb: Vec<u8> = iter::repeat(0).take((width as usize) * 3).collect();
+        let mut read_rgb: Vec<u8> = vec![0; (width as usize) * 3];
 
         for _ in 0..height {
             reader.next_row_rgb(&mut read_rgb).unwrap();
@@ -131,7 +348,7 @@ mod tests {
         {
             let mut writer = WriterPaletted::new(&mut pcx, (width, height), (300, 300)).unwrap();
 
-            let mut p: Vec<u8> = iter::repeat(88).take(width as usize).collect();
+            let mut p: Vec<u8> = vec![88; width as usize];
             for y in 0..height {
                 for x in 0..width {
                     p[x as usize] = (y & 0xFF) as u8;
@@ -148,7 +365,7 @@ mod tests {
         assert!(reader.is_paletted());
         assert_eq!(reader.palette_length(), Some(256));
 
-        let mut p: Vec<u8> = iter::repeat(0).take(width as usize).collect();
+        let mut p: Vec<u8> = vec![0; width as usize];
 
         for y in 0..height {
             reader.next_row_paletted(&mut p).unwrap();
@@ -163,6 +380,50 @@ mod tests {
         assert_eq!(&palette[..], &palette_read[..]);
     }
 
+    fn round_trip_planar(width: u16, height: u16, number_of_color_planes: u8) {
+        let mut pcx = Vec::new();
+
+        let palette_length = 1 << number_of_color_planes;
+        // Monochrome images are always read as black and white.
+        let palette: Vec<u8> = match number_of_color_planes {
+            1 => vec![0, 0, 0, 255, 255, 255],
+            _ => (0..palette_length * 3).map(|v| (v * 17) as u8).collect(),
+        };
+        let pixel = |x: u16, y: u16| ((x as u32 / 3 + y as u32 * 5) % palette_length as u32) as u8;
+        {
+            let mut writer = WriterPlanar::new(&mut pcx, (width, height), (300, 300), number_of_color_planes, &palette).unwrap();
+
+            let mut p: Vec<u8> = vec![0; width as usize];
+            for y in 0..height {
+                for x in 0..width {
+                    p[x as usize] = pixel(x, y);
+                }
+
+                writer.write_row(&p).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let mut reader = Reader::new(&pcx[..]).unwrap();
+        assert_eq!(reader.dimensions(), (width, height));
+        assert!(reader.is_paletted());
+        assert_eq!(reader.palette_length(), Some(palette_length));
+
+        let mut p: Vec<u8> = vec![0; width as usize];
+
+        for y in 0..height {
+            reader.next_row_paletted(&mut p).unwrap();
+
+            for x in 0..width {
+                assert_eq!(p[x as usize], pixel(x, y));
+            }
+        }
+
+        let mut palette_read = [0; 3 * 16];
+        assert_eq!(reader.read_palette(&mut palette_read).unwrap(), palette_length as usize);
+        assert_eq!(&palette[..], &palette_read[..palette.len()]);
+    }
+
     #[test]
     fn small_round_trip() {
         for width in 1..40 {
@@ -170,6 +431,10 @@ mod tests {
                 round_trip_rgb_separate(width, height);
                 round_trip_rgb_interleaved(width, height);
                 round_trip_paletted(width, height);
+                round_trip_planar(width, height, 1);
+                round_trip_planar(width, height, 2);
+                round_trip_planar(width, height, 3);
+                round_trip_planar(width, height, 4);
             }
         }
     }
@@ -187,4 +452,467 @@ mod tests {
         round_trip_paletted(0xFFFF - 1, 1);
         round_trip_paletted(1, 0xFFFF);
     }
+
+    #[test]
+    fn large_round_trip_planar() {
+        round_trip_planar(0xFFFF - 1, 1, 2);
+        round_trip_planar(1, 0xFFFF, 2);
+    }
+
+    #[test]
+    fn monochrome_lane_padding() {
+        for width in 1..40u16 {
+            let row: Vec<u8> = (0..width).map(|x| ((x * 7) % 3 == 0) as u8).collect();
+            let mut pcx = Vec::new();
+            {
+                let mut writer = WriterPlanar::new(&mut pcx, (width, 2), (300, 300), 1, &[]).unwrap();
+                writer.write_row(&row).unwrap();
+                writer.write_row(&row).unwrap();
+                writer.finish().unwrap();
+            }
+
+            let reader = Reader::new(&pcx[..]).unwrap();
+            assert_eq!((reader.header.bit_depth, reader.header.number_of_color_planes), (1, 1));
+            assert_eq!(reader.header.lane_length, width.div_ceil(16) * 2);
+            let padding = reader.header.lane_padding();
+            assert_eq!(reader.read_image().unwrap().pixels, Pixels::Paletted { indices: [&row[..], &row[..]].concat(), palette: vec![0, 0, 0, 255, 255, 255] });
+
+            // Some writers omit the padding of the very last lane.
+            if padding != 0 {
+                let mut uncompressed = Vec::new();
+                {
+                    let options = WriterOptions { compress: false, ..WriterOptions::default() };
+                    let mut writer = WriterPlanar::new_with_options(&mut uncompressed, (width, 2), (300, 300), 1, &[], options).unwrap();
+                    writer.write_row(&row).unwrap();
+                    writer.write_row(&row).unwrap();
+                    writer.finish().unwrap();
+                }
+                uncompressed.pop();
+                let mut reader = Reader::new(&uncompressed[..]).unwrap();
+                let mut indices = vec![0; width as usize];
+                reader.next_row_paletted(&mut indices).unwrap();
+                reader.next_row_paletted(&mut indices).unwrap();
+                assert_eq!(indices, row);
+            }
+        }
+    }
+
+    #[test]
+    fn window_and_dpi() {
+        let options = WriterOptions { start: (100, 200), ..WriterOptions::default() };
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterRgb::new_with_options(&mut pcx, (254, 127), (254, 127), options).unwrap();
+            for _ in 0..127 {
+                writer.write_row(&[0; 254 * 3]).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let reader = Reader::new(&pcx[..]).unwrap();
+        assert_eq!(reader.dpi(), (254, 127));
+        assert_eq!(reader.window(), Window { start: (100, 200), size: (254, 127) });
+        assert_eq!(reader.window().end(), (353, 326));
+        assert_eq!(reader.physical_size_mm(), Some((25.4, 25.4)));
+        assert_eq!(probe(&pcx[..]).unwrap().window(), reader.window());
+
+        let options = WriterOptions { start: (0xFFFF, 0), ..WriterOptions::default() };
+        assert!(WriterRgb::new_with_options(Vec::new(), (2, 1), (300, 300), options).is_err());
+    }
+
+    #[test]
+    fn raw_lanes() {
+        use std::fs;
+
+        for path in &["test-data/gmarbles.pcx", "test-data/marbles.pcx", "test-data/cga/CGA_BW.PCX", "test-data/cga/CGA_RGBI.PCX"] {
+            let original = fs::read(path).unwrap();
+            let mut reader = Reader::new(&original[..]).unwrap();
+            let mut header = reader.header;
+            header.dpi = (600, 600);
+
+            let mut pcx = Vec::new();
+            let mut writer = WriterRaw::new(&mut pcx, &header).unwrap();
+            let mut lane = Vec::new();
+            while reader.has_more_rows() {
+                reader.next_lane_raw(&mut lane).unwrap();
+                writer.write_lane_raw(&lane).unwrap();
+            }
+            assert!(writer.write_lane_raw(&lane).is_err());
+            assert!(reader.next_lane_raw(&mut lane).is_err());
+
+            match reader.finish().unwrap() {
+                (Some(ref palette), _) if header.palette_length() == Some(256) => writer.write_palette(&palette.to_rgb()).unwrap(),
+                _ => writer.finish().unwrap(),
+            }
+            assert_eq!(&pcx[128..], &original[128..], "{}", path);
+            assert_eq!(Reader::new(&pcx[..]).unwrap().dpi(), (600, 600));
+        }
+
+        let header = Reader::new(&fs::read("test-data/gmarbles.pcx").unwrap()[..]).unwrap().header;
+        let mut writer = WriterRaw::new(Vec::new(), &header).unwrap();
+        assert!(writer.write_lane_raw(&[0xC5]).is_err());
+        assert!(writer.write_lane_raw(&[0xC5, 0]).is_err());
+        assert!(writer.finish().is_err());
+    }
+
+    #[test]
+    fn ega() {
+        let ega = Palette::standard(16).unwrap();
+        let mut rgb = ega.to_rgb();
+        rgb.extend_from_slice(&[0x2A; 16 * 3]);
+
+        let mut pcx = Vec::new();
+        ::encode_ega(&mut pcx, &rgb, (16, 2), (300, 300)).unwrap();
+        let reader = Reader::new(&pcx[..]).unwrap();
+        assert_eq!((reader.header.bit_depth, reader.header.number_of_color_planes), (1, 4));
+        match reader.read_image().unwrap().pixels {
+            Pixels::Paletted { indices, palette } => {
+                assert_eq!(palette, ega.to_rgb());
+                assert_eq!(&indices[..16], &(0..16).collect::<Vec<u8>>()[..]);
+                assert!(indices[16..].iter().all(|&index| index == 0 || index == 8));
+            }
+            _ => panic!("EGA image must be paletted"),
+        }
+
+        assert!(::encode_ega(Vec::new(), &rgb, (16, 3), (300, 300)).is_err());
+    }
+
+    #[test]
+    fn non_strict_writer() {
+        use low_level::header::Version;
+        use {Options, Warning};
+
+        let options = WriterOptions { strict: false, version: Some(Version::V0), lane_length: Some(7), ..WriterOptions::default() };
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new_with_options(&mut pcx, (5, 2), (300, 300), options).unwrap();
+            writer.write_row(&[1, 2, 3, 4, 5]).unwrap();
+            writer.write_row(&[5, 4, 3, 2, 1]).unwrap();
+            writer.write_palette(&[0; 256 * 3]).unwrap();
+        }
+
+        assert!(Reader::new_with_options(&pcx[..], Options { strict_version: true, ..Options::default() }).is_err());
+        let reader = Reader::new(&pcx[..]).unwrap();
+        assert_eq!((reader.header.version, reader.header.lane_length), (Version::V0, 7));
+        assert!(matches!(reader.warnings(), [Warning::UnsupportedByVersion { version: Version::V0, .. }]));
+        assert_eq!(reader.screen_size(), (0, 0));
+        assert_eq!(reader.read_image().unwrap().into_raw_parts().0, [1, 2, 3, 4, 5, 5, 4, 3, 2, 1]);
+
+        // Quirks are rejected unless explicitly allowed.
+        for &options in &[WriterOptions { strict: true, ..options }, WriterOptions { lane_length: None, strict: true, ..options }] {
+            assert!(WriterPaletted::new_with_options(Vec::new(), (5, 2), (300, 300), options).is_err());
+        }
+        let options = WriterOptions { lane_length: Some(4), ..options };
+        assert!(WriterPaletted::new_with_options(Vec::new(), (5, 2), (300, 300), options).is_err());
+    }
+
+    #[test]
+    fn screen_size() {
+        let mut pcx = Vec::new();
+        ::encode(&mut pcx, &::Image { width: 3, height: 2, pixels: Pixels::Rgb(vec![0; 18]) }, (300, 300)).unwrap();
+        assert_eq!(Reader::new(&pcx[..]).unwrap().screen_size(), (3, 2));
+
+        let options = WriterOptions { screen_size: Some((640, 480)), ..WriterOptions::default() };
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPlanar::new_with_options(&mut pcx, (3, 2), (300, 300), 4, &[], options).unwrap();
+            writer.write_row(&[1, 2, 3]).unwrap();
+            writer.write_row(&[4, 5, 6]).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(&pcx[70..74], &[0x80, 0x02, 0xE0, 0x01]);
+        assert_eq!(probe(&pcx[..]).unwrap().screen_size, (640, 480));
+    }
+
+    #[test]
+    fn uncompressed() {
+        let uncompressed = WriterOptions { compress: false, ..WriterOptions::default() };
+        let rgb: Vec<u8> = (0..(5 * 3 * 2)).map(|v| if v < 15 { 0xC9 } else { v as u8 }).collect();
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterRgb::new_with_options(&mut pcx, (5, 2), (300, 300), uncompressed).unwrap();
+            for row in rgb.chunks(5 * 3) {
+                writer.write_row(row).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert_eq!(pcx[2], 0);
+        assert_eq!(pcx.len(), 128 + 6 * 3 * 2);
+        let mut reader = Reader::new(&pcx[..]).unwrap();
+        assert!(!reader.header.is_compressed);
+        let mut row = [0; 5 * 3];
+        for expected in rgb.chunks(5 * 3) {
+            reader.next_row_rgb(&mut row).unwrap();
+            assert_eq!(&row[..], expected);
+        }
+
+        let indices = [0xC0, 0xC0, 0xC0, 1, 2, 0xFF];
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new_with_options(&mut pcx, (3, 2), (300, 300), uncompressed).unwrap();
+            for row in indices.chunks(3) {
+                writer.write_row(row).unwrap();
+            }
+            writer.write_palette(&[7; 256 * 3]).unwrap();
+        }
+        assert_eq!(pcx.len(), 128 + 4 * 2 + 1 + 256 * 3);
+        assert_eq!(::decode(&pcx[..]).unwrap().pixels, ::Pixels::Paletted { indices: indices.to_vec(), palette: vec![7; 256 * 3] });
+
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPlanar::new_with_options(&mut pcx, (3, 1), (300, 300), 4, &[0; 16 * 3], uncompressed).unwrap();
+            writer.write_row(&[15, 0, 9]).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(&pcx[128..], &[0xA0, 0, 0x80, 0, 0x80, 0, 0xA0, 0]);
+        assert_eq!(::decode(&pcx[..]).unwrap().pixels, ::Pixels::Paletted { indices: vec![15, 0, 9], palette: vec![0; 16 * 3] });
+    }
+
+    #[test]
+    fn packed_rgb() {
+        let rgb: Vec<u8> = (0..(7 * 3 * 3)).map(|v| (v * 13) as u8).collect();
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterRgb::new(&mut pcx, (7, 3), (300, 300)).unwrap();
+            for row in rgb.chunks(7 * 3) {
+                let packed: Vec<u32> = row.chunks(3).map(|pixel| ChannelOrder::Bgra.pack([pixel[0], pixel[1], pixel[2], 0x80])).collect();
+                writer.write_row_packed(&packed, ChannelOrder::Bgra).unwrap();
+            }
+            assert!(writer.write_row_packed(&[0; 7], ChannelOrder::Argb).is_err());
+            writer.finish().unwrap();
+        }
+        assert_eq!(::decode(&pcx[..]).unwrap().pixels, ::Pixels::Rgb(rgb.clone()));
+
+        let mut writer = WriterRgb::new(Vec::new(), (7, 3), (300, 300)).unwrap();
+        assert!(writer.write_row_packed(&[0; 6], ChannelOrder::Argb).is_err());
+
+        // Quantized output gets the same colors as from interleaved rows.
+        let mut pcx = Vec::new();
+        let mut writer = WriterQuantized::new(&mut pcx, (7, 3), (300, 300)).unwrap();
+        for row in rgb.chunks(7 * 3) {
+            let packed: Vec<u32> = row.chunks(3).map(|pixel| ChannelOrder::Argb.pack([pixel[0], pixel[1], pixel[2], 0xFF])).collect();
+            writer.write_row_packed(&packed, ChannelOrder::Argb).unwrap();
+        }
+        assert!(writer.write_row_packed(&[0; 7], ChannelOrder::Argb).is_err());
+        writer.finish().unwrap();
+        assert_eq!(::decode(&pcx[..]).unwrap().to_rgb(), rgb);
+
+        let mut writer = WriterQuantized::new(Vec::new(), (7, 3), (300, 300)).unwrap();
+        assert!(writer.write_row_packed(&[0; 8], ChannelOrder::Argb).is_err());
+    }
+
+    #[test]
+    fn quantized() {
+        let rgb: Vec<u8> = (0..(9 * 4)).flat_map(|i| vec![(i % 3) as u8 * 100, 7, (i / 9) as u8]).collect();
+        let mut pcx = Vec::new();
+        let mut writer = WriterQuantized::new(&mut pcx, (9, 4), (300, 300)).unwrap();
+        for row in rgb.chunks(9 * 3).take(3) {
+            writer.write_row(row).unwrap();
+        }
+        assert!(writer.write_row(&rgb[..9]).is_err());
+        writer.write_row(&rgb[(9 * 3 * 3)..]).unwrap();
+        assert!(writer.write_row(&rgb[..(9 * 3)]).is_err());
+        let palette = writer.finish().unwrap();
+        assert_eq!(palette.len(), 12);
+
+        // Few colors are preserved exactly.
+        let image = ::decode(&pcx[..]).unwrap();
+        assert!(image.is_paletted());
+        assert_eq!(image.to_rgb(), rgb);
+
+        let mut pcx = Vec::new();
+        let mut writer = WriterQuantized::new(&mut pcx, (9, 4), (300, 300)).unwrap();
+        writer.set_quantizer(Quantizer::new(3).unwrap());
+        for row in rgb.chunks(9 * 3) {
+            writer.write_row(row).unwrap();
+        }
+        assert_eq!(writer.finish().unwrap().len(), 3);
+        match ::decode(&pcx[..]).unwrap().pixels {
+            ::Pixels::Paletted { indices, palette } => {
+                assert!(indices.iter().all(|&index| index < 3));
+                assert_eq!(palette[(3 * 3)..], [0; 253 * 3][..]);
+            }
+            _ => panic!("image must be paletted"),
+        }
+
+        assert!(WriterQuantized::new(Vec::new(), (9, 4), (300, 300)).unwrap().finish().is_err());
+        assert!(WriterQuantized::new(Vec::new(), (0, 4), (300, 300)).is_err());
+    }
+
+    #[test]
+    fn quantized_verified() {
+        let rgb: Vec<u8> = (0..(9 * 4)).flat_map(|i| vec![(i % 3) as u8 * 100, 7, (i / 9) as u8]).collect();
+        let encode = |colors| {
+            let mut pcx = Vec::new();
+            let mut writer = WriterQuantized::new(&mut pcx, (9, 4), (300, 300)).unwrap();
+            writer.set_quantizer(Quantizer::new(colors).unwrap());
+            for row in rgb.chunks(9 * 3) {
+                writer.write_row(row).unwrap();
+            }
+            let (_, error) = writer.finish_verified().unwrap();
+            (pcx, error)
+        };
+
+        let (pcx, error) = encode(256);
+        assert_eq!(error, ::ColorError { max: 0, mean: 0.0 });
+        assert_eq!(::decode(&pcx[..]).unwrap().to_rgb(), rgb);
+
+        let (pcx, error) = encode(2);
+        assert!(error.max >= 50 && error.mean > 0.0);
+        assert_eq!(error, ::ColorError::between(&rgb, &::decode(&pcx[..]).unwrap().to_rgb()));
+
+        assert!(WriterQuantized::new(Vec::new(), (9, 4), (300, 300)).unwrap().finish_verified().is_err());
+    }
+
+    #[test]
+    fn encoded_length() {
+        let options = ::WriterOptions { compress: false, ..::WriterOptions::default() };
+        for data in &[&include_bytes!("../test-data/marbles.pcx")[..], &include_bytes!("../test-data/gmarbles.pcx")[..]] {
+            let image = ::decode(*data).unwrap();
+            let mut length = None;
+            let mut body = Vec::new();
+            ::encode_response(&image, (300, 300), options, |content_length| {
+                length = content_length;
+                Ok(&mut body)
+            })
+            .unwrap();
+            assert_eq!(length, Some(body.len() as u64));
+            assert_eq!(::decode(&body[..]).unwrap(), image);
+
+            let odd = ::WriterOptions { lane_length: Some(1000), ..options };
+            let mut body = Vec::new();
+            ::encode_with_options(&mut body, &image, (300, 300), odd).unwrap();
+            assert_eq!(::encoded_length(&image, &odd), Some(body.len() as u64));
+            assert_eq!(::encoded_length(&image, &::WriterOptions::default()), None);
+        }
+    }
+
+    #[test]
+    fn writer_memory() {
+        let mut writer = WriterRgb::new(Vec::new(), (100, 10), (300, 300)).unwrap();
+        assert_eq!(writer.estimated_peak_memory(), 0);
+        writer.add_row_transform(::RowTransform::SwapChannels(0, 2));
+        assert_eq!(writer.estimated_peak_memory(), 600);
+
+        let writer = WriterPlanar::new(Vec::new(), (100, 10), (300, 300), 4, &[]).unwrap();
+        assert_eq!(writer.estimated_peak_memory(), 13);
+
+        // Rows, histogram, indices and palette.
+        let writer = WriterQuantized::new(Vec::new(), (100, 10), (300, 300)).unwrap();
+        assert_eq!(writer.estimated_peak_memory(), 3000 + 1000 * 32 + 1000 + 768);
+    }
+
+    #[test]
+    fn quantized_two_pass() {
+        let rgb: Vec<u8> = (0..(9 * 4)).flat_map(|i| vec![(i * 7) as u8, 7, (i / 9) as u8 * 50]).collect();
+        let quantizer = Quantizer::new(5).unwrap();
+
+        let mut expected = Vec::new();
+        let mut writer = WriterQuantized::new(&mut expected, (9, 4), (300, 300)).unwrap();
+        writer.set_quantizer(quantizer);
+        for row in rgb.chunks(9 * 3) {
+            writer.write_row(row).unwrap();
+        }
+        let expected_palette = writer.finish().unwrap();
+
+        let mut requested = Vec::new();
+        let mut pcx = Vec::new();
+        let palette = ::write_quantized_two_pass(&mut pcx, (9, 4), (300, 300), ::WriterOptions::default(), &quantizer, |y, row| {
+            requested.push(y);
+            row.copy_from_slice(&rgb[(y as usize * 9 * 3)..((y as usize + 1) * 9 * 3)]);
+            Ok(())
+        }).unwrap();
+        assert_eq!(requested, [0, 1, 2, 3, 0, 1, 2, 3]);
+        assert_eq!((palette, pcx), (expected_palette, expected));
+
+        let failing = ::write_quantized_two_pass(Vec::new(), (9, 4), (300, 300), ::WriterOptions::default(), &quantizer, |_, _| {
+            Err(::std::io::Error::other("source failed"))
+        });
+        assert!(failing.is_err());
+    }
+
+    #[test]
+    fn profiles() {
+        use Profile;
+
+        let rgb = ::decode(&include_bytes!("../test-data/marbles.pcx")[..]).unwrap().to_rgb();
+        let encode = |profile| {
+            let options = WriterOptions { profile, ..WriterOptions::default() };
+            let mut pcx = Vec::new();
+            let mut writer = WriterQuantized::new_with_options(&mut pcx, (143, 101), (300, 300), options).unwrap();
+            writer.set_quantizer(Quantizer::new(16).unwrap());
+            for row in rgb.chunks(143 * 3) {
+                writer.write_row(row).unwrap();
+            }
+            writer.finish().unwrap();
+            pcx
+        };
+        let error = |pcx: &[u8]| -> u64 {
+            let decoded = ::decode(pcx).unwrap().to_rgb();
+            decoded.iter().zip(&rgb).map(|(&a, &b)| (a as i64 - b as i64).pow(2) as u64).sum()
+        };
+
+        let (fast, small) = (encode(::Profile::Fast), encode(Profile::Small));
+        assert!(error(&small) < error(&fast));
+        assert!(small.len() < fast.len());
+
+        // Dithering continues across rows written by the two-pass writer.
+        let options = WriterOptions { profile: Profile::Small, ..WriterOptions::default() };
+        let mut two_pass = Vec::new();
+        let read_row = |y: u16, row: &mut [u8]| {
+            row.copy_from_slice(&rgb[(y as usize * 143 * 3)..((y as usize + 1) * 143 * 3)]);
+            Ok(())
+        };
+        ::write_quantized_two_pass(&mut two_pass, (143, 101), (300, 300), options, &Quantizer::new(16).unwrap(), read_row).unwrap();
+        assert_eq!(two_pass, small);
+
+        // Padding extends runs, lanes of odd width end with a run of the same color.
+        let options = WriterOptions { profile: Profile::Small, ..WriterOptions::default() };
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new_with_options(&mut pcx, (3, 1), (300, 300), options).unwrap();
+            writer.write_row(&[1, 2, 2]).unwrap();
+            writer.write_palette(&[0; 256 * 3]).unwrap();
+        }
+        assert_eq!(&pcx[128..131], &[1, 0xC3, 2]);
+        assert_eq!(::decode(&pcx[..]).unwrap().into_raw_parts().0, [1, 2, 2]);
+    }
+
+    #[test]
+    fn paletted_index_validation() {
+        let row = [0, 1, 2, 3, 4, 5];
+        let palette = [0; 4 * 3];
+
+        // Without declared palette length indices are not validated and short palettes are padded.
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new(&mut pcx, (6, 1), (300, 300)).unwrap();
+            writer.write_row(&row).unwrap();
+            writer.write_palette(&palette).unwrap();
+        }
+        assert_eq!(::decode(&pcx[..]).unwrap().into_raw_parts().0, row);
+
+        // Out of range indices are rejected immediately.
+        let mut pcx = Vec::new();
+        let mut writer = WriterPaletted::new(&mut pcx, (6, 1), (300, 300)).unwrap();
+        writer.set_palette_length(4, IndexPolicy::Error).unwrap();
+        assert!(writer.write_row(&row).is_err());
+        writer.write_row(&[3, 2, 1, 0, 1, 2]).unwrap();
+        writer.write_palette(&palette).unwrap();
+
+        // Out of range indices are clamped.
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new(&mut pcx, (6, 1), (300, 300)).unwrap();
+            writer.set_palette_length(4, IndexPolicy::Clamp).unwrap();
+            writer.write_row(&row).unwrap();
+            assert!(writer.set_palette_length(8, IndexPolicy::Clamp).is_err());
+            writer.write_palette(&palette).unwrap();
+        }
+
+        let mut reader = Reader::new(&pcx[..]).unwrap();
+        let mut row_read = [0; 6];
+        reader.next_row_paletted(&mut row_read).unwrap();
+        assert_eq!(row_read, [0, 1, 2, 3, 3, 3]);
+    }
 }
@@ -1,8 +1,9 @@
 //! PCX file header.
-use std::io;
+use std::io::{self, Read};
 use byteorder::{LittleEndian, ReadBytesExt, WriteBytesExt};
 
-use low_level::MAGIC_BYTE;
+use low_level::{HEADER_LENGTH, MAGIC_BYTE};
+use {Error, Options, Result, WriterOptions};
 
 /*
 typedef struct _PcxHeader
@@ -44,6 +45,140 @@ pub enum Version {
     V5 = 5,
 }
 
+impl Version {
+    /// Version with the given number as stored in the header or `None` if the number is unknown.
+    pub const fn from_u8(version: u8) -> Option<Version> {
+        match version {
+            0 => Some(Version::V0),
+            2 => Some(Version::V2),
+            3 => Some(Version::V3),
+            4 => Some(Version::V4),
+            5 => Some(Version::V5),
+            _ => None,
+        }
+    }
+
+    /// Whether files of this version may contain 256-color images with the palette at the end of file.
+    pub const fn supports_256_colors(self) -> bool {
+        matches!(self, Version::V5)
+    }
+
+    /// Whether files of this version may contain images with 8-bit color planes: 24-bit RGB, 32-bit RGBA and grayscale with alpha.
+    pub const fn supports_true_color(self) -> bool {
+        matches!(self, Version::V5)
+    }
+
+    /// Whether the palette stored in the header of files of this version is valid. Version 2.5 files use the fixed EGA palette and
+    /// version 2.8 files without palette information leave it uninitialized.
+    pub const fn has_header_palette(self) -> bool {
+        !matches!(self, Version::V0 | Version::V3)
+    }
+
+    /// Whether files of this version may contain images with the given bit depth and number of color planes.
+    pub const fn supports_format(self, bit_depth: u8, number_of_color_planes: u8) -> bool {
+        match (bit_depth, number_of_color_planes) {
+            (8, 1) => self.supports_256_colors(),
+            (8, _) => self.supports_true_color(),
+            _ => true,
+        }
+    }
+}
+
+/// Kind of pixel data in the image, determined by the bit depth and the number of color planes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorFormat {
+    /// Palette indices, either packed into one plane with 1, 2, 4 or 8 bits per pixel or split into 2, 3 or 4 one-bit planes.
+    Paletted,
+    /// 24-bit RGB stored in three 8-bit planes.
+    Rgb,
+    /// 8-bit grayscale with alpha stored in two 8-bit planes.
+    GrayAlpha,
+    /// 32-bit RGBA stored in four 8-bit planes. Some writers store intensity instead of alpha in the fourth plane.
+    Rgba,
+}
+
+impl ColorFormat {
+    /// Number of values per pixel in decoded images: 1 palette index, 3 for RGB, 2 for grayscale with alpha and 4 for RGBA.
+    #[inline]
+    pub const fn channels(self) -> usize {
+        match self {
+            ColorFormat::Paletted => 1,
+            ColorFormat::Rgb => 3,
+            ColorFormat::GrayAlpha => 2,
+            ColorFormat::Rgba => 4,
+        }
+    }
+}
+
+/// Placement of the image, stored in the header as the coordinates of its top-left and bottom-right corners.
+///
+/// Most files are placed at `(0, 0)`, non-zero start is used e.g. by scanning software to record the position of the scanned
+/// area on the page.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Window {
+    /// Position of the top-left corner in pixels.
+    pub start: (u16, u16),
+
+    /// Width and height of the image.
+    pub size: (u16, u16),
+}
+
+impl Window {
+    /// Position of the bottom-right corner (inclusive) as stored in the header.
+    pub const fn end(&self) -> (u16, u16) {
+        (self.start.0.saturating_add(self.size.0.saturating_sub(1)), self.start.1.saturating_add(self.size.1.saturating_sub(1)))
+    }
+}
+
+/// Header bytes exactly as stored in the file, including the fields which `Header` does not keep, e.g. for forensic tools
+/// identifying which program produced a file by the values of its reserved bytes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RawHeader {
+    bytes: [u8; HEADER_LENGTH],
+}
+
+impl RawHeader {
+    /// Wrap raw header bytes, they are not validated.
+    pub fn new(bytes: [u8; HEADER_LENGTH]) -> Self {
+        RawHeader { bytes }
+    }
+
+    /// All header bytes.
+    pub fn bytes(&self) -> &[u8; HEADER_LENGTH] {
+        &self.bytes
+    }
+
+    /// Manufacturer byte at offset 0, always `MAGIC_BYTE` in files accepted by `Header::load()`.
+    pub fn manufacturer(&self) -> u8 {
+        self.bytes[0]
+    }
+
+    /// Version number at offset 1, including numbers unknown to `Version`.
+    pub fn version(&self) -> u8 {
+        self.bytes[1]
+    }
+
+    /// Encoding at offset 2, 1 for RLE and 0 for uncompressed data.
+    pub fn encoding(&self) -> u8 {
+        self.bytes[2]
+    }
+
+    /// Reserved byte at offset 64 between the header palette and the number of color planes, usually 0.
+    pub fn reserved(&self) -> u8 {
+        self.bytes[64]
+    }
+
+    /// Palette type at offset 68: 1 for color or monochrome and 2 for grayscale, 0 in files of many programs.
+    pub fn palette_info(&self) -> u16 {
+        u16::from_le_bytes([self.bytes[68], self.bytes[69]])
+    }
+
+    /// Filler bytes at offsets 74 to 127, usually zero but some programs store their own data there.
+    pub fn filler(&self) -> &[u8] {
+        &self.bytes[74..]
+    }
+}
+
 /// Parsed header of PCX file.
 #[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
 pub struct Header {
@@ -59,7 +194,7 @@ pub struct Header {
     /// Width and height of the image.
     pub size: (u16, u16),
 
-    /// Offset indicating where to render this image. This is usually set to `(0, 0)` and can be ignored.
+    /// Offset indicating where to render this image. This is usually set to `(0, 0)`, see `Window`.
     pub start: (u16, u16),
 
     /// Dots per inch.
@@ -73,35 +208,90 @@ pub struct Header {
 
     /// Lane length including padding bytes.
     pub lane_length: u16,
+
+    /// Horizontal and vertical size of the screen in pixels the image was created on, which some viewers use to select a video
+    /// mode. Only stored by version 5, `(0, 0)` if not set.
+    pub screen_size: (u16, u16),
 }
 
-fn error<T>(msg: &str) -> io::Result<T> {
-    Err(io::Error::new(io::ErrorKind::InvalidData, msg))
+/// Builder of `Header` for constructing headers programmatically, see `Header::builder()`.
+///
+/// Fields which are not set default to an empty version 5 RLE-compressed 256-color image at 300 dpi with black header
+/// palette and lanes padded to even length.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct HeaderBuilder {
+    version: Version,
+    is_compressed: bool,
+    bit_depth: u8,
+    number_of_color_planes: u8,
+    size: (u16, u16),
+    start: (u16, u16),
+    dpi: (u16, u16),
+    palette: [[u8; 3]; 16],
+    lane_length: Option<u16>,
+    screen_size: (u16, u16),
 }
 
-fn lane_proper_length(width: u16, bit_depth: u8) -> u16 {
+/// Length of the lane without padding for the image with given width and bit depth.
+pub const fn lane_proper_length(width: u16, bit_depth: u8) -> u16 {
     (((width as u32) * (bit_depth as u32) - 1) / 8 + 1) as u16
 }
 
 impl Header {
-    pub fn load<R: io::Read>(stream: &mut R) -> io::Result<Self> {
+    /// Start building a header, e.g. `Header::builder().size(640, 480).bit_depth(8).planes(3).dpi(300, 300).build()`.
+    pub fn builder() -> HeaderBuilder {
+        HeaderBuilder::new()
+    }
+
+    /// Header of RLE-compressed 24-bit RGB image of the given size at 300 dpi, with lane length computed from the width.
+    ///
+    /// Fields which do not affect the layout of pixel data, e.g. `dpi` and `start`, can be changed afterwards.
+    pub fn new_rgb(width: u16, height: u16) -> Result<Header> {
+        HeaderBuilder::new().size(width, height).bit_depth(8).planes(3).build()
+    }
+
+    /// Header of RLE-compressed paletted image of the given size at 300 dpi with `bit_depth` bits per pixel in one plane: 1, 2,
+    /// 4 or 8 for 2, 4, 16 or 256 colors. Lane length is computed from the width and the bit depth.
+    pub fn new_paletted(width: u16, height: u16, bit_depth: u8) -> Result<Header> {
+        HeaderBuilder::new().size(width, height).bit_depth(bit_depth).planes(1).build()
+    }
+
+    /// Read and validate the header.
+    pub fn load<R: io::Read>(stream: &mut R) -> Result<Self> {
+        Self::load_with_options(stream, &Options::default())
+    }
+
+    /// Read and validate the header, repairing recoverable inconsistencies unless `options.strict` is set.
+    pub fn load_with_options<R: io::Read>(stream: &mut R, options: &Options) -> Result<Self> {
+        Self::load_raw_with_options(stream, options).map(|(header, _)| header)
+    }
+
+    /// Read and validate the header like `load_with_options()`, also returning the bytes as stored in the file.
+    pub fn load_raw_with_options<R: io::Read>(stream: &mut R, options: &Options) -> Result<(Self, RawHeader)> {
         let magic = stream.read_u8()?;
         if magic != MAGIC_BYTE {
-            return error("not a PCX file");
+            return Err(Error::NotPcx);
         }
 
+        // Read the rest of the header at once, multi-byte fields are little-endian.
+        let mut bytes = [0; HEADER_LENGTH];
+        bytes[0] = magic;
+        stream.read_exact(&mut bytes[1..])?;
+        Ok((Self::parse(&bytes, options)?, RawHeader::new(bytes)))
+    }
+
+    // Validate and parse header bytes starting with the magic byte.
+    fn parse(bytes: &[u8; HEADER_LENGTH], options: &Options) -> Result<Self> {
+        let mut stream = &bytes[1..];
+
         let version = match stream.read_u8()? {
-            0 => Version::V0,
-            2 => Version::V2,
-            3 => Version::V3,
-            4 => Version::V4,
-            5 => Version::V5,
-            _ => return error("PCX: unknown version"),
+            version if options.strict => Version::from_u8(version).ok_or(Error::UnknownVersion(version))?,
+            version => Version::from_u8(version).unwrap_or(Version::V5),
         };
 
         let encoding = stream.read_u8()?;
-        if encoding != 0 && encoding != 1 {
-            return error("PCX: unknown encoding");
+        if encoding != 0 && encoding != 1 && options.strict {
+            return Err(Error::UnknownEncoding(encoding));
         }
 
         let bit_depth = stream.read_u8()?;
@@ -111,78 +301,218 @@ impl Header {
         let x_end = stream.read_u16::<LittleEndian>()?;
         let y_end = stream.read_u16::<LittleEndian>()?;
 
-        if x_end < x_start || y_end < y_start {
-            return error("PCX: invalid dimensions");
+        // Width and height of 0x10000 pixels can not be represented.
+        if x_end < x_start || y_end < y_start || x_end - x_start == 0xFFFF || y_end - y_start == 0xFFFF {
+            return Err(Error::InvalidDimensions);
         }
 
         let (width, height) = (x_end - x_start + 1, y_end - y_start + 1);
+        check_limit("width", options.limits.max_width.map(u64::from), width as u64)?;
+        check_limit("height", options.limits.max_height.map(u64::from), height as u64)?;
 
         let x_dpi = stream.read_u16::<LittleEndian>()?;
         let y_dpi = stream.read_u16::<LittleEndian>()?;
 
         let mut palette = [[0; 3]; 16];
-        for i in 0..16 {
-            stream.read_exact(&mut palette[i])?;
+        for color in &mut palette {
+            stream.read_exact(color)?;
         }
 
         let _reserved_0 = stream.read_u8()?;
         let number_of_color_planes = stream.read_u8()?;
-        let lane_length = stream.read_u16::<LittleEndian>()?;
+        let mut lane_length = stream.read_u16::<LittleEndian>()?;
         let _palette_kind = stream.read_u16::<LittleEndian>()?;
+        let x_screen_size = stream.read_u16::<LittleEndian>()?;
+        let y_screen_size = stream.read_u16::<LittleEndian>()?;
 
-        let mut _reserved_1 = [0; 58];
+        let mut _reserved_1 = [0; 54];
         stream.read_exact(&mut _reserved_1)?;
 
-        // Must be one of the supported format.
-        match (number_of_color_planes, bit_depth) {
-            (3, 8) | // 24-bit RGB
-            (1, 1) | // monochrome
-            (1, 2) | // 4-color palette
-            (1, 4) | // 16-color palette
-            (1, 8) | // 256-color palette
-            (2, 1) |
-            (3, 1) |
-            (4, 1) => {},
-            _ => return error("PCX: invalid or unsupported color format"),
+        debug_assert!(stream.is_empty());
+        debug_assert_eq!(x_start, u16::from_le_bytes([bytes[4], bytes[5]]));
+        debug_assert_eq!(y_start, u16::from_le_bytes([bytes[6], bytes[7]]));
+        debug_assert_eq!(x_end, u16::from_le_bytes([bytes[8], bytes[9]]));
+        debug_assert_eq!(y_end, u16::from_le_bytes([bytes[10], bytes[11]]));
+        debug_assert_eq!(x_dpi, u16::from_le_bytes([bytes[12], bytes[13]]));
+        debug_assert_eq!(y_dpi, u16::from_le_bytes([bytes[14], bytes[15]]));
+        debug_assert_eq!(lane_length, u16::from_le_bytes([bytes[66], bytes[67]]));
+        debug_assert_eq!(x_screen_size, u16::from_le_bytes([bytes[70], bytes[71]]));
+        debug_assert_eq!(y_screen_size, u16::from_le_bytes([bytes[72], bytes[73]]));
+
+        check_format(bit_depth, number_of_color_planes)?;
+        if options.strict_version && !version.supports_format(bit_depth, number_of_color_planes) {
+            return Err(Error::UnsupportedByVersion { version, bit_depth, number_of_color_planes });
         }
 
         if lane_length < lane_proper_length(width, bit_depth) {
-            return error("PCX: invalid lane length");
+            if options.strict {
+                return Err(Error::InvalidLaneLength);
+            }
+            lane_length = lane_proper_length(width, bit_depth);
         }
 
+        let decoded_bytes = (lane_length as u64) * (number_of_color_planes as u64) * (height as u64);
+        check_limit("decoded bytes", options.limits.max_decoded_bytes, decoded_bytes)?;
+
         Ok(Header {
-            version: version,
-            is_compressed: encoding == 1,
-            bit_depth: bit_depth,
+            version,
+            is_compressed: encoding != 0,
+            bit_depth,
             size: (width, height),
             start: (x_start, y_start),
             dpi: (x_dpi, y_dpi),
-            palette: palette,
-            number_of_color_planes: number_of_color_planes,
-            lane_length: lane_length,
+            palette,
+            number_of_color_planes,
+            lane_length,
+            screen_size: (x_screen_size, y_screen_size),
         })
     }
 
+    /// Validate and write the header to the stream. Pixel data must follow in the format described by the header.
+    pub fn save<W: io::Write>(&self, stream: &mut W) -> io::Result<()> {
+        self.save_with_options(stream, &WriterOptions::default())
+    }
+
+    /// Validate and write the header to the stream, with `options.strict` disabled the version is not checked against the
+    /// format of the image.
+    pub fn save_with_options<W: io::Write>(&self, stream: &mut W, options: &WriterOptions) -> io::Result<()> {
+        self.validate(options.strict)?;
+
+        // Assemble the header in memory first so the layout can be verified, multi-byte fields are little-endian.
+        let mut bytes = [0; HEADER_LENGTH];
+        {
+            let mut stream = &mut bytes[..];
+            self.write_fields(&mut stream)?;
+            debug_assert!(stream.is_empty());
+        }
+
+        debug_assert_eq!(&bytes[4..6], &self.start.0.to_le_bytes());
+        debug_assert_eq!(&bytes[6..8], &self.start.1.to_le_bytes());
+        debug_assert_eq!(&bytes[8..10], &(self.start.0 + (self.size.0 - 1)).to_le_bytes());
+        debug_assert_eq!(&bytes[10..12], &(self.start.1 + (self.size.1 - 1)).to_le_bytes());
+        debug_assert_eq!(&bytes[12..14], &self.dpi.0.to_le_bytes());
+        debug_assert_eq!(&bytes[14..16], &self.dpi.1.to_le_bytes());
+        debug_assert_eq!(&bytes[66..68], &self.lane_length.to_le_bytes());
+        debug_assert_eq!(&bytes[70..72], &self.screen_size.0.to_le_bytes());
+        debug_assert_eq!(&bytes[72..74], &self.screen_size.1.to_le_bytes());
+
+        stream.write_all(&bytes)
+    }
+
+    // Check the same invariants as `load` does, the version only if `strict` is set.
+    fn validate(&self, strict: bool) -> Result<()> {
+        let (width, height) = self.size;
+        if width == 0 || height == 0 || (self.start.0 as u32) + (width as u32) > 0x10000 || (self.start.1 as u32) + (height as u32) > 0x10000 {
+            return Err(Error::InvalidDimensions);
+        }
+
+        check_format(self.bit_depth, self.number_of_color_planes)?;
+        if strict && !self.version.supports_format(self.bit_depth, self.number_of_color_planes) {
+            return Err(Error::UnsupportedByVersion {
+                version: self.version,
+                bit_depth: self.bit_depth,
+                number_of_color_planes: self.number_of_color_planes,
+            });
+        }
+
+        if self.lane_length < self.lane_proper_length() {
+            return Err(Error::InvalidLaneLength);
+        }
+        Ok(())
+    }
+
+    fn write_fields<W: io::Write>(&self, stream: &mut W) -> io::Result<()> {
+        stream.write_u8(MAGIC_BYTE)?;
+        stream.write_u8(self.version as u8)?;
+        stream.write_u8(self.is_compressed as u8)?; // encoding
+        stream.write_u8(self.bit_depth)?;
+        stream.write_u16::<LittleEndian>(self.start.0)?;
+        stream.write_u16::<LittleEndian>(self.start.1)?;
+        stream.write_u16::<LittleEndian>(self.start.0 + (self.size.0 - 1))?;
+        stream.write_u16::<LittleEndian>(self.start.1 + (self.size.1 - 1))?;
+        stream.write_u16::<LittleEndian>(self.dpi.0)?;
+        stream.write_u16::<LittleEndian>(self.dpi.1)?;
+
+        for color in &self.palette {
+            stream.write_all(color)?;
+        }
+
+        stream.write_u8(0)?; // reserved
+        stream.write_u8(self.number_of_color_planes)?;
+        stream.write_u16::<LittleEndian>(self.lane_length)?;
+        stream.write_u16::<LittleEndian>(1)?; // palette kind (not used)
+        stream.write_u16::<LittleEndian>(self.screen_size.0)?;
+        stream.write_u16::<LittleEndian>(self.screen_size.1)?;
+
+        // Unused values in header.
+        stream.write_all(&[0; 54])
+    }
+
     /// Length of each lane without padding.
-    pub fn lane_proper_length(&self) -> u16 {
+    pub const fn lane_proper_length(&self) -> u16 {
         lane_proper_length(self.size.0, self.bit_depth)
     }
 
+    /// Placement of the image.
+    pub const fn window(&self) -> Window {
+        Window { start: self.start, size: self.size }
+    }
+
+    /// Physical size of the image in millimeters computed from its DPI, `None` if DPI is not set.
+    pub fn physical_size_mm(&self) -> Option<(f64, f64)> {
+        physical_size_mm(self.size, self.dpi)
+    }
+
     /// Number of padding bytes in each lane.
-    pub fn lane_padding(&self) -> u16 {
+    pub const fn lane_padding(&self) -> u16 {
         self.lane_length - self.lane_proper_length()
     }
 
-    pub fn palette_length(&self) -> Option<u16> {
+    /// Kind of pixel data in the image.
+    pub const fn color_format(&self) -> ColorFormat {
         match (self.number_of_color_planes, self.bit_depth) {
-            (3, 8) => None,
-            (number_of_color_planes, bit_depth) => Some(1 << ((bit_depth as u16) * (number_of_color_planes as u16))),
+            (3, 8) => ColorFormat::Rgb,
+            (2, 8) => ColorFormat::GrayAlpha,
+            (4, 8) => ColorFormat::Rgba,
+            _ => ColorFormat::Paletted,
         }
     }
+
+    /// Number of colors in the palette or `None` if the image is not paletted.
+    pub fn palette_length(&self) -> Option<u16> {
+        match self.color_format() {
+            ColorFormat::Paletted => Some(1 << ((self.bit_depth as u16) * (self.number_of_color_planes as u16))),
+            ColorFormat::Rgb | ColorFormat::GrayAlpha | ColorFormat::Rgba => None,
+        }
+    }
+}
+
+/// Physical size in millimeters of an image with the given size in pixels and DPI, `None` if any DPI value is zero.
+pub fn physical_size_mm(size: (u16, u16), dpi: (u16, u16)) -> Option<(f64, f64)> {
+    const MM_PER_INCH: f64 = 25.4;
+    if dpi.0 == 0 || dpi.1 == 0 {
+        return None;
+    }
+    Some(((size.0 as f64) * MM_PER_INCH / (dpi.0 as f64), (size.1 as f64) * MM_PER_INCH / (dpi.1 as f64)))
 }
 
 /// Write header to the stream.
 pub fn write<W: io::Write>(stream: &mut W, paletted: bool, size: (u16, u16), dpi: (u16, u16)) -> io::Result<()> {
+    // 16-color palette is not used as we will use 256-color palette instead.
+    write_format(stream, 8, if paletted { 1 } else { 3 }, size, dpi, &[[0; 3]; 16])
+}
+
+/// Write header of the image with given bit depth and number of color planes to the stream.
+///
+/// `palette` is only used by images with 16 colors or less.
+pub fn write_format<W: io::Write>(stream: &mut W, bit_depth: u8, number_of_color_planes: u8, size: (u16, u16), dpi: (u16, u16), palette: &[[u8; 3]; 16]) -> io::Result<()> {
+    writer_header(bit_depth, number_of_color_planes, size, dpi, palette)?.save(stream)
+}
+
+/// Header of RLE-compressed image with given bit depth and number of color planes as written by the writers of this crate.
+///
+/// `palette` is only used by images with 16 colors or less.
+pub fn writer_header(bit_depth: u8, number_of_color_planes: u8, size: (u16, u16), dpi: (u16, u16), palette: &[[u8; 3]; 16]) -> io::Result<Header> {
     if size.0 == 0xFFFF {
         // we'll need to round width up to even number which is not possible for 0xFFFF due to overflow
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot save PCX with width equal to 0xFFFF"));
@@ -192,34 +522,310 @@ pub fn write<W: io::Write>(stream: &mut W, paletted: bool, size: (u16, u16), dpi
         return Err(io::Error::new(io::ErrorKind::InvalidInput, "cannot save PCX with zero size"));
     }
 
-    // Write header.
-    stream.write_u8(MAGIC_BYTE)?;
-    stream.write_u8(Version::V5 as u8)?;
-    stream.write_u8(1)?; // encoding = compressed
-    stream.write_u8(8)?; // bit depth
-    stream.write_u16::<LittleEndian>(0)?; // x_start
-    stream.write_u16::<LittleEndian>(0)?; // y_start
-    stream.write_u16::<LittleEndian>(size.0 - 1)?;
-    stream.write_u16::<LittleEndian>(size.1 - 1)?;
-    stream.write_u16::<LittleEndian>(dpi.0)?;
-    stream.write_u16::<LittleEndian>(dpi.1)?;
+    Ok(Header {
+        version: Version::V5,
+        is_compressed: true,
+        bit_depth,
+        size,
+        start: (0, 0),
+        dpi,
+        palette: *palette,
+        number_of_color_planes,
+        lane_length: lane_length(size.0, bit_depth),
+        screen_size: (0, 0),
+    })
+}
+
+impl HeaderBuilder {
+    /// Create builder with default values.
+    pub fn new() -> Self {
+        HeaderBuilder {
+            version: Version::V5,
+            is_compressed: true,
+            bit_depth: 8,
+            number_of_color_planes: 1,
+            size: (0, 0),
+            start: (0, 0),
+            dpi: (300, 300),
+            palette: [[0; 3]; 16],
+            lane_length: None,
+            screen_size: (0, 0),
+        }
+    }
+
+    /// Set file format version.
+    pub fn version(&mut self, version: Version) -> &mut Self {
+        self.version = version;
+        self
+    }
+
+    /// Set whether pixel data is RLE-compressed.
+    pub fn compressed(&mut self, is_compressed: bool) -> &mut Self {
+        self.is_compressed = is_compressed;
+        self
+    }
+
+    /// Set width and height of the image.
+    pub fn size(&mut self, width: u16, height: u16) -> &mut Self {
+        self.size = (width, height);
+        self
+    }
+
+    /// Set the offset where to render the image.
+    pub fn start(&mut self, x: u16, y: u16) -> &mut Self {
+        self.start = (x, y);
+        self
+    }
+
+    /// Set bits per pixel per color plane.
+    pub fn bit_depth(&mut self, bit_depth: u8) -> &mut Self {
+        self.bit_depth = bit_depth;
+        self
+    }
 
-    // Write 16-color palette (not used as we will use 256-color palette instead).
-    for _ in 0..16 {
-        stream.write(&[0, 0, 0])?;
+    /// Set number of color planes.
+    pub fn planes(&mut self, number_of_color_planes: u8) -> &mut Self {
+        self.number_of_color_planes = number_of_color_planes;
+        self
     }
 
-    let lane_length = size.0 + (size.0 & 1); // width rounded up to even
+    /// Set dots per inch.
+    pub fn dpi(&mut self, x: u16, y: u16) -> &mut Self {
+        self.dpi = (x, y);
+        self
+    }
 
-    stream.write_u8(0)?; // reserved
-    stream.write_u8(if paletted { 1 } else { 3 })?; // number of color planes
-    stream.write_u16::<LittleEndian>(lane_length)?;
-    stream.write_u16::<LittleEndian>(1)?; // palette kind (not used)
+    /// Set the header palette, used by images with 16 colors or less.
+    pub fn palette(&mut self, palette: &[[u8; 3]; 16]) -> &mut Self {
+        self.palette = *palette;
+        self
+    }
 
-    // Unused values in header.
-    for _ in 0..58 {
-        stream.write(&[0])?;
+    /// Set lane length including padding instead of the default even length.
+    pub fn lane_length(&mut self, lane_length: u16) -> &mut Self {
+        self.lane_length = Some(lane_length);
+        self
     }
 
-    Ok(())
+    /// Set the size of the screen in pixels the image was created on, stored by version 5 only.
+    pub fn screen_size(&mut self, width: u16, height: u16) -> &mut Self {
+        self.screen_size = (width, height);
+        self
+    }
+
+    /// Validate the combination of fields and create the header.
+    pub fn build(&self) -> Result<Header> {
+        if self.size.0 == 0 || self.size.1 == 0 {
+            return Err(Error::InvalidDimensions);
+        }
+        check_format(self.bit_depth, self.number_of_color_planes)?;
+
+        let lane_length = match self.lane_length {
+            Some(lane_length) => lane_length,
+            None => {
+                // Padding to even length is not possible if the lane is 0xFFFF bytes long.
+                let proper_length = lane_proper_length(self.size.0, self.bit_depth);
+                proper_length.checked_add(proper_length & 1).ok_or(Error::InvalidDimensions)?
+            }
+        };
+
+        let header = Header {
+            version: self.version,
+            is_compressed: self.is_compressed,
+            bit_depth: self.bit_depth,
+            size: self.size,
+            start: self.start,
+            dpi: self.dpi,
+            palette: self.palette,
+            number_of_color_planes: self.number_of_color_planes,
+            lane_length,
+            screen_size: self.screen_size,
+        };
+        header.validate(true)?;
+        Ok(header)
+    }
+}
+
+impl Default for HeaderBuilder {
+    fn default() -> Self {
+        HeaderBuilder::new()
+    }
+}
+
+// Combination of bit depth and number of color planes must be one of the supported formats.
+fn check_format(bit_depth: u8, number_of_color_planes: u8) -> Result<()> {
+    match (number_of_color_planes, bit_depth) {
+        (3, 8) | // 24-bit RGB
+        (2, 8) | // grayscale + alpha
+        (4, 8) | // RGB + alpha
+        (1, 1) | // monochrome
+        (1, 2) | // 4-color palette
+        (1, 4) | // 16-color palette
+        (1, 8) | // 256-color palette
+        (2, 1) |
+        (3, 1) |
+        (4, 1) => Ok(()),
+        _ => Err(Error::UnsupportedFormat { bit_depth, number_of_color_planes }),
+    }
+}
+
+fn check_limit(limit: &'static str, maximum: Option<u64>, required: u64) -> Result<()> {
+    match maximum {
+        Some(maximum) if required > maximum => Err(Error::LimitExceeded { limit, maximum, required }),
+        _ => Ok(()),
+    }
+}
+
+/// Length of the lane including padding used when writing images, lanes are padded to even number of bytes.
+pub fn lane_length(width: u16, bit_depth: u8) -> u16 {
+    let proper_length = lane_proper_length(width, bit_depth);
+    proper_length + (proper_length & 1)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{write_format, Header, RawHeader, Version};
+    use {Error, Limits, Options};
+
+    // Header of 291x258 24-bit RGB image at 300x200 dpi with byte offsets spelled out, every multi-byte field has
+    // distinct low and high byte so byte order mistakes are detected on both little-endian and big-endian targets.
+    fn header_bytes() -> Vec<u8> {
+        let mut bytes = vec![0; 128];
+        bytes[..4].copy_from_slice(&[0x0A, 5, 1, 8]);
+        bytes[4..6].copy_from_slice(&[0x02, 0x01]); // x_start = 0x0102
+        bytes[6..8].copy_from_slice(&[0x04, 0x03]); // y_start = 0x0304
+        bytes[8..10].copy_from_slice(&[0x24, 0x02]); // x_end = 0x0224
+        bytes[10..12].copy_from_slice(&[0x05, 0x04]); // y_end = 0x0405
+        bytes[12..14].copy_from_slice(&[0x2C, 0x01]); // x_dpi = 300
+        bytes[14..16].copy_from_slice(&[0xC8, 0x00]); // y_dpi = 200
+        bytes[16..19].copy_from_slice(&[1, 2, 3]);
+        bytes[65] = 3;
+        bytes[66..68].copy_from_slice(&[0x24, 0x01]); // lane_length = 0x0124
+        bytes[68..70].copy_from_slice(&[0x01, 0x00]);
+        bytes[70..72].copy_from_slice(&[0x80, 0x02]); // x_screen_size = 640
+        bytes[72..74].copy_from_slice(&[0xE0, 0x01]); // y_screen_size = 480
+        bytes
+    }
+
+    #[test]
+    fn load_little_endian() {
+        let header = Header::load(&mut &header_bytes()[..]).unwrap();
+        assert_eq!(header.version, Version::V5);
+        assert!(header.is_compressed);
+        assert_eq!(header.start, (0x0102, 0x0304));
+        assert_eq!(header.size, (0x0123, 0x0102));
+        assert_eq!(header.dpi, (300, 200));
+        assert_eq!(header.palette[0], [1, 2, 3]);
+        assert_eq!(header.number_of_color_planes, 3);
+        assert_eq!(header.lane_length, 0x0124);
+        assert_eq!(header.screen_size, (640, 480));
+    }
+
+    #[test]
+    fn raw_header() {
+        let mut bytes = header_bytes();
+        bytes[64] = 0x55;
+        bytes[127] = 0xAA;
+        let (header, raw) = Header::load_raw_with_options(&mut &bytes[..], &Options::default()).unwrap();
+        assert_eq!(header, Header::load(&mut &bytes[..]).unwrap());
+        assert_eq!(&raw.bytes()[..], &bytes[..]);
+        assert_eq!((raw.manufacturer(), raw.version(), raw.encoding()), (0x0A, 5, 1));
+        assert_eq!((raw.reserved(), raw.palette_info()), (0x55, 1));
+        assert_eq!(raw.filler().len(), 54);
+        assert_eq!(raw.filler()[53], 0xAA);
+        assert_eq!(RawHeader::new(*raw.bytes()), raw);
+    }
+
+    #[test]
+    fn write_little_endian() {
+        let mut palette = [[0; 3]; 16];
+        palette[0] = [1, 2, 3];
+
+        let mut bytes = Vec::new();
+        write_format(&mut bytes, 8, 3, (0x0123, 0x0102), (300, 200), &palette).unwrap();
+
+        let mut expected = header_bytes();
+        expected[4..8].copy_from_slice(&[0, 0, 0, 0]);
+        expected[8..12].copy_from_slice(&[0x22, 0x01, 0x01, 0x01]);
+        expected[70..74].copy_from_slice(&[0, 0, 0, 0]);
+        assert_eq!(bytes, expected);
+    }
+
+    #[test]
+    fn builder() {
+        let header = Header::builder().size(0x0123, 0x0102).start(0x0102, 0x0304).bit_depth(8).planes(3).dpi(300, 200).lane_length(0x0124).screen_size(640, 480).build().unwrap();
+        let mut bytes = Vec::new();
+        header.save(&mut bytes).unwrap();
+        let mut expected = header_bytes();
+        expected[16..19].copy_from_slice(&[0, 0, 0]);
+        assert_eq!(bytes, expected);
+        assert_eq!(Header::load(&mut &bytes[..]).unwrap(), header);
+
+        let header = Header::builder().size(5, 1).bit_depth(1).planes(4).version(Version::V2).compressed(false).build().unwrap();
+        assert_eq!(header.lane_length, 2);
+        let mut bytes = Vec::new();
+        header.save(&mut bytes).unwrap();
+        assert_eq!(Header::load(&mut &bytes[..]).unwrap(), header);
+
+        assert!(matches!(Header::builder().build(), Err(Error::InvalidDimensions)));
+        assert!(matches!(Header::builder().size(0xFFFF, 1).build(), Err(Error::InvalidDimensions)));
+        assert!(matches!(Header::builder().size(2, 2).start(0xFFFF, 0).build(), Err(Error::InvalidDimensions)));
+        assert!(matches!(Header::builder().size(2, 2).planes(2).bit_depth(4).build(), Err(Error::UnsupportedFormat { bit_depth: 4, number_of_color_planes: 2 })));
+        assert!(matches!(Header::builder().size(8, 2).lane_length(7).build(), Err(Error::InvalidLaneLength)));
+        assert!(matches!(
+            Header::builder().size(2, 2).planes(3).version(Version::V2).build(),
+            Err(Error::UnsupportedByVersion { version: Version::V2, bit_depth: 8, number_of_color_planes: 3 })
+        ));
+
+        let mut header = Header::builder().size(8, 2).build().unwrap();
+        header.bit_depth = 3;
+        assert_eq!(header.save(&mut Vec::new()).unwrap_err().kind(), ::std::io::ErrorKind::InvalidData);
+    }
+
+    #[test]
+    fn constructors() {
+        let header = Header::new_rgb(5, 3).unwrap();
+        assert_eq!(header, Header::builder().size(5, 3).planes(3).build().unwrap());
+        assert_eq!((header.lane_length, header.lane_padding()), (6, 1));
+
+        let header = Header::new_paletted(13, 2, 1).unwrap();
+        assert_eq!((header.bit_depth, header.number_of_color_planes, header.lane_length), (1, 1, 2));
+        assert_eq!(header.palette_length(), Some(2));
+        assert_eq!(Header::new_paletted(13, 2, 4).unwrap().lane_length, 8);
+
+        assert!(matches!(Header::new_rgb(0, 3), Err(Error::InvalidDimensions)));
+        assert!(matches!(Header::new_paletted(4, 4, 3), Err(Error::UnsupportedFormat { bit_depth: 3, number_of_color_planes: 1 })));
+    }
+
+    #[test]
+    fn version_capabilities() {
+        assert_eq!(Version::from_u8(3), Some(Version::V3));
+        assert_eq!(Version::from_u8(1), None);
+
+        assert!(Version::V5.supports_format(8, 1) && Version::V5.supports_format(8, 3));
+        assert!(!Version::V4.supports_format(8, 1) && !Version::V2.supports_format(8, 4));
+        assert!(Version::V0.supports_format(1, 4) && Version::V3.supports_format(2, 1));
+
+        assert!(Version::V2.has_header_palette() && Version::V5.has_header_palette());
+        assert!(!Version::V0.has_header_palette() && !Version::V3.has_header_palette());
+    }
+
+    #[test]
+    fn limits() {
+        let load = |limits| Header::load_with_options(&mut &header_bytes()[..], &Options { limits, ..Options::default() });
+
+        assert!(load(Limits { max_width: Some(0x0123), max_height: Some(0x0102), max_decoded_bytes: Some(0x0124 * 3 * 0x0102) }).is_ok());
+        assert!(matches!(
+            load(Limits { max_width: Some(0x0122), ..Limits::default() }),
+            Err(Error::LimitExceeded { limit: "width", maximum: 0x0122, required: 0x0123 })
+        ));
+        assert!(matches!(
+            load(Limits { max_height: Some(0x0101), ..Limits::default() }),
+            Err(Error::LimitExceeded { limit: "height", maximum: 0x0101, required: 0x0102 })
+        ));
+        assert!(matches!(
+            load(Limits { max_decoded_bytes: Some(1000), ..Limits::default() }),
+            Err(Error::LimitExceeded { limit: "decoded bytes", maximum: 1000, required }) if required == 0x0124 * 3 * 0x0102
+        ));
+    }
 }
@@ -1,11 +1,16 @@
 //! Low-level handling of PCX. You generally don't need to use this module.
 pub mod rle;
 pub mod header;
+pub mod trace;
+pub mod scanline;
 
-pub use self::header::Header;
+pub use self::header::{Header, HeaderBuilder, RawHeader};
 
 /// Magic byte which is used as first byte in all PCX files.
 pub const MAGIC_BYTE: u8 = 0xA;
 
+/// Length of the file header in bytes, pixel data starts right after it.
+pub const HEADER_LENGTH: usize = 128;
+
 /// Byte marking start of 256-color palette.
 pub const PALETTE_START: u8 = 0xC;
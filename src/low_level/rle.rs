@@ -1,13 +1,24 @@
 //! Implementation of compression/decompression using variant of RLE (run-length-encoding) used in PCX files.
 
-use std::io;
-use byteorder::{ReadBytesExt, WriteBytesExt};
+use std::{cmp, io};
+use byteorder::WriteBytesExt;
+
+/// Number of bytes `Decompressor` reads from the stream at once.
+pub const DECOMPRESSOR_BUFFER_LENGTH: usize = 16 * 1024;
 
 /// Decompress RLE.
+///
+/// Compressed data is read from the stream in blocks of `DECOMPRESSOR_BUFFER_LENGTH` bytes, so the stream is generally read
+/// beyond the end of the data decompressed so far, see `buffered()`.
 #[derive(Clone, Debug)]
 pub struct Decompressor<S: io::Read> {
     stream: S,
 
+    // Compressed data read from the stream, `buffer[position..length]` is not decompressed yet.
+    buffer: Vec<u8>,
+    position: usize,
+    length: usize,
+
     run_count: u8,
     run_value: u8,
 }
@@ -16,49 +27,205 @@ impl<S: io::Read> Decompressor<S> {
     /// Create new decompressor from the stream.
     pub fn new(stream: S) -> Self {
         Decompressor {
-            stream: stream,
+            stream,
+            buffer: vec![0; DECOMPRESSOR_BUFFER_LENGTH],
+            position: 0,
+            length: 0,
             run_count: 0,
             run_value: 0,
         }
     }
 
+    /// Get a reference to the underlying stream.
+    pub fn get_ref(&self) -> &S {
+        &self.stream
+    }
+
+    /// Get a mutable reference to the underlying stream.
+    ///
+    /// The stream is positioned after the data returned by `buffered()`. If the position is changed `discard_buffered()` must be
+    /// called before decompressing further.
+    pub fn get_mut(&mut self) -> &mut S {
+        &mut self.stream
+    }
+
+    /// Compressed data which was read from the stream but not decompressed yet.
+    pub fn buffered(&self) -> &[u8] {
+        &self.buffer[self.position..self.length]
+    }
+
+    /// Drop compressed data which was read from the stream but not decompressed yet, e.g. after seeking the stream.
+    pub fn discard_buffered(&mut self) {
+        self.position = 0;
+        self.length = 0;
+    }
+
+    /// Remaining length and value of the run which was started but not fully returned yet.
+    ///
+    /// Together with the number of compressed bytes consumed it describes the state of decompression, so decompression can be
+    /// resumed later after seeking away, see `set_pending_run()`.
+    pub fn pending_run(&self) -> (u8, u8) {
+        (self.run_count, self.run_value)
+    }
+
+    /// Replace the pending run, e.g. to resume decompression with a state saved by `pending_run()`.
+    pub fn set_pending_run(&mut self, count: u8, value: u8) {
+        self.run_count = count;
+        self.run_value = value;
+    }
+
+    /// Skip `count` bytes of decompressed data without returning them.
+    ///
+    /// This is considerably faster than decompressing since runs are skipped as a whole.
+    pub fn skip(&mut self, mut count: u64) -> io::Result<()> {
+        while count > 0 {
+            if self.run_count > 0 {
+                let skipped = cmp::min(self.run_count as u64, count);
+                self.run_count -= skipped as u8;
+                count -= skipped;
+                continue;
+            }
+
+            if !self.next_code()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+        }
+        Ok(())
+    }
+
+    /// Copy compressed codes covering the next `length` bytes of decompressed data to `output` without decompressing them.
+    ///
+    /// Codes are copied verbatim, except that a run extending past `length` is split: the part covered is written as a new
+    /// code and the rest stays pending. Returns the number of decompressed bytes covered, which is less than `length` only
+    /// at the end of the stream.
+    pub fn read_raw(&mut self, length: usize, output: &mut Vec<u8>) -> io::Result<usize> {
+        let mut covered = 0;
+        while covered < length {
+            if self.run_count == 0 {
+                if self.position == self.length && !self.fill_buffer()? {
+                    break;
+                }
+
+                let byte = self.buffer[self.position];
+                self.position += 1;
+                if (byte & 0xC0) != 0xC0 {
+                    output.push(byte);
+                    covered += 1;
+                    continue;
+                }
+
+                if self.position == self.length && !self.fill_buffer()? {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                let value = self.buffer[self.position];
+                self.position += 1;
+
+                let count = (byte & 0x3F) as usize;
+                if count <= length - covered {
+                    output.extend_from_slice(&[byte, value]);
+                    covered += count;
+                    continue;
+                }
+                self.run_count = count as u8;
+                self.run_value = value;
+            }
+
+            let count = cmp::min(self.run_count as usize, length - covered);
+            output.extend_from_slice(&[0xC0 | (count as u8), self.run_value]);
+            self.run_count -= count as u8;
+            covered += count;
+        }
+        Ok(covered)
+    }
+
+    /// Next byte of compressed data without consuming it, `None` at the end of the stream.
+    pub fn peek(&mut self) -> io::Result<Option<u8>> {
+        if self.position == self.length && !self.fill_buffer()? {
+            return Ok(None);
+        }
+        Ok(Some(self.buffer[self.position]))
+    }
+
     /// Stop decompression process and get underlying stream.
+    ///
+    /// Compressed data read ahead is lost, use `into_parts()` to retrieve it.
     pub fn finish(self) -> S {
         self.stream
     }
+
+    /// Stop decompression process and get underlying stream together with the data returned by `buffered()`.
+    pub fn into_parts(mut self) -> (S, Vec<u8>) {
+        self.buffer.truncate(self.length);
+        self.buffer.drain(..self.position);
+        (self.stream, self.buffer)
+    }
+
+    // Decode next code into the pending run, literal bytes become runs of length 1. Returns `false` at the end of the stream.
+    fn next_code(&mut self) -> io::Result<bool> {
+        if self.position == self.length && !self.fill_buffer()? {
+            return Ok(false);
+        }
+
+        let byte = self.buffer[self.position];
+        self.position += 1;
+        if (byte & 0xC0) != 0xC0 {
+            // 1-byte code
+            self.run_count = 1;
+            self.run_value = byte;
+        } else {
+            // 2-byte code
+            if self.position == self.length && !self.fill_buffer()? {
+                return Err(io::ErrorKind::UnexpectedEof.into());
+            }
+            self.run_count = byte & 0x3F;
+            self.run_value = self.buffer[self.position];
+            self.position += 1;
+        }
+        Ok(true)
+    }
+
+    // Read the next block of compressed data, all buffered data must have been consumed. Returns `false` at the end of the stream.
+    fn fill_buffer(&mut self) -> io::Result<bool> {
+        debug_assert_eq!(self.position, self.length);
+        loop {
+            match self.stream.read(&mut self.buffer) {
+                Ok(read) => {
+                    self.position = 0;
+                    self.length = read;
+                    return Ok(read != 0);
+                }
+                Err(ref error) if error.kind() == io::ErrorKind::Interrupted => {}
+                Err(error) => return Err(error),
+            }
+        }
+    }
 }
 
 impl<S: io::Read> io::Read for Decompressor<S> {
-    fn read(&mut self, mut buffer: &mut [u8]) -> io::Result<usize> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
         let mut read = 0;
-        while buffer.len() > 0 {
+        while read < buffer.len() {
             // Write the pixel run to the buffer.
-            while self.run_count > 0 && buffer.len() > 0 {
-                buffer.write_u8(self.run_value)?;
-                self.run_count -= 1;
-                read += 1;
+            if self.run_count > 0 {
+                let count = cmp::min(self.run_count as usize, buffer.len() - read);
+                buffer[read..(read + count)].fill(self.run_value);
+                self.run_count -= count as u8;
+                read += count;
+                continue;
             }
 
-            if buffer.len() == 0 {
-                return Ok(read);
+            // Copy literal bytes at once.
+            let input = &self.buffer[self.position..self.length];
+            let literals = input.iter().take(buffer.len() - read).take_while(|&&byte| (byte & 0xC0) != 0xC0).count();
+            if literals > 0 {
+                buffer[read..(read + literals)].copy_from_slice(&input[..literals]);
+                self.position += literals;
+                read += literals;
+                continue;
             }
 
-            let byte = {
-                let mut byte_buffer = [0; 1];
-                if self.stream.read(&mut byte_buffer)? == 0 {
-                    return Ok(read);
-                }
-                byte_buffer[0]
-            };
-
-            if (byte & 0xC0) != 0xC0 {
-                // 1-byte code
-                buffer.write_u8(byte)?;
-                read += 1;
-            } else {
-                // 2-byte code
-                self.run_count = byte & 0x3F;
-                self.run_value = self.stream.read_u8()?;
+            if !self.next_code()? {
+                break;
             }
         }
 
@@ -68,6 +235,8 @@ impl<S: io::Read> io::Read for Decompressor<S> {
 
 /// Compress using RLE.
 ///
+/// Runs never cross lane boundaries so each lane can be decoded independently as required by the PCX specification.
+///
 /// Warning: compressor does not implement `Drop` and will not automatically get flushed on destruction. Call `finish()` or `flush()` to flush it.
 /// If it would implement `Drop` it would be impossible to implement `finish()` due to
 /// [restrictions](https://doc.rust-lang.org/error-index.html#E0509) of the Rust language.
@@ -75,6 +244,8 @@ impl<S: io::Read> io::Read for Decompressor<S> {
 pub struct Compressor<S: io::Write> {
     stream: S,
 
+    compress: bool,
+    extend_runs: bool,
     lane_length: u16,
     lane_position: u16,
 
@@ -82,24 +253,48 @@ pub struct Compressor<S: io::Write> {
     run_value: u8,
 }
 
+/// Maximal length of a run which can be encoded by a 2-byte code.
+const MAX_RUN_COUNT: u8 = 0x3F;
+
 impl<S: io::Write> Compressor<S> {
     /// Create new compressor which will write to the stream.
+    ///
+    /// `lane_length` is the length of each lane including padding. Pass 0 if data is not split into lanes.
     pub fn new(stream: S, lane_length: u16) -> Self {
         Compressor {
-            stream: stream,
+            stream,
+            compress: true,
+            extend_runs: false,
             run_count: 0,
             run_value: 0,
-            lane_length: lane_length,
+            lane_length,
             lane_position: 0,
         }
     }
 
+    /// Create new compressor which will write data to the stream as is, for writing non-compressed files.
+    ///
+    /// Lanes are handled in the same way as by `new()`, so the two can be used interchangeably.
+    pub fn new_uncompressed(stream: S, lane_length: u16) -> Self {
+        Compressor {
+            compress: false,
+            ..Self::new(stream, lane_length)
+        }
+    }
+
+    /// Pad by repeating the last value of the lane instead of writing zeros, which saves a byte whenever a lane ending with
+    /// a run is padded. Values of padding are ignored by decoders. Disabled by default.
+    pub fn set_extend_runs(&mut self, extend_runs: bool) {
+        self.extend_runs = extend_runs;
+    }
+
     /// Pad to the lane length.
     pub fn pad(&mut self) -> io::Result<()> {
         use std::io::Write;
 
+        let value = if self.extend_runs && self.run_count > 0 { self.run_value } else { 0 };
         while self.lane_position != 0 {
-            self.write(&[0])?;
+            self.write_all(&[value])?;
         }
 
         Ok(())
@@ -107,14 +302,22 @@ impl<S: io::Write> Compressor<S> {
 
     /// Stop compression process and get underlying stream.
     pub fn finish(mut self) -> io::Result<S> {
-        self.flush_compressor()?;
+        use std::io::Write;
+
+        self.flush()?;
         Ok(self.stream)
     }
 
-    fn flush_compressor(&mut self) -> io::Result<()> {
+    // Write pending run to the stream.
+    fn write_run(&mut self) -> io::Result<()> {
         match (self.run_count, self.run_value) {
             (0, _) => {}
-            (1, run_value @ 0...0xBF) => {
+            (run_count, run_value) if !self.compress => {
+                for _ in 0..run_count {
+                    self.stream.write_u8(run_value)?;
+                }
+            }
+            (1, run_value @ 0..=0xBF) => {
                 self.stream.write_u8(run_value)?;
             }
             (run_count, run_value) => {
@@ -123,48 +326,36 @@ impl<S: io::Write> Compressor<S> {
             }
         }
 
-        self.stream.flush()
+        self.run_count = 0;
+        Ok(())
     }
 }
 
 impl<S: io::Write> io::Write for Compressor<S> {
-    fn write(&mut self, mut buffer: &[u8]) -> io::Result<usize> {
-        use std::io::Read;
-
-        let mut written = 0;
-
-        while buffer.len() > 0 {
-            let byte = {
-                let mut byte_buffer = [0; 1];
-                if buffer.read(&mut byte_buffer)? == 0 {
-                    return Ok(written);
-                }
-                byte_buffer[0]
-            };
-
-            self.lane_position += 1;
-            written += 1;
-
-            if byte == self.run_value && self.run_count < 62 && self.lane_position != self.lane_length {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        for &byte in buffer {
+            if self.run_count > 0 && byte == self.run_value && self.run_count < MAX_RUN_COUNT {
                 self.run_count += 1;
-                continue;
+            } else {
+                self.write_run()?;
+                self.run_count = 1;
+                self.run_value = byte;
             }
 
-            if self.lane_position == self.lane_length {
-                self.lane_position = 0;
+            if self.lane_length != 0 {
+                self.lane_position += 1;
+                if self.lane_position == self.lane_length {
+                    self.write_run()?;
+                    self.lane_position = 0;
+                }
             }
-
-            self.flush_compressor()?;
-
-            self.run_count = 1;
-            self.run_value = byte;
         }
 
-        Ok(written)
+        Ok(buffer.len())
     }
 
     fn flush(&mut self) -> io::Result<()> {
-        self.flush_compressor()?;
+        self.write_run()?;
         self.stream.flush()
     }
 }
@@ -181,7 +372,7 @@ mod tests {
 
         {
             let mut compressor = Compressor::new(&mut compressed, 8);
-            compressor.write_all(&data).unwrap();
+            compressor.write_all(data).unwrap();
             compressor.flush().unwrap();
         }
 
@@ -237,4 +428,162 @@ mod tests {
         round_trip(&data);
         round_trip_one_by_one(&data);
     }
+
+    #[test]
+    fn runs_do_not_cross_lanes() {
+        use std::io::Write;
+
+        let mut compressed = Vec::new();
+        {
+            let mut compressor = Compressor::new(&mut compressed, 4);
+            compressor.write_all(&[7; 8]).unwrap();
+            compressor.write_all(&[0xC5; 1]).unwrap();
+            compressor.pad().unwrap();
+            compressor.flush().unwrap();
+            compressor.flush().unwrap();
+        }
+
+        assert_eq!(compressed, [0xC4, 7, 0xC4, 7, 0xC1, 0xC5, 0xC3, 0]);
+    }
+
+    #[test]
+    fn extend_runs() {
+        use std::io::Write;
+
+        let mut compressed = Vec::new();
+        {
+            let mut compressor = Compressor::new(&mut compressed, 4);
+            compressor.set_extend_runs(true);
+            compressor.write_all(&[3, 0xC5]).unwrap();
+            compressor.pad().unwrap();
+            compressor.pad().unwrap();
+            compressor.finish().unwrap();
+        }
+
+        assert_eq!(compressed, [3, 0xC3, 0xC5]);
+    }
+
+    #[test]
+    fn long_runs() {
+        use std::io::Write;
+
+        let mut compressed = Vec::new();
+        {
+            let mut compressor = Compressor::new(&mut compressed, 0);
+            compressor.write_all(&[9; 64]).unwrap();
+            compressor.write_all(&vec![1; 0x10000]).unwrap();
+            compressor.finish().unwrap();
+        }
+
+        assert_eq!(&compressed[..5], &[0xFF, 9, 9, 0xFF, 1]);
+    }
+
+    #[test]
+    fn uncompressed() {
+        use std::io::Write;
+
+        let mut written = Vec::new();
+        {
+            let mut compressor = Compressor::new_uncompressed(&mut written, 4);
+            compressor.write_all(&[7; 8]).unwrap();
+            compressor.write_all(&[0xC5; 1]).unwrap();
+            compressor.pad().unwrap();
+            compressor.finish().unwrap();
+        }
+
+        assert_eq!(written, [7, 7, 7, 7, 7, 7, 7, 7, 0xC5, 0, 0, 0]);
+    }
+
+    #[test]
+    fn skip() {
+        use std::io::{self, Read, Write};
+
+        let data = [0, 1, 2, 3, 5, 5, 5, 128, 128, 128, 7, 7, 255, 7, 255, 255, 254, 0, 0, 0, 4, 4, 177, 177, 4, 177, 177];
+        let mut compressed = Vec::new();
+        {
+            let mut compressor = Compressor::new(&mut compressed, 0);
+            compressor.write_all(&data).unwrap();
+            compressor.finish().unwrap();
+        }
+
+        for split in 0..data.len() {
+            let mut decompressor = Decompressor::new(&compressed[..]);
+            decompressor.skip(split as u64).unwrap();
+            let mut rest = Vec::new();
+            decompressor.read_to_end(&mut rest).unwrap();
+            assert_eq!(&rest[..], &data[split..]);
+        }
+
+        let mut decompressor = Decompressor::new(&compressed[..]);
+        assert_eq!(decompressor.skip(data.len() as u64 + 1).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_ahead() {
+        use std::io::{self, Read, Write};
+
+        // Stream returning one byte per read, so that 2-byte codes are split between reads.
+        struct Trickle<'a>(&'a [u8]);
+
+        impl<'a> Read for Trickle<'a> {
+            fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+                let length = self.0.len().min(buffer.len()).min(1);
+                (&self.0[..length]).read(buffer)?;
+                self.0 = &self.0[length..];
+                Ok(length)
+            }
+        }
+
+        let data: Vec<u8> = (0..200u16).map(|i| if i % 3 == 0 { 0xC7 } else { (i / 5) as u8 }).collect();
+        let mut compressed = Vec::new();
+        {
+            let mut compressor = Compressor::new(&mut compressed, 0);
+            compressor.write_all(&data).unwrap();
+            compressor.finish().unwrap();
+        }
+        let compressed_length = compressed.len();
+        compressed.extend_from_slice(b"trailer");
+
+        let mut result = vec![0; data.len()];
+        let mut decompressor = Decompressor::new(Trickle(&compressed));
+        decompressor.read_exact(&mut result).unwrap();
+        assert_eq!(result, data);
+
+        let mut decompressor = Decompressor::new(&compressed[..]);
+        decompressor.read_exact(&mut result).unwrap();
+        assert_eq!(result, data);
+        assert_eq!(decompressor.buffered(), b"trailer");
+        let (stream, buffered) = decompressor.into_parts();
+        assert_eq!(buffered, b"trailer");
+        assert!(stream.is_empty());
+
+        let mut decompressor = Decompressor::new(&compressed[..(compressed_length - 1)]);
+        assert_eq!(decompressor.read_exact(&mut result).unwrap_err().kind(), io::ErrorKind::UnexpectedEof);
+    }
+
+    #[test]
+    fn read_raw() {
+        use std::io::Read;
+
+        let compressed = [1, 0xC3, 7, 0xC1, 2, 0xC4, 9, 5];
+        let mut decompressor = Decompressor::new(&compressed[..]);
+
+        let mut raw = Vec::new();
+        assert_eq!(decompressor.read_raw(5, &mut raw).unwrap(), 5);
+        assert_eq!(raw, [1, 0xC3, 7, 0xC1, 2]);
+
+        // Run crossing the end is split.
+        raw.clear();
+        assert_eq!(decompressor.read_raw(2, &mut raw).unwrap(), 2);
+        assert_eq!(raw, [0xC2, 9]);
+        assert_eq!(decompressor.peek().unwrap(), Some(5));
+
+        let mut rest = Vec::new();
+        assert_eq!(decompressor.read_to_end(&mut rest).unwrap(), 3);
+        assert_eq!(rest, [9, 9, 5]);
+
+        raw.clear();
+        assert_eq!(decompressor.read_raw(4, &mut raw).unwrap(), 0);
+        assert_eq!(decompressor.peek().unwrap(), None);
+    }
 }
@@ -0,0 +1,112 @@
+//! Conversion between palette indices and the lanes of a PCX scanline, also useful for other ZSoft-derived formats.
+//!
+//! A scanline consists of one lane per color plane. Plane `p` holds bits `p * bit_depth` to `(p + 1) * bit_depth - 1` of
+//! each index and pixels are packed into bytes starting from the most significant bits. Lanes handled here are not padded.
+use {Error, Result};
+
+/// Split palette indices of one row into lanes of `planes` color planes with `bit_depth` bits per pixel each.
+///
+/// `bit_depth` must be 1, 2, 4 or 8 and `bit_depth * planes` at most 8. Returns `Error::InvalidUsage` if some index does not
+/// fit into `bit_depth * planes` bits.
+pub fn pack_row(indices: &[u8], bit_depth: u8, planes: u8) -> Result<Vec<Vec<u8>>> {
+    check_format(bit_depth, planes)?;
+    let bits = (bit_depth as usize) * (planes as usize);
+    if bits < 8 && indices.iter().any(|&index| index >> bits != 0) {
+        return Err(Error::InvalidUsage("pixel value does not fit into the bits per pixel of the scanline"));
+    }
+
+    let bit_depth = bit_depth as usize;
+    let mask = (1 << bit_depth) - 1;
+    let lane_length = lane_length(indices.len(), bit_depth);
+    let lanes = (0..planes as usize)
+        .map(|plane| {
+            if bit_depth == 8 {
+                return indices.to_vec();
+            }
+            let mut lane = vec![0; lane_length];
+            for (x, &index) in indices.iter().enumerate() {
+                let value = ((index as usize) >> (plane * bit_depth)) & mask;
+                let bit = x * bit_depth;
+                lane[bit / 8] |= (value << (8 - bit_depth - bit % 8)) as u8;
+            }
+            lane
+        })
+        .collect();
+    Ok(lanes)
+}
+
+/// Combine lanes of one row, one per color plane, into `width` palette indices, the inverse of `pack_row()`.
+///
+/// Returns `Error::BufferLength` if a lane is shorter than `width` pixels with `bit_depth` bits each, further bytes such as
+/// padding are ignored.
+pub fn unpack_row<L: AsRef<[u8]>>(lanes: &[L], width: u16, bit_depth: u8) -> Result<Vec<u8>> {
+    if lanes.len() > 8 {
+        return Err(Error::InvalidUsage("number of planes and bits per pixel must give at most 8 bits per pixel"));
+    }
+    check_format(bit_depth, lanes.len() as u8)?;
+    let lane_length = lane_length(width as usize, bit_depth as usize);
+    if let Some(lane) = lanes.iter().find(|lane| lane.as_ref().len() < lane_length) {
+        return Err(Error::BufferLength { expected: lane_length, actual: lane.as_ref().len() });
+    }
+
+    let bit_depth = bit_depth as usize;
+    let mask = ((1u16 << bit_depth) - 1) as u8;
+    let mut indices = vec![0; width as usize];
+    for (plane, lane) in lanes.iter().enumerate() {
+        let lane = lane.as_ref();
+        for (x, index) in indices.iter_mut().enumerate() {
+            let bit = x * bit_depth;
+            let value = (lane[bit / 8] >> (8 - bit_depth - bit % 8)) & mask;
+            *index |= value << (plane * bit_depth);
+        }
+    }
+    Ok(indices)
+}
+
+// Length of a lane of `width` pixels in bytes without padding.
+fn lane_length(width: usize, bit_depth: usize) -> usize {
+    (width * bit_depth).div_ceil(8)
+}
+
+fn check_format(bit_depth: u8, planes: u8) -> Result<()> {
+    if ![1, 2, 4, 8].contains(&bit_depth) {
+        return Err(Error::InvalidUsage("bits per pixel must be 1, 2, 4 or 8"));
+    }
+    if planes == 0 || (bit_depth as u32) * (planes as u32) > 8 {
+        return Err(Error::InvalidUsage("number of planes and bits per pixel must give at most 8 bits per pixel"));
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{pack_row, unpack_row};
+    use Error;
+
+    #[test]
+    fn roundtrip() {
+        let indices: Vec<u8> = (0..13).collect();
+        let lanes = pack_row(&indices, 1, 4).unwrap();
+        assert_eq!(lanes, [[0b0101_0101, 0b0101_0000], [0b0011_0011, 0b0011_0000], [0b0000_1111, 0b0000_1000], [0, 0b1111_1000]]);
+        assert_eq!(unpack_row(&lanes, 13, 1).unwrap(), indices);
+
+        let lanes = pack_row(&[1, 2, 3, 0, 3], 2, 1).unwrap();
+        assert_eq!(lanes, [[0b0110_1100, 0b1100_0000]]);
+        assert_eq!(unpack_row(&lanes, 5, 2).unwrap(), [1, 2, 3, 0, 3]);
+
+        for &(bit_depth, planes) in &[(1, 1), (1, 2), (1, 3), (2, 2), (4, 1), (4, 2), (8, 1)] {
+            let indices: Vec<u8> = (0..37u32).map(|i| ((i * 7) % (1 << (bit_depth * planes))) as u8).collect();
+            let lanes = pack_row(&indices, bit_depth, planes).unwrap();
+            assert_eq!(lanes.len(), planes as usize);
+            assert_eq!(unpack_row(&lanes, 37, bit_depth).unwrap(), indices);
+        }
+
+        // Padding is ignored.
+        assert_eq!(unpack_row(&[[0xFF, 0xAA]], 4, 1).unwrap(), [1; 4]);
+
+        assert!(matches!(pack_row(&[4], 1, 2), Err(Error::InvalidUsage(_))));
+        assert!(matches!(pack_row(&[0], 8, 3), Err(Error::InvalidUsage(_))));
+        assert!(matches!(pack_row(&[0], 3, 1), Err(Error::InvalidUsage(_))));
+        assert!(matches!(unpack_row(&[[0]], 9, 1), Err(Error::BufferLength { expected: 2, actual: 1 })));
+    }
+}
@@ -0,0 +1,227 @@
+//! Human-readable trace of RLE compression, useful for finding out why a file got bigger after re-encoding.
+use std::{fmt, io};
+
+use low_level::{Header, HEADER_LENGTH};
+
+/// Stream adapter which passes the encoded file through to the underlying stream and writes a trace of RLE codes to a
+/// separate sink.
+///
+/// For each lane the trace contains the row and plane number, compressed and uncompressed size and the sequence of codes:
+/// runs, literals and escaped values (single values 0xC0 and above which take two bytes). A summary line follows the last lane.
+///
+/// It works with any writer of this crate, e.g. `WriterRgb::new(TraceWriter::new(file, io::stderr()), size, dpi)`.
+#[derive(Debug)]
+pub struct TraceWriter<W: io::Write, T: io::Write> {
+    stream: W,
+    trace: T,
+
+    header: Vec<u8>,
+    layout: Option<Layout>,
+
+    lane: u32,
+    lane_decoded: u32,
+    lane_compressed: u32,
+    codes: Vec<Code>,
+    pending_run: Option<u8>,
+
+    total_compressed: u64,
+}
+
+#[derive(Copy, Clone, Debug)]
+struct Layout {
+    lane_length: u32,
+    number_of_color_planes: u8,
+    number_of_lanes: u32,
+}
+
+#[derive(Copy, Clone, Debug)]
+enum Code {
+    Literals(u32),
+    Run(u8, u8),
+    Escaped(u8),
+}
+
+impl fmt::Display for Code {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match *self {
+            Code::Literals(count) => write!(f, "{} literal{}", count, if count == 1 { "" } else { "s" }),
+            Code::Run(count, value) => write!(f, "run {}x{:02X}", count, value),
+            Code::Escaped(value) => write!(f, "escaped {:02X}", value),
+        }
+    }
+}
+
+impl<W: io::Write, T: io::Write> TraceWriter<W, T> {
+    /// Create new adapter writing the encoded file to `stream` and the trace to `trace`.
+    pub fn new(stream: W, trace: T) -> Self {
+        TraceWriter {
+            stream,
+            trace,
+            header: Vec::with_capacity(HEADER_LENGTH),
+            layout: None,
+            lane: 0,
+            lane_decoded: 0,
+            lane_compressed: 0,
+            codes: Vec::new(),
+            pending_run: None,
+            total_compressed: 0,
+        }
+    }
+
+    /// Get the underlying stream and trace sink.
+    pub fn into_inner(self) -> (W, T) {
+        (self.stream, self.trace)
+    }
+
+    fn process(&mut self, bytes: &[u8]) -> io::Result<()> {
+        for &byte in bytes {
+            if self.header.len() < HEADER_LENGTH {
+                self.header.push(byte);
+                if self.header.len() == HEADER_LENGTH {
+                    self.start()?;
+                }
+                continue;
+            }
+
+            let layout = match self.layout {
+                Some(layout) if self.lane < layout.number_of_lanes => layout,
+                _ => continue,
+            };
+
+            self.lane_compressed += 1;
+            match self.pending_run.take() {
+                Some(count) => {
+                    self.codes.push(if count == 1 { Code::Escaped(byte) } else { Code::Run(count, byte) });
+                    self.advance(layout, count as u32)?;
+                }
+                None if byte >= 0xC0 => self.pending_run = Some(byte & 0x3F),
+                None => {
+                    match self.codes.last_mut() {
+                        Some(&mut Code::Literals(ref mut count)) => *count += 1,
+                        _ => self.codes.push(Code::Literals(1)),
+                    }
+                    self.advance(layout, 1)?;
+                }
+            }
+        }
+        Ok(())
+    }
+
+    fn start(&mut self) -> io::Result<()> {
+        let header = match Header::load(&mut &self.header[..]) {
+            Ok(header) => header,
+            Err(error) => return writeln!(self.trace, "invalid header: {}", error),
+        };
+
+        writeln!(
+            self.trace,
+            "{}x{} image, {} bits per pixel, {} color planes, lane length {}",
+            header.size.0, header.size.1, header.bit_depth, header.number_of_color_planes, header.lane_length
+        )?;
+        if !header.is_compressed {
+            return writeln!(self.trace, "pixel data is not compressed");
+        }
+
+        self.layout = Some(Layout {
+            lane_length: header.lane_length as u32,
+            number_of_color_planes: header.number_of_color_planes,
+            number_of_lanes: (header.size.1 as u32) * (header.number_of_color_planes as u32),
+        });
+        Ok(())
+    }
+
+    fn advance(&mut self, layout: Layout, count: u32) -> io::Result<()> {
+        self.lane_decoded += count;
+        while self.lane_decoded >= layout.lane_length && self.lane < layout.number_of_lanes {
+            self.lane_decoded -= layout.lane_length;
+            self.finish_lane(layout)?;
+        }
+        Ok(())
+    }
+
+    fn finish_lane(&mut self, layout: Layout) -> io::Result<()> {
+        let planes = layout.number_of_color_planes as u32;
+        write!(
+            self.trace,
+            "row {} plane {}: {} -> {} bytes:",
+            self.lane / planes,
+            self.lane % planes,
+            self.lane_compressed,
+            layout.lane_length
+        )?;
+        for (i, code) in self.codes.iter().enumerate() {
+            write!(self.trace, "{}{}", if i == 0 { " " } else { ", " }, code)?;
+        }
+        writeln!(self.trace)?;
+
+        self.total_compressed += self.lane_compressed as u64;
+        self.lane_compressed = 0;
+        self.codes.clear();
+        self.lane += 1;
+
+        if self.lane == layout.number_of_lanes {
+            let raw = (layout.number_of_lanes as u64) * (layout.lane_length as u64);
+            writeln!(
+                self.trace,
+                "total: {} compressed bytes for {} bytes of pixel data ({:.1}%)",
+                self.total_compressed,
+                raw,
+                (self.total_compressed as f64) * 100.0 / (raw as f64)
+            )?;
+        }
+        Ok(())
+    }
+}
+
+impl<W: io::Write, T: io::Write> io::Write for TraceWriter<W, T> {
+    fn write(&mut self, buffer: &[u8]) -> io::Result<usize> {
+        let written = self.stream.write(buffer)?;
+        self.process(&buffer[..written])?;
+        Ok(written)
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        self.stream.flush()?;
+        self.trace.flush()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::TraceWriter;
+    use WriterRgb;
+
+    #[test]
+    fn trace_rgb() {
+        let rgb: Vec<u8> = (0..(70 * 2)).flat_map(|x| vec![if x < 70 { 0 } else { 0xC8 }, (x % 3) as u8, 7]).collect();
+
+        let mut direct = Vec::new();
+        {
+            let mut writer = WriterRgb::new(&mut direct, (70, 2), (300, 300)).unwrap();
+            for row in rgb.chunks(70 * 3) {
+                writer.write_row(row).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let (mut pcx, mut trace) = (Vec::new(), Vec::new());
+        {
+            let mut writer = WriterRgb::new(TraceWriter::new(&mut pcx, &mut trace), (70, 2), (300, 300)).unwrap();
+            for row in rgb.chunks(70 * 3) {
+                writer.write_row(row).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        assert_eq!(pcx, direct);
+
+        let trace = String::from_utf8(trace).unwrap();
+        let lines: Vec<_> = trace.lines().collect();
+        assert_eq!(lines.len(), 1 + 2 * 3 + 1);
+        assert_eq!(lines[0], "70x2 image, 8 bits per pixel, 3 color planes, lane length 70");
+        assert_eq!(lines[1], "row 0 plane 0: 4 -> 70 bytes: run 63x00, run 7x00");
+        assert_eq!(lines[2], "row 0 plane 1: 70 -> 70 bytes: 70 literals");
+        assert_eq!(lines[3], "row 0 plane 2: 4 -> 70 bytes: run 63x07, run 7x07");
+        assert_eq!(lines[4], "row 1 plane 0: 4 -> 70 bytes: run 63xC8, run 7xC8");
+        assert!(lines[7].starts_with("total: 156 compressed bytes for 420 bytes of pixel data"));
+    }
+}
@@ -0,0 +1,164 @@
+//! Conversion of grayscale images to 1-bit monochrome images.
+use std::io;
+
+use dither::bayer_matrix;
+use {user_error, Dithering, Error, Palette, Result, WriterOptions, WriterPlanar};
+
+/// Method of converting gray values to black and white.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Binarization {
+    /// Values greater than or equal to the threshold become white, other values become black.
+    Threshold(u8),
+
+    /// Threshold chosen from the histogram of the image with Otsu's method, suitable for scanned documents.
+    Otsu,
+
+    /// Ordered dithering with 4x4 Bayer matrix, gives regular patterns which survive printing and scaling well.
+    Ordered,
+
+    /// Floyd-Steinberg error diffusion dithering, see `Palette::dither()`.
+    Diffusion,
+
+    /// Dithering with the given method, e.g. `Dithering::Noise` with a fixed seed for reproducible results.
+    Dithered(Dithering),
+}
+
+impl Binarization {
+    /// Convert gray values forming rows of `width` pixels to palette indices of a monochrome image: 0 for black and 1 for
+    /// white.
+    ///
+    /// Length of `gray` must be a multiple of `width`.
+    pub fn apply(self, gray: &[u8], width: usize) -> Result<Vec<u8>> {
+        if gray.is_empty() {
+            return Ok(Vec::new());
+        }
+        if width == 0 || gray.len() % width != 0 {
+            return Err(Error::InvalidUsage("gray buffer length must be a multiple of the row length"));
+        }
+
+        Ok(match self {
+            Binarization::Threshold(threshold) => threshold_indices(gray, threshold),
+            Binarization::Otsu => threshold_indices(gray, otsu_threshold(gray)),
+            Binarization::Ordered => {
+                let matrix = bayer_matrix(4);
+                gray.iter()
+                    .enumerate()
+                    .map(|(i, &value)| {
+                        let (x, y) = (i % width, i / width);
+                        (value as i32 * 16 >= matrix[(y % 4) * 4 + x % 4] * 255 + 128) as u8
+                    })
+                    .collect()
+            }
+            Binarization::Diffusion => {
+                let rgb: Vec<u8> = gray.iter().flat_map(|&value| [value; 3]).collect();
+                Palette::standard(2).unwrap().dither(&rgb, width)?
+            }
+            Binarization::Dithered(dithering) => {
+                let rgb: Vec<u8> = gray.iter().flat_map(|&value| [value; 3]).collect();
+                Palette::standard(2).unwrap().dither_with(&rgb, width, dithering)?
+            }
+        })
+    }
+}
+
+// Threshold maximizing the variance between black and white values (Otsu's method).
+fn otsu_threshold(gray: &[u8]) -> u8 {
+    let mut histogram = [0u64; 256];
+    for &value in gray {
+        histogram[value as usize] += 1;
+    }
+
+    let total = gray.len() as f64;
+    let sum: f64 = histogram.iter().enumerate().map(|(value, &count)| (value as f64) * (count as f64)).sum();
+    let (mut black_count, mut black_sum) = (0.0, 0.0);
+    let (mut best_threshold, mut best_variance) = (0, -1.0);
+    for threshold in 1..256 {
+        black_count += histogram[threshold - 1] as f64;
+        black_sum += ((threshold - 1) as f64) * (histogram[threshold - 1] as f64);
+        let white_count = total - black_count;
+        if black_count == 0.0 || white_count == 0.0 {
+            continue;
+        }
+
+        let difference = black_sum / black_count - (sum - black_sum) / white_count;
+        let variance = black_count * white_count * difference * difference;
+        if variance > best_variance {
+            best_threshold = threshold;
+            best_variance = variance;
+        }
+    }
+    best_threshold as u8
+}
+
+fn threshold_indices(gray: &[u8], threshold: u8) -> Vec<u8> {
+    gray.iter().map(|&value| (value >= threshold) as u8).collect()
+}
+
+/// Encode gray values as 1-bit monochrome image, e.g. for fax machines, receipt printers and plotters.
+///
+/// Length of `gray` must be equal to the number of pixels.
+pub fn encode_monochrome<W: io::Write>(stream: W, gray: &[u8], image_size: (u16, u16), dpi: (u16, u16), binarization: Binarization) -> io::Result<()> {
+    encode_monochrome_with_options(stream, gray, image_size, dpi, binarization, WriterOptions::default())
+}
+
+/// Encode gray values as 1-bit monochrome image with the given writer options.
+pub fn encode_monochrome_with_options<W: io::Write>(stream: W, gray: &[u8], image_size: (u16, u16), dpi: (u16, u16), binarization: Binarization, options: WriterOptions) -> io::Result<()> {
+    let width = image_size.0 as usize;
+    if gray.len() != width * (image_size.1 as usize) {
+        return user_error("pcx::encode_monochrome: buffer length must be equal to the number of pixels");
+    }
+
+    let indices = binarization.apply(gray, width)?;
+    let mut writer = WriterPlanar::new_with_options(stream, image_size, dpi, 1, &[], options)?;
+    for row in indices.chunks(width) {
+        writer.write_row(row)?;
+    }
+    writer.finish()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{encode_monochrome, otsu_threshold, Binarization};
+    use Dithering;
+    use {Pixels, Reader};
+
+    #[test]
+    fn binarization() {
+        let gray = [0, 50, 100, 150, 200, 250];
+        assert_eq!(Binarization::Threshold(100).apply(&gray, 3).unwrap(), [0, 0, 1, 1, 1, 1]);
+        assert_eq!(Binarization::Threshold(0).apply(&gray, 3).unwrap(), [1; 6]);
+        assert!(Binarization::Otsu.apply(&gray, 4).is_err());
+        assert!(Binarization::Ordered.apply(&[], 0).unwrap().is_empty());
+
+        // Two clusters are separated between them.
+        let clusters = [10, 12, 14, 10, 200, 210, 220, 205];
+        let threshold = otsu_threshold(&clusters);
+        assert!(threshold > 14 && threshold <= 200);
+        assert_eq!(Binarization::Otsu.apply(&clusters, 4).unwrap(), [0, 0, 0, 0, 1, 1, 1, 1]);
+
+        // Black and white stay unchanged, middle gray becomes half white.
+        let dithered = [Binarization::Dithered(Dithering::Ordered { size: 8 }), Binarization::Dithered(Dithering::Noise { seed: 1 })];
+        for &binarization in [Binarization::Ordered, Binarization::Diffusion].iter().chain(&dithered) {
+            assert_eq!(binarization.apply(&[0; 16], 4).unwrap(), [0; 16]);
+            assert_eq!(binarization.apply(&[255; 16], 4).unwrap(), [1; 16]);
+            let white = binarization.apply(&[128; 64], 8).unwrap().iter().filter(|&&index| index == 1).count();
+            assert!((28..=36).contains(&white), "{:?}", binarization);
+        }
+    }
+
+    #[test]
+    fn encode() {
+        let gray: Vec<u8> = (0..(9 * 2)).map(|i| if i % 2 == 0 { 0 } else { 255 }).collect();
+        let mut pcx = Vec::new();
+        encode_monochrome(&mut pcx, &gray, (9, 2), (200, 200), Binarization::Threshold(128)).unwrap();
+
+        let reader = Reader::new(&pcx[..]).unwrap();
+        assert_eq!((reader.header.bit_depth, reader.header.number_of_color_planes), (1, 1));
+        match reader.read_image().unwrap().pixels {
+            Pixels::Paletted { indices, .. } => assert_eq!(indices, gray.iter().map(|&value| value / 255).collect::<Vec<u8>>()),
+            _ => panic!("monochrome image must be paletted"),
+        }
+
+        assert!(encode_monochrome(Vec::new(), &gray, (9, 3), (200, 200), Binarization::Otsu).is_err());
+    }
+}
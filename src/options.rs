@@ -0,0 +1,164 @@
+//! Options controlling how files are read and written.
+use std::time::Duration;
+
+use low_level::header::Version;
+
+/// Options for reading PCX files, pass them to `Reader::new_with_options`.
+///
+/// Use `Options::default()` and override the fields you need, e.g. `Options { strict: false, ..Options::default() }`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Options {
+    /// Reject files violating the specification in ways which could be repaired. Enabled by default.
+    ///
+    /// Real-world files frequently violate the specification yet open fine in old tools. When disabled, the reader repairs
+    /// or ignores such inconsistencies:
+    ///
+    /// * unknown version is treated as version 5 and unknown encoding as RLE,
+    /// * lane length smaller than required by the image width is replaced by the required length,
+    /// * missing marker in front of the 256-color palette is ignored and the last 768 bytes of file are used as the palette,
+    /// * rows missing from truncated files are filled with `fill` by `Reader::read_image()` and `Reader::read_image_rgb()`.
+    pub strict: bool,
+
+    /// Reject files whose version does not support the bit depth and number of color planes stored in the header, e.g. 24-bit
+    /// RGB in version 2.8 file, with `Error::UnsupportedByVersion`. Disabled by default.
+    ///
+    /// Such files are common and decode fine, so by default they are only reported with `Warning::UnsupportedByVersion`.
+    pub strict_version: bool,
+
+    /// Upper bound on heap memory allocated by the reader in bytes, including the image allocated by `Reader::read_image()`.
+    /// Unlimited by default.
+    ///
+    /// Required memory is computed from the header and checked before anything is allocated, files which would exceed the
+    /// bound fail with `Error::LimitExceeded`. Memory allocated by the underlying stream is not counted.
+    pub max_memory: Option<u64>,
+
+    /// Time allowed for decoding the whole image with `Reader::read_image()`, measured from the call. Unlimited by default.
+    ///
+    /// Elapsed time is checked after each row, decoding which takes longer fails with `Error::TimedOut`.
+    pub time_budget: Option<Duration>,
+
+    /// Limits on image dimensions and amount of decoded pixel data. Unlimited by default.
+    pub limits: Limits,
+
+    /// Value of pixels missing from truncated files when `strict` is disabled, zeros by default. Set it to a color which does
+    /// not occur in the images, so that recovered images are visibly marked.
+    pub fill: Fill,
+
+    /// Allocate all scratch buffers of the reader upfront when the header is loaded, failing with `Error::OutOfMemory` instead
+    /// of aborting the process if there is not enough memory, e.g. for servers and plugins embedded in other processes.
+    /// Disabled by default.
+    ///
+    /// Images and rows allocated by the reader are always allocated fallibly. The small fixed-size buffer of the RLE decoder
+    /// and memory allocated by the underlying stream are not covered.
+    pub fallible_allocation: bool,
+}
+
+/// Value of pixels which could not be decoded, part of `Options`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Fill {
+    /// Palette index for paletted images.
+    pub index: u8,
+
+    /// Color as R, G, B, A for other images. Images without color channels get its luma, images without alpha channel ignore alpha.
+    pub color: [u8; 4],
+}
+
+/// Limits for decoding untrusted files, part of `Options`.
+///
+/// Limits are checked when the header is loaded, files which exceed them fail with `Error::LimitExceeded` before any pixel
+/// data is decoded. Number of lanes decoded from the file is fully determined by the header, so hostile RLE data can not
+/// make the reader decode more than `max_decoded_bytes`.
+#[derive(Copy, Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Limits {
+    /// Maximal image width in pixels.
+    pub max_width: Option<u16>,
+
+    /// Maximal image height in pixels.
+    pub max_height: Option<u16>,
+
+    /// Maximal amount of pixel data decoded from the file in bytes, including the padding of lanes.
+    pub max_decoded_bytes: Option<u64>,
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Options {
+            strict: true,
+            strict_version: false,
+            max_memory: None,
+            time_budget: None,
+            limits: Limits::default(),
+            fill: Fill::default(),
+            fallible_allocation: false,
+        }
+    }
+}
+
+/// Options for writing PCX files, pass them to `new_with_options` of the writers.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct WriterOptions {
+    /// Compress pixel data with RLE. Enabled by default.
+    ///
+    /// Uncompressed files are non-standard and larger, but some old tools and game engines only accept them.
+    pub compress: bool,
+
+    /// Position of the top-left corner of the image stored in the header, see `Window`. `(0, 0)` by default.
+    ///
+    /// The image must fit into the 16-bit coordinate space when placed there.
+    pub start: (u16, u16),
+
+    /// Refuse to write files violating the specification. Enabled by default.
+    ///
+    /// Disable it to reproduce historically quirky files: `version` may then be one which does not support the format of the
+    /// image and `lane_length` may be odd. Readers of this crate decode such versions with a warning and reject them if
+    /// `Options::strict_version` is set.
+    pub strict: bool,
+
+    /// File format version stored in the header. Version 5 by default, which supports all formats.
+    pub version: Option<Version>,
+
+    /// Lane length including padding stored in the header. By default lanes are padded to even length as required by the
+    /// specification. It must not be smaller than the length of the pixel data of a lane.
+    pub lane_length: Option<u16>,
+
+    /// Size of the screen in pixels stored in the header of version 5 files, which some DOS viewers use to select a video mode.
+    /// By default the size of the image is stored, or nothing if another `version` is written.
+    pub screen_size: Option<(u16, u16)>,
+
+    /// Trade-off between speed of encoding and size of files, `Profile::Fast` by default.
+    pub profile: Profile,
+}
+
+/// Encoder profile, part of `WriterOptions`. See `benches/profiles.rs` for measurements.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Profile {
+    /// Greedy RLE with lanes padded by zeros. Palettes of `WriterQuantized` and `write_quantized_two_pass()` are built by median
+    /// cut alone and each pixel gets its nearest color.
+    Fast,
+
+    /// Lanes padded by extending their last run, which saves a byte for each lane ending with a run. Images quantized by
+    /// `WriterQuantized` and `write_quantized_two_pass()` are encoded with:
+    ///
+    /// * palettes refined by k-means unless the quantizer has its own refinement set, see `Quantizer::set_refinement()`,
+    /// * ordered dithering at half strength, which breaks up bands between close colors,
+    /// * lookahead encoding, which merges a single pixel between two pixels of the same color into their run if that color
+    ///   is almost as close to it, saving the codes interrupting the run.
+    ///
+    /// Quantized files are a few percent smaller and encoding takes up to three times as long. Dithering raises the mean
+    /// squared error of smooth images while refinement lowers it, use `Palette::dither_with()` for full strength dithering.
+    Small,
+}
+
+impl Default for WriterOptions {
+    fn default() -> Self {
+        WriterOptions {
+            compress: true,
+            start: (0, 0),
+            strict: true,
+            version: None,
+            lane_length: None,
+            screen_size: None,
+            profile: Profile::Fast,
+        }
+    }
+}
@@ -0,0 +1,588 @@
+//! Palette of paletted images.
+use std::collections::HashMap;
+use std::{mem, slice};
+
+use pixels::{Image, Pixels};
+use {ColorSpace, Error, Result};
+
+// Number of colors per row of swatches, see `Palette::render_swatch()`.
+const SWATCH_COLUMNS: usize = 16;
+
+/// Palette of up to 256 RGB colors.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Hash)]
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+}
+
+/// Transparency of paletted images, used when rows are converted to RGBA.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Transparency {
+    /// All colors are opaque.
+    Opaque,
+
+    /// Color with this palette index is fully transparent, other colors are opaque.
+    Index(u8),
+
+    /// Alpha value for each palette entry. Entries beyond the end of the table are opaque.
+    Alpha(Vec<u8>),
+}
+
+/// Result of looking up the closest palette, see `Palette::closest()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct PaletteMatch<'a> {
+    /// Name of the matching palette.
+    pub name: &'a str,
+
+    /// Distance to the matching palette, see `Palette::distance()`.
+    pub distance: f64,
+}
+
+/// Collection of named palettes, so that palettes can be referenced by name (e.g. in configuration files) instead of
+/// binary data.
+///
+/// `PaletteRegistry::default()` contains the built-in standard palettes, see `Palette::standard_palettes()`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct PaletteRegistry {
+    palettes: Vec<(String, Palette)>,
+}
+
+impl Palette {
+    /// Create palette from colors in R, G, B, R, G, B, ... format.
+    ///
+    /// Length of `rgb` must be a multiple of 3 and the palette must not have more than 256 colors.
+    pub fn from_rgb(rgb: &[u8]) -> Result<Self> {
+        if rgb.len() % 3 != 0 {
+            return Err(Error::InvalidUsage("palette length must be a multiple of 3"));
+        }
+        if rgb.len() > 256 * 3 {
+            return Err(Error::InvalidUsage("palette must not have more than 256 colors"));
+        }
+
+        Ok(Palette {
+            colors: rgb.chunks(3).map(|color| [color[0], color[1], color[2]]).collect(),
+        })
+    }
+
+    /// Standard palette used by old files without palette information. Returns `None` if there is no standard palette with
+    /// `number_of_colors` colors.
+    ///
+    /// 2-color palette is black and white, 4-color palette is the high intensity CGA palette 1 (black, cyan, magenta, white),
+    /// 8 and 16-color palettes are the default EGA palette.
+    pub fn standard(number_of_colors: u16) -> Option<Self> {
+        let colors = match number_of_colors {
+            2 => vec![[0x00, 0x00, 0x00], [0xFF, 0xFF, 0xFF]],
+            4 => vec![[0x00, 0x00, 0x00], [0x55, 0xFF, 0xFF], [0xFF, 0x55, 0xFF], [0xFF, 0xFF, 0xFF]],
+            8 | 16 => EGA_PALETTE[..(number_of_colors as usize)].to_vec(),
+            _ => return None,
+        };
+        Some(Palette { colors })
+    }
+
+    /// Default 256-color VGA palette (mode 13h): 16 EGA colors, 16 shades of gray, 9 rings of 24 hues and 8 black entries.
+    pub fn vga() -> Self {
+        // 6-bit levels of hue rings for each intensity and saturation, from the strongest to the weakest color.
+        const RINGS: [[u8; 5]; 9] = [
+            [0, 16, 31, 47, 63],
+            [31, 39, 47, 55, 63],
+            [45, 49, 54, 58, 63],
+            [0, 7, 14, 21, 28],
+            [14, 17, 21, 24, 28],
+            [20, 22, 24, 26, 28],
+            [0, 4, 8, 12, 16],
+            [8, 10, 12, 14, 16],
+            [11, 12, 13, 15, 16],
+        ];
+        const GRAYS: [u8; 16] = [0, 5, 8, 11, 14, 17, 20, 24, 28, 32, 36, 40, 45, 50, 56, 63];
+
+        fn expand(value: u8) -> u8 {
+            (value << 2) | (value >> 4)
+        }
+
+        let mut colors = EGA_PALETTE.to_vec();
+        colors.extend(GRAYS.iter().map(|&gray| [expand(gray); 3]));
+        for levels in &RINGS {
+            // Going around the hue ring starting at blue, one channel changes at a time.
+            let (low, high) = (0, 4);
+            let mut channels = [low, low, high];
+            let steps = [(0, 1), (2, -1), (1, 1), (0, -1), (2, 1), (1, -1)];
+            for &(channel, direction) in &steps {
+                for _ in 0..4 {
+                    colors.push([expand(levels[channels[0]]), expand(levels[channels[1]]), expand(levels[channels[2]])]);
+                    channels[channel] = (channels[channel] as i32 + direction) as usize;
+                }
+            }
+        }
+        colors.extend(vec![[0; 3]; 8]);
+
+        Palette { colors }
+    }
+
+    /// Built-in standard palettes with their names: monochrome, CGA, 8 and 16-color EGA and VGA.
+    pub fn standard_palettes() -> Vec<(&'static str, Palette)> {
+        vec![
+            ("monochrome", Palette::standard(2).unwrap()),
+            ("cga", Palette::standard(4).unwrap()),
+            ("ega-8", Palette::standard(8).unwrap()),
+            ("ega", Palette::standard(16).unwrap()),
+            ("vga", Palette::vga()),
+        ]
+    }
+
+    /// Similarity of two palettes: root mean square difference of color channels of the corresponding entries.
+    ///
+    /// Distance is 0 for equal palettes and at most 255. Returns `None` if palettes have different number of colors.
+    pub fn distance(&self, other: &Palette) -> Option<f64> {
+        if self.len() != other.len() {
+            return None;
+        }
+        if self.is_empty() {
+            return Some(0.0);
+        }
+
+        let sum: u64 = self
+            .iter()
+            .zip(other.iter())
+            .flat_map(|(a, b)| a.iter().zip(b.iter()).map(|(&a, &b)| ((a as i64 - b as i64) * (a as i64 - b as i64)) as u64))
+            .sum();
+        Some(((sum as f64) / ((self.len() * 3) as f64)).sqrt())
+    }
+
+    /// Find the closest of the named `candidates`. Only candidates with the same number of colors are considered.
+    pub fn closest<'a, 'b, I>(&self, candidates: I) -> Option<PaletteMatch<'a>>
+    where
+        I: IntoIterator<Item = (&'a str, &'b Palette)>,
+    {
+        candidates
+            .into_iter()
+            .filter_map(|(name, palette)| self.distance(palette).map(|distance| PaletteMatch { name, distance }))
+            .fold(None, |best: Option<PaletteMatch>, candidate| match best {
+                Some(ref best) if best.distance <= candidate.distance => Some(best.clone()),
+                _ => Some(candidate),
+            })
+    }
+
+    /// Find the closest of the built-in standard palettes, see `standard_palettes()`.
+    pub fn closest_standard(&self) -> Option<PaletteMatch<'static>> {
+        let standard = Palette::standard_palettes();
+        self.closest(standard.iter().map(|&(name, ref palette)| (name, palette)))
+    }
+
+    /// Index of the color closest to `color` in RGB space or `None` if the palette is empty. Ties go to the lower index.
+    pub fn nearest(&self, color: [u8; 3]) -> Option<u8> {
+        let squared_distance = |other: &[u8; 3]| -> u32 { color.iter().zip(other.iter()).map(|(&a, &b)| ((a as i32 - b as i32) * (a as i32 - b as i32)) as u32).sum() };
+        self.iter().enumerate().min_by_key(|&(_, other)| squared_distance(other)).map(|(index, _)| index as u8)
+    }
+
+    /// Index of the color closest to `color` with distances measured in `color_space` or `None` if the palette is empty. Ties
+    /// go to the lower index.
+    pub fn nearest_in(&self, color: [u8; 3], color_space: ColorSpace) -> Option<u8> {
+        let coordinates = color_space.coordinates(color);
+        self.iter().enumerate().min_by_key(|&(_, &other)| color_space.distance(coordinates, color_space.coordinates(other))).map(|(index, _)| index as u8)
+    }
+
+    /// Map RGB values interleaved (R, G, B, R, G, B, ...) to indices of the nearest colors, see `nearest()`.
+    ///
+    /// Length of `rgb` must be a multiple of 3 and the palette must not be empty unless `rgb` is empty.
+    pub fn map_rgb(&self, rgb: &[u8]) -> Result<Vec<u8>> {
+        self.map_rgb_in(rgb, ColorSpace::Rgb)
+    }
+
+    /// Map RGB values interleaved (R, G, B, R, G, B, ...) to indices of the nearest colors in `color_space`, see `nearest_in()`.
+    pub fn map_rgb_in(&self, rgb: &[u8], color_space: ColorSpace) -> Result<Vec<u8>> {
+        if rgb.len() % 3 != 0 {
+            return Err(Error::InvalidUsage("RGB buffer length must be a multiple of 3"));
+        }
+        if self.is_empty() && !rgb.is_empty() {
+            return Err(Error::InvalidUsage("colors can not be mapped to an empty palette"));
+        }
+
+        // Images usually have far fewer distinct colors than pixels.
+        let targets: Vec<[i32; 3]> = self.iter().map(|&color| color_space.coordinates(color)).collect();
+        let mut cache = HashMap::new();
+        Ok(rgb
+            .chunks(3)
+            .map(|color| {
+                let color = [color[0], color[1], color[2]];
+                *cache.entry(color).or_insert_with(|| {
+                    let coordinates = color_space.coordinates(color);
+                    (0..targets.len()).min_by_key(|&index| color_space.distance(coordinates, targets[index])).unwrap() as u8
+                })
+            })
+            .collect())
+    }
+
+    /// Map RGB values interleaved (R, G, B, R, G, B, ...) to indices of palette colors with Floyd-Steinberg dithering: the
+    /// error of each pixel is diffused to its neighbours, so areas of colors missing from the palette keep their average color.
+    ///
+    /// Pixels form rows of `width` pixels, length of `rgb` must be a multiple of `width * 3`. The palette must not be empty
+    /// unless `rgb` is empty.
+    pub fn dither(&self, rgb: &[u8], width: usize) -> Result<Vec<u8>> {
+        if rgb.is_empty() {
+            return Ok(Vec::new());
+        }
+        if width == 0 || rgb.len() % (width * 3) != 0 {
+            return Err(Error::InvalidUsage("RGB buffer length must be a multiple of the row length"));
+        }
+        if self.is_empty() {
+            return Err(Error::InvalidUsage("colors can not be mapped to an empty palette"));
+        }
+
+        // Errors diffused to the current and to the next row multiplied by 16, with one extra pixel on both sides.
+        let mut current = vec![[0i32; 3]; width + 2];
+        let mut next = vec![[0i32; 3]; width + 2];
+        let mut indices = Vec::with_capacity(rgb.len() / 3);
+        for row in rgb.chunks(width * 3) {
+            for (x, color) in row.chunks(3).enumerate() {
+                let mut wanted = [0; 3];
+                for ((wanted, &value), error) in wanted.iter_mut().zip(color).zip(&current[x + 1]) {
+                    *wanted = (value as i32 + error / 16).clamp(0, 255);
+                }
+
+                let index = self.nearest([wanted[0] as u8, wanted[1] as u8, wanted[2] as u8]).unwrap();
+                let actual = self.colors[index as usize];
+                for channel in 0..3 {
+                    let error = wanted[channel] - actual[channel] as i32;
+                    current[x + 2][channel] += error * 7;
+                    next[x][channel] += error * 3;
+                    next[x + 1][channel] += error * 5;
+                    next[x + 2][channel] += error;
+                }
+                indices.push(index);
+            }
+
+            mem::swap(&mut current, &mut next);
+            next.iter_mut().for_each(|error| *error = [0; 3]);
+        }
+        Ok(indices)
+    }
+
+    /// Look up palette `indices` and write their colors to `rgb` as R, G, B, R, G, B, ...
+    ///
+    /// `rgb` length must be equal to the number of indices multiplied by 3. Indices beyond the end of the palette are converted
+    /// to black.
+    pub fn apply(&self, indices: &[u8], rgb: &mut [u8]) -> Result<()> {
+        if rgb.len() != indices.len() * 3 {
+            return Err(Error::BufferLength {
+                expected: indices.len() * 3,
+                actual: rgb.len(),
+            });
+        }
+
+        let mut lookup = [[0; 3]; 256];
+        lookup[..self.len()].copy_from_slice(&self.colors);
+        for (&index, color) in indices.iter().zip(rgb.chunks_mut(3)) {
+            color.copy_from_slice(&lookup[index as usize]);
+        }
+        Ok(())
+    }
+
+    /// Convert palette to colors in R, G, B, R, G, B, ... format.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.colors.iter().flat_map(|color| color.iter().cloned()).collect()
+    }
+
+    /// Number of colors in the palette.
+    #[inline]
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    /// Whether the palette has no colors.
+    #[inline]
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    /// Get color with the given index. Panics if `index` is beyond the end of the palette.
+    #[inline]
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        self.colors[index as usize]
+    }
+
+    /// Get color with the given index or `None` if `index` is beyond the end of the palette.
+    #[inline]
+    pub fn get(&self, index: u8) -> Option<[u8; 3]> {
+        self.colors.get(index as usize).cloned()
+    }
+
+    /// Colors of the palette as a slice.
+    #[inline]
+    pub fn colors(&self) -> &[[u8; 3]] {
+        &self.colors
+    }
+
+    /// Iterate over colors of the palette.
+    #[inline]
+    pub fn iter(&self) -> slice::Iter<'_, [u8; 3]> {
+        self.colors.iter()
+    }
+
+    /// Render the palette as paletted image for visual inspection: one square of `cell_size` pixels per color, 16 colors
+    /// per row in the order of the palette.
+    ///
+    /// Returns `Error::InvalidUsage` if the palette is empty and `Error::InvalidDimensions` if `cell_size` is zero or the
+    /// image would be too large.
+    pub fn render_swatch(&self, cell_size: u16) -> Result<Image> {
+        if self.is_empty() {
+            return Err(Error::InvalidUsage("palette has no colors to render"));
+        }
+
+        let columns = self.len().min(SWATCH_COLUMNS);
+        let rows = self.len().div_ceil(SWATCH_COLUMNS);
+        let cell_size = cell_size as usize;
+        let (width, height) = (columns * cell_size, rows * cell_size);
+        if cell_size == 0 || width > 0xFFFF || height > 0xFFFF {
+            return Err(Error::InvalidDimensions);
+        }
+
+        let indices = (0..height)
+            .flat_map(|y| (0..width).map(move |x| (y / cell_size) * SWATCH_COLUMNS + x / cell_size))
+            .map(|index| if index < self.len() { index as u8 } else { 0 })
+            .collect();
+        Ok(Image {
+            width: width as u16,
+            height: height as u16,
+            pixels: Pixels::Paletted { indices, palette: self.to_rgb() },
+        })
+    }
+}
+
+impl PaletteRegistry {
+    /// Create empty registry.
+    pub fn new() -> Self {
+        PaletteRegistry { palettes: Vec::new() }
+    }
+
+    /// Register palette under the given name. Returns the palette previously registered under this name, if any.
+    pub fn register<S: Into<String>>(&mut self, name: S, palette: Palette) -> Option<Palette> {
+        let name = name.into();
+        match self.palettes.iter_mut().find(|entry| entry.0 == name) {
+            Some(entry) => Some(::std::mem::replace(&mut entry.1, palette)),
+            None => {
+                self.palettes.push((name, palette));
+                None
+            }
+        }
+    }
+
+    /// Remove palette with the given name from the registry.
+    pub fn unregister(&mut self, name: &str) -> Option<Palette> {
+        let position = self.palettes.iter().position(|entry| entry.0 == name)?;
+        Some(self.palettes.remove(position).1)
+    }
+
+    /// Get palette by name.
+    pub fn get(&self, name: &str) -> Option<&Palette> {
+        self.palettes.iter().find(|entry| entry.0 == name).map(|entry| &entry.1)
+    }
+
+    /// Get palette by name, returning `Error::UnknownPalette` if there is no such palette.
+    pub fn lookup(&self, name: &str) -> Result<&Palette> {
+        self.get(name).ok_or_else(|| Error::UnknownPalette(name.to_string()))
+    }
+
+    /// Names of registered palettes in the order of registration.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.palettes.iter().map(|entry| entry.0.as_str())
+    }
+
+    /// Find the registered palette closest to `palette`, see `Palette::closest()`.
+    pub fn closest(&self, palette: &Palette) -> Option<PaletteMatch<'_>> {
+        palette.closest(self.palettes.iter().map(|entry| (entry.0.as_str(), &entry.1)))
+    }
+}
+
+impl Default for PaletteRegistry {
+    fn default() -> Self {
+        PaletteRegistry {
+            palettes: Palette::standard_palettes().into_iter().map(|(name, palette)| (name.to_string(), palette)).collect(),
+        }
+    }
+}
+
+const EGA_PALETTE: [[u8; 3]; 16] = [
+    [0x00, 0x00, 0x00],
+    [0x00, 0x00, 0xAA],
+    [0x00, 0xAA, 0x00],
+    [0x00, 0xAA, 0xAA],
+    [0xAA, 0x00, 0x00],
+    [0xAA, 0x00, 0xAA],
+    [0xAA, 0x55, 0x00],
+    [0xAA, 0xAA, 0xAA],
+    [0x55, 0x55, 0x55],
+    [0x55, 0x55, 0xFF],
+    [0x55, 0xFF, 0x55],
+    [0x55, 0xFF, 0xFF],
+    [0xFF, 0x55, 0x55],
+    [0xFF, 0x55, 0xFF],
+    [0xFF, 0xFF, 0x55],
+    [0xFF, 0xFF, 0xFF],
+];
+
+impl<'a> IntoIterator for &'a Palette {
+    type Item = &'a [u8; 3];
+    type IntoIter = slice::Iter<'a, [u8; 3]>;
+
+    fn into_iter(self) -> Self::IntoIter {
+        self.iter()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Palette, PaletteRegistry};
+    use {ColorSpace, Error};
+
+    #[test]
+    fn rgb_conversion() {
+        let rgb = [1, 2, 3, 4, 5, 6];
+        let palette = Palette::from_rgb(&rgb).unwrap();
+        assert_eq!(palette.len(), 2);
+        assert_eq!(palette.color(1), [4, 5, 6]);
+        assert_eq!(palette.get(2), None);
+        assert_eq!(palette.iter().count(), 2);
+        assert_eq!(palette.to_rgb(), rgb);
+
+        assert!(Palette::from_rgb(&[]).unwrap().is_empty());
+        assert!(matches!(Palette::from_rgb(&[0; 4]), Err(Error::InvalidUsage(_))));
+        assert!(matches!(Palette::from_rgb(&[0; 257 * 3]), Err(Error::InvalidUsage(_))));
+    }
+
+    #[test]
+    fn swatch() {
+        let swatch = Palette::vga().render_swatch(2).unwrap();
+        assert_eq!(swatch.dimensions(), (32, 32));
+        let (indices, _, palette) = swatch.into_raw_parts();
+        assert_eq!(palette.unwrap(), Palette::vga().to_rgb());
+        assert_eq!((indices[0], indices[1], indices[2], indices[32 * 2 + 2]), (0, 0, 1, 17));
+
+        // Last row is filled with the first color.
+        let swatch = Palette::standard(16).unwrap().render_swatch(1).unwrap();
+        assert_eq!(swatch.dimensions(), (16, 1));
+        let swatch = Palette::from_rgb(&[7; 17 * 3]).unwrap().render_swatch(3).unwrap();
+        assert_eq!(swatch.dimensions(), (48, 6));
+
+        assert!(matches!(Palette::vga().render_swatch(0), Err(Error::InvalidDimensions)));
+        assert!(matches!(Palette::vga().render_swatch(0x1000), Err(Error::InvalidDimensions)));
+        assert!(matches!(Palette::default().render_swatch(1), Err(Error::InvalidUsage(_))));
+    }
+
+    #[test]
+    fn standard() {
+        assert_eq!(Palette::standard(2).unwrap().to_rgb(), [0, 0, 0, 255, 255, 255]);
+        assert_eq!(Palette::standard(4).unwrap().color(1), [0x55, 0xFF, 0xFF]);
+        assert_eq!(Palette::standard(8).unwrap().len(), 8);
+        assert_eq!(Palette::standard(16).unwrap().color(6), [0xAA, 0x55, 0x00]);
+        assert_eq!(Palette::standard(256), None);
+    }
+
+    #[test]
+    fn vga() {
+        let vga = Palette::vga();
+        assert_eq!(vga.len(), 256);
+        assert_eq!(&vga.colors()[..16], Palette::standard(16).unwrap().colors());
+        assert_eq!(vga.color(16), [0, 0, 0]);
+        assert_eq!(vga.color(31), [255, 255, 255]);
+        assert_eq!(vga.color(32), [0, 0, 255]);
+        assert_eq!(vga.color(36), [255, 0, 255]);
+        assert_eq!(vga.color(40), [255, 0, 0]);
+        assert_eq!(vga.color(44), [255, 255, 0]);
+        assert_eq!(vga.color(48), [0, 255, 0]);
+        assert_eq!(vga.color(52), [0, 255, 255]);
+        assert_eq!(vga.color(55), [0, 65, 255]);
+        assert_eq!(vga.color(104), [0, 0, 113]);
+        assert_eq!(vga.color(247), [44, 48, 65]);
+        assert_eq!(vga.color(255), [0, 0, 0]);
+    }
+
+    #[test]
+    fn dither() {
+        let ega = Palette::standard(16).unwrap();
+
+        // Colors of the palette are kept.
+        let rgb = ega.to_rgb();
+        assert_eq!(ega.dither(&rgb, 4).unwrap(), (0..16).collect::<Vec<u8>>());
+
+        // Gray between black and dark gray is a mix of both with the right average.
+        let indices = ega.dither(&[0x2A; 8 * 8 * 3], 8).unwrap();
+        let dark_gray = indices.iter().filter(|&&index| index == 8).count();
+        assert_eq!(indices.iter().filter(|&&index| index == 0).count() + dark_gray, 64);
+        assert!((28..=36).contains(&dark_gray));
+
+        assert!(ega.dither(&[0; 9], 2).is_err());
+        assert!(Palette::default().dither(&[0; 3], 1).is_err());
+        assert!(Palette::default().dither(&[], 0).unwrap().is_empty());
+    }
+
+    #[test]
+    fn closest() {
+        let ega = Palette::standard(16).unwrap();
+        let found = ega.closest_standard().unwrap();
+        assert_eq!((found.name, found.distance), ("ega", 0.0));
+
+        let mut rgb = ega.to_rgb();
+        rgb[0] = 6;
+        let found = Palette::from_rgb(&rgb).unwrap().closest_standard().unwrap();
+        assert_eq!(found.name, "ega");
+        assert_eq!(found.distance, (36.0f64 / 48.0).sqrt());
+
+        assert_eq!(Palette::from_rgb(&[0; 9]).unwrap().closest_standard(), None);
+
+        let custom = Palette::from_rgb(&rgb).unwrap();
+        let found = ega.closest(vec![("ega", &ega), ("custom", &custom)]).unwrap();
+        assert_eq!(found.name, "ega");
+        assert_eq!(ega.distance(&Palette::vga()), None);
+    }
+
+    #[test]
+    fn registry() {
+        let mut registry = PaletteRegistry::default();
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["monochrome", "cga", "ega-8", "ega", "vga"]);
+        assert_eq!(registry.get("vga"), Some(&Palette::vga()));
+
+        let doom = Palette::from_rgb(&[0, 0, 0, 31, 23, 11, 23, 15, 7]).unwrap();
+        assert_eq!(registry.register("doom", doom.clone()), None);
+        assert_eq!(registry.lookup("doom").unwrap(), &doom);
+        assert_eq!(registry.closest(&doom).unwrap().name, "doom");
+        assert_eq!(registry.register("doom", Palette::vga()), Some(doom));
+        assert_eq!(registry.names().count(), 6);
+
+        assert_eq!(registry.unregister("doom"), Some(Palette::vga()));
+        assert!(matches!(registry.lookup("doom"), Err(Error::UnknownPalette(ref name)) if name == "doom"));
+        assert!(PaletteRegistry::new().get("vga").is_none());
+    }
+
+    #[test]
+    fn apply() {
+        let palette = Palette::from_rgb(&[1, 2, 3, 4, 5, 6]).unwrap();
+        let mut rgb = [0xFF; 9];
+        palette.apply(&[1, 0, 7], &mut rgb).unwrap();
+        assert_eq!(rgb, [4, 5, 6, 1, 2, 3, 0, 0, 0]);
+        assert!(matches!(palette.apply(&[0], &mut rgb), Err(Error::BufferLength { expected: 3, actual: 9 })));
+    }
+
+    #[test]
+    fn nearest() {
+        let palette = Palette::from_rgb(&[0, 0, 0, 255, 255, 255, 200, 0, 0, 200, 0, 0]).unwrap();
+        assert_eq!(palette.nearest([10, 20, 30]), Some(0));
+        assert_eq!(palette.nearest([150, 20, 30]), Some(2));
+        assert_eq!(palette.nearest([200, 200, 180]), Some(1));
+        assert_eq!(Palette::default().nearest([0, 0, 0]), None);
+
+        assert_eq!(palette.map_rgb(&[250, 250, 250, 190, 0, 9, 1, 1, 1, 250, 250, 250]).unwrap(), [1, 2, 0, 1]);
+        assert!(matches!(palette.map_rgb(&[0; 4]), Err(Error::InvalidUsage(_))));
+        assert!(matches!(Palette::default().map_rgb(&[0; 3]), Err(Error::InvalidUsage(_))));
+        assert!(Palette::default().map_rgb(&[]).unwrap().is_empty());
+
+        // Dark gray is closer to black in linear light.
+        let grays = Palette::from_rgb(&[0, 0, 0, 90, 90, 90]).unwrap();
+        assert_eq!(grays.nearest_in([50; 3], ColorSpace::Rgb), Some(1));
+        assert_eq!(grays.nearest_in([50; 3], ColorSpace::LinearRgb), Some(0));
+        assert_eq!(grays.nearest_in([50; 3], ColorSpace::Lab), Some(1));
+        assert_eq!(grays.map_rgb_in(&[50, 50, 50, 80, 80, 80], ColorSpace::LinearRgb).unwrap(), [0, 1]);
+        assert_eq!(Palette::default().nearest_in([0; 3], ColorSpace::Lab), None);
+        assert_eq!(ColorSpace::Lab.coordinates([255; 3]), [1600, 0, 0]);
+        assert_eq!(ColorSpace::Lab.coordinates([0; 3]), [0, 0, 0]);
+        assert_eq!(ColorSpace::LinearRgb.coordinates([0, 128, 255]), [0, 884, 4095]);
+    }
+}
@@ -0,0 +1,137 @@
+//! Editing of the palette of decoded paletted images.
+use {Error, Result};
+use pixels::{Image, Pixels};
+
+impl Image {
+    /// Swap palette entries `a` and `b`.
+    ///
+    /// If `rewrite_indices` is set, pixels referring to one entry are changed to refer to the other one, so the image looks the
+    /// same and only the order of the palette changes. Otherwise pixels are left unchanged and swap their colors.
+    pub fn swap_palette_entries(&mut self, a: u8, b: u8, rewrite_indices: bool) -> Result<()> {
+        let (indices, palette) = self.paletted_mut()?;
+        check_index(palette, a)?;
+        check_index(palette, b)?;
+
+        for channel in 0..3 {
+            palette.swap((a as usize) * 3 + channel, (b as usize) * 3 + channel);
+        }
+        if rewrite_indices {
+            rewrite(indices, |index| match index {
+                index if index == a => b,
+                index if index == b => a,
+                index => index,
+            });
+        }
+        Ok(())
+    }
+
+    /// Replace color of the palette entry `index`.
+    pub fn set_palette_color(&mut self, index: u8, color: [u8; 3]) -> Result<()> {
+        let (_, palette) = self.paletted_mut()?;
+        check_index(palette, index)?;
+
+        let offset = (index as usize) * 3;
+        palette[offset..(offset + 3)].copy_from_slice(&color);
+        Ok(())
+    }
+
+    /// Insert `color` into the palette at `index`, moving the entries from `index` on one position up.
+    ///
+    /// Pixels referring to the moved entries are changed to follow them, so the image looks the same. `index` may be equal to
+    /// the number of colors to append the color. Fails if the palette already has 256 colors.
+    pub fn insert_palette_entry(&mut self, index: u8, color: [u8; 3]) -> Result<()> {
+        let (indices, palette) = self.paletted_mut()?;
+        if palette.len() >= 256 * 3 {
+            return Err(Error::InvalidUsage("palette already has 256 colors"));
+        }
+        if (index as usize) * 3 > palette.len() {
+            return Err(Error::InvalidUsage("palette index is out of range"));
+        }
+
+        let offset = (index as usize) * 3;
+        palette.splice(offset..offset, color.iter().cloned());
+        rewrite(indices, |other| if other >= index { other.saturating_add(1) } else { other });
+        Ok(())
+    }
+
+    /// Change pixels referring to palette entry `from` to refer to `to` instead, e.g. to merge duplicate colors.
+    ///
+    /// The palette itself is left unchanged, entry `from` becomes unused.
+    pub fn replace_palette_index(&mut self, from: u8, to: u8) -> Result<()> {
+        let (indices, palette) = self.paletted_mut()?;
+        check_index(palette, to)?;
+
+        rewrite(indices, |index| if index == from { to } else { index });
+        Ok(())
+    }
+
+    // Indices and palette of paletted image.
+    fn paletted_mut(&mut self) -> Result<(&mut Vec<u8>, &mut Vec<u8>)> {
+        match self.pixels {
+            Pixels::Paletted { ref mut indices, ref mut palette } => Ok((indices, palette)),
+            Pixels::Rgb(_) | Pixels::GrayAlpha(_) | Pixels::Rgba(_) => Err(Error::NotPaletted),
+        }
+    }
+}
+
+fn check_index(palette: &[u8], index: u8) -> Result<()> {
+    if (index as usize) * 3 >= palette.len() {
+        return Err(Error::InvalidUsage("palette index is out of range"));
+    }
+    Ok(())
+}
+
+// Replace each index using a lookup table built from `map`.
+fn rewrite<F: Fn(u8) -> u8>(indices: &mut [u8], map: F) {
+    let mut table = [0; 256];
+    for (index, entry) in table.iter_mut().enumerate() {
+        *entry = map(index as u8);
+    }
+    for index in indices {
+        *index = table[*index as usize];
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use pixels::{Image, Pixels};
+    use Error;
+
+    fn image() -> Image {
+        Image {
+            width: 4,
+            height: 1,
+            pixels: Pixels::Paletted { indices: vec![0, 1, 2, 1], palette: vec![10, 10, 10, 20, 20, 20, 30, 30, 30] },
+        }
+    }
+
+    #[test]
+    fn edit() {
+        let mut swapped = image();
+        swapped.swap_palette_entries(0, 2, true).unwrap();
+        assert_eq!(swapped.to_rgb(), image().to_rgb());
+        assert_eq!(swapped.pixels, Pixels::Paletted { indices: vec![2, 1, 0, 1], palette: vec![30, 30, 30, 20, 20, 20, 10, 10, 10] });
+
+        let mut swapped = image();
+        swapped.swap_palette_entries(0, 2, false).unwrap();
+        assert_eq!(&swapped.to_rgb()[..3], &[30, 30, 30]);
+
+        let mut inserted = image();
+        inserted.insert_palette_entry(1, [1, 2, 3]).unwrap();
+        assert_eq!(inserted.to_rgb(), image().to_rgb());
+        assert_eq!(inserted.pixels, Pixels::Paletted { indices: vec![0, 2, 3, 2], palette: vec![10, 10, 10, 1, 2, 3, 20, 20, 20, 30, 30, 30] });
+        inserted.insert_palette_entry(4, [4, 5, 6]).unwrap();
+        assert!(matches!(inserted.insert_palette_entry(6, [0; 3]), Err(Error::InvalidUsage(_))));
+
+        let mut edited = image();
+        edited.set_palette_color(1, [0, 0, 0]).unwrap();
+        edited.replace_palette_index(2, 1).unwrap();
+        assert_eq!(edited.to_rgb(), vec![10, 10, 10, 0, 0, 0, 0, 0, 0, 0, 0, 0]);
+        assert!(matches!(edited.set_palette_color(3, [0; 3]), Err(Error::InvalidUsage(_))));
+
+        let mut full = Image { width: 1, height: 1, pixels: Pixels::Paletted { indices: vec![255], palette: vec![0; 256 * 3] } };
+        assert!(matches!(full.insert_palette_entry(0, [0; 3]), Err(Error::InvalidUsage(_))));
+        let mut rgb = Image { width: 1, height: 1, pixels: Pixels::Rgb(vec![0; 3]) };
+        assert!(matches!(rgb.swap_palette_entries(0, 0, true), Err(Error::NotPaletted)));
+    }
+}
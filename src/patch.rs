@@ -0,0 +1,191 @@
+//! Replacing rows of existing PCX files in place.
+use std::io;
+use std::io::Write;
+use std::fs::OpenOptions;
+use std::path::Path;
+
+use {Error, Reader, Result};
+use low_level::Header;
+use low_level::rle::Compressor;
+use low_level::scanline::pack_row;
+
+/// Replace a band of rows starting at `first_row` of the PCX file in `file`, re-encoding only these rows.
+///
+/// `rows` contains whole rows in the layout of `Pixels` for the color format of the image: palette indices for paletted images,
+/// interleaved channel values otherwise. Data following the band, e.g. the 256-color palette, is moved if the length of the
+/// encoded band changes. Returns the new length of the file, which may be shorter than before: the file must then be
+/// truncated to this length, e.g. with `File::set_len()` or by using `patch_rows_file()`.
+///
+/// Files with RLE runs crossing the edges of the band can not be patched.
+pub fn patch_rows<F: io::Read + io::Write + io::Seek>(file: &mut F, first_row: u16, rows: &[u8]) -> Result<u64> {
+    file.seek(io::SeekFrom::Start(0))?;
+    let mut reader = Reader::new(&mut *file)?;
+    let header = reader.header;
+
+    let row_length = (header.size.0 as usize) * header.color_format().channels();
+    if rows.is_empty() || rows.len() % row_length != 0 {
+        return Err(Error::InvalidUsage("rows buffer length must be a multiple of the row length"));
+    }
+    let end_row = (first_row as usize) + rows.len() / row_length;
+    if end_row > header.size.1 as usize {
+        return Err(Error::InvalidUsage("rows extend past the end of the image"));
+    }
+
+    reader.seek_row(first_row)?;
+    let (start, pending_run) = reader.stored_position();
+    if end_row < header.size.1 as usize {
+        reader.seek_row(end_row as u16)?;
+    } else {
+        // Padding of the very last lane is consumed only when present.
+        reader.seek_row(header.size.1 - 1)?;
+        let mut lane = Vec::new();
+        for _ in 0..header.number_of_color_planes {
+            reader.next_lane_raw(&mut lane)?;
+        }
+    }
+    let (end, end_pending_run) = reader.stored_position();
+    if pending_run.0 != 0 || end_pending_run.0 != 0 {
+        return Err(Error::InvalidUsage("RLE runs cross the edges of the band"));
+    }
+
+    let mut band = Vec::new();
+    {
+        let mut compressor = if header.is_compressed {
+            Compressor::new(&mut band, header.lane_length)
+        } else {
+            Compressor::new_uncompressed(&mut band, header.lane_length)
+        };
+        for row in rows.chunks(row_length) {
+            write_row(&mut compressor, &header, row)?;
+        }
+        compressor.finish()?;
+    }
+
+    if band.len() as u64 == end - start {
+        file.seek(io::SeekFrom::Start(start))?;
+        file.write_all(&band)?;
+        file.flush()?;
+        return Ok(file.seek(io::SeekFrom::End(0))?);
+    }
+
+    let mut tail = Vec::new();
+    file.seek(io::SeekFrom::Start(end))?;
+    file.read_to_end(&mut tail)?;
+    file.seek(io::SeekFrom::Start(start))?;
+    file.write_all(&band)?;
+    file.write_all(&tail)?;
+    file.flush()?;
+    Ok(start + (band.len() as u64) + (tail.len() as u64))
+}
+
+/// Replace a band of rows of the PCX file at `path`, see `patch_rows()`.
+pub fn patch_rows_file<P: AsRef<Path>>(path: P, first_row: u16, rows: &[u8]) -> Result<()> {
+    let mut file = OpenOptions::new().read(true).write(true).open(path)?;
+    let length = patch_rows(&mut file, first_row, rows)?;
+    file.set_len(length)?;
+    Ok(())
+}
+
+// Write lanes of one row given as palette indices or interleaved channel values.
+fn write_row<W: io::Write>(compressor: &mut Compressor<W>, header: &Header, row: &[u8]) -> Result<()> {
+    let planes = header.number_of_color_planes as usize;
+    let lanes = if header.bit_depth == 8 {
+        (0..planes).map(|plane| row.iter().skip(plane).step_by(planes).cloned().collect()).collect()
+    } else {
+        pack_row(row, header.bit_depth, header.number_of_color_planes)?
+    };
+    for lane in lanes {
+        compressor.write_all(&lane)?;
+        compressor.pad()?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::patch_rows;
+    use {Reader, WriterPaletted, WriterPlanar, WriterRgb};
+
+    #[test]
+    fn paletted() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        let (mut indices, layout, palette) = image.into_raw_parts();
+        let width = layout.size.0 as usize;
+
+        // Band in the middle, with noise which compresses worse than the original.
+        let band: Vec<u8> = (0..(width * 3)).map(|i| (i * 37) as u8).collect();
+        let mut file = io::Cursor::new(data.to_vec());
+        let length = patch_rows(&mut file, 40, &band).unwrap();
+        assert_eq!(length, file.get_ref().len() as u64);
+        indices[(40 * width)..(43 * width)].copy_from_slice(&band);
+
+        // Band at the end, with uniform color which compresses better.
+        let band = vec![7; width * 2];
+        let length = patch_rows(&mut file, 97, &band).unwrap();
+        let mut patched = file.into_inner();
+        assert!(length < patched.len() as u64);
+        patched.truncate(length as usize);
+        indices[(97 * width)..].copy_from_slice(&band);
+
+        let image = Reader::new(&patched[..]).unwrap().read_image().unwrap();
+        assert_eq!(image.into_raw_parts(), (indices, layout, palette));
+    }
+
+    #[test]
+    fn formats() {
+        let mut rgb = Vec::new();
+        {
+            let mut writer = WriterRgb::new(&mut rgb, (3, 3), (300, 300)).unwrap();
+            for _ in 0..3 {
+                writer.write_row(&[0; 9]).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+        let mut planar = Vec::new();
+        {
+            let mut writer = WriterPlanar::new(&mut planar, (11, 3), (300, 300), 4, &[]).unwrap();
+            for _ in 0..3 {
+                writer.write_row(&[0; 11]).unwrap();
+            }
+            writer.finish().unwrap();
+        }
+
+        let band: Vec<u8> = (0..9).collect();
+        let mut file = io::Cursor::new(rgb);
+        patch_rows(&mut file, 1, &band).unwrap();
+        let mut reader = Reader::new(&file.get_ref()[..]).unwrap();
+        let mut row = [0; 9];
+        for expected in &[[0; 9], [0, 1, 2, 3, 4, 5, 6, 7, 8], [0; 9]] {
+            reader.next_row_rgb(&mut row).unwrap();
+            assert_eq!(&row, expected);
+        }
+
+        let band: Vec<u8> = (0..22).map(|i| (i % 16) as u8).collect();
+        let mut file = io::Cursor::new(planar);
+        patch_rows(&mut file, 0, &band).unwrap();
+        let mut reader = Reader::new(&file.get_ref()[..]).unwrap();
+        let mut row = [0; 11];
+        for expected in band.chunks(11).chain(Some(&[0; 11][..])) {
+            reader.next_row_paletted(&mut row).unwrap();
+            assert_eq!(&row[..], expected);
+        }
+
+        assert!(patch_rows(&mut file, 2, &band).is_err());
+        assert!(patch_rows(&mut file, 0, &[16; 11]).is_err());
+        assert!(patch_rows(&mut file, 0, &[0; 10]).is_err());
+        assert!(patch_rows(&mut io::Cursor::new(Vec::new()), 0, &[0; 3]).is_err());
+
+        let mut paletted = Vec::new();
+        {
+            let mut writer = WriterPaletted::new(&mut paletted, (2, 1), (300, 300)).unwrap();
+            writer.write_row(&[1, 2]).unwrap();
+            writer.write_palette(&[9; 256 * 3]).unwrap();
+        }
+        let mut file = io::Cursor::new(paletted);
+        assert_eq!(patch_rows(&mut file, 0, &[3, 4]).unwrap(), 128 + 2 + 1 + 256 * 3);
+        let image = ::decode(&file.get_ref()[..]).unwrap();
+        assert_eq!(image.pixels, ::Pixels::Paletted { indices: vec![3, 4], palette: vec![9; 256 * 3] });
+    }
+}
@@ -0,0 +1,571 @@
+//! Owned, fully decoded images.
+use std::cmp;
+
+use {Error, Result};
+use low_level::header::ColorFormat;
+
+/// Decoded PCX image stored in memory.
+///
+/// Use `pcx::decode` or `Reader::read_image` to obtain it.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Image {
+    /// Width of the image.
+    pub width: u16,
+
+    /// Height of the image.
+    pub height: u16,
+
+    /// Pixel data.
+    pub pixels: Pixels,
+}
+
+/// Pixel data of the decoded image.
+///
+/// Order of rows is from top to bottom, order of pixels is from left to right.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Pixels {
+    /// Paletted image.
+    Paletted {
+        /// One palette index per pixel, length is equal to `width * height`.
+        indices: Vec<u8>,
+
+        /// Palette colors in R, G, B, R, G, B, ... format. Length is equal to the number of colors multiplied by 3.
+        palette: Vec<u8>,
+    },
+
+    /// 24-bit RGB image with interleaved values (R, G, B, R, G, B, ...), length is equal to `width * height * 3`.
+    Rgb(Vec<u8>),
+
+    /// Grayscale image with alpha channel (gray, alpha, gray, alpha, ...), length is equal to `width * height * 2`.
+    GrayAlpha(Vec<u8>),
+
+    /// 32-bit RGBA image with interleaved values (R, G, B, A, R, G, B, A, ...), length is equal to `width * height * 4`.
+    Rgba(Vec<u8>),
+}
+
+/// One decoded row of the image, yielded by `Reader::rows()`.
+///
+/// Layout of the values is the same as in `Pixels`, order of pixels is from left to right.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Row {
+    /// Palette indices, one per pixel.
+    Paletted(Vec<u8>),
+
+    /// Interleaved RGB values (R, G, B, R, G, B, ...).
+    Rgb(Vec<u8>),
+
+    /// Interleaved gray and alpha values (gray, alpha, gray, alpha, ...).
+    GrayAlpha(Vec<u8>),
+
+    /// Interleaved RGBA values (R, G, B, A, R, G, B, A, ...).
+    Rgba(Vec<u8>),
+}
+
+/// Layout of pixels in buffers filled by `Reader::next_row_into()` and `Reader::read_rows_into()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum PixelFormat {
+    /// One palette index per pixel, only paletted images can be read in this format.
+    Indexed,
+    /// R, G, B, R, G, B, ...
+    Rgb,
+    /// B, G, R, B, G, R, ...
+    Bgr,
+    /// R, G, B, A, R, G, B, A, ...
+    Rgba,
+    /// B, G, R, A, B, G, R, A, ...
+    Bgra,
+}
+
+impl PixelFormat {
+    /// 8-bit palette indices, the same as `PixelFormat::Indexed`.
+    pub const INDEXED8: PixelFormat = PixelFormat::Indexed;
+    /// 24-bit RGB, the same as `PixelFormat::Rgb`.
+    pub const RGB24: PixelFormat = PixelFormat::Rgb;
+    /// 24-bit BGR, the same as `PixelFormat::Bgr`.
+    pub const BGR24: PixelFormat = PixelFormat::Bgr;
+    /// 32-bit RGBA, the same as `PixelFormat::Rgba`.
+    pub const RGBA32: PixelFormat = PixelFormat::Rgba;
+    /// 32-bit BGRA, the same as `PixelFormat::Bgra`.
+    pub const BGRA32: PixelFormat = PixelFormat::Bgra;
+
+    /// Number of bytes per pixel.
+    #[inline]
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            PixelFormat::Indexed => 1,
+            PixelFormat::Rgb | PixelFormat::Bgr => 3,
+            PixelFormat::Rgba | PixelFormat::Bgra => 4,
+        }
+    }
+
+    /// Length in bytes of a row of `width` pixels, e.g. to size static buffers for `Reader::next_row_into()` at compile time.
+    #[inline]
+    pub const fn row_length(self, width: u16) -> usize {
+        (width as usize) * self.bytes_per_pixel()
+    }
+}
+
+/// Layout of pixels in framebuffer dumps accepted by `Image::from_raw()`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum RawFormat {
+    /// One palette index per pixel.
+    Indexed8,
+    /// R, G, B, R, G, B, ...
+    Rgb24,
+    /// B, G, R, B, G, R, ...
+    Bgr24,
+    /// R, G, B, A, R, G, B, A, ...
+    Rgba32,
+    /// Little-endian 16-bit words with 5 bits of red in the most significant bits, 6 bits of green and 5 bits of blue.
+    Rgb565,
+}
+
+impl RawFormat {
+    /// Number of bytes per pixel.
+    #[inline]
+    pub const fn bytes_per_pixel(self) -> usize {
+        match self {
+            RawFormat::Indexed8 => 1,
+            RawFormat::Rgb565 => 2,
+            RawFormat::Rgb24 | RawFormat::Bgr24 => 3,
+            RawFormat::Rgba32 => 4,
+        }
+    }
+}
+
+/// Vertical order of rows in a raw pixel buffer.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Origin {
+    /// First row in the buffer is the top row of the image, as in PCX files.
+    TopLeft,
+    /// First row in the buffer is the bottom row of the image, as in BMP files and OpenGL textures.
+    BottomLeft,
+}
+
+/// Layout of a raw pixel buffer, see `Image::into_raw_parts()` and `Image::from_raw_parts()`.
+///
+/// Values of each pixel are interleaved in the same order as in `Pixels`, `format.channels()` values per pixel.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Layout {
+    /// Width and height of the image.
+    pub size: (u16, u16),
+
+    /// Kind of pixel values.
+    pub format: ColorFormat,
+
+    /// Distance between the starts of consecutive rows in bytes, not less than the width multiplied by the number of channels.
+    pub stride: usize,
+
+    /// Vertical order of rows.
+    pub origin: Origin,
+}
+
+impl Layout {
+    /// Layout of tightly packed rows ordered from top to bottom.
+    pub const fn packed(size: (u16, u16), format: ColorFormat) -> Self {
+        Layout {
+            size,
+            format,
+            stride: (size.0 as usize) * format.channels(),
+            origin: Origin::TopLeft,
+        }
+    }
+
+    /// Length of the values of one row in bytes.
+    #[inline]
+    pub const fn row_length(&self) -> usize {
+        (self.size.0 as usize) * self.format.channels()
+    }
+
+    /// Minimal length of a buffer with this layout. The last row does not need to be padded to the stride.
+    pub const fn buffer_length(&self) -> usize {
+        match self.size.1 {
+            0 => 0,
+            height => self.stride * ((height as usize) - 1) + self.row_length(),
+        }
+    }
+}
+
+/// Order of channels in pixels packed into `u32` words, from the most significant byte to the least significant one.
+///
+/// Byte order of the words in memory depends on the endianness of the target, e.g. `Argb` words are stored as B, G, R, A on
+/// little-endian targets.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ChannelOrder {
+    /// 0xAARRGGBB, the native format of most windowing framebuffers and software rasterizers.
+    Argb,
+    /// 0xAABBGGRR.
+    Abgr,
+    /// 0xRRGGBBAA.
+    Rgba,
+    /// 0xBBGGRRAA.
+    Bgra,
+}
+
+impl ChannelOrder {
+    /// Pack RGBA color into a word.
+    #[inline]
+    pub fn pack(self, rgba: [u8; 4]) -> u32 {
+        let [r, g, b, a] = rgba;
+        let bytes = match self {
+            ChannelOrder::Argb => [a, r, g, b],
+            ChannelOrder::Abgr => [a, b, g, r],
+            ChannelOrder::Rgba => [r, g, b, a],
+            ChannelOrder::Bgra => [b, g, r, a],
+        };
+        u32::from_be_bytes(bytes)
+    }
+
+    /// Unpack word into RGBA color.
+    #[inline]
+    pub fn unpack(self, word: u32) -> [u8; 4] {
+        let bytes = word.to_be_bytes();
+        match self {
+            ChannelOrder::Argb => [bytes[1], bytes[2], bytes[3], bytes[0]],
+            ChannelOrder::Abgr => [bytes[3], bytes[2], bytes[1], bytes[0]],
+            ChannelOrder::Rgba => bytes,
+            ChannelOrder::Bgra => [bytes[2], bytes[1], bytes[0], bytes[3]],
+        }
+    }
+}
+
+impl Image {
+    /// Get width and height of the image.
+    #[inline]
+    pub fn dimensions(&self) -> (u16, u16) {
+        (self.width, self.height)
+    }
+
+    /// Width and height of the image as `u32`.
+    #[inline]
+    pub fn dimensions_u32(&self) -> (u32, u32) {
+        (self.width as u32, self.height as u32)
+    }
+
+    /// Width and height of the image as `usize`, e.g. for indexing pixels.
+    #[inline]
+    pub fn dimensions_usize(&self) -> (usize, usize) {
+        (self.width as usize, self.height as usize)
+    }
+
+    /// Number of pixels in the image.
+    #[inline]
+    pub fn pixel_count(&self) -> u64 {
+        (self.width as u64) * (self.height as u64)
+    }
+
+    /// Whether this image is paletted, otherwise its pixels are RGB, grayscale with alpha or RGBA, see `Pixels`.
+    #[inline]
+    pub fn is_paletted(&self) -> bool {
+        match self.pixels {
+            Pixels::Paletted { .. } => true,
+            Pixels::Rgb(_) | Pixels::GrayAlpha(_) | Pixels::Rgba(_) => false,
+        }
+    }
+
+    /// Convert pixels to interleaved RGB values (R, G, B, R, G, B, ...) looking up paletted pixels in the palette.
+    ///
+    /// Pixels referring to colors beyond the end of the palette are converted to black, alpha channel is dropped.
+    pub fn to_rgb(&self) -> Vec<u8> {
+        match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                let mut rgb = Vec::with_capacity(indices.len() * 3);
+                for &index in indices {
+                    let offset = (index as usize) * 3;
+                    match palette.get(offset..(offset + 3)) {
+                        Some(color) => rgb.extend_from_slice(color),
+                        None => rgb.extend_from_slice(&[0, 0, 0]),
+                    }
+                }
+                rgb
+            }
+            Pixels::Rgb(ref rgb) => rgb.clone(),
+            Pixels::GrayAlpha(ref gray_alpha) => gray_alpha.chunks(2).flat_map(|pixel| vec![pixel[0]; 3]).collect(),
+            Pixels::Rgba(ref rgba) => rgba.chunks(4).flat_map(|pixel| pixel[..3].to_vec()).collect(),
+        }
+    }
+
+    /// Scale the image down to fit into `max_size` preserving the aspect ratio, e.g. for making thumbnails.
+    ///
+    /// Values of each pixel are averaged over the area it covers, except for paletted images where the pixel in the middle of the
+    /// area is taken since palette indices can not be averaged. Images which already fit are returned unchanged.
+    pub fn thumbnail(&self, max_size: (u16, u16)) -> Image {
+        let (width, height) = (self.width as u32, self.height as u32);
+        let (max_width, max_height) = (cmp::max(max_size.0, 1) as u32, cmp::max(max_size.1, 1) as u32);
+        if width <= max_width && height <= max_height {
+            return self.clone();
+        }
+
+        let size = if width * max_height > height * max_width {
+            (max_width, cmp::max((height * max_width + width / 2) / width, 1))
+        } else {
+            (cmp::max((width * max_height + height / 2) / height, 1), max_height)
+        };
+
+        let pixels = match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => Pixels::Paletted {
+                indices: shrink(indices, 1, (width, height), size, false),
+                palette: palette.clone(),
+            },
+            Pixels::Rgb(ref rgb) => Pixels::Rgb(shrink(rgb, 3, (width, height), size, true)),
+            Pixels::GrayAlpha(ref gray_alpha) => Pixels::GrayAlpha(shrink(gray_alpha, 2, (width, height), size, true)),
+            Pixels::Rgba(ref rgba) => Pixels::Rgba(shrink(rgba, 4, (width, height), size, true)),
+        };
+
+        Image {
+            width: size.0 as u16,
+            height: size.1 as u16,
+            pixels,
+        }
+    }
+
+    /// Kind of pixel values of the image.
+    pub fn color_format(&self) -> ColorFormat {
+        match self.pixels {
+            Pixels::Paletted { .. } => ColorFormat::Paletted,
+            Pixels::Rgb(_) => ColorFormat::Rgb,
+            Pixels::GrayAlpha(_) => ColorFormat::GrayAlpha,
+            Pixels::Rgba(_) => ColorFormat::Rgba,
+        }
+    }
+
+    /// Take the image apart into pixel values, their layout and the palette of paletted images, e.g. to hand the buffer over to
+    /// FFI or GPU upload code without copying.
+    ///
+    /// Values are tightly packed and rows are ordered from top to bottom, see `Layout::packed()`.
+    pub fn into_raw_parts(self) -> (Vec<u8>, Layout, Option<Vec<u8>>) {
+        let layout = Layout::packed((self.width, self.height), self.color_format());
+        match self.pixels {
+            Pixels::Paletted { indices, palette } => (indices, layout, Some(palette)),
+            Pixels::Rgb(values) | Pixels::GrayAlpha(values) | Pixels::Rgba(values) => (values, layout, None),
+        }
+    }
+
+    /// Create image from pixel values stored in `buffer` with the given layout, copying them.
+    ///
+    /// `palette` in R, G, B, R, G, B, ... format must be passed for paletted images and only for them. Returns
+    /// `Error::BufferLength` if `buffer` is shorter than `layout.buffer_length()`.
+    pub fn from_raw_parts(buffer: &[u8], layout: Layout, palette: Option<Vec<u8>>) -> Result<Image> {
+        let row_length = layout.row_length();
+        if layout.stride < row_length {
+            return Err(Error::InvalidUsage("stride must not be less than the length of a row"));
+        }
+        if buffer.len() < layout.buffer_length() {
+            return Err(Error::BufferLength {
+                expected: layout.buffer_length(),
+                actual: buffer.len(),
+            });
+        }
+
+        let height = layout.size.1 as usize;
+        let mut values = Vec::with_capacity(row_length * height);
+        for y in 0..height {
+            let row = match layout.origin {
+                Origin::TopLeft => y,
+                Origin::BottomLeft => height - 1 - y,
+            };
+            values.extend_from_slice(&buffer[(row * layout.stride)..(row * layout.stride + row_length)]);
+        }
+
+        let pixels = match (layout.format, palette) {
+            (ColorFormat::Paletted, Some(palette)) => {
+                if palette.len() % 3 != 0 || palette.len() > 256 * 3 {
+                    return Err(Error::InvalidUsage("palette length must be a multiple of 3 not larger than 768"));
+                }
+                Pixels::Paletted { indices: values, palette }
+            }
+            (ColorFormat::Paletted, None) => return Err(Error::InvalidUsage("paletted image requires a palette")),
+            (_, Some(_)) => return Err(Error::InvalidUsage("palette can only be passed for paletted images")),
+            (ColorFormat::Rgb, None) => Pixels::Rgb(values),
+            (ColorFormat::GrayAlpha, None) => Pixels::GrayAlpha(values),
+            (ColorFormat::Rgba, None) => Pixels::Rgba(values),
+        };
+
+        Ok(Image {
+            width: layout.size.0,
+            height: layout.size.1,
+            pixels,
+        })
+    }
+
+    /// Create image from a framebuffer dump with tightly packed rows ordered from top to bottom, converting the pixels.
+    ///
+    /// `RawFormat::Indexed8` gives a paletted image with a grayscale palette of 256 colors, to be replaced with the actual
+    /// palette. `RawFormat::Rgba32` gives an RGBA image, the other formats RGB images. Returns `Error::BufferLength` if `bytes`
+    /// is shorter than the image.
+    pub fn from_raw(width: u16, height: u16, format: RawFormat, bytes: &[u8]) -> Result<Image> {
+        let length = (width as usize) * (height as usize) * format.bytes_per_pixel();
+        if bytes.len() < length {
+            return Err(Error::BufferLength {
+                expected: length,
+                actual: bytes.len(),
+            });
+        }
+
+        let bytes = &bytes[..length];
+        let pixels = match format {
+            RawFormat::Indexed8 => Pixels::Paletted {
+                indices: bytes.to_vec(),
+                palette: (0..=255).flat_map(|value| [value; 3]).collect(),
+            },
+            RawFormat::Rgb24 => Pixels::Rgb(bytes.to_vec()),
+            RawFormat::Bgr24 => Pixels::Rgb(bytes.chunks(3).flat_map(|pixel| [pixel[2], pixel[1], pixel[0]]).collect()),
+            RawFormat::Rgba32 => Pixels::Rgba(bytes.to_vec()),
+            RawFormat::Rgb565 => Pixels::Rgb(
+                bytes
+                    .chunks(2)
+                    .flat_map(|pixel| {
+                        let word = u16::from_le_bytes([pixel[0], pixel[1]]);
+                        let (r, g, b) = ((word >> 11) as u8, ((word >> 5) & 0x3F) as u8, (word & 0x1F) as u8);
+                        // Replicate the high bits into the low ones, so that full intensity becomes 255.
+                        [(r << 3) | (r >> 2), (g << 2) | (g >> 4), (b << 3) | (b >> 2)]
+                    })
+                    .collect(),
+            ),
+        };
+        Ok(Image { width, height, pixels })
+    }
+
+    /// Convert pixels to words with packed channels in the given order, one word per pixel.
+    ///
+    /// Pixels referring to colors beyond the end of the palette are converted to black, images without alpha channel are opaque.
+    pub fn to_packed(&self, order: ChannelOrder) -> Vec<u32> {
+        match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                let mut lookup = [order.pack([0, 0, 0, 0xFF]); 256];
+                for (entry, color) in lookup.iter_mut().zip(palette.chunks(3)) {
+                    *entry = order.pack([color[0], color[1], color[2], 0xFF]);
+                }
+                indices.iter().map(|&index| lookup[index as usize]).collect()
+            }
+            Pixels::Rgb(ref rgb) => rgb.chunks(3).map(|pixel| order.pack([pixel[0], pixel[1], pixel[2], 0xFF])).collect(),
+            Pixels::GrayAlpha(ref gray_alpha) => gray_alpha.chunks(2).map(|pixel| order.pack([pixel[0], pixel[0], pixel[0], pixel[1]])).collect(),
+            Pixels::Rgba(ref rgba) => rgba.chunks(4).map(|pixel| order.pack([pixel[0], pixel[1], pixel[2], pixel[3]])).collect(),
+        }
+    }
+}
+
+// Scale interleaved values down from `size` to `new_size`, either averaging or sampling the area covered by each new pixel.
+fn shrink(values: &[u8], channels: usize, size: (u32, u32), new_size: (u32, u32), average: bool) -> Vec<u8> {
+    // Range of source coordinates covered by the new coordinate.
+    fn span(i: u32, length: u32, new_length: u32) -> (usize, usize) {
+        let start = i * length / new_length;
+        let end = cmp::max((i + 1) * length / new_length, start + 1);
+        (start as usize, end as usize)
+    }
+
+    let width = size.0 as usize;
+    let mut result = Vec::with_capacity((new_size.0 as usize) * (new_size.1 as usize) * channels);
+    for new_y in 0..new_size.1 {
+        let (y0, y1) = span(new_y, size.1, new_size.1);
+        for new_x in 0..new_size.0 {
+            let (x0, x1) = span(new_x, size.0, new_size.0);
+            if !average {
+                let offset = ((y0 + (y1 - y0) / 2) * width + x0 + (x1 - x0) / 2) * channels;
+                result.extend_from_slice(&values[offset..(offset + channels)]);
+                continue;
+            }
+
+            let area = ((x1 - x0) * (y1 - y0)) as u32;
+            for channel in 0..channels {
+                let mut sum = 0u32;
+                for y in y0..y1 {
+                    for x in x0..x1 {
+                        sum += values[(y * width + x) * channels + channel] as u32;
+                    }
+                }
+                result.push(((sum + area / 2) / area) as u8);
+            }
+        }
+    }
+    result
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Image, Layout, Origin, PixelFormat, Pixels, RawFormat};
+    use low_level::header::{lane_proper_length, ColorFormat, Version};
+    use Error;
+
+    // Descriptors usable at compile time, e.g. for static buffers on embedded targets.
+    const ROW: usize = PixelFormat::RGB24.row_length(320);
+    const LAYOUT: Layout = Layout::packed((320, 200), ColorFormat::Rgba);
+    const LANE: u16 = lane_proper_length(13, 1);
+    const TRUE_COLOR: bool = Version::V5.supports_format(8, 3);
+
+    #[test]
+    fn const_descriptors() {
+        let buffer = [0u8; ROW];
+        assert_eq!(buffer.len(), 960);
+        assert_eq!(LAYOUT.buffer_length(), 320 * 200 * 4);
+        assert_eq!((LANE, TRUE_COLOR), (2, true));
+        assert_eq!(PixelFormat::INDEXED8, PixelFormat::Indexed);
+        assert_eq!(PixelFormat::BGRA32.bytes_per_pixel(), 4);
+
+        let image = Image { width: 0xFFFF, height: 2, pixels: Pixels::Rgb(Vec::new()) };
+        assert_eq!((image.dimensions_u32(), image.dimensions_usize(), image.pixel_count()), ((0xFFFF, 2), (0xFFFF, 2), 0x1FFFE));
+    }
+
+    #[test]
+    fn raw_parts() {
+        let image = Image { width: 2, height: 2, pixels: Pixels::GrayAlpha(vec![1, 2, 3, 4, 5, 6, 7, 8]) };
+        let (values, layout, palette) = image.clone().into_raw_parts();
+        assert_eq!(layout, Layout { size: (2, 2), format: ColorFormat::GrayAlpha, stride: 4, origin: Origin::TopLeft });
+        assert_eq!(palette, None);
+        assert_eq!(Image::from_raw_parts(&values, layout, None).unwrap(), image);
+
+        // Bottom-up rows padded to 6 bytes, the last row is not padded.
+        let buffer = [5, 6, 7, 8, 0, 0, 1, 2, 3, 4];
+        let layout = Layout { stride: 6, origin: Origin::BottomLeft, ..layout };
+        assert_eq!(Image::from_raw_parts(&buffer, layout, None).unwrap(), image);
+        assert!(matches!(Image::from_raw_parts(&buffer[..9], layout, None), Err(Error::BufferLength { expected: 10, actual: 9 })));
+        assert!(matches!(Image::from_raw_parts(&buffer, Layout { stride: 3, ..layout }, None), Err(Error::InvalidUsage(_))));
+        assert!(matches!(Image::from_raw_parts(&buffer, layout, Some(vec![0; 3])), Err(Error::InvalidUsage(_))));
+
+        let paletted = Image { width: 3, height: 1, pixels: Pixels::Paletted { indices: vec![0, 1, 0], palette: vec![9; 6] } };
+        let (values, layout, palette) = paletted.clone().into_raw_parts();
+        assert_eq!(palette, Some(vec![9; 6]));
+        assert!(matches!(Image::from_raw_parts(&values, layout, None), Err(Error::InvalidUsage(_))));
+        assert_eq!(Image::from_raw_parts(&values, layout, palette).unwrap(), paletted);
+    }
+
+    #[test]
+    fn thumbnail() {
+        let image = Image {
+            width: 4,
+            height: 2,
+            pixels: Pixels::GrayAlpha(vec![0, 255, 10, 255, 20, 255, 30, 255, 40, 0, 50, 0, 60, 0, 70, 0]),
+        };
+        let thumbnail = image.thumbnail((2, 2));
+        assert_eq!(thumbnail.dimensions(), (2, 1));
+        assert_eq!(thumbnail.pixels, Pixels::GrayAlpha(vec![25, 128, 45, 128]));
+        assert_eq!(image.thumbnail((10, 10)), image);
+
+        let image = Image {
+            width: 3,
+            height: 3,
+            pixels: Pixels::Paletted {
+                indices: (0..9).collect(),
+                palette: vec![0; 27],
+            },
+        };
+        let thumbnail = image.thumbnail((1, 100));
+        assert_eq!(thumbnail.dimensions(), (1, 1));
+        assert_eq!(thumbnail.pixels, Pixels::Paletted { indices: vec![4], palette: vec![0; 27] });
+    }
+
+    #[test]
+    fn from_raw() {
+        let bgr = Image::from_raw(2, 1, RawFormat::Bgr24, &[1, 2, 3, 4, 5, 6, 7]).unwrap();
+        assert_eq!(bgr.pixels, Pixels::Rgb(vec![3, 2, 1, 6, 5, 4]));
+
+        let rgb565 = Image::from_raw(3, 1, RawFormat::Rgb565, &[0x00, 0xF8, 0xE0, 0x07, 0x10, 0x84]).unwrap();
+        assert_eq!(rgb565.pixels, Pixels::Rgb(vec![255, 0, 0, 0, 255, 0, 132, 130, 132]));
+
+        let indexed = Image::from_raw(1, 2, RawFormat::Indexed8, &[9, 200]).unwrap();
+        assert_eq!(indexed.to_rgb(), [9, 9, 9, 200, 200, 200]);
+        assert_eq!(Image::from_raw(1, 1, RawFormat::Rgba32, &[1, 2, 3, 4]).unwrap().pixels, Pixels::Rgba(vec![1, 2, 3, 4]));
+
+        assert!(matches!(Image::from_raw(2, 2, RawFormat::Rgb24, &[0; 11]), Err(Error::BufferLength { expected: 12, actual: 11 })));
+    }
+}
@@ -0,0 +1,82 @@
+//! Import of binary PPM and PGM images, e.g. to convert them to PCX or to compare decoded images with reference images.
+use std::io;
+
+use pixels::{Image, Pixels};
+
+impl Image {
+    /// Parse binary PPM (P6) or PGM (P5) image with maximal value 255. Pixels of PGM images are converted to RGB.
+    ///
+    /// Other formats and malformed or truncated files are reported as `io::ErrorKind::InvalidData`.
+    pub fn from_ppm(data: &[u8]) -> io::Result<Image> {
+        let mut position = 0;
+        let mut fields = Vec::new();
+        while fields.len() < 4 {
+            match data.get(position) {
+                Some(b'#') => {
+                    while data.get(position).is_some_and(|&byte| byte != b'\n') {
+                        position += 1;
+                    }
+                }
+                Some(byte) if byte.is_ascii_whitespace() => position += 1,
+                Some(_) => {
+                    let start = position;
+                    while data.get(position).is_some_and(|byte| !byte.is_ascii_whitespace()) {
+                        position += 1;
+                    }
+                    fields.push(String::from_utf8_lossy(&data[start..position]).into_owned());
+                }
+                None => return Err(invalid_data("PPM: truncated header")),
+            }
+        }
+
+        let channels = match &fields[0][..] {
+            "P5" => 1,
+            "P6" => 3,
+            _ => return Err(invalid_data("PPM: only binary PPM (P6) and PGM (P5) are supported")),
+        };
+        if fields[3] != "255" {
+            return Err(invalid_data("PPM: maximal value must be 255"));
+        }
+        let (width, height): (u16, u16) = match (fields[1].parse(), fields[2].parse()) {
+            (Ok(width), Ok(height)) => (width, height),
+            _ => return Err(invalid_data("PPM: invalid dimensions")),
+        };
+
+        // Exactly one whitespace character separates the header from the pixel data.
+        let start = position + 1;
+        let end = (width as usize)
+            .checked_mul(height as usize)
+            .and_then(|pixels| pixels.checked_mul(channels))
+            .and_then(|length| length.checked_add(start));
+        let pixels = end.and_then(|end| data.get(start..end)).ok_or_else(|| invalid_data("PPM: truncated pixel data"))?;
+        let rgb = match channels {
+            1 => pixels.iter().flat_map(|&value| [value; 3]).collect(),
+            _ => pixels.to_vec(),
+        };
+        Ok(Image { width, height, pixels: Pixels::Rgb(rgb) })
+    }
+}
+
+fn invalid_data(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use pixels::{Image, Pixels};
+
+    #[test]
+    fn from_ppm() {
+        let commented = b"P6 # comment\n2 1 255\n\x01\x02\x03\x04\x05\x06";
+        let image = Image::from_ppm(commented).unwrap();
+        assert_eq!(image, Image { width: 2, height: 1, pixels: Pixels::Rgb(vec![1, 2, 3, 4, 5, 6]) });
+
+        let gray = Image::from_ppm(b"P5\n2 1\n255\n\x07\x09").unwrap();
+        assert_eq!(gray.to_rgb(), [7, 7, 7, 9, 9, 9]);
+
+        for data in &[&b"P3\n2 1 255\n"[..], b"P6\n2 1 255\n\x01", b"P6\n2 1 65535\n", b"P6\n70000 1 255\n", b"P6\n2 1"] {
+            assert_eq!(Image::from_ppm(data).unwrap_err().kind(), io::ErrorKind::InvalidData);
+        }
+    }
+}
@@ -0,0 +1,130 @@
+//! Heuristic identification of the program which produced a file from quirks of its header, e.g. for cataloguing collections
+//! of DOS-era images.
+use low_level::{RawHeader, HEADER_LENGTH};
+use {Error, Result};
+
+// Built-in fingerprints: name of the program and header bytes as (offset, value) pairs.
+const FINGERPRINTS: &[(&str, &[(usize, u8)])] = &[
+    ("PC Paintbrush 2.5", &[(1, 0)]),
+    ("PC Paintbrush 2.8", &[(1, 2)]),
+    ("PC Paintbrush 2.8 without palette", &[(1, 3)]),
+    ("PC Paintbrush for Windows", &[(1, 4)]),
+    ("PC Paintbrush 3.0 or later", &[(1, 5), (68, 1), (69, 0)]),
+    ("PC Paintbrush 3.0 or later, grayscale", &[(1, 5), (68, 2), (69, 0)]),
+];
+
+/// Program recognized by the values of some of the header bytes.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Fingerprint {
+    /// Name of the program.
+    pub producer: String,
+
+    /// Offsets of header bytes and the values the program writes there.
+    pub bytes: Vec<(usize, u8)>,
+}
+
+impl Fingerprint {
+    /// Whether all bytes of the fingerprint match `header`.
+    pub fn matches(&self, header: &RawHeader) -> bool {
+        self.bytes.iter().all(|&(offset, value)| header.bytes()[offset] == value)
+    }
+}
+
+/// Collection of fingerprints of programs writing PCX files.
+///
+/// `ProducerDatabase::default()` contains fingerprints of the ZSoft programs derived from the version numbers and palette
+/// types of the specification. Files of other programs are often indistinguishable from these, so results are guesses.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct ProducerDatabase {
+    fingerprints: Vec<Fingerprint>,
+}
+
+impl ProducerDatabase {
+    /// Create empty database.
+    pub fn new() -> Self {
+        ProducerDatabase { fingerprints: Vec::new() }
+    }
+
+    /// Register fingerprint of `producer` writing `bytes` given as (offset, value) pairs, e.g. nonzero filler bytes with
+    /// offsets from 74 to 127. There must be at least one byte and offsets must be less than `HEADER_LENGTH`.
+    pub fn register<S: Into<String>>(&mut self, producer: S, bytes: &[(usize, u8)]) -> Result<()> {
+        if bytes.is_empty() {
+            return Err(Error::InvalidUsage("fingerprint must contain at least one byte"));
+        }
+        if bytes.iter().any(|&(offset, _)| offset >= HEADER_LENGTH) {
+            return Err(Error::InvalidUsage("fingerprint offsets must be within the header"));
+        }
+        self.fingerprints.push(Fingerprint { producer: producer.into(), bytes: bytes.to_vec() });
+        Ok(())
+    }
+
+    /// Registered fingerprints in the order of registration.
+    pub fn fingerprints(&self) -> &[Fingerprint] {
+        &self.fingerprints
+    }
+
+    /// Name of the program which most probably produced a file with `header` or `None` if no fingerprint matches.
+    ///
+    /// The matching fingerprint with the most bytes wins, ties go to the one registered first.
+    pub fn probable_producer(&self, header: &RawHeader) -> Option<&str> {
+        self.fingerprints
+            .iter()
+            .filter(|fingerprint| fingerprint.matches(header))
+            .fold(None, |best: Option<&Fingerprint>, candidate| match best {
+                Some(best) if best.bytes.len() >= candidate.bytes.len() => Some(best),
+                _ => Some(candidate),
+            })
+            .map(|fingerprint| fingerprint.producer.as_str())
+    }
+}
+
+impl Default for ProducerDatabase {
+    fn default() -> Self {
+        let mut database = ProducerDatabase::new();
+        for &(producer, bytes) in FINGERPRINTS {
+            database.register(producer, bytes).unwrap();
+        }
+        database
+    }
+}
+
+impl RawHeader {
+    /// Name of the program which most probably produced the file according to the built-in fingerprints, see
+    /// `ProducerDatabase`.
+    pub fn probable_producer(&self) -> Option<String> {
+        ProducerDatabase::default().probable_producer(self).map(str::to_string)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::ProducerDatabase;
+    use low_level::{Header, RawHeader};
+    use Error;
+
+    #[test]
+    fn producers() {
+        let mut bytes = [0; 128];
+        Header::new_rgb(4, 4).unwrap().save(&mut &mut bytes[..]).unwrap();
+        let raw = RawHeader::new(bytes);
+        assert_eq!(raw.probable_producer().unwrap(), "PC Paintbrush 3.0 or later");
+
+        bytes[1] = 2;
+        assert_eq!(RawHeader::new(bytes).probable_producer().unwrap(), "PC Paintbrush 2.8");
+        bytes[1] = 7;
+        assert_eq!(RawHeader::new(bytes).probable_producer(), None);
+
+        // More specific fingerprints win.
+        bytes[1] = 5;
+        bytes[100] = 0x42;
+        let mut database = ProducerDatabase::default();
+        database.register("Game editor", &[(1, 5), (68, 1), (69, 0), (100, 0x42)]).unwrap();
+        assert_eq!(database.probable_producer(&RawHeader::new(bytes)), Some("Game editor"));
+        assert_eq!(database.probable_producer(&raw), Some("PC Paintbrush 3.0 or later"));
+        assert_eq!(database.fingerprints().len(), 7);
+        assert!(ProducerDatabase::new().probable_producer(&raw).is_none());
+
+        assert!(matches!(database.register("empty", &[]), Err(Error::InvalidUsage(_))));
+        assert!(matches!(database.register("outside", &[(128, 0)]), Err(Error::InvalidUsage(_))));
+    }
+}
@@ -0,0 +1,467 @@
+//! Color quantization for writing RGB images as paletted ones.
+use std::cmp;
+use std::collections::HashMap;
+
+use {Error, Palette, Result};
+
+/// Builds palettes of limited size for RGB images using median cut.
+///
+/// Images with no more distinct colors than allowed get a palette of exactly these colors, so they are converted losslessly.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Quantizer {
+    max_colors: u16,
+
+    // Colors of the slots locked by `lock()`.
+    locked: [Option<[u8; 3]>; 256],
+
+    // Maximal number of k-means iterations refining the median cut palette.
+    refinement: u8,
+
+    // Only every `sample_rate`-th pixel is counted by `palette()`.
+    sample_rate: u16,
+
+    color_space: ColorSpace,
+}
+
+/// Space in which `Quantizer` and `Palette::nearest_in()` measure distances between colors.
+///
+/// Distances are computed from integer coordinates, so results are the same on every machine.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum ColorSpace {
+    /// Plain RGB, all channels weigh the same.
+    Rgb,
+
+    /// RGB with channels weighted 2, 4 and 3 by how sensitive the eye is to their differences, which keeps more shades of green
+    /// and fewer of blue.
+    WeightedRgb,
+
+    /// RGB with the sRGB gamma curve removed, so distances follow the amount of light instead of the stored values. Dark shades
+    /// are closer together than in plain RGB.
+    LinearRgb,
+
+    /// CIELAB with the D65 white point, distances roughly follow perceived differences. Noticeably better than plain RGB for
+    /// palettes of few colors, e.g. 16-color conversions, at the cost of slower conversion.
+    Lab,
+}
+
+impl ColorSpace {
+    // Weights of squared coordinate differences.
+    fn weights(self) -> [u32; 3] {
+        match self {
+            ColorSpace::WeightedRgb => [2, 4, 3],
+            _ => [1, 1, 1],
+        }
+    }
+
+    // Coordinates of `color` in the color space. Linear values are scaled to 0 to 4095, Lab values are multiplied by 16.
+    pub(crate) fn coordinates(self, color: [u8; 3]) -> [i32; 3] {
+        match self {
+            ColorSpace::Rgb | ColorSpace::WeightedRgb => color.map(|value| value as i32),
+            ColorSpace::LinearRgb => color.map(|value| (linearize(value) * 4095.0).round() as i32),
+            ColorSpace::Lab => {
+                let [r, g, b] = color.map(linearize);
+                // sRGB to XYZ relative to the D65 white point.
+                let xyz = [
+                    (0.4124 * r + 0.3576 * g + 0.1805 * b) / 0.95047,
+                    0.2126 * r + 0.7152 * g + 0.0722 * b,
+                    (0.0193 * r + 0.1192 * g + 0.9505 * b) / 1.08883,
+                ];
+                let [x, y, z] = xyz.map(|t| if t > 0.008856 { t.cbrt() } else { 7.787 * t + 16.0 / 116.0 });
+                [116.0 * y - 16.0, 500.0 * (x - y), 200.0 * (y - z)].map(|value| (value * 16.0).round() as i32)
+            }
+        }
+    }
+
+    // Weighted squared distance of two colors given by their coordinates.
+    pub(crate) fn distance(self, a: [i32; 3], b: [i32; 3]) -> u32 {
+        let weights = self.weights();
+        (0..3).map(|channel| weights[channel] * (a[channel] - b[channel]).pow(2) as u32).sum()
+    }
+}
+
+// Value of the sRGB channel as linear light between 0 and 1.
+fn linearize(value: u8) -> f64 {
+    let value = value as f64 / 255.0;
+    if value <= 0.04045 {
+        value / 12.92
+    } else {
+        ((value + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+/// Numbers of pixels of each distinct color, collected incrementally so that palettes can be built for images which are not
+/// held in memory, see `Quantizer::palette_from_histogram()`.
+#[derive(Clone, Debug, Default, PartialEq, Eq)]
+pub struct ColorHistogram {
+    counts: HashMap<[u8; 3], u64>,
+}
+
+impl ColorHistogram {
+    /// Create empty histogram.
+    pub fn new() -> Self {
+        ColorHistogram::default()
+    }
+
+    /// Count pixels with RGB values interleaved (R, G, B, R, G, B, ...). Length of `rgb` must be a multiple of 3.
+    pub fn add_rgb(&mut self, rgb: &[u8]) -> Result<()> {
+        if rgb.len() % 3 != 0 {
+            return Err(Error::InvalidUsage("RGB buffer length must be a multiple of 3"));
+        }
+        self.count(rgb);
+        Ok(())
+    }
+
+    fn count(&mut self, rgb: &[u8]) {
+        for color in rgb.chunks(3) {
+            *self.counts.entry([color[0], color[1], color[2]]).or_insert(0) += 1;
+        }
+    }
+
+    /// Number of distinct colors.
+    pub fn len(&self) -> usize {
+        self.counts.len()
+    }
+
+    /// Whether no pixels were counted.
+    pub fn is_empty(&self) -> bool {
+        self.counts.is_empty()
+    }
+}
+
+impl Quantizer {
+    /// Create quantizer producing palettes of at most `max_colors` colors, which must be between 1 and 256.
+    pub fn new(max_colors: u16) -> Result<Self> {
+        if max_colors == 0 || max_colors > 256 {
+            return Err(Error::InvalidUsage("number of colors must be between 1 and 256"));
+        }
+        Ok(Quantizer { max_colors, ..Quantizer::default() })
+    }
+
+    /// Maximal number of colors in the produced palettes.
+    pub fn max_colors(&self) -> u16 {
+        self.max_colors
+    }
+
+    /// Lock palette slot `index` to `color`, e.g. index 0 for the transparent color or slots of user interface colors required
+    /// by an engine.
+    ///
+    /// Produced palettes contain `color` at `index` and the other colors in the remaining slots, extended with black colors up
+    /// to the highest locked index. Locked slots count towards `max_colors()`, pixels of locked colors do not take up other
+    /// slots.
+    pub fn lock(&mut self, index: u8, color: [u8; 3]) {
+        self.locked[index as usize] = Some(color);
+    }
+
+    /// Release slot locked by `lock()`.
+    pub fn unlock(&mut self, index: u8) {
+        self.locked[index as usize] = None;
+    }
+
+    /// Locked slots with their colors in the order of indices.
+    pub fn locked(&self) -> impl Iterator<Item = (u8, [u8; 3])> + '_ {
+        self.locked.iter().enumerate().filter_map(|(index, color)| color.map(|color| (index as u8, color)))
+    }
+
+    /// Refine the palette produced by median cut with up to `iterations` iterations of k-means, which moves each color to the
+    /// average of the pixels mapped to it. Iteration stops early once no color moves. Gives closer colors at the cost of a slower
+    /// `palette()`. No refinement by default.
+    pub fn set_refinement(&mut self, iterations: u8) {
+        self.refinement = iterations;
+    }
+
+    /// Maximal number of refinement iterations set by `set_refinement()`.
+    pub fn refinement(&self) -> u8 {
+        self.refinement
+    }
+
+    /// Count only every `rate`-th pixel when building palettes with `palette()`, e.g. 4 to build palettes of large images about
+    /// four times faster. Colors of rare pixels may be missed. All pixels are counted by default, `rate` of 0 is treated as 1.
+    pub fn set_sample_rate(&mut self, rate: u16) {
+        self.sample_rate = cmp::max(rate, 1);
+    }
+
+    /// Sample rate set by `set_sample_rate()`.
+    pub fn sample_rate(&self) -> u16 {
+        self.sample_rate
+    }
+
+    /// Set the space in which distances between colors are measured when splitting the colors, refining the palette and
+    /// mapping pixels to it in `WriterQuantized`. `ColorSpace::Rgb` by default.
+    pub fn set_color_space(&mut self, color_space: ColorSpace) {
+        self.color_space = color_space;
+    }
+
+    /// Color space set by `set_color_space()`.
+    pub fn color_space(&self) -> ColorSpace {
+        self.color_space
+    }
+
+    /// Build palette for the image with RGB values interleaved (R, G, B, R, G, B, ...).
+    ///
+    /// Length of `rgb` must be a multiple of 3, otherwise `Error::InvalidUsage` is returned. Palette is empty if `rgb` is empty
+    /// and no slots are locked.
+    pub fn palette(&self, rgb: &[u8]) -> Result<Palette> {
+        if rgb.len() % 3 != 0 {
+            return Err(Error::InvalidUsage("RGB buffer length must be a multiple of 3"));
+        }
+
+        let mut histogram = ColorHistogram::new();
+        if self.sample_rate > 1 {
+            let sampled: Vec<u8> = rgb.chunks(3).step_by(self.sample_rate as usize).flatten().cloned().collect();
+            histogram.add_rgb(&sampled)?;
+        } else {
+            histogram.add_rgb(rgb)?;
+        }
+        Ok(self.palette_from_histogram(&histogram))
+    }
+
+    /// Build palette for the pixels counted in `histogram`, the same as `palette()` for these pixels.
+    pub fn palette_from_histogram(&self, histogram: &ColorHistogram) -> Palette {
+        let locked: Vec<(u8, [u8; 3])> = self.locked().collect();
+        let free_colors = (self.max_colors as usize).saturating_sub(locked.len());
+        let colors = median_cut(&histogram.counts, free_colors, self.refinement, self.color_space, |color| locked.iter().any(|&(_, locked)| locked == color));
+
+        // Fill free slots in order, then extend up to the highest locked index.
+        let length = cmp::max(colors.len() + locked.len(), locked.last().map_or(0, |&(index, _)| index as usize + 1));
+        let mut palette = vec![[0; 3]; length];
+        for &(index, color) in &locked {
+            palette[index as usize] = color;
+        }
+        let free = (0..length).filter(|&index| self.locked[index].is_none());
+        for (index, color) in free.zip(colors) {
+            palette[index] = color;
+        }
+
+        let rgb: Vec<u8> = palette.iter().flat_map(|color| color.iter().cloned()).collect();
+        Palette::from_rgb(&rgb).expect("median cut produces at most 256 colors")
+    }
+}
+
+impl Default for Quantizer {
+    /// Quantizer producing palettes of up to 256 colors.
+    fn default() -> Self {
+        Quantizer { max_colors: 256, locked: [None; 256], refinement: 0, sample_rate: 1, color_space: ColorSpace::Rgb }
+    }
+}
+
+// Color with its coordinates in the color space and number of pixels.
+type ColorCount = ([i32; 3], [u8; 3], u64);
+
+// Reduce colors of the histogram to at most `max_colors` colors sorted by value, refined by up to `refinement` iterations of
+// k-means. Boxes are split along the coordinates of `color_space`, colors for which `excluded` returns `true` are ignored.
+fn median_cut<F: Fn([u8; 3]) -> bool>(histogram: &HashMap<[u8; 3], u64>, max_colors: usize, refinement: u8, color_space: ColorSpace, excluded: F) -> Vec<[u8; 3]> {
+    // Sorted so that the result does not depend on the iteration order of the hash map.
+    let mut colors: Vec<ColorCount> = histogram
+        .iter()
+        .filter(|&(&color, _)| !excluded(color))
+        .map(|(&color, &count)| (color_space.coordinates(color), color, count))
+        .collect();
+    colors.sort_unstable();
+    let weights = color_space.weights();
+
+    let mut boxes = Vec::new();
+    if !colors.is_empty() && max_colors > 0 {
+        boxes.push(0..colors.len());
+    }
+    while boxes.len() < max_colors {
+        // Split the box with the widest channel range, preferring more populated boxes on ties.
+        let widest = boxes
+            .iter()
+            .enumerate()
+            .filter(|&(_, range)| range.len() > 1)
+            .max_by_key(|&(_, range)| {
+                let (_, extent) = widest_channel(&colors[range.clone()], weights);
+                (extent, colors[range.clone()].iter().map(|&(_, _, count)| count).sum::<u64>())
+            })
+            .map(|(i, _)| i);
+        let i = match widest {
+            Some(i) => i,
+            None => break,
+        };
+
+        let range = boxes[i].clone();
+        let colors = &mut colors[range.clone()];
+        let (channel, _) = widest_channel(colors, weights);
+        colors.sort_by_key(|&(coordinates, _, _)| coordinates[channel]);
+
+        // Split at the weighted median, keeping both halves non-empty.
+        let total: u64 = colors.iter().map(|&(_, _, count)| count).sum();
+        let mut accumulated = 0;
+        let mut split = 1;
+        for (j, &(_, _, count)) in colors.iter().enumerate().take(colors.len() - 1) {
+            accumulated += count;
+            split = j + 1;
+            if accumulated * 2 >= total {
+                break;
+            }
+        }
+
+        boxes[i] = range.start..(range.start + split);
+        boxes.push((range.start + split)..range.end);
+    }
+
+    let mut palette = Vec::with_capacity(boxes.len());
+    for range in boxes {
+        let mut sum = [0u64; 3];
+        let mut total = 0;
+        for &(_, color, count) in &colors[range] {
+            for (sum, &value) in sum.iter_mut().zip(color.iter()) {
+                *sum += (value as u64) * count;
+            }
+            total += count;
+        }
+        palette.push(sum.map(|sum| ((sum + total / 2) / total) as u8));
+    }
+    for _ in 0..refinement {
+        if !refine(&mut palette, &colors, color_space) {
+            break;
+        }
+    }
+    palette.sort_unstable();
+    palette
+}
+
+// One iteration of k-means: move each palette color to the weighted average of the colors nearest to it. Returns whether any
+// color moved.
+fn refine(palette: &mut [[u8; 3]], colors: &[ColorCount], color_space: ColorSpace) -> bool {
+    let targets: Vec<[i32; 3]> = palette.iter().map(|&color| color_space.coordinates(color)).collect();
+    let mut sums = vec![([0u64; 3], 0u64); palette.len()];
+    for &(coordinates, color, count) in colors {
+        let nearest = (0..palette.len()).min_by_key(|&i| color_space.distance(targets[i], coordinates)).unwrap();
+        let (ref mut sum, ref mut total) = sums[nearest];
+        for (sum, &value) in sum.iter_mut().zip(color.iter()) {
+            *sum += (value as u64) * count;
+        }
+        *total += count;
+    }
+    let mut moved = false;
+    for (color, &(sum, total)) in palette.iter_mut().zip(&sums) {
+        if total != 0 {
+            let average = sum.map(|sum| ((sum + total / 2) / total) as u8);
+            moved |= *color != average;
+            *color = average;
+        }
+    }
+    moved
+}
+
+// Channel with the largest weighted range of coordinates and the range.
+fn widest_channel(colors: &[ColorCount], weights: [u32; 3]) -> (usize, u32) {
+    let mut min = [i32::MAX; 3];
+    let mut max = [i32::MIN; 3];
+    for &(coordinates, _, _) in colors {
+        for channel in 0..3 {
+            min[channel] = min[channel].min(coordinates[channel]);
+            max[channel] = max[channel].max(coordinates[channel]);
+        }
+    }
+    (0..3).map(|channel| (channel, weights[channel] * (max[channel] - min[channel]) as u32)).max_by_key(|&(channel, extent)| (extent, 2 - channel)).unwrap()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{ColorSpace, Quantizer};
+    use Error;
+
+    #[test]
+    fn exact() {
+        let rgb = [9, 9, 9, 1, 2, 3, 9, 9, 9, 200, 0, 0];
+        let palette = Quantizer::default().palette(&rgb).unwrap();
+        assert_eq!(palette.to_rgb(), [1, 2, 3, 9, 9, 9, 200, 0, 0]);
+        assert!(Quantizer::default().palette(&[]).unwrap().is_empty());
+        assert!(matches!(Quantizer::default().palette(&[0; 4]), Err(Error::InvalidUsage(_))));
+        assert!(matches!(Quantizer::new(0), Err(Error::InvalidUsage(_))));
+        assert!(matches!(Quantizer::new(257), Err(Error::InvalidUsage(_))));
+    }
+
+    #[test]
+    fn median_cut() {
+        // Two clusters of reds and blues.
+        let rgb: Vec<u8> = (0..20u8).flat_map(|i| if i < 10 { vec![250 - i, 0, 0] } else { vec![0, 0, 230 + i] }).collect();
+        let palette = Quantizer::new(2).unwrap().palette(&rgb).unwrap();
+        assert_eq!(palette.to_rgb(), [0, 0, 245, 246, 0, 0]);
+
+        let palette = Quantizer::new(16).unwrap().palette(&rgb).unwrap();
+        assert_eq!(palette.len(), 16);
+
+        let gray: Vec<u8> = (0..=255u8).flat_map(|i| vec![i; 3]).collect();
+        let palette = Quantizer::new(4).unwrap().palette(&gray).unwrap();
+        assert_eq!(palette.to_rgb(), [32, 32, 32, 96, 96, 96, 160, 160, 160, 224, 224, 224]);
+    }
+
+    #[test]
+    fn refinement() {
+        // Many dark pixels and few bright ones, median cut splits the range of values evenly.
+        let gray: Vec<u8> = (0..100u8).map(|i| if i < 90 { i % 10 } else { 150 + i }).flat_map(|value| vec![value; 3]).collect();
+        let error = |palette: &::Palette| -> u32 {
+            gray.chunks(3).map(|pixel| {
+                let nearest = palette.color(palette.nearest([pixel[0]; 3]).unwrap());
+                (nearest[0] as i32 - pixel[0] as i32).pow(2) as u32
+            }).sum()
+        };
+
+        let mut quantizer = Quantizer::new(4).unwrap();
+        let coarse = quantizer.palette(&gray).unwrap();
+        quantizer.set_refinement(8);
+        assert_eq!(quantizer.refinement(), 8);
+        let refined = quantizer.palette(&gray).unwrap();
+        assert_eq!(refined.len(), 4);
+        assert!(error(&refined) <= error(&coarse), "{:?} {:?}", coarse.colors(), refined.colors());
+
+        // Exact palettes stay exact.
+        let rgb = [9, 9, 9, 1, 2, 3, 200, 0, 0];
+        assert_eq!(quantizer.palette(&rgb).unwrap().to_rgb(), [1, 2, 3, 9, 9, 9, 200, 0, 0]);
+    }
+
+    #[test]
+    fn locked() {
+        let rgb = [9, 9, 9, 1, 2, 3, 255, 0, 255, 200, 0, 0];
+        let mut quantizer = Quantizer::new(4).unwrap();
+        quantizer.lock(0, [255, 0, 255]);
+        quantizer.lock(6, [255; 3]);
+        assert_eq!(quantizer.locked().collect::<Vec<_>>(), [(0, [255, 0, 255]), (6, [255; 3])]);
+
+        // Locked color present in the image does not take up another slot.
+        let palette = quantizer.palette(&rgb).unwrap();
+        assert_eq!(palette.len(), 7);
+        assert_eq!(palette.color(0), [255, 0, 255]);
+        assert_eq!(palette.color(6), [255; 3]);
+        assert_eq!(palette.colors()[1..3], [[5, 6, 6], [200, 0, 0]]);
+        assert_eq!(palette.colors()[3..6], [[0; 3]; 3]);
+
+        quantizer.unlock(6);
+        assert_eq!(quantizer.palette(&rgb).unwrap().colors(), [[255, 0, 255], [1, 2, 3], [9, 9, 9], [200, 0, 0]]);
+        assert_eq!(quantizer.palette(&[]).unwrap().colors(), [[255, 0, 255]]);
+    }
+
+    #[test]
+    fn knobs() {
+        // Every other pixel is black.
+        let rgb: Vec<u8> = (0..40u8).flat_map(|i| if i % 2 == 0 { vec![0; 3] } else { vec![100 + i, 0, 0] }).collect();
+        let mut quantizer = Quantizer::new(8).unwrap();
+        quantizer.set_sample_rate(2);
+        assert_eq!(quantizer.sample_rate(), 2);
+        assert_eq!(quantizer.palette(&rgb).unwrap().colors(), [[0; 3]]);
+        quantizer.set_sample_rate(0);
+        assert_eq!(quantizer.sample_rate(), 1);
+        assert_eq!(quantizer.palette(&rgb).unwrap().len(), 8);
+
+        // Weighted green differences are split before larger blue ones.
+        let rgb = [0, 0, 0, 0, 0, 90, 0, 80, 0];
+        let mut quantizer = Quantizer::new(2).unwrap();
+        assert_eq!(quantizer.palette(&rgb).unwrap().colors(), [[0, 0, 90], [0, 40, 0]]);
+        quantizer.set_color_space(ColorSpace::WeightedRgb);
+        assert_eq!(quantizer.color_space(), ColorSpace::WeightedRgb);
+        assert_eq!(quantizer.palette(&rgb).unwrap().colors(), [[0, 0, 45], [0, 80, 0]]);
+
+        // Refinement in a perceptual space moves the boundary between dark and bright shades.
+        let gray: Vec<u8> = (0..=255u8).flat_map(|i| vec![i; 3]).collect();
+        let mut quantizer = Quantizer::new(2).unwrap();
+        quantizer.set_refinement(8);
+        let rgb_palette = quantizer.palette(&gray).unwrap();
+        quantizer.set_color_space(ColorSpace::Lab);
+        let lab_palette = quantizer.palette(&gray).unwrap();
+        assert_eq!(rgb_palette.colors(), [[64; 3], [192; 3]]);
+        assert_eq!(lab_palette.colors(), [[61; 3], [189; 3]]);
+    }
+}
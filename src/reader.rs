@@ -1,40 +1,215 @@
-use std::io;
+use std::{cmp, io, mem, ops};
 use std::path::Path;
 use std::fs::File;
+use std::time::Instant;
+#[cfg(feature = "parallel")]
+use std::thread;
 use byteorder::ReadBytesExt;
 
-use user_error;
-use low_level::{Header, PALETTE_START};
-use low_level::rle::Decompressor;
+use {Error, Options, Result, Warning};
+use pixels::{ChannelOrder, Image, PixelFormat, Pixels, Row};
+use palette::{Palette, Transparency};
+use transform::RowTransform;
+use watch::{DecoderState, Watch, Watchpoint};
+use low_level::{Header, RawHeader, HEADER_LENGTH, PALETTE_START};
+use low_level::header::{ColorFormat, Window};
+use low_level::rle::{Decompressor, DECOMPRESSOR_BUFFER_LENGTH};
+
+// Stream wrapper counting bytes read from the underlying stream.
+#[derive(Clone, Debug)]
+struct CountingReader<R: io::Read> {
+    stream: R,
+    bytes_read: u64,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buffer)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
 
 #[derive(Clone, Debug)]
 enum PixelReader<R: io::Read> {
-    Compressed(Decompressor<R>),
-    NotCompressed(R),
+    Compressed(Decompressor<CountingReader<R>>),
+    NotCompressed(CountingReader<R>),
+}
+
+impl<R: io::Read> PixelReader<R> {
+    // Number of bytes of pixel data consumed from the underlying stream, not counting data buffered by the decompressor.
+    fn bytes_read(&self) -> u64 {
+        match *self {
+            PixelReader::Compressed(ref decompressor) => decompressor.get_ref().bytes_read - (decompressor.buffered().len() as u64),
+            PixelReader::NotCompressed(ref stream) => stream.bytes_read,
+        }
+    }
+
+    // Get reference to the underlying stream.
+    fn get_ref(&self) -> &R {
+        match *self {
+            PixelReader::Compressed(ref decompressor) => &decompressor.get_ref().stream,
+            PixelReader::NotCompressed(ref stream) => &stream.stream,
+        }
+    }
+
+    // Get mutable reference to the underlying stream.
+    fn get_mut(&mut self) -> &mut R {
+        match *self {
+            PixelReader::Compressed(ref mut decompressor) => &mut decompressor.get_mut().stream,
+            PixelReader::NotCompressed(ref mut stream) => &mut stream.stream,
+        }
+    }
+
+    // Get the underlying stream and the data buffered by the decompressor, which together continue right after the pixel data
+    // consumed so far.
+    fn into_parts(self) -> (R, Vec<u8>) {
+        match self {
+            PixelReader::Compressed(decompressor) => {
+                let (stream, buffered) = decompressor.into_parts();
+                (stream.stream, buffered)
+            }
+            PixelReader::NotCompressed(stream) => (stream.stream, Vec::new()),
+        }
+    }
+}
+
+impl<R: io::Read + io::Seek> PixelReader<R> {
+    // Position of the start of pixel data in the underlying stream.
+    fn start(&mut self) -> io::Result<u64> {
+        let bytes_read = match *self {
+            PixelReader::Compressed(ref decompressor) => decompressor.get_ref().bytes_read,
+            PixelReader::NotCompressed(ref stream) => stream.bytes_read,
+        };
+        Ok(self.get_mut().stream_position()? - bytes_read)
+    }
+
+    // Continue reading from the position where `bytes_read` bytes of pixel data were read and decompression was in the given
+    // state. Pixel data starts at `start` in the underlying stream.
+    fn resume(&mut self, start: u64, bytes_read: u64, pending_run: (u8, u8)) -> io::Result<()> {
+        match *self {
+            PixelReader::Compressed(ref mut decompressor) => {
+                decompressor.discard_buffered();
+                decompressor.set_pending_run(pending_run.0, pending_run.1);
+                decompressor.get_mut().bytes_read = bytes_read;
+            }
+            PixelReader::NotCompressed(ref mut stream) => stream.bytes_read = bytes_read,
+        }
+        self.get_mut().seek(io::SeekFrom::Start(start + bytes_read)).map(|_| ())
+    }
+}
+
+impl<R: io::Read> PixelReader<R> {
+    // Skip `count` bytes of pixel data.
+    fn skip(&mut self, count: u64) -> io::Result<()> {
+        match *self {
+            PixelReader::Compressed(ref mut decompressor) => decompressor.skip(count),
+            PixelReader::NotCompressed(ref mut stream) => {
+                if io::copy(&mut io::Read::take(stream, count), &mut io::sink())? != count {
+                    return Err(io::ErrorKind::UnexpectedEof.into());
+                }
+                Ok(())
+            }
+        }
+    }
+
+    // Copy stored pixel data covering the next `length` bytes to `output`, returns the number of bytes covered.
+    fn read_raw(&mut self, length: usize, output: &mut Vec<u8>) -> io::Result<usize> {
+        match *self {
+            PixelReader::Compressed(ref mut decompressor) => decompressor.read_raw(length, output),
+            PixelReader::NotCompressed(ref mut stream) => io::Read::read_to_end(&mut io::Read::take(stream, length as u64), output),
+        }
+    }
+
+    // State of decompression which is not captured by the stream position.
+    fn pending_run(&self) -> (u8, u8) {
+        match *self {
+            PixelReader::Compressed(ref decompressor) => decompressor.pending_run(),
+            PixelReader::NotCompressed(_) => (0, 0),
+        }
+    }
 }
 
 impl<R: io::Read> io::Read for PixelReader<R> {
     fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
-        match self {
-            &mut PixelReader::Compressed(ref mut decompressor) => decompressor.read(buffer),
-            &mut PixelReader::NotCompressed(ref mut stream) => stream.read(buffer),
+        match *self {
+            PixelReader::Compressed(ref mut decompressor) => decompressor.read(buffer),
+            PixelReader::NotCompressed(ref mut stream) => stream.read(buffer),
         }
     }
 }
 
+// Start positions of rows for random access.
+#[derive(Clone, Debug)]
+struct RowIndex {
+    // Start of pixel data in the underlying stream.
+    start: u64,
+    // Number of bytes of pixel data read and pending run at the start of each row reached so far.
+    checkpoints: Vec<(u64, (u8, u8))>,
+}
+
+// Position of the reader in the pixel data.
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+enum State {
+    // Next lane to read is `plane` of the row `row`. All rows were read when `row` is equal to the image height.
+    Lane { row: u16, plane: u8 },
+
+    // Reading of the row `row` failed after some pixel data might have been consumed, position in the stream is unknown.
+    Failed { row: u16 },
+}
+
 /// PCX file reader.
 #[derive(Clone, Debug)]
 pub struct Reader<R: io::Read> {
     /// File header. All useful values are available via `Reader` methods so you don't actually need it.
     pub header: Header,
 
+    // Header bytes as stored in the file.
+    raw_header: RawHeader,
+
     pixel_reader: PixelReader<R>,
-    num_lanes_read: u32,
+    state: State,
+
+    // Offsets of the starts of the rows read so far relative to the start of file, followed by the offset of the end of the last row.
+    row_offsets: Vec<u64>,
+
+    // Start positions of rows recorded by `seek_row`.
+    row_index: Option<RowIndex>,
+
+    // 256-color palette read by `read_palette_early`.
+    palette: Option<Palette>,
+
+    options: Options,
+
+    // Problems tolerated while loading the header.
+    warnings: Vec<Warning>,
+
+    // Total length of the stream set by `set_len_hint`.
+    len_hint: Option<u64>,
+
+    // Padding of the last lane was consumed by `next_lane_raw`.
+    padding_read: bool,
+
+    // Options for conversion to RGBA.
+    transparency: Transparency,
+    premultiply_alpha: bool,
+
+    // Transforms applied by `next_row_into` in order.
+    row_transforms: Vec<RowTransform>,
+
+    // Watchpoints checked at the start of each lane.
+    watchpoints: Vec<Watch>,
+
+    // Scratch buffer for decoding images which require reading all planes of a row at once.
+    plane_buffer: Vec<u8>,
+
+    // Scratch buffer for conversion of rows to packed pixels.
+    rgba_buffer: Vec<u8>,
 }
 
 impl Reader<io::BufReader<File>> {
     /// Start reading PCX file.
-    pub fn from_file<P: AsRef<Path>>(path: P) -> io::Result<Self> {
+    pub fn from_file<P: AsRef<Path>>(path: P) -> Result<Self> {
         let file = File::open(path)?;
         Self::new(io::BufReader::new(file))
     }
@@ -42,19 +217,66 @@ impl Reader<io::BufReader<File>> {
 
 impl<R: io::Read> Reader<R> {
     /// Start reading PCX file.
-    pub fn new(mut stream: R) -> io::Result<Self> {
-        let header = Header::load(&mut stream)?;
+    pub fn new(stream: R) -> Result<Self> {
+        Self::new_with_options(stream, Options::default())
+    }
+
+    /// Start reading PCX file with the given options.
+    pub fn new_with_options(mut stream: R, options: Options) -> Result<Self> {
+        let (header, raw_header) = Header::load_raw_with_options(&mut stream, &options)?;
+        Self::with_header(stream, header, raw_header, options)
+    }
+
+    // Start reading pixel data following the already loaded header.
+    pub(crate) fn with_header(stream: R, header: Header, raw_header: RawHeader, options: Options) -> Result<Self> {
+        check_memory(&options, scratch_memory(&header))?;
+
+        let stream = CountingReader { stream, bytes_read: 0 };
         let pixel_reader = if header.is_compressed {
             PixelReader::Compressed(Decompressor::new(stream))
         } else {
             PixelReader::NotCompressed(stream)
         };
 
-        Ok(Reader {
-            header: header,
-            pixel_reader: pixel_reader,
-            num_lanes_read: 0,
-        })
+        let mut reader = Reader {
+            header,
+            raw_header,
+            pixel_reader,
+            state: State::Lane { row: 0, plane: 0 },
+            row_offsets: vec![HEADER_LENGTH as u64],
+            row_index: None,
+            palette: None,
+            options,
+            warnings: Vec::new(),
+            len_hint: None,
+            padding_read: false,
+            transparency: Transparency::Opaque,
+            premultiply_alpha: false,
+            row_transforms: Vec::new(),
+            watchpoints: Vec::new(),
+            plane_buffer: Vec::new(),
+            rgba_buffer: Vec::new(),
+        };
+
+        let header = &reader.header;
+        if !header.version.supports_format(header.bit_depth, header.number_of_color_planes) {
+            reader.warnings.push(Warning::UnsupportedByVersion {
+                version: header.version,
+                bit_depth: header.bit_depth,
+                number_of_color_planes: header.number_of_color_planes,
+            });
+        }
+
+        // Allocate scratch buffers with exact capacity upfront, so they never grow beyond the amount accounted for and never
+        // allocate later.
+        if options.max_memory.is_some() || options.fallible_allocation {
+            let width = reader.width() as usize;
+            let planes = (reader.header.lane_proper_length() as usize) * (reader.header.number_of_color_planes as usize);
+            reserve(&mut reader.row_offsets, reader.header.size.1 as usize)?;
+            reserve(&mut reader.plane_buffer, planes)?;
+            reserve(&mut reader.rgba_buffer, width * 4)?;
+        }
+        Ok(reader)
     }
 
     /// Get width and height of the image.
@@ -63,6 +285,65 @@ impl<R: io::Read> Reader<R> {
         self.header.size
     }
 
+    /// Width and height of the image as `u32`.
+    #[inline]
+    pub fn dimensions_u32(&self) -> (u32, u32) {
+        (self.header.size.0 as u32, self.header.size.1 as u32)
+    }
+
+    /// Width and height of the image as `usize`, e.g. for indexing buffers.
+    #[inline]
+    pub fn dimensions_usize(&self) -> (usize, usize) {
+        (self.header.size.0 as usize, self.header.size.1 as usize)
+    }
+
+    /// Number of pixels in the image.
+    #[inline]
+    pub fn pixel_count(&self) -> u64 {
+        (self.header.size.0 as u64) * (self.header.size.1 as u64)
+    }
+
+    /// Length in bytes of a row in `format`, the length of buffers passed to `next_row_into()`.
+    #[inline]
+    pub fn row_length(&self, format: PixelFormat) -> usize {
+        format.row_length(self.width())
+    }
+
+    /// Length in bytes of the whole image in `format`, the length of buffers passed to `read_rows_into()` for all rows. `None`
+    /// if the length does not fit into `usize`, which can happen on 32-bit targets.
+    pub fn buffer_length(&self, format: PixelFormat) -> Option<usize> {
+        self.row_length(format).checked_mul(self.height() as usize)
+    }
+
+    /// Header bytes exactly as stored in the file, including manufacturer, reserved and filler bytes which `header` does not
+    /// keep.
+    pub fn raw_header(&self) -> &RawHeader {
+        &self.raw_header
+    }
+
+    /// Horizontal and vertical resolution in dots per inch.
+    #[inline]
+    pub fn dpi(&self) -> (u16, u16) {
+        self.header.dpi
+    }
+
+    /// Size of the screen in pixels the image was created on, `(0, 0)` if not stored, see `Header::screen_size`.
+    #[inline]
+    pub fn screen_size(&self) -> (u16, u16) {
+        self.header.screen_size
+    }
+
+    /// Placement of the image: position of its top-left corner and its size.
+    #[inline]
+    pub fn window(&self) -> Window {
+        self.header.window()
+    }
+
+    /// Physical size of the image in millimeters computed from its DPI, `None` if DPI is not set.
+    pub fn physical_size_mm(&self) -> Option<(f64, f64)> {
+        self.header.physical_size_mm()
+    }
+
     /// The width of this image.
     #[inline]
     pub fn width(&self) -> u16 {
@@ -75,88 +356,214 @@ impl<R: io::Read> Reader<R> {
         self.header.size.1
     }
 
-    /// Whether this image is paletted or 24-bit RGB.
+    /// Whether this image is paletted, otherwise it is 24-bit RGB, grayscale with alpha or RGBA, see `color_format()`.
     #[inline]
     pub fn is_paletted(&self) -> bool {
         self.header.palette_length().is_some()
     }
 
+    /// Kind of pixel data in the image.
+    #[inline]
+    pub fn color_format(&self) -> ColorFormat {
+        self.header.color_format()
+    }
+
+    /// Get a reference to the underlying stream, e.g. to query a `HashingReader`. The stream may have been read ahead of the
+    /// decoded data.
+    pub fn get_ref(&self) -> &R {
+        self.pixel_reader.get_ref()
+    }
+
+    // Get a mutable reference to the underlying stream, e.g. to append data for the rows to be read next.
+    #[cfg(feature = "async")]
+    pub(crate) fn get_mut(&mut self) -> &mut R {
+        self.pixel_reader.get_mut()
+    }
+
+    /// Number of bytes of the PCX data consumed so far, counted from the start of the header: the header and the stored pixel
+    /// data decoded so far. Does not depend on `Seek`, e.g. for container parsers checking that an embedded image ends where
+    /// its entry ends, see also `finish_with_length()`.
+    pub fn bytes_consumed(&self) -> u64 {
+        (HEADER_LENGTH as u64) + self.pixel_reader.bytes_read()
+    }
+
+    /// Number of bytes read from the underlying stream so far, including the header and data read ahead of the decoded data.
+    /// Not less than `bytes_consumed()`.
+    pub fn stream_bytes_read(&self) -> u64 {
+        let bytes_read = match self.pixel_reader {
+            PixelReader::Compressed(ref decompressor) => decompressor.get_ref().bytes_read,
+            PixelReader::NotCompressed(ref stream) => stream.bytes_read,
+        };
+        (HEADER_LENGTH as u64) + bytes_read
+    }
+
+    /// Problems in the header which were tolerated because `Options::strict` is not set.
+    pub fn warnings(&self) -> &[Warning] {
+        &self.warnings
+    }
+
+    /// Estimated peak of memory allocated by the reader in bytes while reading the whole image with `read_image()`, including
+    /// the image itself, computed from the header. This is the amount checked against `Options::max_memory` by `read_image()`,
+    /// reading row by row needs only the scratch buffers allocated by `new()`. Memory of the underlying stream is not counted.
+    pub fn estimated_peak_memory(&self) -> u64 {
+        image_memory(&self.header)
+    }
+
     /// Get number of colors in the palette if this image is paletted. Number of colors is either 2, 4, 8, 16 or 256.
     #[inline]
     pub fn palette_length(&self) -> Option<u16> {
         self.header.palette_length()
     }
 
+    /// Number of rows which were already read.
+    #[inline]
+    pub fn rows_read(&self) -> u16 {
+        match self.state {
+            State::Lane { row, .. } | State::Failed { row } => row,
+        }
+    }
+
+    /// Number of rows which are left to read.
+    #[inline]
+    pub fn rows_remaining(&self) -> u16 {
+        self.height() - self.rows_read()
+    }
+
+    /// Whether there are rows left to read.
+    #[inline]
+    pub fn has_more_rows(&self) -> bool {
+        self.rows_remaining() > 0
+    }
+
+    /// Whether no more rows can be read: either all rows declared in the header were read or reading of a row failed.
+    ///
+    /// Reading rows after that fails without touching the stream, with `Error::EndOfImage` in the former case.
+    #[inline]
+    pub fn is_finished(&self) -> bool {
+        match self.state {
+            State::Lane { row, .. } => row >= self.height(),
+            State::Failed { .. } => true,
+        }
+    }
+
+    /// Byte ranges occupied by the compressed data of the rows which were already read, offsets are relative to the start of file.
+    ///
+    /// Useful for finding image regions which compress poorly or building an index for random access. If the encoder let
+    /// RLE runs cross row boundaries, a run is attributed to the row in which it starts.
+    pub fn row_spans(&self) -> impl Iterator<Item = ops::Range<u64>> + '_ {
+        self.row_offsets.windows(2).map(|offsets| offsets[0]..offsets[1])
+    }
+
+    /// Set transparency of paletted images used by `next_row_rgba()`. Images are opaque by default.
+    pub fn set_transparency(&mut self, transparency: Transparency) {
+        self.transparency = transparency;
+    }
+
+    /// Set total length of the PCX file in bytes including the header, e.g. from `Content-Length` of a HTTP response.
+    ///
+    /// With the length known `read_palette()` reads the 256-color palette from its position instead of reading the stream
+    /// until its end, so data following the image is left unread. It also makes `progress()` available.
+    pub fn set_len_hint(&mut self, length: u64) {
+        self.len_hint = Some(length);
+    }
+
+    /// Total length of the PCX file set by `set_len_hint()`.
+    pub fn len_hint(&self) -> Option<u64> {
+        self.len_hint
+    }
+
+    /// Fraction of the file consumed so far, between 0 and 1. `None` if the length is unknown, see `set_len_hint()`.
+    pub fn progress(&self) -> Option<f64> {
+        self.len_hint.map(|length| {
+            let consumed = (HEADER_LENGTH as u64) + self.pixel_reader.bytes_read();
+            if length == 0 { 1.0 } else { (consumed as f64 / length as f64).min(1.0) }
+        })
+    }
+
+    /// Set whether `next_row_rgba()` should output color values premultiplied by alpha. Disabled by default.
+    pub fn set_premultiply_alpha(&mut self, premultiply_alpha: bool) {
+        self.premultiply_alpha = premultiply_alpha;
+    }
+
+    /// Append `transform` to the transforms applied to rows read by `next_row_into()`, `read_rows_into()` and `row()`.
+    ///
+    /// Transforms are applied in the order they were added, after conversion to the requested pixel format. Other methods
+    /// return rows unchanged.
+    pub fn add_row_transform(&mut self, transform: RowTransform) {
+        self.row_transforms.push(transform);
+    }
+
+    /// Remove all transforms added with `add_row_transform()`.
+    pub fn clear_row_transforms(&mut self) {
+        self.row_transforms.clear();
+    }
+
+    /// Call `callback` with the state of the decoder once decoding reaches `watchpoint`, e.g. to find out where the RLE data of
+    /// a malformed file gets out of sync with the lanes.
+    ///
+    /// Each watchpoint triggers at most once. Watchpoints are checked by all methods reading pixel data, including raw lanes.
+    pub fn add_watchpoint<F: Fn(&DecoderState) + Send + Sync + 'static>(&mut self, watchpoint: Watchpoint, callback: F) {
+        self.watchpoints.push(Watch::new(watchpoint, callback));
+    }
+
+    /// Remove all watchpoints added with `add_watchpoint()`.
+    pub fn clear_watchpoints(&mut self) {
+        self.watchpoints.clear();
+    }
+
+    /// Iterate over the remaining rows of the image.
+    ///
+    /// Each row is decoded into a newly allocated `Row` matching `color_format()`. Iteration stops after the first error.
+    pub fn rows(&mut self) -> Rows<'_, R> {
+        Rows { reader: self, failed: false }
+    }
+
     /// Read next row of the paletted image.  Check that `is_paletted()` is `true` before calling this function.
     ///
     /// `buffer` length must be equal to the image width.
     ///
     /// Order of rows is from top to bottom, order of pixels is from left to right.
-    pub fn next_row_paletted(&mut self, buffer: &mut [u8]) -> io::Result<()> {
+    pub fn next_row_paletted(&mut self, buffer: &mut [u8]) -> Result<()> {
         if !self.is_paletted() {
-            return user_error("pcx::Reader::next_row_paletted called on non-paletted image");
+            return Err(Error::NotPaletted);
+        }
+
+        if buffer.len() != self.width() as usize {
+            return Err(Error::BufferLength {
+                expected: self.width() as usize,
+                actual: buffer.len(),
+            });
         }
 
+        self.check_row_start()?;
+
         if self.palette_length() == Some(256) {
             self.next_lane(buffer)?;
         } else if self.header.number_of_color_planes == 1 {
-            // All packed formats, max. 16 colors.
-            let lane_length = self.header.lane_proper_length() as usize;
-            let buffer_len = buffer.len();
-            let offset = buffer.len() - lane_length;
-
-            // Place packed row at the end of buffer, this will allow us easily to unpack it.
-            self.next_lane(&mut buffer[offset..buffer_len])?;
-
-            macro_rules! unpack_bits {
-                ($bits:expr) => {
-                    for i in 0..lane_length {
-                        for j in 0..(8/$bits) {
-                            buffer[i*(8/$bits) + j] = (buffer[offset + i] & (((1 << $bits) - 1) << (8 - $bits*(j + 1)))) >> (8 - $bits*(j + 1));
-                        }
-                    }
-                }
-            };
+            // All packed formats, max. 16 colors. The last byte of the lane may hold bits of fewer pixels than fit into it.
+            self.next_lanes()?;
 
-            // Unpack packed bits into bytes.
-            match self.header.bit_depth {
-                1 => unpack_bits!(1),
-                2 => unpack_bits!(2),
-                4 => unpack_bits!(4),
-                _ => unreachable!(), // bit depth was checked while reading header
+            // Unpack packed bits into bytes, pixels are stored starting from the most significant bits.
+            let bits = self.header.bit_depth as usize;
+            let mask = (1 << bits) - 1;
+            for (x, pixel) in buffer.iter_mut().enumerate() {
+                let bit_offset = x * bits;
+                let shift = 8 - bits - (bit_offset & 7);
+                *pixel = (self.plane_buffer[bit_offset >> 3] >> shift) & mask;
             }
         } else {
-            // Planar, 4, 8 or 16 colors.
+            // Planar, 4, 8 or 16 colors. Each plane holds one bit of the palette index.
             let lane_length = self.header.lane_proper_length() as usize;
-            let number_of_color_planes = self.header.number_of_color_planes as usize;
-            let half_len = buffer.len() / 2;
-
-            // Place packed rows at the first half of the buffer, this will allow us easily to unpack them.
-            for i in 0..number_of_color_planes {
-                self.next_lane(&mut buffer[(lane_length * i)..(lane_length * (i + 1))])?;
-            }
-
-            for x in 0..self.width() {
-                let m = 0x80 >> (x & 7);
-                let mut v = 0;
-                for i in (0..number_of_color_planes).rev() {
-                    v <<= 1;
-                    v += if buffer[i * lane_length + (x as usize >> 3)] & m != 0 {
-                        1
-                    } else {
-                        0
-                    };
-                }
-                if x % 2 == 0 {
-                    buffer[half_len + (x / 2) as usize] = v << 4;
-                } else {
-                    buffer[half_len + (x / 2) as usize] |= v;
-                }
-            }
+            self.next_lanes()?;
 
-            for i in 0..half_len {
-                buffer[i * 2] = (buffer[half_len + i] & 0xF0) >> 4;
-                buffer[i * 2 + 1] = buffer[half_len + i] & 0xF;
+            for (x, pixel) in buffer.iter_mut().enumerate() {
+                let mask = 0x80 >> (x & 7);
+                *pixel = 0;
+                for (i, lane) in self.plane_buffer.chunks(lane_length).enumerate() {
+                    if lane[x >> 3] & mask != 0 {
+                        *pixel |= 1 << i;
+                    }
+                }
             }
         }
 
@@ -168,13 +575,41 @@ impl<R: io::Read> Reader<R> {
     /// `r`, `g`, `b` buffer lengths must be equal to the image width.
     ///
     /// Order of rows is from top to bottom, order of pixels is from left to right.
-    pub fn next_row_rgb_separate(&mut self, r: &mut [u8], g: &mut [u8], b: &mut [u8]) -> io::Result<()> {
+    pub fn next_row_rgb_separate(&mut self, r: &mut [u8], g: &mut [u8], b: &mut [u8]) -> Result<()> {
         if self.is_paletted() {
-            return user_error("pcx::Reader::next_row_rgb_separate called on paletted image");
+            return Err(Error::NotRgb);
+        }
+
+        for buffer in &[&r, &g, &b] {
+            if buffer.len() != self.width() as usize {
+                return Err(Error::BufferLength {
+                    expected: self.width() as usize,
+                    actual: buffer.len(),
+                });
+            }
         }
 
-        /// API for reading lanes is not exposed so users have no way of messing that up.
-        assert_eq!(self.num_lanes_read % 3, 0);
+        self.check_row_start()?;
+
+        let width = r.len();
+        match self.header.color_format() {
+            ColorFormat::GrayAlpha => {
+                self.next_lanes()?;
+                let gray = &self.plane_buffer[..width];
+                r.copy_from_slice(gray);
+                g.copy_from_slice(gray);
+                b.copy_from_slice(gray);
+                return Ok(());
+            }
+            ColorFormat::Rgba => {
+                self.next_lanes()?;
+                r.copy_from_slice(&self.plane_buffer[..width]);
+                g.copy_from_slice(&self.plane_buffer[width..(width * 2)]);
+                b.copy_from_slice(&self.plane_buffer[(width * 2)..(width * 3)]);
+                return Ok(());
+            }
+            _ => {}
+        }
 
         self.next_lane(r)?;
         self.next_lane(g)?;
@@ -183,184 +618,2277 @@ impl<R: io::Read> Reader<R> {
 
     /// Read next row of the RGB image to one buffer with interleaved RGB values. Check that `is_paletted()` is `false` before calling this function.
     ///
+    /// Pixels are stored as R, G, B, R, G, B, ... which is the layout expected by most consumers (OpenGL textures, image buffers),
+    /// color planes are interleaved internally. Use `next_row_rgb_separate()` if you need each channel in its own buffer.
+    ///
+    /// Images with alpha channel are converted to RGB dropping the alpha channel, this applies to `next_row_rgb_separate()` as well.
+    ///
     /// `rgb` buffer length must be equal to the image width multiplied by 3.
     ///
     /// Order of rows is from top to bottom, order of pixels is from left to right.
-    pub fn next_row_rgb(&mut self, rgb: &mut [u8]) -> io::Result<()> {
+    pub fn next_row_rgb(&mut self, rgb: &mut [u8]) -> Result<()> {
         if self.is_paletted() {
-            return user_error("pcx::Reader::next_row_rgb called on paletted image");
+            return Err(Error::NotRgb);
         }
 
-        /// API for reading lanes is not exposed so users have no way of messing that up.
-        assert_eq!(self.num_lanes_read % 3, 0);
+        self.check_row_start()?;
 
         if rgb.len() != (self.width() as usize) * 3 {
-            return user_error("pcx::Reader::next_row_rgb: buffer length must be equal to the width of the image multiplied by 3");
+            return Err(Error::BufferLength {
+                expected: (self.width() as usize) * 3,
+                actual: rgb.len(),
+            });
         }
 
-        for color in 0..3 {
-            for x in 0..(self.width() as usize) {
-                rgb[x * 3 + color] = self.pixel_reader.read_u8()?;
+        // Read whole lanes at once and interleave them afterwards, which is much faster than decoding pixel by pixel.
+        self.next_lanes()?;
+        let width = self.width() as usize;
+        if self.header.color_format() == ColorFormat::GrayAlpha {
+            for (pixel, &gray) in rgb.chunks_mut(3).zip(self.plane_buffer.iter()) {
+                pixel.copy_from_slice(&[gray; 3]);
+            }
+        } else {
+            let (r, rest) = self.plane_buffer.split_at(width);
+            let (g, b) = rest.split_at(width);
+            for (((pixel, &r), &g), &b) in rgb.chunks_mut(3).zip(r).zip(g).zip(b) {
+                pixel.copy_from_slice(&[r, g, b]);
             }
-            self.skip_padding()?;
         }
 
         Ok(())
     }
 
-    fn skip_padding(&mut self) -> io::Result<()> {
-        if self.num_lanes_read + 1 < (self.height() as u32) * (self.header.number_of_color_planes as u32) {
-            // Skip padding.
-            for _ in 0..self.header.lane_padding() {
-                self.pixel_reader.read_u8()?;
-            }
+    /// Read next row of the grayscale image with alpha channel. Check that `color_format()` is `ColorFormat::GrayAlpha` before calling this function.
+    ///
+    /// Pixels are stored as gray, alpha, gray, alpha, ... `buffer` length must be equal to the image width multiplied by 2.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn next_row_gray_alpha(&mut self, buffer: &mut [u8]) -> Result<()> {
+        if self.header.color_format() != ColorFormat::GrayAlpha {
+            return Err(Error::NotGrayAlpha);
         }
 
-        self.num_lanes_read += 1;
-        Ok(())
-    }
+        let width = self.width() as usize;
+        if buffer.len() != width * 2 {
+            return Err(Error::BufferLength {
+                expected: width * 2,
+                actual: buffer.len(),
+            });
+        }
 
-    // Read next lane. Format is dependent on file format. Buffer length must be equal to `Header::lane_proper_length()`.
-    //
-    // Order of lanes is from top to bottom.
-    fn next_lane(&mut self, buffer: &mut [u8]) -> io::Result<()> {
-        use std::io::Read;
+        self.check_row_start()?;
 
-        if buffer.len() != self.header.lane_proper_length() as usize {
-            return user_error("pcx::Reader::next_lane: incorrect buffer size.");
+        self.next_lanes()?;
+        let (gray, alpha) = self.plane_buffer.split_at(width);
+        for (pixel, (&gray, &alpha)) in buffer.chunks_mut(2).zip(gray.iter().zip(alpha.iter())) {
+            pixel[0] = gray;
+            pixel[1] = alpha;
         }
 
-        self.pixel_reader.read_exact(buffer)?;
-        self.skip_padding()
+        Ok(())
     }
 
-    /// Read color palette.
+    /// Read next row of the image converted to RGBA.
     ///
-    /// If palette contains 256-colors then it is stored at the end of file and this function will read the file to the end.
+    /// Images of all formats can be read: paletted pixels are looked up in the palette and get alpha according to
+    /// `set_transparency()`, images without alpha channel are opaque. Colors are premultiplied by alpha if enabled with
+    /// `set_premultiply_alpha()`. 256-color palette is stored at the end of file, so it must be loaded with
+    /// `read_palette_early()` before reading rows of 256-color images.
     ///
-    /// Returns number of colors in palette or zero if there is no palette. The actual number of bytes written to the output buffer is
-    /// equal to the returned value multiplied by 3. Format of the output buffer is R, G, B, R, G, B, ...
-    pub fn read_palette(self, buffer: &mut [u8]) -> io::Result<usize> {
-        match self.header.palette_length() {
-            Some(2) => {
-                // Special case - monochrome image.
-
-                // Black.
-                buffer[0] = 0;
-                buffer[1] = 0;
-                buffer[2] = 0;
-
-                // White.
-                buffer[3] = 255;
-                buffer[4] = 255;
-                buffer[5] = 255;
-
-                return Ok(2 as usize);
-            }
-            Some(palette_length @ 1...16) => {
-                // Palettes of 16 colors or smaller are stored in the header.
-                for i in 0..(palette_length as usize) {
-                    (&mut buffer[(i * 3)..((i + 1) * 3)]).copy_from_slice(&self.header.palette[i]);
-                }
-                return Ok(palette_length as usize);
+    /// Pixels are stored as R, G, B, A, R, G, B, A, ... `rgba` buffer length must be equal to the image width multiplied by 4.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn next_row_rgba(&mut self, rgba: &mut [u8]) -> Result<()> {
+        let width = self.width() as usize;
+        if rgba.len() != width * 4 {
+            return Err(Error::BufferLength {
+                expected: width * 4,
+                actual: rgba.len(),
+            });
+        }
+
+        let color_format = self.color_format();
+        if color_format == ColorFormat::Paletted {
+            let lookup = self.rgba_lookup()?;
+            self.next_row_paletted(&mut rgba[..width])?;
+
+            // Expand indices in place going backwards, so no index is overwritten before it is looked up.
+            for x in (0..width).rev() {
+                let color = lookup[rgba[x] as usize];
+                rgba[(x * 4)..(x * 4 + 4)].copy_from_slice(&color);
             }
-            Some(256) => {
-                // 256-color palette is located at the end of file, we will read it below.
+            return Ok(());
+        }
+
+        self.check_row_start()?;
+        self.next_lanes()?;
+
+        let planes = &self.plane_buffer;
+        for (x, pixel) in rgba.chunks_mut(4).enumerate() {
+            let color = match color_format {
+                ColorFormat::GrayAlpha => [planes[x], planes[x], planes[x], planes[width + x]],
+                ColorFormat::Rgba => [planes[x], planes[width + x], planes[width * 2 + x], planes[width * 3 + x]],
+                _ => [planes[x], planes[width + x], planes[width * 2 + x], 0xFF],
+            };
+            pixel.copy_from_slice(&color);
+            if self.premultiply_alpha {
+                premultiply(pixel);
             }
-            _ => return Ok(0),
         }
 
-        // Stop decompressing and continue reading underlying stream.
-        let mut stream = match self.pixel_reader {
-            PixelReader::Compressed(decompressor) => decompressor.finish(),
-            PixelReader::NotCompressed(stream) => stream,
-        };
+        Ok(())
+    }
 
-        // 256-color palette is located at the end of file. To avoid seeking we are using a bit convoluted method here to read it.
-        const PALETTE_LENGTH: usize = 256 * 3;
-        const TEMP_BUFFER_LENGTH: usize = PALETTE_LENGTH + 1;
+    /// Read next row of the image converted to RGBA with channels of each pixel packed into one word in the given order.
+    ///
+    /// Conversion is the same as in `next_row_rgba()`. `buffer` length must be equal to the image width.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn next_row_packed(&mut self, buffer: &mut [u32], order: ChannelOrder) -> Result<()> {
+        let width = self.width() as usize;
+        if buffer.len() != width {
+            return Err(Error::BufferLength {
+                expected: width,
+                actual: buffer.len(),
+            });
+        }
 
-        let mut temp_buffer = [0; TEMP_BUFFER_LENGTH];
-        let mut pos = 0;
+        let mut rgba = mem::take(&mut self.rgba_buffer);
+        rgba.resize(width * 4, 0);
+        let result = self.next_row_rgba(&mut rgba);
+        if result.is_ok() {
+            for (word, pixel) in buffer.iter_mut().zip(rgba.chunks(4)) {
+                *word = order.pack([pixel[0], pixel[1], pixel[2], pixel[3]]);
+            }
+        }
+        self.rgba_buffer = rgba;
+        result
+    }
 
-        loop {
-            let read = stream.read(&mut temp_buffer[pos..TEMP_BUFFER_LENGTH])?;
-            if read != 0 {
-                pos = (pos + read) % TEMP_BUFFER_LENGTH;
-            } else {
-                // We've reached the end of file, therefore temp_buffer must now contain the palette.
-                if temp_buffer[pos] != PALETTE_START {
-                    return Err(io::Error::new(io::ErrorKind::InvalidData, "no 256-color palette"));
+    /// Read next row of the image converted to the given pixel format.
+    ///
+    /// Conversion to RGB is the same as in `next_row_rgb()` except that paletted pixels are looked up in the palette, conversion
+    /// to RGBA is the same as in `next_row_rgba()`. 256-color palette is stored at the end of file, so it must be loaded with
+    /// `read_palette_early()` before reading rows of 256-color images in formats other than `PixelFormat::Indexed`. Transforms
+    /// added with `add_row_transform()` are applied to the converted row.
+    ///
+    /// `buffer` length must be equal to the image width multiplied by `format.bytes_per_pixel()`.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn next_row_into(&mut self, buffer: &mut [u8], format: PixelFormat) -> Result<()> {
+        let width = self.width() as usize;
+        if buffer.len() != width * format.bytes_per_pixel() {
+            return Err(Error::BufferLength {
+                expected: width * format.bytes_per_pixel(),
+                actual: buffer.len(),
+            });
+        }
+
+        match format {
+            PixelFormat::Indexed => self.next_row_paletted(buffer)?,
+            PixelFormat::Rgba | PixelFormat::Bgra => self.next_row_rgba(buffer)?,
+            PixelFormat::Rgb | PixelFormat::Bgr if self.is_paletted() => {
+                let mut lookup = [[0; 3]; 256];
+                for (entry, color) in lookup.iter_mut().zip(self.palette_or_default()?.iter()) {
+                    *entry = *color;
                 }
+                self.next_row_paletted(&mut buffer[..width])?;
 
-                &mut buffer[0..(TEMP_BUFFER_LENGTH - pos - 1)].copy_from_slice(&temp_buffer[(pos + 1)..TEMP_BUFFER_LENGTH]);
-                &mut buffer[(TEMP_BUFFER_LENGTH - pos - 1)..PALETTE_LENGTH].copy_from_slice(&temp_buffer[0..pos]);
+                // Expand indices in place going backwards, so no index is overwritten before it is looked up.
+                for x in (0..width).rev() {
+                    let color = lookup[buffer[x] as usize];
+                    buffer[(x * 3)..(x * 3 + 3)].copy_from_slice(&color);
+                }
+            }
+            PixelFormat::Rgb | PixelFormat::Bgr => self.next_row_rgb(buffer)?,
+        }
 
-                return Ok(256);
+        if format == PixelFormat::Bgr || format == PixelFormat::Bgra {
+            for pixel in buffer.chunks_mut(format.bytes_per_pixel()) {
+                pixel.swap(0, 2);
             }
         }
+        for transform in &self.row_transforms {
+            transform.apply(buffer, format);
+        }
+        Ok(())
     }
-}
 
-#[cfg(test)]
-mod tests {
-    use std::iter;
+    /// Read all remaining rows into `buffer` converting them to the given pixel format, see `next_row_into()`.
+    ///
+    /// Rows start `stride` bytes apart, pass `None` for tightly packed rows. Bytes between the end of one row and the start of the
+    /// next one are left untouched. `buffer` must be large enough to hold all remaining rows, the last row does not need padding.
+    pub fn read_rows_into(&mut self, buffer: &mut [u8], format: PixelFormat, stride: Option<usize>) -> Result<()> {
+        let row_length = (self.width() as usize) * format.bytes_per_pixel();
+        let stride = stride.unwrap_or(row_length);
+        if stride < row_length {
+            return Err(Error::InvalidUsage("stride must not be less than the length of a row"));
+        }
 
-    use super::Reader;
-    use low_level::header;
+        let rows = self.rows_remaining() as usize;
+        let expected = if rows == 0 { 0 } else { stride * (rows - 1) + row_length };
+        if buffer.len() < expected {
+            return Err(Error::BufferLength {
+                expected,
+                actual: buffer.len(),
+            });
+        }
 
-    #[test]
-    fn gmarbles() {
-        let data = include_bytes!("../test-data/gmarbles.pcx");
-        let read = &mut &data[..];
+        (0..rows).try_for_each(|y| self.next_row_into(&mut buffer[(y * stride)..(y * stride + row_length)], format))
+    }
+
+    /// Read next row of the image converted to grayscale, one luminance value per pixel.
+    ///
+    /// Colors are converted using Rec. 601 luma coefficients, paletted pixels are looked up in the palette and alpha channel
+    /// is dropped. 256-color palette is stored at the end of file, so it must be loaded with `read_palette_early()` before
+    /// reading rows of 256-color images.
+    ///
+    /// `buffer` length must be equal to the image width.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn next_row_gray(&mut self, buffer: &mut [u8]) -> Result<()> {
+        let width = self.width() as usize;
+        if buffer.len() != width {
+            return Err(Error::BufferLength {
+                expected: width,
+                actual: buffer.len(),
+            });
+        }
+
+        if self.color_format() == ColorFormat::Paletted {
+            let lookup = self.gray_lookup()?;
+            self.next_row_paletted(buffer)?;
+            for value in buffer.iter_mut() {
+                *value = lookup[*value as usize];
+            }
+            return Ok(());
+        }
+
+        self.check_row_start()?;
+        self.next_lanes()?;
+
+        let planes = &self.plane_buffer;
+        if self.header.color_format() == ColorFormat::GrayAlpha {
+            buffer.copy_from_slice(&planes[..width]);
+        } else {
+            for (x, value) in buffer.iter_mut().enumerate() {
+                *value = luma([planes[x], planes[width + x], planes[width * 2 + x]]);
+            }
+        }
+
+        Ok(())
+    }
+
+    // Luminance of each palette entry, entries beyond the end of palette are black.
+    fn gray_lookup(&self) -> Result<[u8; 256]> {
+        let mut lookup = [0; 256];
+        for (value, color) in lookup.iter_mut().zip(self.palette_or_default()?.iter()) {
+            *value = luma(*color);
+        }
+        Ok(lookup)
+    }
+
+    // RGBA value of each palette entry with transparency and premultiplication applied, entries beyond the end of palette
+    // are black.
+    fn rgba_lookup(&self) -> Result<[[u8; 4]; 256]> {
+        let mut lookup = [[0, 0, 0, 0xFF]; 256];
+        for (entry, color) in lookup.iter_mut().zip(self.palette_or_default()?.iter()) {
+            entry[..3].copy_from_slice(color);
+        }
+
+        match self.transparency {
+            Transparency::Opaque => {}
+            Transparency::Index(index) => lookup[index as usize][3] = 0,
+            Transparency::Alpha(ref alpha) => {
+                for (entry, &alpha) in lookup.iter_mut().zip(alpha.iter()) {
+                    entry[3] = alpha;
+                }
+            }
+        }
+
+        if self.premultiply_alpha {
+            for entry in lookup.iter_mut() {
+                premultiply(entry);
+            }
+        }
+        Ok(lookup)
+    }
+
+
+    // Read next row in the layout used by `Pixels` for the color format of the image.
+    #[cfg(feature = "parallel")]
+    fn next_row_native(&mut self, buffer: &mut [u8]) -> Result<()> {
+        match self.color_format() {
+            ColorFormat::Paletted => self.next_row_paletted(buffer),
+            ColorFormat::Rgb => self.next_row_rgb(buffer),
+            ColorFormat::GrayAlpha => self.next_row_gray_alpha(buffer),
+            ColorFormat::Rgba => self.next_row_rgba(buffer),
+        }
+    }
+
+    // Check that the reader is positioned at the start of a row which can be read.
+    fn check_row_start(&mut self) -> Result<()> {
+        match self.state {
+            State::Lane { row, plane: 0 } if row < self.height() => Ok(()),
+            State::Lane { plane: 0, .. } => Err(Error::EndOfImage),
+            State::Lane { .. } => Err(Error::InvalidUsage("previous row was not read completely")),
+            State::Failed { .. } => Err(Error::InvalidUsage("reading of the previous row failed, position in the stream is unknown")),
+        }
+    }
+
+    // Complete reading of the current lane: skip padding and advance to the next lane. The reader is marked as failed
+    // if `result` of reading the lane is an error.
+    fn finish_lane(&mut self, result: Result<()>) -> Result<()> {
+        let (row, plane) = match self.state {
+            State::Lane { row, plane } => (row, plane),
+            State::Failed { .. } => return Err(Error::InvalidUsage("reading of the previous row failed, position in the stream is unknown")),
+        };
+
+        let last_plane = plane + 1 == self.header.number_of_color_planes;
+        let result = result.and_then(|_| {
+            // Padding of the very last lane may be missing.
+            if !(last_plane && row + 1 == self.height()) {
+                for _ in 0..self.header.lane_padding() {
+                    self.pixel_reader.read_u8()?;
+                }
+            }
+            Ok(())
+        });
+        self.padding_read = false;
+        self.advance_lane(row, plane, result)
+    }
+
+    // Call the callbacks of watchpoints reached by the lane about to be read.
+    fn check_watchpoints(&mut self) {
+        let (row, plane) = match self.state {
+            State::Lane { row, plane } if !self.watchpoints.is_empty() => (row, plane),
+            _ => return,
+        };
+
+        let lane_length = self.header.lane_length as u64;
+        let state = DecoderState {
+            row,
+            plane,
+            offset: (HEADER_LENGTH as u64) + self.pixel_reader.bytes_read(),
+            decoded: ((row as u64) * (self.header.number_of_color_planes as u64) + (plane as u64)) * lane_length,
+            pending_run: self.pixel_reader.pending_run(),
+        };
+        for watch in &mut self.watchpoints {
+            watch.check(&state);
+        }
+    }
+
+    // Advance to the lane following `plane` of the row `row`, which was read including padding. The reader is marked as
+    // failed if `result` of reading the lane is an error.
+    fn advance_lane(&mut self, row: u16, plane: u8, result: Result<()>) -> Result<()> {
+        let last_plane = plane + 1 == self.header.number_of_color_planes;
+        self.state = match result {
+            Err(_) => State::Failed { row },
+            Ok(_) if last_plane => {
+                // Rows read out of order with `seek_row` are not recorded.
+                if self.row_offsets.len() == (row as usize) + 1 {
+                    self.row_offsets.push((HEADER_LENGTH as u64) + self.pixel_reader.bytes_read());
+                }
+                State::Lane { row: row + 1, plane: 0 }
+            }
+            Ok(_) => State::Lane { row, plane: plane + 1 },
+        };
+        result
+    }
+
+    // Read all lanes of the next row one after another into `plane_buffer`.
+    fn next_lanes(&mut self) -> Result<()> {
+        let lane_length = self.header.lane_proper_length() as usize;
+        let mut planes = mem::take(&mut self.plane_buffer);
+        planes.resize(lane_length * (self.header.number_of_color_planes as usize), 0);
+
+        let result = planes.chunks_mut(lane_length).try_for_each(|lane| self.next_lane(lane));
+        self.plane_buffer = planes;
+        result
+    }
+
+    // Read next lane. Format is dependent on file format. Buffer length must be equal to `Header::lane_proper_length()`.
+    //
+    // Order of lanes is from top to bottom.
+    fn next_lane(&mut self, buffer: &mut [u8]) -> Result<()> {
+        use std::io::Read;
+
+        if buffer.len() != self.header.lane_proper_length() as usize {
+            return Err(Error::BufferLength {
+                expected: self.header.lane_proper_length() as usize,
+                actual: buffer.len(),
+            });
+        }
+
+        self.check_watchpoints();
+        let result = self.pixel_reader.read_exact(buffer).map_err(Error::from);
+        self.finish_lane(result)
+    }
+
+    // Skip the next lane without copying its pixel data out.
+    fn skip_lane(&mut self) -> Result<()> {
+        self.check_watchpoints();
+        let result = self.pixel_reader.skip(self.header.lane_proper_length() as u64).map_err(Error::from);
+        self.finish_lane(result)
+    }
+
+    /// Read the stored pixel data of the next lane without decoding it, e.g. to copy it verbatim with `WriterRaw` while
+    /// changing the header.
+    ///
+    /// `buffer` is replaced with the RLE codes of the lane including padding, or with the bytes of the lane if the image is not
+    /// compressed. Lanes are returned in the order they are stored: all planes of a row from first to last, rows from top to
+    /// bottom. The data is exactly as stored in the file, except that a run crossing the end of the lane is split between the
+    /// lanes. Padding of the very last lane is only included if it is present in a compressed image.
+    pub fn next_lane_raw(&mut self, buffer: &mut Vec<u8>) -> Result<()> {
+        let (row, plane) = match self.state {
+            State::Lane { row, plane } if row < self.height() => (row, plane),
+            State::Lane { .. } => return Err(Error::EndOfImage),
+            State::Failed { .. } => return Err(Error::InvalidUsage("reading of the previous row failed, position in the stream is unknown")),
+        };
+
+        buffer.clear();
+        self.check_watchpoints();
+        let result = if row + 1 == self.height() && plane + 1 == self.header.number_of_color_planes {
+            self.last_lane_raw(buffer)
+        } else {
+            let lane_length = self.header.lane_length as usize;
+            match self.pixel_reader.read_raw(lane_length, buffer) {
+                Ok(covered) if covered == lane_length => Ok(()),
+                Ok(_) => Err(io::Error::from(io::ErrorKind::UnexpectedEof)),
+                Err(error) => Err(error),
+            }
+        };
+        self.advance_lane(row, plane, result.map_err(Error::from))
+    }
+
+    // Read the stored data of the very last lane. Its padding may be missing or be followed directly by the 256-color
+    // palette, in the same way as handled by `finish()`.
+    fn last_lane_raw(&mut self, buffer: &mut Vec<u8>) -> io::Result<()> {
+        let lane_length = self.header.lane_proper_length() as usize;
+        if self.pixel_reader.read_raw(lane_length, buffer)? != lane_length {
+            return Err(io::ErrorKind::UnexpectedEof.into());
+        }
+
+        let padding = self.header.lane_padding() as usize;
+        let palette_at_end = self.palette_length() == Some(256);
+        if let PixelReader::Compressed(ref mut decompressor) = self.pixel_reader {
+            match decompressor.pending_run() {
+                (0, _) => {
+                    let missing = match decompressor.peek()? {
+                        None => true,
+                        Some(code) => palette_at_end && code == PALETTE_START,
+                    };
+                    if !missing {
+                        decompressor.read_raw(padding, buffer)?;
+                    }
+                }
+                (pending, value) => {
+                    // Join the run which was split at the end of the lane with its part in the padding, as it is stored.
+                    let count = cmp::min(pending as usize, padding);
+                    let last_code = buffer.len() - 2;
+                    buffer[last_code] += count as u8;
+                    decompressor.set_pending_run(pending - (count as u8), value);
+                    decompressor.read_raw(padding - count, buffer)?;
+                }
+            }
+            self.padding_read = true;
+        }
+        Ok(())
+    }
+
+    /// Read the whole image into memory, including the palette if the image is paletted.
+    ///
+    /// This is a convenience alternative to reading the image row by row, it must be called before any rows were read.
+    ///
+    /// Unless `Options::strict` is set, truncated files are decoded as far as possible and the missing rows are filled with
+    /// `Options::fill`. The 256-color palette is then missing as well and the default VGA palette is returned instead.
+    pub fn read_image(mut self) -> Result<Image> {
+        if self.rows_read() != 0 {
+            return Err(Error::InvalidUsage("read_image called after some rows were already read"));
+        }
+
+        check_memory(&self.options, image_memory(&self.header))?;
+
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let fill = self.options.fill;
+        let pixels = match self.color_format() {
+            ColorFormat::Paletted => {
+                let mut indices = zeroed(width * height)?;
+                let complete = self.read_rows_or_fill(&mut indices, width, &[fill.index], Self::next_row_paletted)?;
+
+                let mut palette = vec![0; 256 * 3];
+                if complete || self.palette_length() != Some(256) {
+                    let palette_length = self.read_palette(&mut palette)?;
+                    palette.truncate(palette_length * 3);
+                } else {
+                    palette = Palette::vga().to_rgb();
+                }
+
+                Pixels::Paletted { indices, palette }
+            }
+            ColorFormat::Rgb => {
+                let mut rgb = zeroed(width * height * 3)?;
+                self.read_rows_or_fill(&mut rgb, width * 3, &fill.color[..3], Self::next_row_rgb)?;
+                Pixels::Rgb(rgb)
+            }
+            ColorFormat::GrayAlpha => {
+                let mut gray_alpha = zeroed(width * height * 2)?;
+                let gray = luma([fill.color[0], fill.color[1], fill.color[2]]);
+                self.read_rows_or_fill(&mut gray_alpha, width * 2, &[gray, fill.color[3]], Self::next_row_gray_alpha)?;
+                Pixels::GrayAlpha(gray_alpha)
+            }
+            ColorFormat::Rgba => {
+                let mut rgba = zeroed(width * height * 4)?;
+                self.read_rows_or_fill(&mut rgba, width * 4, &fill.color, Self::next_row_rgba)?;
+                Pixels::Rgba(rgba)
+            }
+        };
+
+        Ok(Image { width: width as u16, height: height as u16, pixels })
+    }
+
+    // Read all rows into `values` with `next_row`, checking the time budget after each row. Unless strict, rows missing from a
+    // truncated file are filled with `fill`, which holds the values of one pixel. Returns whether all rows were read.
+    fn read_rows_or_fill(&mut self, values: &mut [u8], row_length: usize, fill: &[u8], next_row: fn(&mut Self, &mut [u8]) -> Result<()>) -> Result<bool> {
+        let check_time = time_check(&self.options);
+        for start in (0..values.len()).step_by(row_length) {
+            match next_row(self, &mut values[start..(start + row_length)]) {
+                Err(Error::Io(ref error)) if !self.options.strict && error.kind() == io::ErrorKind::UnexpectedEof => {
+                    for pixel in values[start..].chunks_mut(fill.len()) {
+                        pixel.copy_from_slice(fill);
+                    }
+                    return Ok(false);
+                }
+                result => result?,
+            }
+            check_time()?;
+        }
+        Ok(true)
+    }
+
+    /// Read the whole image into memory as interleaved RGB values (R, G, B, R, G, B, ...), whatever its color format.
+    ///
+    /// Paletted pixels are looked up in the palette returned by `palette_or_default()`, the 256-color palette at the end of
+    /// file is read after the rows. Alpha channel is dropped. Like `read_image()` it must be called before any rows were read.
+    pub fn read_image_rgb(mut self) -> Result<Vec<u8>> {
+        if self.rows_read() != 0 {
+            return Err(Error::InvalidUsage("read_image_rgb called after some rows were already read"));
+        }
+
+        let (width, height) = (self.width() as usize, self.height() as usize);
+        let indices_length = if self.is_paletted() { width * height } else { 0 };
+        check_memory(&self.options, scratch_memory(&self.header) + ((width * height * 3 + indices_length) as u64) + 256 * 3)?;
+
+        let fill = self.options.fill;
+        let mut rgb = zeroed(width * height * 3)?;
+        if !self.is_paletted() {
+            self.read_rows_or_fill(&mut rgb, width * 3, &fill.color[..3], Self::next_row_rgb)?;
+            return Ok(rgb);
+        }
+
+        let mut indices = zeroed(indices_length)?;
+        let complete = self.read_rows_or_fill(&mut indices, width, &[fill.index], Self::next_row_paletted)?;
+
+        let palette = match self.palette_length() {
+            Some(256) if complete => self.into_palette()?,
+            Some(256) => Palette::vga(),
+            _ => self.palette_or_default()?,
+        };
+        palette.apply(&indices, &mut rgb)?;
+        Ok(rgb)
+    }
+
+    /// Get color palette, falling back to the standard palette for old files without palette information.
+    ///
+    /// Files of version 2.5 (fixed EGA palette), 2.8 without palette information and files with all-black palette in the header
+    /// get the standard CGA/EGA palette according to the number of colors, see `Palette::standard()`. 256-color palette is
+    /// stored at the end of file, so it must be loaded with `read_palette_early()` first. Returns `Error::NotPaletted` for
+    /// images without palette.
+    pub fn palette_or_default(&self) -> Result<Palette> {
+        let palette_length = match self.palette_length() {
+            Some(palette_length) => palette_length,
+            None => return Err(Error::NotPaletted),
+        };
+
+        let header = &self.header;
+        let no_header_palette = !header.version.has_header_palette() || header.palette.iter().all(|color| *color == [0; 3]);
+        if palette_length <= 16 && no_header_palette {
+            if let Some(palette) = Palette::standard(palette_length) {
+                return Ok(palette);
+            }
+        }
+
+        let mut rgb = [0; 256 * 3];
+        if let Some(palette_length) = header_palette(&self.header, &mut rgb) {
+            return Palette::from_rgb(&rgb[..(palette_length * 3)]);
+        }
+
+        match self.palette {
+            Some(ref palette) => Ok(palette.clone()),
+            None => Err(Error::InvalidUsage("256-color palette must be read with read_palette_early first")),
+        }
+    }
+
+    /// Read color palette of the paletted image, returns `Error::NotPaletted` for images without palette.
+    ///
+    /// If palette contains 256-colors then it is stored at the end of file and this function will read the file to the end.
+    pub fn into_palette(self) -> Result<Palette> {
+        if !self.is_paletted() {
+            return Err(Error::NotPaletted);
+        }
+
+        let mut rgb = [0; 256 * 3];
+        let palette_length = self.read_palette(&mut rgb)?;
+        Palette::from_rgb(&rgb[..(palette_length * 3)])
+    }
+
+    /// Read color palette.
+    ///
+    /// If palette contains 256-colors then it is stored at the end of file and this function will read the file to the end,
+    /// unless the length of the file was set with `set_len_hint()`.
+    ///
+    /// Returns number of colors in palette or zero if there is no palette. The actual number of bytes written to the output buffer is
+    /// equal to the returned value multiplied by 3. Format of the output buffer is R, G, B, R, G, B, ...
+    pub fn read_palette(self, buffer: &mut [u8]) -> Result<usize> {
+        use std::io::Read;
+
+        let palette_length = self.header.palette_length().unwrap_or(0) as usize;
+        if buffer.len() < palette_length * 3 {
+            return Err(Error::BufferLength {
+                expected: palette_length * 3,
+                actual: buffer.len(),
+            });
+        }
+
+        if let Some(palette_length) = header_palette(&self.header, buffer) {
+            return Ok(palette_length);
+        }
+
+        // Stop decompressing and continue reading underlying stream.
+        let consumed = (HEADER_LENGTH as u64) + self.pixel_reader.bytes_read();
+        let (stream, buffered) = self.pixel_reader.into_parts();
+        let mut stream = (&buffered[..]).chain(stream);
+
+        if let Some(length) = self.len_hint {
+            return read_palette_at(stream, consumed, length, self.options.strict, buffer);
+        }
+
+        // 256-color palette is located at the end of file. To avoid seeking we are using a bit convoluted method here to read it.
+        const PALETTE_LENGTH: usize = 256 * 3;
+        const TEMP_BUFFER_LENGTH: usize = PALETTE_LENGTH + 1;
+
+        let mut temp_buffer = [0; TEMP_BUFFER_LENGTH];
+        let mut pos = 0;
+
+        loop {
+            let read = stream.read(&mut temp_buffer[pos..TEMP_BUFFER_LENGTH])?;
+            if read != 0 {
+                pos = (pos + read) % TEMP_BUFFER_LENGTH;
+            } else {
+                // We've reached the end of file, therefore temp_buffer must now contain the palette.
+                if temp_buffer[pos] != PALETTE_START && self.options.strict {
+                    return Err(Error::NoPalette);
+                }
+
+                buffer[0..(TEMP_BUFFER_LENGTH - pos - 1)].copy_from_slice(&temp_buffer[(pos + 1)..TEMP_BUFFER_LENGTH]);
+                buffer[(TEMP_BUFFER_LENGTH - pos - 1)..PALETTE_LENGTH].copy_from_slice(&temp_buffer[0..pos]);
+
+                return Ok(256);
+            }
+        }
+    }
+
+    /// Consume the rest of the PCX data and get the underlying stream positioned right after it, e.g. to parse the data following
+    /// an image embedded in a larger file.
+    ///
+    /// Rows which were not read yet are skipped, then padding of the last lane and the 256-color palette are consumed. Padding
+    /// must be present unless it is followed by the palette or by the end of stream. Returns the palette of paletted images
+    /// (see `palette_or_default()`) together with the stream. Data which was read ahead is chained in front of the underlying
+    /// stream, seekable streams can be positioned exactly with `into_inner()` instead.
+    pub fn finish(self) -> Result<(Option<Palette>, Remainder<R>)> {
+        self.finish_with_length().map(|(palette, stream, _)| (palette, stream))
+    }
+
+    /// Consume the rest of the PCX data like `finish()`, also returning the total length of the PCX data in bytes: header,
+    /// pixel data, padding and palette.
+    pub fn finish_with_length(mut self) -> Result<(Option<Palette>, Remainder<R>, u64)> {
+        use std::io::Read;
+
+        while self.has_more_rows() {
+            self.check_row_start()?;
+            self.next_lanes()?;
+        }
+
+        let palette_at_end = self.palette_length() == Some(256);
+        let palette = match self.palette_length() {
+            Some(256) | None => None,
+            Some(_) => Some(self.palette_or_default()?),
+        };
+
+        // Part of the padding may have been decompressed already as a pending run.
+        let padding = if self.padding_read {
+            0
+        } else {
+            self.header.lane_padding().saturating_sub(self.pixel_reader.pending_run().0 as u16)
+        };
+        let (compressed, strict) = (self.header.is_compressed, self.options.strict);
+        let consumed = self.bytes_consumed();
+        let (stream, buffered) = self.pixel_reader.into_parts();
+        let mut stream = CountingReader { stream: io::Cursor::new(buffered).chain(stream), bytes_read: 0 };
+
+        // Decode padding code by code, so nothing past it is consumed.
+        let mut remaining = padding;
+        let mut marker_read = false;
+        while remaining > 0 {
+            let mut code = [0];
+            if stream.read(&mut code)? == 0 {
+                break;
+            }
+            if palette_at_end && remaining == padding && code[0] == PALETTE_START {
+                marker_read = true;
+                break;
+            }
+
+            if compressed && (code[0] & 0xC0) == 0xC0 {
+                stream.read_u8()?;
+                remaining = remaining.saturating_sub((code[0] & 0x3F) as u16);
+            } else {
+                remaining -= 1;
+            }
+        }
+
+        if !palette_at_end {
+            return Ok((palette, stream.stream, consumed + stream.bytes_read));
+        }
+
+        let mut rgb = [PALETTE_START; 256 * 3 + 1];
+        stream.read_exact(&mut rgb[(marker_read as usize)..])?;
+        if rgb[0] != PALETTE_START && strict {
+            return Err(Error::NoPalette);
+        }
+        Ok((Some(Palette::from_rgb(&rgb[1..])?), stream.stream, consumed + stream.bytes_read))
+    }
+}
+
+// Read 256-color palette which is located at the end of file with the known `length`, after `consumed` bytes were read.
+fn read_palette_at<R: io::Read>(mut stream: R, consumed: u64, length: u64, strict: bool, buffer: &mut [u8]) -> Result<usize> {
+    use std::io::Read;
+
+    const PALETTE_LENGTH: u64 = 256 * 3 + 1;
+
+    if length < consumed + PALETTE_LENGTH {
+        return Err(Error::NoPalette);
+    }
+
+    let skip = length - PALETTE_LENGTH - consumed;
+    if io::copy(&mut (&mut stream).take(skip), &mut io::sink())? != skip {
+        return Err(io::Error::from(io::ErrorKind::UnexpectedEof).into());
+    }
+
+    let mut palette = [0; PALETTE_LENGTH as usize];
+    stream.read_exact(&mut palette)?;
+    if palette[0] != PALETTE_START && strict {
+        return Err(Error::NoPalette);
+    }
+
+    buffer[..(256 * 3)].copy_from_slice(&palette[1..]);
+    Ok(256)
+}
+
+/// Stream following the PCX data returned by `Reader::finish()`: data read ahead by the reader chained in front of the underlying stream.
+pub type Remainder<R> = io::Chain<io::Cursor<Vec<u8>>, R>;
+
+/// Iterator over the rows of the image, created by `Reader::rows()`.
+#[derive(Debug)]
+pub struct Rows<'a, R: io::Read + 'a> {
+    reader: &'a mut Reader<R>,
+    failed: bool,
+}
+
+/// Iterator over the rows of the image from bottom to top, created by `Reader::rows_rev()`.
+///
+/// When the iterator is dropped the reader is positioned after the last row, so the palette can be read afterwards.
+#[derive(Debug)]
+pub struct RowsRev<'a, R: io::Read + io::Seek + 'a> {
+    reader: &'a mut Reader<R>,
+    // Start of pixel data in the underlying stream.
+    start: u64,
+    // Number of bytes of pixel data read and pending run at the start of each row, followed by the same at the end of the last row.
+    checkpoints: Vec<(u64, (u8, u8))>,
+    rows_left: u16,
+    failed: bool,
+}
+
+/// Iterator over the values of one color plane in all rows of the image, created by `Reader::plane_reader()`.
+///
+/// Each item contains `width` values of the plane. Lanes of the other planes are skipped without copying them out.
+#[derive(Debug)]
+pub struct PlaneRows<'a, R: io::Read + io::Seek + 'a> {
+    reader: &'a mut Reader<R>,
+    plane: u8,
+    failed: bool,
+}
+
+// Decode next row of the reader into a newly allocated `Row`.
+pub(crate) fn next_row<R: io::Read>(reader: &mut Reader<R>) -> Result<Row> {
+    let width = reader.width() as usize;
+    match reader.color_format() {
+        ColorFormat::Paletted => {
+            let mut indices = zeroed(width)?;
+            reader.next_row_paletted(&mut indices)?;
+            Ok(Row::Paletted(indices))
+        }
+        ColorFormat::Rgb => {
+            let mut rgb = zeroed(width * 3)?;
+            reader.next_row_rgb(&mut rgb)?;
+            Ok(Row::Rgb(rgb))
+        }
+        ColorFormat::GrayAlpha => {
+            let mut gray_alpha = zeroed(width * 2)?;
+            reader.next_row_gray_alpha(&mut gray_alpha)?;
+            Ok(Row::GrayAlpha(gray_alpha))
+        }
+        ColorFormat::Rgba => {
+            let mut rgba = zeroed(width * 4)?;
+            reader.next_row_rgba(&mut rgba)?;
+            Ok(Row::Rgba(rgba))
+        }
+    }
+}
+
+impl<'a, R: io::Read> Iterator for Rows<'a, R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || !self.reader.has_more_rows() {
+            return None;
+        }
+
+        let row = next_row(self.reader);
+        self.failed = row.is_err();
+        Some(row)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, Some(self.reader.rows_remaining() as usize))
+        }
+    }
+}
+
+impl<'a, R: io::Read + io::Seek> Iterator for RowsRev<'a, R> {
+    type Item = Result<Row>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || self.rows_left == 0 {
+            return None;
+        }
+
+        let y = self.rows_left - 1;
+        let (bytes_read, pending_run) = self.checkpoints[y as usize];
+        let result = self.reader.pixel_reader.resume(self.start, bytes_read, pending_run).map_err(Error::from).and_then(|_| {
+            self.reader.state = State::Lane { row: y, plane: 0 };
+            next_row(self.reader)
+        });
+
+        // Offsets of all rows were recorded while indexing.
+        self.reader.row_offsets.truncate(self.checkpoints.len());
+
+        self.rows_left = y;
+        self.failed = result.is_err();
+        Some(result)
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, Some(self.rows_left as usize))
+        }
+    }
+}
+
+impl<'a, R: io::Read + io::Seek> Iterator for PlaneRows<'a, R> {
+    type Item = Result<Vec<u8>>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if self.failed || !self.reader.has_more_rows() {
+            return None;
+        }
+
+        let mut values = vec![0; self.reader.width() as usize];
+        let (reader, wanted) = (&mut *self.reader, self.plane);
+        let result = reader.check_row_start().and_then(|_| {
+            (0..reader.header.number_of_color_planes).try_for_each(|plane| {
+                if plane == wanted {
+                    reader.next_lane(&mut values)
+                } else {
+                    reader.skip_lane()
+                }
+            })
+        });
+        self.failed = result.is_err();
+        Some(result.map(|_| values))
+    }
+
+    fn size_hint(&self) -> (usize, Option<usize>) {
+        if self.failed {
+            (0, Some(0))
+        } else {
+            (0, Some(self.reader.rows_remaining() as usize))
+        }
+    }
+}
+
+impl<'a, R: io::Read + io::Seek> Drop for RowsRev<'a, R> {
+    fn drop(&mut self) {
+        let height = self.reader.height();
+        let (bytes_read, pending_run) = self.checkpoints[height as usize];
+        self.reader.state = match self.reader.pixel_reader.resume(self.start, bytes_read, pending_run) {
+            Ok(_) => State::Lane { row: height, plane: 0 },
+            Err(_) => State::Failed { row: height },
+        };
+    }
+}
+
+impl<R: io::Read + io::Seek> Reader<R> {
+    /// Consume the rest of the PCX data like `finish()` and get the underlying stream positioned right after it.
+    pub fn into_inner(self) -> Result<(Option<Palette>, R)> {
+        let (palette, stream) = self.finish()?;
+        let (read_ahead, mut stream) = stream.into_inner();
+        let unread = (read_ahead.get_ref().len() as u64) - read_ahead.position();
+        stream.seek(io::SeekFrom::Current(-(unread as i64)))?;
+        Ok((palette, stream))
+    }
+
+    /// Position the reader at the start of row `y`, so that the next call to one of the `next_row_*()` functions reads it.
+    ///
+    /// Rows can be visited in any order, e.g. to render a cropped region. Start positions of rows are recorded as they are
+    /// reached, so seeking to a row which was reached before is immediate and seeking further skips the compressed data
+    /// without decoding it.
+    pub fn seek_row(&mut self, y: u16) -> Result<()> {
+        if y >= self.height() {
+            return Err(Error::InvalidUsage("row index is out of range"));
+        }
+
+        if self.row_index.is_none() {
+            let checkpoints = (self.height() as usize) * mem::size_of::<(u64, (u8, u8))>();
+            check_memory(&self.options, scratch_memory(&self.header) + (checkpoints as u64))?;
+            let start = self.pixel_reader.start()?;
+            let mut checkpoints = Vec::new();
+            reserve(&mut checkpoints, self.height() as usize)?;
+            checkpoints.push((0, (0, 0)));
+            self.row_index = Some(RowIndex { start, checkpoints });
+        }
+
+        let lane_length = (self.header.lane_proper_length() as u64) + (self.header.lane_padding() as u64);
+        let row_length = lane_length * (self.header.number_of_color_planes as u64);
+        let (index, pixel_reader) = (self.row_index.as_mut().unwrap(), &mut self.pixel_reader);
+        let known = cmp::min(y as usize, index.checkpoints.len() - 1);
+        let (bytes_read, pending_run) = index.checkpoints[known];
+
+        let mut result = pixel_reader.resume(index.start, bytes_read, pending_run);
+        for _ in known..(y as usize) {
+            result = result.and_then(|_| pixel_reader.skip(row_length));
+            if result.is_err() {
+                break;
+            }
+            index.checkpoints.push((pixel_reader.bytes_read(), pixel_reader.pending_run()));
+        }
+
+        self.state = match result {
+            Ok(_) => State::Lane { row: y, plane: 0 },
+            Err(_) => State::Failed { row: y },
+        };
+        result.map_err(Error::from)
+    }
+
+    // Offset of the stored pixel data following the data read so far, relative to the start of file, together with the run
+    // continuing into it.
+    pub(crate) fn stored_position(&self) -> (u64, (u8, u8)) {
+        ((HEADER_LENGTH as u64) + self.pixel_reader.bytes_read(), self.pixel_reader.pending_run())
+    }
+
+    /// Read row `y` into `buffer` converting it to the given pixel format, see `seek_row()` and `next_row_into()`.
+    pub fn row(&mut self, y: u16, buffer: &mut [u8], format: PixelFormat) -> Result<()> {
+        self.seek_row(y)?;
+        self.next_row_into(buffer, format)
+    }
+
+    /// Get the stored pixel data of row `y` without decoding it, e.g. for tools comparing or patching files row by row.
+    ///
+    /// `buffer` is replaced with the lanes of all planes of the row one after another, each as returned by `next_lane_raw()`.
+    /// The reader is left positioned at the next row, see `seek_row()`.
+    pub fn raw_row(&mut self, y: u16, buffer: &mut Vec<u8>) -> Result<()> {
+        self.seek_row(y)?;
+        buffer.clear();
+        let mut lane = Vec::new();
+        for _ in 0..self.header.number_of_color_planes {
+            self.next_lane_raw(&mut lane)?;
+            buffer.extend_from_slice(&lane);
+        }
+        Ok(())
+    }
+
+    /// Iterate over the values of color plane `plane` in all rows from top to bottom, e.g. to process only the red channel of a
+    /// 24-bit image. Plane 0 is red (or gray), 1 green, 2 blue and 3 alpha, as far as present in the image.
+    ///
+    /// The reader is positioned at the first row first, so the planes can be read one after another, each with its own iterator.
+    /// Returns `Error::NotRgb` for paletted images, which do not store color channels in separate planes.
+    pub fn plane_reader(&mut self, plane: u8) -> Result<PlaneRows<'_, R>> {
+        if self.is_paletted() {
+            return Err(Error::NotRgb);
+        }
+        if plane >= self.header.number_of_color_planes {
+            return Err(Error::InvalidUsage("plane index is out of range"));
+        }
+
+        self.seek_row(0)?;
+        Ok(PlaneRows { reader: self, plane, failed: false })
+    }
+
+    /// Iterate over the rows of the image from bottom to top, e.g. for uploading to APIs expecting bottom-up images.
+    ///
+    /// Must be called before any rows were read. This function decodes all rows once to find out where each of them starts,
+    /// then the iterator seeks to the rows one by one, so only one row is kept in memory at a time.
+    pub fn rows_rev(&mut self) -> Result<RowsRev<'_, R>> {
+        if self.rows_read() != 0 {
+            return Err(Error::InvalidUsage("rows_rev called after some rows were already read"));
+        }
+
+        let start = self.pixel_reader.start()?;
+        let mut checkpoints = Vec::new();
+        reserve(&mut checkpoints, (self.height() as usize) + 1)?;
+        for _ in 0..self.height() {
+            checkpoints.push((self.pixel_reader.bytes_read(), self.pixel_reader.pending_run()));
+            self.check_row_start()?;
+            self.next_lanes()?;
+        }
+        checkpoints.push((self.pixel_reader.bytes_read(), self.pixel_reader.pending_run()));
+
+        Ok(RowsRev {
+            rows_left: self.height(),
+            reader: self,
+            start,
+            checkpoints,
+            failed: false,
+        })
+    }
+
+    /// Read color palette without reading the rows first. Returns `Error::NotPaletted` for images without palette.
+    ///
+    /// 256-color palette is stored at the end of file, this function seeks there to read it and seeks back afterwards,
+    /// so it can be called at any time and does not affect reading of the rows.
+    pub fn read_palette_early(&mut self) -> Result<Palette> {
+        if !self.is_paletted() {
+            return Err(Error::NotPaletted);
+        }
+
+        let mut rgb = [0; 256 * 3];
+        if let Some(palette_length) = header_palette(&self.header, &mut rgb) {
+            return Palette::from_rgb(&rgb[..(palette_length * 3)]);
+        }
+
+        let stream = self.pixel_reader.get_mut();
+        let position = stream.stream_position()?;
+        let result = read_palette_at_end(stream, &mut rgb, self.options.strict);
+        stream.seek(io::SeekFrom::Start(position))?;
+        result?;
+
+        let palette = Palette::from_rgb(&rgb)?;
+        self.palette = Some(palette.clone());
+        Ok(palette)
+    }
+}
+
+/// Decode the whole PCX image stored in memory, decoding strips of rows on all available cores.
+///
+/// Compressed data is scanned first to find where each strip starts, which is much cheaper than decoding it, then the strips are
+/// decoded in parallel directly into the resulting image. Result is the same as of `pcx::decode`.
+#[cfg(feature = "parallel")]
+pub fn decode_parallel(data: &[u8]) -> Result<Image> {
+    decode_parallel_with_options(data, Options::default())
+}
+
+/// Decode the whole PCX image stored in memory on all available cores like `decode_parallel()`, with the given options.
+///
+/// Limits, `max_memory` and `time_budget` are enforced as by `Reader::read_image()`, the time budget is checked by each thread
+/// after each row. Unlike `read_image()` truncated files are rejected even if `Options::strict` is not set.
+#[cfg(feature = "parallel")]
+pub fn decode_parallel_with_options(data: &[u8], options: Options) -> Result<Image> {
+    let threads = thread::available_parallelism().map(|threads| threads.get()).unwrap_or(1);
+    decode_strips(data, threads, options)
+}
+
+// Decode the image split into at most `strips` strips of rows, each on its own thread.
+#[cfg(feature = "parallel")]
+fn decode_strips(data: &[u8], strips: usize, options: Options) -> Result<Image> {
+    let mut reader = Reader::new_with_options(io::Cursor::new(data), options)?;
+    check_memory(&options, image_memory(&reader.header))?;
+    let check_time = time_check(&options);
+    let (width, height) = reader.dimensions();
+    let rows_per_strip = (height as usize).div_ceil(strips.max(1));
+
+    // Row, number of bytes of pixel data before it and pending run at the start of each strip.
+    let lane_length = (reader.header.lane_proper_length() as u64) + (reader.header.lane_padding() as u64);
+    let row_length = lane_length * (reader.header.number_of_color_planes as u64);
+    let mut checkpoints = Vec::new();
+    for row in (0..height).step_by(rows_per_strip) {
+        if row != 0 {
+            reader.pixel_reader.skip(row_length * (rows_per_strip as u64))?;
+        }
+        checkpoints.push((row, reader.pixel_reader.bytes_read(), reader.pixel_reader.pending_run()));
+    }
+
+    let color_format = reader.color_format();
+    let row_values = (width as usize) * color_format.channels();
+    let mut values = zeroed(row_values * (height as usize))?;
+    let check_time = &check_time;
+    thread::scope(|scope| {
+        let strips: Vec<_> = values
+            .chunks_mut(row_values * rows_per_strip)
+            .zip(checkpoints)
+            .map(|(strip, (row, bytes_read, pending_run))| {
+                scope.spawn(move || -> Result<()> {
+                    let mut reader = Reader::new_with_options(io::Cursor::new(data), options)?;
+                    reader.pixel_reader.resume(HEADER_LENGTH as u64, bytes_read, pending_run)?;
+                    reader.state = State::Lane { row, plane: 0 };
+                    strip.chunks_mut(row_values).try_for_each(|values| {
+                        reader.next_row_native(values)?;
+                        check_time()
+                    })
+                })
+            })
+            .collect();
+        strips.into_iter().try_for_each(|strip| strip.join().expect("decoding thread panicked"))
+    })?;
+
+    let pixels = match color_format {
+        ColorFormat::Paletted => {
+            let mut palette = vec![0; 256 * 3];
+            let palette_length = Reader::new_with_options(data, options)?.read_palette(&mut palette)?;
+            palette.truncate(palette_length * 3);
+            Pixels::Paletted { indices: values, palette }
+        }
+        ColorFormat::Rgb => Pixels::Rgb(values),
+        ColorFormat::GrayAlpha => Pixels::GrayAlpha(values),
+        ColorFormat::Rgba => Pixels::Rgba(values),
+    };
+    Ok(Image { width, height, pixels })
+}
+
+// Upper bound of heap memory used by the reader apart from the image allocated by `read_image`: scratch buffers, row offsets,
+// cached palette and a row yielded by `rows()`.
+fn scratch_memory(header: &Header) -> u64 {
+    let width = header.size.0 as u64;
+    let planes = (header.lane_proper_length() as u64) * (header.number_of_color_planes as u64);
+    let row_offsets = ((header.size.1 as u64) + 1) * 8;
+    let decompressor = if header.is_compressed { DECOMPRESSOR_BUFFER_LENGTH as u64 } else { 0 };
+    planes + width * 4 + row_offsets + 256 * 3 + width * 4 + decompressor
+}
+
+// Memory required for reading the whole image with `Reader::read_image()`.
+pub(crate) fn image_memory(header: &Header) -> u64 {
+    let channels = header.color_format().channels() as u64;
+    scratch_memory(header) + (header.size.0 as u64) * (header.size.1 as u64) * channels + 256 * 3
+}
+
+// Closure failing with `Error::TimedOut` once the time budget from `options` measured from now is exhausted.
+fn time_check(options: &Options) -> impl Fn() -> Result<()> {
+    let (started, time_budget) = (Instant::now(), options.time_budget);
+    move || match time_budget {
+        Some(time_budget) if started.elapsed() > time_budget => Err(Error::TimedOut),
+        _ => Ok(()),
+    }
+}
+
+fn check_memory(options: &Options, required: u64) -> Result<()> {
+    match options.max_memory {
+        Some(maximum) if required > maximum => Err(Error::LimitExceeded {
+            limit: "memory",
+            maximum,
+            required,
+        }),
+        _ => Ok(()),
+    }
+}
+
+// Allocate zero-filled buffer for the whole image, reporting failure as an error instead of aborting.
+fn zeroed(length: usize) -> Result<Vec<u8>> {
+    let mut buffer = Vec::new();
+    reserve(&mut buffer, length)?;
+    buffer.resize(length, 0);
+    Ok(buffer)
+}
+
+// Reserve capacity for exactly `additional` more elements, failing with `Error::OutOfMemory` instead of aborting.
+fn reserve<T>(buffer: &mut Vec<T>, additional: usize) -> Result<()> {
+    buffer.try_reserve_exact(additional).map_err(|_| Error::OutOfMemory {
+        required: additional.saturating_mul(mem::size_of::<T>()),
+    })
+}
+
+// Luminance of the color using Rec. 601 coefficients.
+fn luma(rgb: [u8; 3]) -> u8 {
+    ((77 * (rgb[0] as u32) + 150 * (rgb[1] as u32) + 29 * (rgb[2] as u32) + 128) >> 8) as u8
+}
+
+// Multiply color values of RGBA pixel by its alpha.
+fn premultiply(pixel: &mut [u8]) {
+    let alpha = pixel[3] as u32;
+    for value in &mut pixel[..3] {
+        *value = (((*value as u32) * alpha + 127) / 255) as u8;
+    }
+}
+
+// Copy palette stored in the header to `buffer` and return number of colors. Returns `None` for 256-color palette which is
+// stored at the end of file.
+fn header_palette(header: &Header, buffer: &mut [u8]) -> Option<usize> {
+    match header.palette_length() {
+        Some(2) => {
+            // Special case - monochrome image.
+
+            // Black.
+            buffer[0] = 0;
+            buffer[1] = 0;
+            buffer[2] = 0;
+
+            // White.
+            buffer[3] = 255;
+            buffer[4] = 255;
+            buffer[5] = 255;
+
+            Some(2)
+        }
+        Some(palette_length @ 1..=16) => {
+            // Palettes of 16 colors or smaller are stored in the header.
+            for i in 0..(palette_length as usize) {
+                buffer[(i * 3)..((i + 1) * 3)].copy_from_slice(&header.palette[i]);
+            }
+            Some(palette_length as usize)
+        }
+        Some(256) => None,
+        _ => Some(0),
+    }
+}
+
+// Seek to the 256-color palette at the end of file and read it to `buffer`. Missing marker is ignored unless `strict` is set.
+fn read_palette_at_end<S: io::Read + io::Seek>(stream: &mut S, buffer: &mut [u8], strict: bool) -> Result<()> {
+    let mut temp_buffer = [0; 256 * 3 + 1];
+    stream.seek(io::SeekFrom::End(-(temp_buffer.len() as i64)))?;
+    stream.read_exact(&mut temp_buffer)?;
+    if temp_buffer[0] != PALETTE_START && strict {
+        return Err(Error::NoPalette);
+    }
+
+    buffer[..(256 * 3)].copy_from_slice(&temp_buffer[1..]);
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::{self, Write};
+    use std::time::Duration;
+
+    use super::Reader;
+    use {Error, Fill, Options, Warning};
+    use low_level::header::{self, ColorFormat};
+    use low_level::rle;
+    use pixels::{ChannelOrder, PixelFormat, Pixels, Row};
+    use palette::{Palette, Transparency};
+
+    #[test]
+    fn gmarbles() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let read = &mut &data[..];
         let mut reader = Reader::new(read).unwrap();
 
-        assert_eq!(reader.header.version, header::Version::V5);
-        assert_eq!(reader.header.is_compressed, true);
-        assert_eq!(reader.header.bit_depth, 8);
-        assert_eq!(reader.header.size, (141, 99));
-        assert_eq!(reader.header.start, (0, 0));
-        assert_eq!(reader.header.dpi, (300, 300));
-        assert_eq!(reader.header.number_of_color_planes, 1);
-        assert_eq!(reader.header.lane_length, 142);
+        assert_eq!(reader.header.version, header::Version::V5);
+        assert!(reader.header.is_compressed);
+        assert_eq!(reader.header.bit_depth, 8);
+        assert_eq!(reader.header.size, (141, 99));
+        assert_eq!(reader.header.start, (0, 0));
+        assert_eq!(reader.header.dpi, (300, 300));
+        assert_eq!(reader.header.number_of_color_planes, 1);
+        assert_eq!(reader.header.lane_length, 142);
+        assert_eq!(&reader.raw_header().bytes()[..], &data[..128]);
+        assert_eq!((reader.dimensions_u32(), reader.dimensions_usize(), reader.pixel_count()), ((141, 99), (141, 99), 141 * 99));
+        assert_eq!((reader.row_length(PixelFormat::Rgba), reader.buffer_length(PixelFormat::Rgb)), (141 * 4, Some(141 * 99 * 3)));
+
+        assert!(reader.is_paletted());
+        assert_eq!(reader.palette_length(), Some(256));
+
+        let mut row: Vec<u8> = vec![0; reader.width() as usize];
+        for y in 0..reader.height() {
+            assert_eq!(reader.rows_read(), y);
+            assert_eq!(reader.rows_remaining(), reader.height() - y);
+            assert!(reader.has_more_rows());
+            reader.next_row_paletted(&mut row[..]).unwrap();
+        }
+        assert_eq!(reader.rows_read(), reader.height());
+        assert_eq!(reader.rows_remaining(), 0);
+        assert!(!reader.has_more_rows());
+
+        let mut palette = [0; 256 * 3];
+        assert_eq!(reader.read_palette(&mut palette).unwrap(), 256);
+    }
+
+    #[test]
+    fn marbles() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let read = &mut &data[..];
+        let mut reader = Reader::new(read).unwrap();
+
+        assert_eq!(reader.header.version, header::Version::V5);
+        assert!(reader.header.is_compressed);
+        assert_eq!(reader.header.bit_depth, 8);
+        assert_eq!(reader.header.size, (143, 101));
+        assert_eq!(reader.header.start, (0, 0));
+        assert_eq!(reader.header.dpi, (300, 300));
+        assert_eq!(reader.header.number_of_color_planes, 3);
+        assert_eq!(reader.header.lane_length, 144);
+
+        assert!(!reader.is_paletted());
+
+        let mut r: Vec<u8> = vec![0; reader.width() as usize];
+        let mut g: Vec<u8> = vec![0; reader.width() as usize];
+        let mut b: Vec<u8> = vec![0; reader.width() as usize];
+        while reader.has_more_rows() {
+            reader.next_row_rgb_separate(&mut r[..], &mut g[..], &mut b[..]).unwrap();
+        }
+        assert_eq!(reader.rows_read(), reader.height());
+
+        let mut palette = [0; 0];
+        assert_eq!(reader.read_palette(&mut palette).unwrap(), 0);
+    }
+
+    #[test]
+    fn marbles_interleaved() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut separate = Reader::new(&data[..]).unwrap();
+        let mut interleaved = Reader::new(&data[..]).unwrap();
+
+        let width = separate.width() as usize;
+        let mut r: Vec<u8> = vec![0; width];
+        let mut g: Vec<u8> = vec![0; width];
+        let mut b: Vec<u8> = vec![0; width];
+        let mut rgb: Vec<u8> = vec![0; width * 3];
+        for _ in 0..separate.height() {
+            separate.next_row_rgb_separate(&mut r, &mut g, &mut b).unwrap();
+            interleaved.next_row_rgb(&mut rgb).unwrap();
+
+            for x in 0..width {
+                assert_eq!(&rgb[(x * 3)..(x * 3 + 3)], &[r[x], g[x], b[x]]);
+            }
+        }
+    }
+
+    #[test]
+    fn read_image_paletted() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        assert_eq!(image.dimensions(), (141, 99));
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut row: Vec<u8> = vec![0; reader.width() as usize];
+        let (indices, palette) = match image.pixels {
+            Pixels::Paletted { indices, palette } => (indices, palette),
+            _ => panic!("gmarbles.pcx is paletted"),
+        };
+        for y in 0..(reader.height() as usize) {
+            reader.next_row_paletted(&mut row).unwrap();
+            assert_eq!(&indices[(y * row.len())..((y + 1) * row.len())], &row[..]);
+        }
+
+        let mut palette_read = [0; 256 * 3];
+        reader.read_palette(&mut palette_read).unwrap();
+        assert_eq!(&palette[..], &palette_read[..]);
+    }
+
+    #[test]
+    fn read_image_rgb() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        assert_eq!(image.dimensions(), (143, 101));
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut row: Vec<u8> = vec![0; (reader.width() as usize) * 3];
+        let rgb = match image.pixels {
+            Pixels::Rgb(rgb) => rgb,
+            _ => panic!("marbles.pcx is RGB"),
+        };
+        for y in 0..(reader.height() as usize) {
+            reader.next_row_rgb(&mut row).unwrap();
+            assert_eq!(&rgb[(y * row.len())..((y + 1) * row.len())], &row[..]);
+        }
+    }
+
+    #[test]
+    fn errors() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+
+        assert!(matches!(Reader::new(&data[1..]), Err(Error::NotPcx)));
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut rgb: Vec<u8> = vec![0; (reader.width() as usize) * 3];
+        assert!(matches!(reader.next_row_rgb(&mut rgb), Err(Error::NotRgb)));
+        assert!(matches!(reader.next_row_paletted(&mut rgb), Err(Error::BufferLength { expected: 141, actual: 423 })));
+
+        let mut reader = Reader::new(&data[..200]).unwrap();
+        let mut row: Vec<u8> = vec![0; reader.width() as usize];
+        let result = (0..reader.height()).map(|_| reader.next_row_paletted(&mut row)).find(|result| result.is_err());
+        match result {
+            Some(Err(Error::Io(ref error))) if error.kind() == io::ErrorKind::UnexpectedEof => {}
+            result => panic!("expected unexpected EOF, got {:?}", result),
+        }
+
+        let mut corrupted = data.to_vec();
+        let marker_position = corrupted.len() - 256 * 3 - 1;
+        corrupted[marker_position] = 0;
+        let mut reader = Reader::new(&corrupted[..]).unwrap();
+        for _ in 0..reader.height() {
+            reader.next_row_paletted(&mut row).unwrap();
+        }
+        let mut palette = [0; 256 * 3];
+        assert!(matches!(reader.read_palette(&mut palette), Err(Error::NoPalette)));
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(&data[..]).unwrap();
+        assert!(matches!(reader.next_row_paletted(&mut row), Err(Error::NotPaletted)));
+    }
+
+    #[test]
+    fn gray_alpha() {
+        let (width, height) = (5u16, 3u16);
+        let lane_length = header::lane_length(width, 8);
+        let mut data = Vec::new();
+        header::write_format(&mut data, 8, 2, (width, height), (300, 300), &[[0; 3]; 16]).unwrap();
+        {
+            let mut compressor = rle::Compressor::new(&mut data, lane_length);
+            for y in 0..height {
+                let mut lane = vec![0; lane_length as usize];
+                for x in 0..width {
+                    lane[x as usize] = (x * 40 + y) as u8;
+                }
+                compressor.write_all(&lane).unwrap();
+                for x in 0..width {
+                    lane[x as usize] = (255 - x * 10 - y) as u8;
+                }
+                compressor.write_all(&lane).unwrap();
+            }
+            compressor.finish().unwrap();
+        }
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        assert_eq!(reader.color_format(), ColorFormat::GrayAlpha);
+        assert!(!reader.is_paletted());
+        assert_eq!(reader.palette_length(), None);
+
+        let mut gray_alpha = vec![0; 10];
+        reader.next_row_gray_alpha(&mut gray_alpha).unwrap();
+        assert_eq!(gray_alpha, [0, 255, 40, 245, 80, 235, 120, 225, 160, 215]);
+
+        let mut rgb = vec![0; 15];
+        reader.next_row_rgb(&mut rgb).unwrap();
+        assert_eq!(rgb, [1, 1, 1, 41, 41, 41, 81, 81, 81, 121, 121, 121, 161, 161, 161]);
+
+        let (mut r, mut g, mut b) = (vec![0; 5], vec![0; 5], vec![0; 5]);
+        reader.next_row_rgb_separate(&mut r, &mut g, &mut b).unwrap();
+        assert_eq!(r, [2, 42, 82, 122, 162]);
+        assert_eq!(r, g);
+        assert_eq!(r, b);
+
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        match image.pixels {
+            Pixels::GrayAlpha(ref pixels) => assert_eq!(&pixels[..10], &[0, 255, 40, 245, 80, 235, 120, 225, 160, 215]),
+            ref pixels => panic!("expected grayscale with alpha, got {:?}", pixels),
+        }
+        assert_eq!(&image.to_rgb()[..6], &[0, 0, 0, 40, 40, 40]);
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(&data[..]).unwrap();
+        assert!(matches!(reader.next_row_gray_alpha(&mut gray_alpha), Err(Error::NotGrayAlpha)));
+    }
 
-        assert!(reader.is_paletted());
-        assert_eq!(reader.palette_length(), Some(256));
+    #[test]
+    fn rows() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        let indices = match image.pixels {
+            Pixels::Paletted { indices, .. } => indices,
+            _ => panic!("gmarbles.pcx is paletted"),
+        };
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut row: Vec<u8> = vec![0; reader.width() as usize];
+        reader.next_row_paletted(&mut row).unwrap();
+        assert_eq!(reader.rows().size_hint(), (0, Some(98)));
+
+        let rows: Vec<_> = reader.rows().map(|row| row.unwrap()).collect();
+        assert_eq!(rows.len(), 98);
+        for (y, row) in rows.into_iter().enumerate() {
+            let expected = indices[((y + 1) * 141)..((y + 2) * 141)].to_vec();
+            assert_eq!(row, Row::Paletted(expected));
+        }
+        assert!(reader.rows().next().is_none());
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(&data[..]).unwrap();
+        match reader.rows().next() {
+            Some(Ok(Row::Rgb(ref rgb))) => assert_eq!(rgb.len(), 143 * 3),
+            row => panic!("expected RGB row, got {:?}", row),
+        }
 
-        let mut row: Vec<u8> = iter::repeat(0).take(reader.width() as usize).collect();
+        let mut reader = Reader::new(&data[..200]).unwrap();
+        let results: Vec<_> = reader.rows().collect();
+        assert!(results.last().unwrap().is_err());
+        assert!(results[..(results.len() - 1)].iter().all(|row| row.is_ok()));
+    }
+
+    #[test]
+    fn row_state() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut rgb: Vec<u8> = vec![0; (reader.width() as usize) * 3];
         for _ in 0..reader.height() {
-            reader.next_row_paletted(&mut row[..]).unwrap();
+            reader.next_row_rgb(&mut rgb).unwrap();
         }
+        assert!(reader.is_finished());
+        assert!(matches!(reader.next_row_rgb(&mut rgb), Err(Error::EndOfImage)));
+        assert!(matches!(reader.next_row_rgba(&mut vec![0; 143 * 4]), Err(Error::EndOfImage)));
+        assert_eq!(reader.rows_read(), 101);
 
-        let mut palette = [0; 256 * 3];
-        assert_eq!(reader.read_palette(&mut palette).unwrap(), 256);
+        // Truncated in the middle of a row.
+        let mut reader = Reader::new(&data[..2000]).unwrap();
+        assert!(!reader.is_finished());
+        let (mut r, mut g, mut b) = (vec![0; 143], vec![0; 143], vec![0; 143]);
+        let result = (0..reader.height()).map(|_| reader.next_row_rgb_separate(&mut r, &mut g, &mut b)).find(|result| result.is_err());
+        assert!(matches!(result, Some(Err(Error::Io(_)))));
+        let rows_read = reader.rows_read();
+        assert!(reader.is_finished());
+        assert!(matches!(reader.next_row_rgb_separate(&mut r, &mut g, &mut b), Err(Error::InvalidUsage(_))));
+        assert!(matches!(reader.next_row_rgb(&mut rgb), Err(Error::InvalidUsage(_))));
+        assert_eq!(reader.rows_read(), rows_read);
+        assert!(reader.rows().next().unwrap().is_err());
     }
 
     #[test]
-    fn marbles() {
+    fn row_spans() {
         let data = include_bytes!("../test-data/marbles.pcx");
-        let read = &mut &data[..];
-        let mut reader = Reader::new(read).unwrap();
+        let mut reader = Reader::new(&data[..]).unwrap();
+        assert_eq!(reader.row_spans().count(), 0);
 
-        assert_eq!(reader.header.version, header::Version::V5);
-        assert!(reader.header.is_compressed);
-        assert_eq!(reader.header.bit_depth, 8);
-        assert_eq!(reader.header.size, (143, 101));
-        assert_eq!(reader.header.start, (0, 0));
-        assert_eq!(reader.header.dpi, (300, 300));
-        assert_eq!(reader.header.number_of_color_planes, 3);
-        assert_eq!(reader.header.lane_length, 144);
+        let mut rgb: Vec<u8> = vec![0; (reader.width() as usize) * 3];
+        reader.next_row_rgb(&mut rgb).unwrap();
+        assert_eq!(reader.row_spans().next(), Some(128..149));
+        assert_eq!(reader.row_spans().count(), 1);
+
+        while reader.has_more_rows() {
+            reader.next_row_rgb(&mut rgb).unwrap();
+        }
+        let spans: Vec<_> = reader.row_spans().collect();
+        assert_eq!(spans.len(), 101);
+        assert!(spans.windows(2).all(|spans| spans[0].end == spans[1].start));
+        // Padding of the very last lane is not read.
+        assert_eq!(spans[100].end, (data.len() - 1) as u64);
+
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let mut reader = Reader::new(&data[..]).unwrap();
+        reader.rows().for_each(|row| {
+            row.unwrap();
+        });
+        assert_eq!(reader.row_spans().last().unwrap().end, (data.len() - 256 * 3 - 1) as u64);
+    }
+
+    #[test]
+    fn into_palette() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let mut rgb = [0; 256 * 3];
+        Reader::new(&data[..]).unwrap().read_palette(&mut rgb).unwrap();
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        reader.rows().for_each(|row| {
+            row.unwrap();
+        });
+        let palette = reader.into_palette().unwrap();
+        assert_eq!(palette.len(), 256);
+        assert_eq!(palette.to_rgb(), &rgb[..]);
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        assert!(matches!(Reader::new(&data[..]).unwrap().into_palette(), Err(Error::NotPaletted)));
+    }
+
+    #[test]
+    fn len_hint() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let mut expected = [0; 256 * 3];
+        Reader::new(&data[..]).unwrap().read_palette(&mut expected).unwrap();
+
+        // Data following the image must not be mistaken for the palette.
+        let mut stream = data.to_vec();
+        stream.extend_from_slice(&[0xAB; 1000]);
+        let mut reader = Reader::new(&stream[..]).unwrap();
+        assert_eq!(reader.progress(), None);
+        reader.set_len_hint(data.len() as u64);
+        assert_eq!(reader.progress(), Some(128.0 / data.len() as f64));
+        reader.rows().for_each(|row| {
+            row.unwrap();
+        });
+        assert_eq!(reader.progress(), Some((data.len() - 256 * 3 - 1) as f64 / data.len() as f64));
+
+        let mut rgb = [0; 256 * 3];
+        assert_eq!(reader.read_palette(&mut rgb).unwrap(), 256);
+        assert_eq!(&rgb[..], &expected[..]);
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        reader.set_len_hint(data.len() as u64 + 10);
+        assert!(matches!(reader.read_palette(&mut rgb), Err(Error::Io(_))));
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        reader.set_len_hint(200);
+        assert!(matches!(reader.read_palette(&mut rgb), Err(Error::NoPalette)));
+    }
+
+    #[test]
+    fn finish() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let palette = Reader::new(&data[..]).unwrap().into_palette().unwrap();
+        let mut embedded = data.to_vec();
+        embedded.extend_from_slice(b"next record");
+
+        let mut reader = Reader::new(&embedded[..]).unwrap();
+        let mut row = vec![0; reader.width() as usize];
+        reader.next_row_paletted(&mut row).unwrap();
+        let (finished_palette, mut stream) = reader.finish().unwrap();
+        assert_eq!(finished_palette, Some(palette.clone()));
+        let mut rest = Vec::new();
+        io::Read::read_to_end(&mut stream, &mut rest).unwrap();
+        assert_eq!(rest, b"next record");
+
+        let (finished_palette, stream) = Reader::new(io::Cursor::new(&embedded[..])).unwrap().into_inner().unwrap();
+        assert_eq!(finished_palette, Some(palette));
+        assert_eq!(stream.position(), data.len() as u64);
+
+        // Padding of the last lane is present in the file even though it is not needed for decoding.
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut embedded = data.to_vec();
+        embedded.extend_from_slice(b"next record");
+        let (finished_palette, stream) = Reader::new(io::Cursor::new(&embedded[..])).unwrap().into_inner().unwrap();
+        assert_eq!(finished_palette, None);
+        assert_eq!(stream.position(), data.len() as u64);
+    }
+
+    #[test]
+    fn bytes_consumed() {
+        for data in &[&include_bytes!("../test-data/gmarbles.pcx")[..], &include_bytes!("../test-data/marbles.pcx")[..]] {
+            let mut embedded = data.to_vec();
+            embedded.extend_from_slice(b"next record");
+
+            let mut reader = Reader::new(&embedded[..]).unwrap();
+            assert_eq!(reader.bytes_consumed(), 128);
+            reader.next_lanes().unwrap();
+            let spans: Vec<_> = reader.row_spans().collect();
+            assert_eq!(reader.bytes_consumed(), spans[0].end);
+            assert!(reader.stream_bytes_read() >= reader.bytes_consumed());
+
+            let (_, _, length) = reader.finish_with_length().unwrap();
+            assert_eq!(length, data.len() as u64);
+        }
+    }
+
+    #[test]
+    fn seek_row() {
+        for data in &[&include_bytes!("../test-data/gmarbles.pcx")[..], &include_bytes!("../test-data/marbles.pcx")[..]] {
+            let mut reader = Reader::new(io::Cursor::new(*data)).unwrap();
+            let format = if reader.is_paletted() { PixelFormat::Indexed } else { PixelFormat::Rgb };
+            let row_length = (reader.width() as usize) * format.bytes_per_pixel();
+            let mut rows = vec![0; row_length * (reader.height() as usize)];
+            reader.read_rows_into(&mut rows, format, None).unwrap();
+            let spans: Vec<_> = reader.row_spans().collect();
+
+            let mut reader = Reader::new(io::Cursor::new(*data)).unwrap();
+            let mut row = vec![0; row_length];
+            for &y in &[50u16, 3, 98, 0, 50, 51, 97, 96] {
+                reader.row(y, &mut row, format).unwrap();
+                assert_eq!(&row[..], &rows[((y as usize) * row_length)..((y as usize + 1) * row_length)]);
+            }
+            assert!(matches!(reader.seek_row(reader.height()), Err(Error::InvalidUsage(_))));
+
+            // Rows read in order after seeking to the start are recorded as usual.
+            reader.seek_row(0).unwrap();
+            while reader.has_more_rows() {
+                reader.next_row_into(&mut row, format).unwrap();
+            }
+            assert_eq!(reader.row_spans().collect::<Vec<_>>(), spans);
+        }
+    }
+
+    #[test]
+    fn raw_row() {
+        for data in &[&include_bytes!("../test-data/gmarbles.pcx")[..], &include_bytes!("../test-data/marbles.pcx")[..]] {
+            let mut reader = Reader::new(io::Cursor::new(*data)).unwrap();
+            while reader.has_more_rows() {
+                reader.next_lanes().unwrap();
+            }
+            let spans: Vec<_> = reader.row_spans().collect();
+
+            let mut raw = Vec::new();
+            for &y in &[5u16, 0, 60, 61, 1] {
+                reader.raw_row(y, &mut raw).unwrap();
+                let span = &spans[y as usize];
+                assert_eq!(&raw[..], &data[(span.start as usize)..(span.end as usize)]);
+            }
+            assert!(matches!(reader.raw_row(reader.height(), &mut raw), Err(Error::InvalidUsage(_))));
+        }
+    }
+
+    #[test]
+    fn plane_reader() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        let (width, height) = (reader.width() as usize, reader.height() as usize);
+        let mut rgb = vec![0; width * height * 3];
+        reader.read_rows_into(&mut rgb, PixelFormat::Rgb, None).unwrap();
+
+        for &plane in &[1u8, 0, 2] {
+            let rows: Vec<Vec<u8>> = reader.plane_reader(plane).unwrap().map(Result::unwrap).collect();
+            assert_eq!(rows.len(), height);
+            let expected: Vec<u8> = rgb.iter().skip(plane as usize).step_by(3).cloned().collect();
+            assert_eq!(rows.concat(), expected);
+        }
+        assert!(reader.finish().is_ok());
+
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        assert!(matches!(reader.plane_reader(3), Err(Error::InvalidUsage(_))));
+        let mut reader = Reader::new(io::Cursor::new(&include_bytes!("../test-data/gmarbles.pcx")[..])).unwrap();
+        assert!(matches!(reader.plane_reader(0), Err(Error::NotRgb)));
+    }
+
+    #[test]
+    fn read_palette_early() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        let mut row: Vec<u8> = vec![0; reader.width() as usize];
+        reader.next_row_paletted(&mut row).unwrap();
+
+        let palette = reader.read_palette_early().unwrap();
+        let mut indices = row.clone();
+        while reader.has_more_rows() {
+            reader.next_row_paletted(&mut row).unwrap();
+            indices.extend_from_slice(&row);
+        }
+        assert_eq!(reader.into_palette().unwrap(), palette);
+        assert_eq!(image.pixels, Pixels::Paletted { indices, palette: palette.to_rgb() });
+
+        let mut corrupted = data.to_vec();
+        let marker_position = corrupted.len() - 256 * 3 - 1;
+        corrupted[marker_position] = 0;
+        let mut reader = Reader::new(io::Cursor::new(&corrupted[..])).unwrap();
+        assert!(matches!(reader.read_palette_early(), Err(Error::NoPalette)));
+        reader.next_row_paletted(&mut row).unwrap();
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        assert!(matches!(reader.read_palette_early(), Err(Error::NotPaletted)));
+    }
+
+    #[test]
+    fn rgba() {
+        let (width, height) = (3u16, 2u16);
+        let lane_length = header::lane_length(width, 8);
+        let mut data = Vec::new();
+        header::write_format(&mut data, 8, 4, (width, height), (300, 300), &[[0; 3]; 16]).unwrap();
+        {
+            let mut compressor = rle::Compressor::new(&mut data, lane_length);
+            for y in 0..height {
+                for plane in 0..4 {
+                    let mut lane = vec![0; lane_length as usize];
+                    for x in 0..width {
+                        lane[x as usize] = (plane * 60 + x * 10 + y) as u8;
+                    }
+                    compressor.write_all(&lane).unwrap();
+                }
+            }
+            compressor.finish().unwrap();
+        }
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        assert_eq!(reader.color_format(), ColorFormat::Rgba);
+        assert_eq!(reader.palette_length(), None);
+
+        let mut rgba = vec![0; 12];
+        reader.next_row_rgba(&mut rgba).unwrap();
+        assert_eq!(rgba, [0, 60, 120, 180, 10, 70, 130, 190, 20, 80, 140, 200]);
+
+        let mut rgb = vec![0; 9];
+        reader.next_row_rgb(&mut rgb).unwrap();
+        assert_eq!(rgb, [1, 61, 121, 11, 71, 131, 21, 81, 141]);
+        assert!(!reader.has_more_rows());
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let (mut r, mut g, mut b) = (vec![0; 3], vec![0; 3], vec![0; 3]);
+        reader.next_row_rgb_separate(&mut r, &mut g, &mut b).unwrap();
+        assert_eq!((r, g, b), (vec![0, 10, 20], vec![60, 70, 80], vec![120, 130, 140]));
+
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        assert_eq!(&image.to_rgb()[..3], &[0, 60, 120]);
+
+        let mut reader = Reader::new(&data[..]).unwrap();
+        reader.set_premultiply_alpha(true);
+        reader.next_row_rgba(&mut rgba).unwrap();
+        assert_eq!(rgba, [0, 42, 85, 180, 7, 52, 97, 190, 16, 63, 110, 200]);
+    }
+
+    #[test]
+    fn rgba_conversion() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        let (indices, rgb) = match image.pixels {
+            Pixels::Paletted { ref indices, .. } => (indices.clone(), image.to_rgb()),
+            _ => panic!("gmarbles.pcx is paletted"),
+        };
+
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        let mut rgba = vec![0; (reader.width() as usize) * 4];
+        assert!(matches!(reader.next_row_rgba(&mut rgba), Err(Error::InvalidUsage(_))));
+        reader.read_palette_early().unwrap();
+        reader.set_transparency(Transparency::Index(indices[0]));
+        reader.set_premultiply_alpha(true);
+        reader.next_row_rgba(&mut rgba).unwrap();
+        for (x, pixel) in rgba.chunks(4).enumerate() {
+            if indices[x] == indices[0] {
+                assert_eq!(pixel, [0, 0, 0, 0]);
+            } else {
+                assert_eq!(pixel, [rgb[x * 3], rgb[x * 3 + 1], rgb[x * 3 + 2], 0xFF]);
+            }
+        }
+
+        reader.set_transparency(Transparency::Alpha(vec![0x80; 256]));
+        reader.set_premultiply_alpha(false);
+        reader.next_row_rgba(&mut rgba).unwrap();
+        let offset = (reader.width() as usize) * 3;
+        for (x, pixel) in rgba.chunks(4).enumerate() {
+            assert_eq!(pixel, [rgb[offset + x * 3], rgb[offset + x * 3 + 1], rgb[offset + x * 3 + 2], 0x80]);
+        }
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let rgb = Reader::new(&data[..]).unwrap().read_image().unwrap().to_rgb();
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut rgba = vec![0; (reader.width() as usize) * 4];
+        reader.next_row_rgba(&mut rgba).unwrap();
+        for (x, pixel) in rgba.chunks(4).enumerate() {
+            assert_eq!(pixel, [rgb[x * 3], rgb[x * 3 + 1], rgb[x * 3 + 2], 0xFF]);
+        }
+    }
+
+    #[test]
+    fn gray() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let rgb = Reader::new(&data[..]).unwrap().read_image().unwrap().to_rgb();
+        let expected: Vec<u8> = rgb.chunks(3).map(|rgb| super::luma([rgb[0], rgb[1], rgb[2]])).collect();
+
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        let mut row = vec![0; reader.width() as usize];
+        assert!(matches!(reader.next_row_gray(&mut row), Err(Error::InvalidUsage(_))));
+        reader.read_palette_early().unwrap();
+        for y in 0..(reader.height() as usize) {
+            reader.next_row_gray(&mut row).unwrap();
+            assert_eq!(&row[..], &expected[(y * row.len())..((y + 1) * row.len())]);
+        }
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let rgb = Reader::new(&data[..]).unwrap().read_image().unwrap().to_rgb();
+        let mut reader = Reader::new(&data[..]).unwrap();
+        let mut row = vec![0; reader.width() as usize];
+        for y in 0..(reader.height() as usize) {
+            reader.next_row_gray(&mut row).unwrap();
+            for (x, &value) in row.iter().enumerate() {
+                let offset = (y * row.len() + x) * 3;
+                assert_eq!(value, super::luma([rgb[offset], rgb[offset + 1], rgb[offset + 2]]));
+            }
+        }
+
+        assert_eq!(super::luma([0, 0, 0]), 0);
+        assert_eq!(super::luma([255, 255, 255]), 255);
+        assert_eq!(super::luma([100, 100, 100]), 100);
+    }
+
+    #[test]
+    fn palette_or_default() {
+        use WriterPlanar;
+
+        let ega = Palette::standard(16).unwrap();
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPlanar::new(&mut pcx, (3, 1), (300, 300), 4, &[]).unwrap();
+            writer.write_row(&[0, 1, 2]).unwrap();
+            writer.finish().unwrap();
+        }
+        assert_eq!(Reader::new(&pcx[..]).unwrap().palette_or_default().unwrap(), ega);
+
+        // Version 2.8 without palette information.
+        pcx[1] = 3;
+        pcx[16..19].copy_from_slice(&[1, 2, 3]);
+        assert_eq!(Reader::new(&pcx[..]).unwrap().palette_or_default().unwrap(), ega);
+
+        pcx[1] = 5;
+        let palette = Reader::new(&pcx[..]).unwrap().palette_or_default().unwrap();
+        assert_eq!(palette.color(0), [1, 2, 3]);
+        assert_eq!(palette.len(), 16);
+
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        assert!(matches!(reader.palette_or_default(), Err(Error::InvalidUsage(_))));
+        assert_eq!(reader.read_palette_early().unwrap(), reader.palette_or_default().unwrap());
+    }
+
+    #[test]
+    fn read_image_rgb_any_format() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let expected = Reader::new(&data[..]).unwrap().read_image().unwrap().to_rgb();
+        assert_eq!(Reader::new(&data[..]).unwrap().read_image_rgb().unwrap(), expected);
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let expected = Reader::new(&data[..]).unwrap().read_image().unwrap().to_rgb();
+        assert_eq!(Reader::new(&data[..]).unwrap().read_image_rgb().unwrap(), expected);
+
+        // 16-color image without palette information gets the standard EGA palette.
+        let mut pcx = Vec::new();
+        {
+            let mut writer = ::WriterPlanar::new(&mut pcx, (3, 1), (300, 300), 4, &[]).unwrap();
+            writer.write_row(&[0, 1, 15]).unwrap();
+            writer.finish().unwrap();
+        }
+        let ega = Palette::standard(16).unwrap();
+        let expected: Vec<u8> = [0, 1, 15].iter().flat_map(|&index| ega.color(index).to_vec()).collect();
+        assert_eq!(Reader::new(&pcx[..]).unwrap().read_image_rgb().unwrap(), expected);
+    }
+
+    #[test]
+    fn packed_widths() {
+        for &bit_depth in &[1u8, 2, 4] {
+            for width in 1..18u16 {
+                let pixels_per_byte = 8 / (bit_depth as usize);
+                let indices: Vec<u8> = (0..(width as usize)).map(|x| ((x * 5 + 1) % (1 << bit_depth)) as u8).collect();
+                let mut lane = vec![0; header::lane_length(width, bit_depth) as usize];
+                for (x, &index) in indices.iter().enumerate() {
+                    lane[x / pixels_per_byte] |= index << (8 - (bit_depth as usize) * (x % pixels_per_byte + 1));
+                }
+
+                let mut data = Vec::new();
+                header::write_format(&mut data, bit_depth, 1, (width, 2), (300, 300), &[[0; 3]; 16]).unwrap();
+                {
+                    let mut compressor = rle::Compressor::new(&mut data, lane.len() as u16);
+                    compressor.write_all(&lane).unwrap();
+                    compressor.write_all(&lane).unwrap();
+                    compressor.finish().unwrap();
+                }
+
+                let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+                match image.pixels {
+                    Pixels::Paletted { indices: ref decoded, .. } => {
+                        assert_eq!(&decoded[..(width as usize)], &indices[..], "bit depth {}, width {}", bit_depth, width);
+                        assert_eq!(&decoded[(width as usize)..], &indices[..], "bit depth {}, width {}", bit_depth, width);
+                    }
+                    _ => panic!("image must be paletted"),
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn malformed_headers() {
+        let mut data = include_bytes!("../test-data/gmarbles.pcx").to_vec();
+        data[4..12].copy_from_slice(&[0, 0, 0, 0, 0xFF, 0xFF, 0, 0]);
+        assert!(matches!(Reader::new(&data[..]), Err(Error::InvalidDimensions)));
+
+        // Decoding must fail gracefully whatever single header byte is corrupted.
+        for &name in &["gmarbles", "marbles"] {
+            let path = format!("{}/test-data/{}.pcx", env!("CARGO_MANIFEST_DIR"), name);
+            let original = ::std::fs::read(path).unwrap();
+            for position in 0..128 {
+                for &value in &[0x00, 0x01, 0x02, 0x04, 0x08, 0x7F, 0x80, 0xFF] {
+                    let mut data = original.clone();
+                    data[position] = value;
+                    if let Ok(mut reader) = Reader::new(&data[..]) {
+                        reader.rows().take(3).for_each(drop);
+                    }
+                    let _ = Reader::new(&data[..200]).and_then(|reader| reader.read_image());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn lenient() {
+        let lenient = Options { strict: false, ..Options::default() };
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let expected = Reader::new(&data[..]).unwrap().read_image().unwrap();
+
+        let mut corrupted = data.to_vec();
+        corrupted[1] = 7;
+        corrupted[2] = 9;
+        let marker_position = corrupted.len() - 256 * 3 - 1;
+        corrupted[marker_position] = 0;
+        assert!(matches!(Reader::new(&corrupted[..]), Err(Error::UnknownVersion(7))));
+        corrupted[1] = 5;
+        assert!(matches!(Reader::new(&corrupted[..]), Err(Error::UnknownEncoding(9))));
+        assert!(Reader::new(&corrupted[..]).and_then(|reader| reader.read_image()).is_err());
+        corrupted[1] = 7;
+
+        assert_eq!(Reader::new_with_options(&corrupted[..], lenient).unwrap().read_image().unwrap(), expected);
+        let mut reader = Reader::new_with_options(io::Cursor::new(&corrupted[..]), lenient).unwrap();
+        assert_eq!(reader.read_palette_early().unwrap().to_rgb(), match expected.pixels {
+            Pixels::Paletted { ref palette, .. } => palette.clone(),
+            _ => panic!("gmarbles.pcx is paletted"),
+        });
+
+        corrupted[66..68].copy_from_slice(&[1, 0]);
+        assert!(matches!(Reader::new_with_options(&corrupted[..], Options::default()), Err(Error::UnknownVersion(7))));
+        corrupted[1] = 5;
+        corrupted[2] = 1;
+        assert!(matches!(Reader::new(&corrupted[..]), Err(Error::InvalidLaneLength)));
+        assert_eq!(Reader::new_with_options(&corrupted[..], lenient).unwrap().header.lane_length, 141);
+    }
+
+    #[test]
+    fn truncated() {
+        let fill = Fill { index: 7, color: [255, 0, 255, 0] };
+        let lenient = Options { strict: false, fill, ..Options::default() };
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let expected = Reader::new(&data[..]).unwrap().read_image_rgb().unwrap();
+        let truncated = &data[..(data.len() / 2)];
+        assert!(Reader::new(truncated).unwrap().read_image().is_err());
+        let rgb = Reader::new_with_options(truncated, lenient).unwrap().read_image_rgb().unwrap();
+        assert_eq!(rgb.len(), expected.len());
+        let decoded = rgb.chunks(3).zip(expected.chunks(3)).position(|(actual, expected)| actual != expected).unwrap();
+        assert!(rgb.chunks(3).skip(decoded).all(|pixel| pixel == [255, 0, 255]));
+        assert_eq!(&rgb[..(decoded * 3)], &expected[..(decoded * 3)]);
+
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new_with_options(&data[..1000], lenient).unwrap().read_image().unwrap();
+        match image.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                assert_eq!(indices.last(), Some(&7));
+                assert_eq!(palette, &Palette::vga().to_rgb());
+            }
+            _ => panic!("gmarbles.pcx is paletted"),
+        }
+        let image = Reader::new_with_options(&data[..], lenient).unwrap().read_image().unwrap();
+        assert_eq!(image, Reader::new(&data[..]).unwrap().read_image().unwrap());
+    }
+
+    #[test]
+    fn version_mismatch() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let expected = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        assert!(Reader::new(&data[..]).unwrap().warnings().is_empty());
+
+        // Mismatches are reported with default options and in lenient mode.
+        let mut mismatched = data.to_vec();
+        mismatched[1] = 0;
+        for &options in &[Options::default(), Options { strict: false, ..Options::default() }] {
+            let reader = Reader::new_with_options(&mismatched[..], options).unwrap();
+            assert_eq!(reader.warnings(), &[Warning::UnsupportedByVersion { version: header::Version::V0, bit_depth: 8, number_of_color_planes: 3 }]);
+            assert_eq!(reader.read_image().unwrap(), expected);
+        }
+
+        let strict_version = Options { strict_version: true, ..Options::default() };
+        assert!(matches!(
+            Reader::new_with_options(&mismatched[..], strict_version),
+            Err(Error::UnsupportedByVersion { version: header::Version::V0, bit_depth: 8, number_of_color_planes: 3 })
+        ));
+        assert!(::decode(&mismatched[..]).is_ok());
+    }
+
+    #[test]
+    fn packed() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        let expected = image.to_packed(ChannelOrder::Argb);
+        assert_eq!(expected.len(), (image.width as usize) * (image.height as usize));
+
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        reader.read_palette_early().unwrap();
+        let mut row = vec![0; image.width as usize];
+        for expected_row in expected.chunks(image.width as usize) {
+            reader.next_row_packed(&mut row, ChannelOrder::Argb).unwrap();
+            assert_eq!(row, expected_row);
+        }
+        assert!(matches!(reader.next_row_packed(&mut [0; 3], ChannelOrder::Argb), Err(Error::BufferLength { expected: 141, actual: 3 })));
+
+        let rgba = [0x11, 0x22, 0x33, 0x44];
+        assert_eq!(ChannelOrder::Argb.pack(rgba), 0x44112233);
+        assert_eq!(ChannelOrder::Abgr.pack(rgba), 0x44332211);
+        assert_eq!(ChannelOrder::Rgba.pack(rgba), 0x11223344);
+        assert_eq!(ChannelOrder::Bgra.pack(rgba), 0x33221144);
+        for &order in &[ChannelOrder::Argb, ChannelOrder::Abgr, ChannelOrder::Rgba, ChannelOrder::Bgra] {
+            assert_eq!(order.unpack(order.pack(rgba)), rgba);
+        }
+    }
+
+    #[test]
+    fn max_memory() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let bounded = |max_memory| Options { max_memory: Some(max_memory), ..Options::default() };
 
-        assert_eq!(reader.is_paletted(), false);
+        // 143x101 RGB image: 3 lanes of 143 bytes, 2 RGBA rows, 102 row offsets, palette and buffer of the decompressor.
+        let scratch = 143 * 3 + 143 * 4 * 2 + 102 * 8 + 256 * 3 + rle::DECOMPRESSOR_BUFFER_LENGTH as u64;
+        let image = 143 * 101 * 3 + 256 * 3;
+        assert!(matches!(
+            Reader::new_with_options(&data[..], bounded(scratch - 1)),
+            Err(Error::LimitExceeded { limit: "memory", maximum, required }) if maximum == scratch - 1 && required == scratch
+        ));
 
-        let mut r: Vec<u8> = iter::repeat(0).take(reader.width() as usize).collect();
-        let mut g: Vec<u8> = iter::repeat(0).take(reader.width() as usize).collect();
-        let mut b: Vec<u8> = iter::repeat(0).take(reader.width() as usize).collect();
+        let mut reader = Reader::new_with_options(&data[..], bounded(scratch)).unwrap();
+        let capacity = (reader.row_offsets.capacity(), reader.plane_buffer.capacity(), reader.rgba_buffer.capacity());
+        let mut rgba = vec![0; 143 * 4];
         for _ in 0..reader.height() {
-            reader.next_row_rgb_separate(&mut r[..], &mut g[..], &mut b[..]).unwrap();
+            reader.next_row_rgba(&mut rgba).unwrap();
         }
+        assert_eq!((reader.row_offsets.capacity(), reader.plane_buffer.capacity(), reader.rgba_buffer.capacity()), capacity);
 
-        let mut palette = [0; 0];
-        assert_eq!(reader.read_palette(&mut palette).unwrap(), 0);
+        let reader = Reader::new_with_options(&data[..], bounded(scratch + image - 1)).unwrap();
+        assert!(matches!(reader.read_image(), Err(Error::LimitExceeded { .. })));
+        let reader = Reader::new_with_options(&data[..], bounded(scratch + image)).unwrap();
+        assert_eq!(reader.estimated_peak_memory(), scratch + image);
+        assert_eq!(reader.read_image().unwrap(), Reader::new(&data[..]).unwrap().read_image().unwrap());
+    }
+
+    #[test]
+    fn fallible_allocation() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let options = Options { fallible_allocation: true, ..Options::default() };
+        let mut reader = Reader::new_with_options(&data[..], options).unwrap();
+        let capacity = (reader.row_offsets.capacity(), reader.plane_buffer.capacity(), reader.rgba_buffer.capacity());
+        assert_eq!(capacity, (102, 143 * 3, 143 * 4));
+        let rows = reader.rows().collect::<Result<Vec<_>, _>>().unwrap();
+        assert_eq!(rows.len(), 101);
+        assert_eq!((reader.row_offsets.capacity(), reader.plane_buffer.capacity(), reader.rgba_buffer.capacity()), capacity);
+
+        let mut buffer = vec![0u32; 1];
+        assert!(matches!(super::reserve(&mut buffer, usize::MAX / 2), Err(Error::OutOfMemory { required: usize::MAX })));
+        assert!(matches!(super::zeroed(usize::MAX), Err(Error::OutOfMemory { .. })));
+    }
+
+    #[test]
+    fn pixel_formats() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        let (width, height) = (image.width as usize, image.height as usize);
+        let rgb = image.to_rgb();
+
+        for &format in &[PixelFormat::Indexed, PixelFormat::Rgb, PixelFormat::Bgr, PixelFormat::Rgba, PixelFormat::Bgra] {
+            let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+            reader.read_palette_early().unwrap();
+
+            // Rows are 2 bytes apart with a marker in between.
+            let bytes_per_pixel = format.bytes_per_pixel();
+            let stride = width * bytes_per_pixel + 2;
+            let mut buffer = vec![0xAB; stride * height - 2];
+            assert!(matches!(reader.read_rows_into(&mut buffer[1..], format, Some(stride)), Err(Error::BufferLength { .. })));
+            assert!(matches!(reader.read_rows_into(&mut buffer, format, Some(1)), Err(Error::InvalidUsage(_))));
+            reader.read_rows_into(&mut buffer, format, Some(stride)).unwrap();
+
+            for (y, row) in buffer.chunks(stride).enumerate() {
+                assert_eq!(&row[(width * bytes_per_pixel)..], &[0xAB; 2][..(row.len() - width * bytes_per_pixel)]);
+                for (x, pixel) in row[..(width * bytes_per_pixel)].chunks(bytes_per_pixel).enumerate() {
+                    let expected = &rgb[((y * width + x) * 3)..((y * width + x) * 3 + 3)];
+                    match format {
+                        PixelFormat::Indexed => assert_eq!(pixel[0], match image.pixels {
+                            Pixels::Paletted { ref indices, .. } => indices[y * width + x],
+                            _ => unreachable!(),
+                        }),
+                        PixelFormat::Rgb => assert_eq!(pixel, expected),
+                        PixelFormat::Bgr => assert_eq!(pixel, [expected[2], expected[1], expected[0]]),
+                        PixelFormat::Rgba => assert_eq!(pixel, [expected[0], expected[1], expected[2], 0xFF]),
+                        PixelFormat::Bgra => assert_eq!(pixel, [expected[2], expected[1], expected[0], 0xFF]),
+                    }
+                }
+            }
+        }
+
+        let marbles = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(&marbles[..]).unwrap();
+        assert!(matches!(reader.next_row_into(&mut [0; 143], PixelFormat::Indexed), Err(Error::NotPaletted)));
+        let mut bgr = [0; 143 * 3];
+        reader.next_row_into(&mut bgr, PixelFormat::Bgr).unwrap();
+        let expected = Reader::new(&marbles[..]).unwrap().read_image().unwrap().to_rgb();
+        assert_eq!(&bgr[..3], &[expected[2], expected[1], expected[0]]);
+    }
+
+    #[test]
+    fn time_budget() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let budget = |time_budget| Options { time_budget: Some(time_budget), ..Options::default() };
+
+        let reader = Reader::new_with_options(&data[..], budget(Duration::from_secs(0))).unwrap();
+        assert!(matches!(reader.read_image(), Err(Error::TimedOut)));
+
+        let reader = Reader::new_with_options(&data[..], budget(Duration::from_secs(3600))).unwrap();
+        assert_eq!(reader.read_image().unwrap(), Reader::new(&data[..]).unwrap().read_image().unwrap());
+    }
+
+    #[test]
+    fn rows_rev() {
+        for data in &[&include_bytes!("../test-data/marbles.pcx")[..], &include_bytes!("../test-data/gmarbles.pcx")[..]] {
+            let rows: Vec<Row> = Reader::new(*data).unwrap().rows().map(|row| row.unwrap()).collect();
+
+            // Embedded in a larger stream.
+            let mut embedded = vec![0xC1; 5];
+            embedded.extend_from_slice(data);
+            let mut stream = io::Cursor::new(&embedded[..]);
+            stream.set_position(5);
+
+            let mut reader = Reader::new(stream).unwrap();
+            {
+                let mut rows_rev = reader.rows_rev().unwrap();
+                assert_eq!(rows_rev.size_hint(), (0, Some(rows.len())));
+                let reversed: Vec<Row> = rows_rev.by_ref().take(3).map(|row| row.unwrap()).collect();
+                assert_eq!(&reversed[..], &[rows[rows.len() - 1].clone(), rows[rows.len() - 2].clone(), rows[rows.len() - 3].clone()][..]);
+                let rest: Vec<Row> = rows_rev.map(|row| row.unwrap()).collect();
+                assert_eq!(rest.len(), rows.len() - 3);
+                assert_eq!(rest.last(), rows.first());
+            }
+            assert!(reader.is_finished());
+            assert_eq!(reader.row_spans().count(), rows.len());
+            assert_eq!(reader.into_palette().ok(), Reader::new(*data).unwrap().into_palette().ok());
+        }
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        reader.rows().next();
+        assert!(matches!(reader.rows_rev(), Err(Error::InvalidUsage(_))));
+    }
+
+    #[cfg(feature = "parallel")]
+    #[test]
+    fn decode_parallel() {
+        use decode;
+        use super::decode_strips;
+
+        for data in &[&include_bytes!("../test-data/marbles.pcx")[..], &include_bytes!("../test-data/gmarbles.pcx")[..]] {
+            let image = decode(*data).unwrap();
+            for &strips in &[1, 2, 3, 7, 1000] {
+                assert_eq!(decode_strips(data, strips, Options::default()).unwrap(), image);
+            }
+            assert_eq!(super::decode_parallel(data).unwrap(), image);
+
+            let truncated = &data[..(data.len() / 2)];
+            assert!(decode_strips(truncated, 4, Options::default()).is_err());
+        }
+
+        // Options of the caller are applied.
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let options = Options { max_memory: Some(1000), ..Options::default() };
+        assert!(matches!(super::decode_parallel_with_options(data, options), Err(Error::LimitExceeded { limit: "memory", .. })));
+        let mut options = Options::default();
+        options.limits.max_width = Some(100);
+        assert!(matches!(super::decode_parallel_with_options(data, options), Err(Error::LimitExceeded { limit: "width", .. })));
     }
 }
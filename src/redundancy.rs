@@ -0,0 +1,124 @@
+//! Analysis of redundant rows, e.g. to decide whether converting assets to another format or resolution is worthwhile.
+use std::collections::hash_map::DefaultHasher;
+use std::collections::HashSet;
+use std::hash::{Hash, Hasher};
+use std::io;
+use std::ops;
+
+use {Error, Reader, Result};
+
+/// Redundancy of the rows of an image, see `Reader::analyze_redundancy()`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct Redundancy {
+    /// Number of rows of the image.
+    pub rows: u16,
+
+    /// Number of rows identical to the row above them.
+    pub duplicate_rows: u16,
+
+    /// Runs of consecutive rows identical to the row above the run, as ranges of row numbers from top to bottom.
+    pub duplicate_runs: Vec<ops::Range<u16>>,
+
+    /// Number of distinct rows in the whole image, including rows which are not consecutive.
+    pub distinct_rows: u16,
+
+    /// Length of the stored pixel data in bytes.
+    pub stored_bytes: u64,
+
+    /// Length of the decoded pixel data in bytes, including the padding of lanes.
+    pub decoded_bytes: u64,
+}
+
+impl Redundancy {
+    /// Fraction of rows identical to the row above them, between 0 and 1.
+    pub fn duplicate_fraction(&self) -> f64 {
+        match self.rows {
+            0 => 0.0,
+            rows => self.duplicate_rows as f64 / rows as f64,
+        }
+    }
+
+    /// Ratio of the length of the decoded pixel data to the length of the stored one, above 1 if RLE saves space.
+    pub fn compression_ratio(&self) -> f64 {
+        match self.stored_bytes {
+            0 => 1.0,
+            stored_bytes => self.decoded_bytes as f64 / stored_bytes as f64,
+        }
+    }
+}
+
+impl<R: io::Read> Reader<R> {
+    /// Read all rows of the image and report identical consecutive rows, the number of distinct rows and how well the pixel
+    /// data compresses.
+    ///
+    /// Must be called before reading any rows. Rows are compared by their decoded values, distinct rows are counted by hash.
+    pub fn analyze_redundancy(&mut self) -> Result<Redundancy> {
+        if self.rows_read() != 0 {
+            return Err(Error::InvalidUsage("redundancy must be analyzed before reading any rows"));
+        }
+
+        let header = self.header;
+        let mut redundancy = Redundancy {
+            rows: header.size.1,
+            duplicate_rows: 0,
+            duplicate_runs: Vec::new(),
+            distinct_rows: 0,
+            stored_bytes: 0,
+            decoded_bytes: (header.lane_length as u64) * (header.number_of_color_planes as u64) * (header.size.1 as u64),
+        };
+
+        let mut hashes = HashSet::new();
+        let mut previous = None;
+        for (y, row) in (0..).zip(self.rows()) {
+            let row = row?;
+            if previous.as_ref() == Some(&row) {
+                redundancy.duplicate_rows += 1;
+                match redundancy.duplicate_runs.last_mut() {
+                    Some(run) if run.end == y => run.end += 1,
+                    _ => redundancy.duplicate_runs.push(y..(y + 1)),
+                }
+            }
+
+            let mut hasher = DefaultHasher::new();
+            row.hash(&mut hasher);
+            hashes.insert(hasher.finish());
+            previous = Some(row);
+        }
+
+        redundancy.distinct_rows = hashes.len() as u16;
+        redundancy.stored_bytes = self.row_spans().map(|span| span.end - span.start).sum();
+        Ok(redundancy)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use {Error, Reader, WriterPaletted};
+
+    #[test]
+    fn redundancy() {
+        let mut pcx = Vec::new();
+        {
+            let mut writer = WriterPaletted::new(&mut pcx, (4, 6), (300, 300)).unwrap();
+            for row in &[[1; 4], [1; 4], [2; 4], [2; 4], [2; 4], [1; 4]] {
+                writer.write_row(row).unwrap();
+            }
+            writer.write_palette(&[0; 3 * 3]).unwrap();
+        }
+
+        let mut reader = Reader::new(&pcx[..]).unwrap();
+        let redundancy = reader.analyze_redundancy().unwrap();
+        assert_eq!((redundancy.rows, redundancy.duplicate_rows, redundancy.distinct_rows), (6, 3, 2));
+        assert_eq!(redundancy.duplicate_runs, [1..2, 3..5]);
+        assert_eq!(redundancy.duplicate_fraction(), 0.5);
+        assert_eq!((redundancy.stored_bytes, redundancy.decoded_bytes), (6 * 2, 6 * 4));
+        assert_eq!(redundancy.compression_ratio(), 2.0);
+        assert!(matches!(reader.analyze_redundancy(), Err(Error::InvalidUsage(_))));
+
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let redundancy = Reader::new(&data[..]).unwrap().analyze_redundancy().unwrap();
+        assert_eq!(redundancy.rows, 101);
+        assert_eq!(redundancy.duplicate_rows as usize, redundancy.duplicate_runs.iter().map(|run| run.len()).sum::<usize>());
+        assert!(redundancy.stored_bytes > 0 && redundancy.stored_bytes < data.len() as u64);
+    }
+}
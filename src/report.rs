@@ -0,0 +1,132 @@
+//! Auditable record of decoding an image.
+use std::io;
+use std::time::{Duration, Instant};
+
+use {Image, Info, Options, Reader, Result, Warning};
+use reader::image_memory;
+
+/// Limit from `Options` together with the value required by the decoded image, part of `DecodeReport`.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct LimitCheck {
+    /// Name of the limit, the same as in `Error::LimitExceeded`.
+    pub limit: &'static str,
+
+    /// Maximal allowed value, `None` if unlimited.
+    pub maximum: Option<u64>,
+
+    /// Value required by the image.
+    pub required: u64,
+}
+
+/// Record of decoding one image with `decode_with_report()`, e.g. for services which must keep track of every processed upload.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecodeReport {
+    /// Properties of the image from its header.
+    pub info: Info,
+
+    /// Options used for decoding.
+    pub options: Options,
+
+    /// Limits on width, height, decoded bytes and memory with the values they were checked against.
+    pub limits: Vec<LimitCheck>,
+
+    /// Problems tolerated because `Options::strict` was not set.
+    pub warnings: Vec<Warning>,
+
+    /// Number of bytes read from the stream, which may include data following the image as the reader reads ahead.
+    pub bytes_read: u64,
+
+    /// Amount of pixel data decoded from the file in bytes, including the padding of lanes.
+    pub decoded_bytes: u64,
+
+    /// Time spent reading and validating the header.
+    pub header_time: Duration,
+
+    /// Time spent decoding the pixel data and the palette.
+    pub decode_time: Duration,
+}
+
+// Stream adapter counting bytes read.
+struct CountingReader<R: io::Read> {
+    stream: R,
+    bytes_read: u64,
+}
+
+impl<R: io::Read> io::Read for CountingReader<R> {
+    fn read(&mut self, buffer: &mut [u8]) -> io::Result<usize> {
+        let read = self.stream.read(buffer)?;
+        self.bytes_read += read as u64;
+        Ok(read)
+    }
+}
+
+/// Decode the whole image like `Reader::read_image()` with the given options and report what was checked and done while
+/// decoding it.
+///
+/// Limits are enforced as usual, decoding which fails returns just the error.
+pub fn decode_with_report<R: io::Read>(stream: R, options: Options) -> Result<(Image, DecodeReport)> {
+    let mut stream = CountingReader { stream, bytes_read: 0 };
+
+    let started = Instant::now();
+    let reader = Reader::new_with_options(&mut stream, options)?;
+    let header_time = started.elapsed();
+
+    let header = reader.header;
+    let decoded_bytes = (header.lane_length as u64) * (header.number_of_color_planes as u64) * (header.size.1 as u64);
+    let limits = vec![
+        LimitCheck { limit: "width", maximum: options.limits.max_width.map(u64::from), required: header.size.0 as u64 },
+        LimitCheck { limit: "height", maximum: options.limits.max_height.map(u64::from), required: header.size.1 as u64 },
+        LimitCheck { limit: "decoded bytes", maximum: options.limits.max_decoded_bytes, required: decoded_bytes },
+        LimitCheck { limit: "memory", maximum: options.max_memory, required: image_memory(&header) },
+    ];
+    let warnings = reader.warnings().to_vec();
+
+    let started = Instant::now();
+    let image = reader.read_image()?;
+    let decode_time = started.elapsed();
+
+    let report = DecodeReport {
+        info: Info::from_header(&header),
+        options,
+        limits,
+        warnings,
+        bytes_read: stream.bytes_read,
+        decoded_bytes,
+        header_time,
+        decode_time,
+    };
+    Ok((image, report))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::decode_with_report;
+    use {decode, Error, Limits, Options, Warning};
+
+    #[test]
+    fn report() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let options = Options { max_memory: Some(1 << 20), ..Options::default() };
+        let (image, report) = decode_with_report(&data[..], options).unwrap();
+        assert_eq!(image, decode(&data[..]).unwrap());
+
+        assert_eq!(report.info.size, (141, 99));
+        assert_eq!(report.options, options);
+        assert!(report.warnings.is_empty());
+        assert_eq!(report.bytes_read, data.len() as u64);
+        assert_eq!(report.decoded_bytes, 142 * 99);
+        let limits: Vec<_> = report.limits.iter().map(|check| (check.limit, check.maximum)).collect();
+        assert_eq!(limits, [("width", None), ("height", None), ("decoded bytes", None), ("memory", Some(1 << 20))]);
+        assert_eq!(report.limits[2].required, report.decoded_bytes);
+        assert!(report.limits.iter().all(|check| check.maximum.map_or(true, |maximum| check.required <= maximum)));
+
+        // Tolerated problems are reported.
+        let mut lenient = data.to_vec();
+        lenient[1] = 2;
+        let (_, report) = decode_with_report(&lenient[..], Options { strict: false, ..Options::default() }).unwrap();
+        assert!(matches!(report.warnings[..], [Warning::UnsupportedByVersion { bit_depth: 8, .. }]));
+
+        let limits = Limits { max_width: Some(100), ..Limits::default() };
+        assert!(matches!(decode_with_report(&data[..], Options { limits, ..Options::default() }), Err(Error::LimitExceeded { limit: "width", .. })));
+    }
+}
@@ -0,0 +1,125 @@
+//! Round trip check of files through the decoder and encoder, e.g. for archival tools certifying that their corpora are
+//! handled losslessly.
+use low_level::Header;
+use pixels::{Image, Pixels};
+use {compare_images, encode_with_options, Comparison, Difference, Reader, Result, WriterOptions};
+
+/// Result of `roundtrip_check()`.
+#[derive(Clone, Debug, PartialEq, Eq, Hash)]
+pub struct RoundtripReport {
+    /// Pixels of the re-encoded file compared with the original ones. Palette indices are compared if both files are
+    /// paletted, otherwise colors are compared exactly while alpha channel is ignored.
+    pub difference: Difference,
+
+    /// Whether colors of the original palette are preserved at the same indices, always set for files without palette.
+    pub palette_preserved: bool,
+
+    /// Names of header fields whose value was not preserved: "version", "compression", "bit depth", "color planes",
+    /// "start", "dpi", "lane length" or "screen size".
+    pub metadata_differences: Vec<&'static str>,
+
+    /// Length of the original file in bytes.
+    pub original_length: u64,
+
+    /// Length of the re-encoded file in bytes.
+    pub reencoded_length: u64,
+}
+
+impl RoundtripReport {
+    /// Whether pixels, palette and all header fields are preserved.
+    pub fn passed(&self) -> bool {
+        self.difference.is_identical() && self.palette_preserved && self.metadata_differences.is_empty()
+    }
+}
+
+/// Decode the file, encode the image again preserving the metadata of the header and compare the result with the original.
+///
+/// Images are written as 256-color paletted or 24-bit RGB as by `encode_with_options()`, so files with fewer colors or with
+/// alpha channel report differing "bit depth" or "color planes" even if their pixels survive. Version and lane length are
+/// kept only if the format is unchanged, quirky values (e.g. odd lane length) are written as found. The palette stored in the
+/// header of 256-color and RGB files is not used by decoders and not compared. Returns the error if the original file can not
+/// be decoded.
+pub fn roundtrip_check(bytes: &[u8]) -> Result<RoundtripReport> {
+    let reader = Reader::new(bytes)?;
+    let header = reader.header;
+    let image = reader.read_image()?;
+
+    let same_format = header.bit_depth == 8 && (header.number_of_color_planes == 1 || header.number_of_color_planes == 3);
+    let options = WriterOptions {
+        compress: header.is_compressed,
+        start: header.start,
+        strict: false,
+        version: if same_format { Some(header.version) } else { None },
+        lane_length: if same_format { Some(header.lane_length) } else { None },
+        screen_size: Some(header.screen_size),
+        ..WriterOptions::default()
+    };
+    let mut reencoded = Vec::new();
+    encode_with_options(&mut reencoded, &image, header.dpi, options)?;
+
+    let reader = Reader::new(&reencoded[..])?;
+    let reencoded_header = reader.header;
+    let reencoded_image = reader.read_image()?;
+
+    let comparison = if image.is_paletted() { Comparison::Values } else { Comparison::Colors { tolerance: 0 } };
+    Ok(RoundtripReport {
+        difference: compare_images(&image, &reencoded_image, comparison)?,
+        palette_preserved: palette(&reencoded_image).starts_with(palette(&image)),
+        metadata_differences: metadata_differences(&header, &reencoded_header),
+        original_length: bytes.len() as u64,
+        reencoded_length: reencoded.len() as u64,
+    })
+}
+
+fn palette(image: &Image) -> &[u8] {
+    match image.pixels {
+        Pixels::Paletted { ref palette, .. } => palette,
+        _ => &[],
+    }
+}
+
+fn metadata_differences(original: &Header, reencoded: &Header) -> Vec<&'static str> {
+    let fields: [(&'static str, bool); 8] = [
+        ("version", original.version == reencoded.version),
+        ("compression", original.is_compressed == reencoded.is_compressed),
+        ("bit depth", original.bit_depth == reencoded.bit_depth),
+        ("color planes", original.number_of_color_planes == reencoded.number_of_color_planes),
+        ("start", original.start == reencoded.start),
+        ("dpi", original.dpi == reencoded.dpi),
+        ("lane length", original.lane_length == reencoded.lane_length),
+        ("screen size", original.screen_size == reencoded.screen_size),
+    ];
+    fields.iter().filter(|&&(_, preserved)| !preserved).map(|&(name, _)| name).collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::roundtrip_check;
+    use {encode_with_options, Image, Pixels, WriterOptions};
+
+    #[test]
+    fn roundtrip() {
+        for data in &[&include_bytes!("../test-data/marbles.pcx")[..], &include_bytes!("../test-data/gmarbles.pcx")[..]] {
+            let report = roundtrip_check(data).unwrap();
+            assert!(report.passed(), "{:?}", report);
+            assert_eq!(report.original_length, data.len() as u64);
+        }
+
+        // Uncompressed files with odd lanes and a window are reproduced.
+        let image = Image { width: 3, height: 2, pixels: Pixels::Paletted { indices: vec![0, 1, 2, 2, 1, 0], palette: vec![9; 9] } };
+        let options = WriterOptions { compress: false, start: (5, 6), strict: false, lane_length: Some(3), ..WriterOptions::default() };
+        let mut pcx = Vec::new();
+        encode_with_options(&mut pcx, &image, (72, 72), options).unwrap();
+        let report = roundtrip_check(&pcx).unwrap();
+        assert!(report.passed(), "{:?}", report);
+        assert_eq!(report.reencoded_length, pcx.len() as u64);
+
+        // Planar files survive with their pixels but change the format.
+        let report = roundtrip_check(include_bytes!("../test-data/cga/CGA_RGBI.PCX")).unwrap();
+        assert!(report.difference.is_identical() && report.palette_preserved);
+        assert!(report.metadata_differences.contains(&"bit depth") || report.metadata_differences.contains(&"color planes"));
+        assert!(!report.passed());
+
+        assert!(roundtrip_check(&[0; 10]).is_err());
+    }
+}
@@ -0,0 +1,189 @@
+//! Runtime self test of the encoder and decoder.
+use std::fmt;
+use std::io::Write;
+
+use {Reader, WriterPaletted, WriterPlanar, WriterRgb};
+use low_level::{header, rle};
+use pixels::Pixels;
+
+/// Result of a round trip of one synthetic image.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestCase {
+    /// Format of the image.
+    pub format: &'static str,
+
+    /// Width and height of the image.
+    pub size: (u16, u16),
+
+    /// Description of the failure or `None` if the decoded image matches the encoded one.
+    pub failure: Option<String>,
+}
+
+/// Report returned by `self_test`.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct SelfTestReport {
+    /// All tested cases.
+    pub cases: Vec<SelfTestCase>,
+}
+
+impl SelfTestReport {
+    /// Whether all cases passed.
+    pub fn passed(&self) -> bool {
+        self.cases.iter().all(|case| case.failure.is_none())
+    }
+
+    /// Cases which failed.
+    pub fn failures(&self) -> Vec<&SelfTestCase> {
+        self.cases.iter().filter(|case| case.failure.is_some()).collect()
+    }
+}
+
+impl fmt::Display for SelfTestReport {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        for case in &self.cases {
+            write!(f, "{} {}x{}: ", case.format, case.size.0, case.size.1)?;
+            match case.failure {
+                Some(ref failure) => writeln!(f, "FAILED ({})", failure)?,
+                None => writeln!(f, "ok")?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Round trip a battery of synthetic images through every supported format and report the results.
+///
+/// Useful for validating the crate on unusual targets (e.g. big-endian) where the test suite can not be run.
+pub fn self_test() -> SelfTestReport {
+    const SIZES: [(u16, u16); 6] = [(1, 1), (2, 3), (7, 5), (16, 2), (63, 3), (131, 4)];
+
+    let mut cases = Vec::new();
+    for &size in &SIZES {
+        cases.push(case("24-bit RGB", size, round_trip_rgb(size)));
+        cases.push(case("256-color paletted", size, round_trip_paletted(size)));
+        for &number_of_color_planes in &[1, 2, 3, 4] {
+            let format = match number_of_color_planes {
+                1 => "monochrome",
+                2 => "4-color planar",
+                3 => "8-color planar",
+                _ => "16-color planar",
+            };
+            cases.push(case(format, size, round_trip_planar(size, number_of_color_planes)));
+        }
+        cases.push(case("grayscale with alpha", size, round_trip_interleaved(size, 2)));
+        cases.push(case("32-bit RGBA", size, round_trip_interleaved(size, 4)));
+    }
+
+    SelfTestReport { cases }
+}
+
+fn case(format: &'static str, size: (u16, u16), result: Result<(), String>) -> SelfTestCase {
+    SelfTestCase {
+        format,
+        size,
+        failure: result.err(),
+    }
+}
+
+// Deterministic pseudo-random pixel values which produce both runs and literals.
+fn pattern(length: usize, seed: u32) -> Vec<u8> {
+    (0..length as u32).map(|i| if (i / 5 + seed) % 3 == 0 { 0xC7 } else { (i.wrapping_mul(2_654_435_761) ^ seed) as u8 }).collect()
+}
+
+fn compare(decoded: Pixels, expected: Pixels) -> Result<(), String> {
+    if decoded == expected {
+        Ok(())
+    } else {
+        Err("decoded pixels do not match".to_string())
+    }
+}
+
+fn round_trip_rgb(size: (u16, u16)) -> Result<(), String> {
+    let rgb = pattern((size.0 as usize) * (size.1 as usize) * 3, 1);
+    let mut pcx = Vec::new();
+    {
+        let mut writer = WriterRgb::new(&mut pcx, size, (300, 300)).map_err(|e| e.to_string())?;
+        for row in rgb.chunks((size.0 as usize) * 3) {
+            writer.write_row(row).map_err(|e| e.to_string())?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+    }
+
+    let image = Reader::new(&pcx[..]).and_then(|reader| reader.read_image()).map_err(|e| e.to_string())?;
+    compare(image.pixels, Pixels::Rgb(rgb))
+}
+
+fn round_trip_paletted(size: (u16, u16)) -> Result<(), String> {
+    let indices = pattern((size.0 as usize) * (size.1 as usize), 2);
+    let palette = pattern(256 * 3, 3);
+    let mut pcx = Vec::new();
+    {
+        let mut writer = WriterPaletted::new(&mut pcx, size, (300, 300)).map_err(|e| e.to_string())?;
+        for row in indices.chunks(size.0 as usize) {
+            writer.write_row(row).map_err(|e| e.to_string())?;
+        }
+        writer.write_palette(&palette).map_err(|e| e.to_string())?;
+    }
+
+    let image = Reader::new(&pcx[..]).and_then(|reader| reader.read_image()).map_err(|e| e.to_string())?;
+    compare(image.pixels, Pixels::Paletted { indices, palette })
+}
+
+fn round_trip_planar(size: (u16, u16), number_of_color_planes: u8) -> Result<(), String> {
+    let mask = (1 << number_of_color_planes) - 1;
+    let indices: Vec<u8> = pattern((size.0 as usize) * (size.1 as usize), 4).iter().map(|&index| index & mask).collect();
+    let palette = match number_of_color_planes {
+        1 => vec![0, 0, 0, 255, 255, 255],
+        _ => pattern(3 << number_of_color_planes, 5),
+    };
+    let mut pcx = Vec::new();
+    {
+        let mut writer = WriterPlanar::new(&mut pcx, size, (300, 300), number_of_color_planes, &palette).map_err(|e| e.to_string())?;
+        for row in indices.chunks(size.0 as usize) {
+            writer.write_row(row).map_err(|e| e.to_string())?;
+        }
+        writer.finish().map_err(|e| e.to_string())?;
+    }
+
+    let image = Reader::new(&pcx[..]).and_then(|reader| reader.read_image()).map_err(|e| e.to_string())?;
+    compare(image.pixels, Pixels::Paletted { indices, palette })
+}
+
+// Round trip grayscale with alpha or RGBA image. There are no writers for these formats, so the file is assembled from the
+// low-level parts.
+fn round_trip_interleaved(size: (u16, u16), number_of_color_planes: u8) -> Result<(), String> {
+    let channels = number_of_color_planes as usize;
+    let values = pattern((size.0 as usize) * (size.1 as usize) * channels, 6);
+    let lane_length = header::lane_length(size.0, 8);
+    let mut pcx = Vec::new();
+    header::write_format(&mut pcx, 8, number_of_color_planes, size, (300, 300), &[[0; 3]; 16]).map_err(|e| e.to_string())?;
+    {
+        let mut compressor = rle::Compressor::new(&mut pcx, lane_length);
+        let mut lane = vec![0; lane_length as usize];
+        for row in values.chunks((size.0 as usize) * channels) {
+            for channel in 0..channels {
+                for (value, pixel) in lane.iter_mut().zip(row.chunks(channels)) {
+                    *value = pixel[channel];
+                }
+                compressor.write_all(&lane).map_err(|e| e.to_string())?;
+            }
+        }
+        compressor.finish().map_err(|e| e.to_string())?;
+    }
+
+    let image = Reader::new(&pcx[..]).and_then(|reader| reader.read_image()).map_err(|e| e.to_string())?;
+    compare(image.pixels, if channels == 2 { Pixels::GrayAlpha(values) } else { Pixels::Rgba(values) })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::self_test;
+
+    #[test]
+    fn self_test_passes() {
+        let report = self_test();
+        assert_eq!(report.cases.len(), 48);
+        assert!(report.passed(), "{}", report);
+        assert!(report.failures().is_empty());
+    }
+}
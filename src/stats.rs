@@ -0,0 +1,193 @@
+//! Statistics of decoded images.
+use pixels::{Image, Pixels};
+
+/// Statistics of one channel of the image.
+#[derive(Clone, Debug, PartialEq)]
+pub struct ChannelStats {
+    /// Smallest value.
+    pub min: u8,
+
+    /// Largest value.
+    pub max: u8,
+
+    /// Mean value.
+    pub mean: f64,
+
+    /// Number of pixels with each value.
+    pub histogram: [u64; 256],
+}
+
+/// Per-channel statistics of the image, see `Image::stats()`.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Stats {
+    /// Statistics of the color channels: R, G and B for paletted and RGB images, gray for grayscale images.
+    ///
+    /// Paletted pixels are looked up in the palette, pixels referring to colors beyond the end of the palette are black.
+    pub colors: Vec<ChannelStats>,
+
+    /// Statistics of the alpha channel if the image has one.
+    pub alpha: Option<ChannelStats>,
+}
+
+impl ChannelStats {
+    /// Compute statistics from the histogram.
+    pub fn from_histogram(histogram: [u64; 256]) -> Self {
+        let count: u64 = histogram.iter().sum();
+        let sum: u64 = histogram.iter().enumerate().map(|(value, &n)| (value as u64) * n).sum();
+        let mut values = histogram.iter().enumerate().filter(|&(_, &n)| n != 0).map(|(value, _)| value as u8);
+        let min = values.next().unwrap_or(0);
+        let max = values.next_back().unwrap_or(min);
+        ChannelStats {
+            min,
+            max,
+            mean: if count == 0 { 0.0 } else { (sum as f64) / (count as f64) },
+            histogram,
+        }
+    }
+
+    /// Number of pixels.
+    pub fn count(&self) -> u64 {
+        self.histogram.iter().sum()
+    }
+
+    /// Darkest value remaining after ignoring the `clip` fraction (e.g. `0.005`) of the darkest pixels as noise.
+    ///
+    /// Equal to `min` if `clip` is zero.
+    pub fn black_point(&self, clip: f64) -> u8 {
+        let ignored = clip_count(self.count(), clip);
+        let mut accumulated = 0;
+        for (value, &n) in self.histogram.iter().enumerate() {
+            accumulated += n;
+            if accumulated > ignored {
+                return value as u8;
+            }
+        }
+        self.max
+    }
+
+    /// Brightest value remaining after ignoring the `clip` fraction (e.g. `0.005`) of the brightest pixels as noise.
+    ///
+    /// Equal to `max` if `clip` is zero.
+    pub fn white_point(&self, clip: f64) -> u8 {
+        let ignored = clip_count(self.count(), clip);
+        let mut accumulated = 0;
+        for (value, &n) in self.histogram.iter().enumerate().rev() {
+            accumulated += n;
+            if accumulated > ignored {
+                return value as u8;
+            }
+        }
+        self.min
+    }
+}
+
+impl Stats {
+    /// Black and white points of all color channels together, see `ChannelStats::black_point()` and `ChannelStats::white_point()`.
+    ///
+    /// Images using the whole range, i.e. `(0, 255)`, will not benefit from contrast normalization.
+    pub fn levels(&self, clip: f64) -> (u8, u8) {
+        let black = self.colors.iter().map(|channel| channel.black_point(clip)).min().unwrap_or(0);
+        let white = self.colors.iter().map(|channel| channel.white_point(clip)).max().unwrap_or(255);
+        (black, white)
+    }
+}
+
+impl Image {
+    /// Compute per-channel statistics of the image.
+    pub fn stats(&self) -> Stats {
+        let (colors, alpha) = match self.pixels {
+            Pixels::Paletted { ref indices, ref palette } => {
+                // Count indices first, then distribute the counts to colors.
+                let index_histogram = histograms(indices, 1).remove(0);
+                let mut histograms = vec![[0; 256]; 3];
+                for (index, &n) in index_histogram.iter().enumerate() {
+                    let color = palette.get((index * 3)..(index * 3 + 3)).unwrap_or(&[0, 0, 0]);
+                    for (histogram, &value) in histograms.iter_mut().zip(color) {
+                        histogram[value as usize] += n;
+                    }
+                }
+                (histograms, None)
+            }
+            Pixels::Rgb(ref rgb) => (histograms(rgb, 3), None),
+            Pixels::GrayAlpha(ref gray_alpha) => {
+                let mut histograms = histograms(gray_alpha, 2);
+                let alpha = histograms.pop();
+                (histograms, alpha)
+            }
+            Pixels::Rgba(ref rgba) => {
+                let mut histograms = histograms(rgba, 4);
+                let alpha = histograms.pop();
+                (histograms, alpha)
+            }
+        };
+
+        Stats {
+            colors: colors.into_iter().map(ChannelStats::from_histogram).collect(),
+            alpha: alpha.map(ChannelStats::from_histogram),
+        }
+    }
+}
+
+// Histograms of interleaved channels.
+fn histograms(values: &[u8], channels: usize) -> Vec<[u64; 256]> {
+    let mut histograms = vec![[0; 256]; channels];
+    for pixel in values.chunks(channels) {
+        for (histogram, &value) in histograms.iter_mut().zip(pixel) {
+            histogram[value as usize] += 1;
+        }
+    }
+    histograms
+}
+
+// Number of pixels to ignore for the given clip fraction.
+fn clip_count(count: u64, clip: f64) -> u64 {
+    ((count as f64) * clip.clamp(0.0, 1.0)) as u64
+}
+
+#[cfg(test)]
+mod tests {
+    use pixels::{Image, Pixels};
+
+    #[test]
+    fn channels() {
+        let image = Image {
+            width: 2,
+            height: 2,
+            pixels: Pixels::Paletted {
+                indices: vec![0, 1, 1, 5],
+                palette: vec![10, 20, 30, 50, 60, 70],
+            },
+        };
+        let stats = image.stats();
+        assert!(stats.alpha.is_none());
+        assert_eq!(stats.colors.len(), 3);
+        assert_eq!((stats.colors[0].min, stats.colors[0].max, stats.colors[0].mean), (0, 50, 27.5));
+        assert_eq!((stats.colors[2].min, stats.colors[2].max), (0, 70));
+        assert_eq!(stats.colors[1].count(), 4);
+
+        let image = Image {
+            width: 2,
+            height: 1,
+            pixels: Pixels::GrayAlpha(vec![40, 255, 200, 0]),
+        };
+        let stats = image.stats();
+        assert_eq!(stats.colors.len(), 1);
+        assert_eq!(stats.levels(0.0), (40, 200));
+        let alpha = stats.alpha.unwrap();
+        assert_eq!((alpha.min, alpha.max, alpha.mean), (0, 255, 127.5));
+    }
+
+    #[test]
+    fn black_white_points() {
+        // One dark and one bright outlier among 98 mid-gray pixels.
+        let mut gray_alpha = vec![0, 255, 255, 255];
+        for i in 0..98 {
+            gray_alpha.extend_from_slice(&[100 + (i % 50) as u8, 255]);
+        }
+        let stats = Image { width: 100, height: 1, pixels: Pixels::GrayAlpha(gray_alpha) }.stats();
+        let gray = &stats.colors[0];
+        assert_eq!((gray.black_point(0.0), gray.white_point(0.0)), (0, 255));
+        assert_eq!((gray.black_point(0.01), gray.white_point(0.01)), (100, 149));
+        assert_eq!(stats.levels(0.01), (100, 149));
+    }
+}
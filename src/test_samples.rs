@@ -1,7 +1,7 @@
 use std::path::Path;
 use std::fs::File;
-use std::{io, iter};
-use walkdir::WalkDir;
+use std::io;
+use walkdir::{WalkDir, WalkDirIterator};
 use image;
 
 use Reader;
@@ -22,7 +22,7 @@ fn test_file(path: &Path, interleaved: bool) {
         print!("paletted ");
         let mut image = Vec::new();
         for _ in 0..pcx.height() {
-            let mut row: Vec<u8> = iter::repeat(0).take(pcx.width() as usize).collect();
+            let mut row: Vec<u8> = vec![0; pcx.width() as usize];
             pcx.next_row_paletted(&mut row).unwrap();
             image.push(row);
         }
@@ -33,11 +33,11 @@ fn test_file(path: &Path, interleaved: bool) {
         for y in 0..reference_image.height() {
             for x in 0..reference_image.width() {
                 let i = image[y as usize][x as usize] as usize;
-                let pcx_r = palette[i * 3 + 0];
+                let pcx_r = palette[i * 3];
                 let pcx_g = palette[i * 3 + 1];
                 let pcx_b = palette[i * 3 + 2];
 
-                let reference_pixel = reference_image.get_pixel(x as u32, y as u32);
+                let reference_pixel = reference_image.get_pixel(x, y);
                 let reference_r = reference_pixel.data[0];
                 let reference_g = reference_pixel.data[1];
                 let reference_b = reference_pixel.data[2];
@@ -52,18 +52,18 @@ fn test_file(path: &Path, interleaved: bool) {
 
         let mut image = Vec::new();
         for _ in 0..pcx.height() {
-            let mut rgb: Vec<u8> = iter::repeat(0).take((pcx.width() as usize) * 3).collect();
+            let mut rgb: Vec<u8> = vec![0; (pcx.width() as usize) * 3];
             pcx.next_row_rgb(&mut rgb).unwrap();
             image.push(rgb);
         }
 
         for y in 0..reference_image.height() {
             for x in 0..reference_image.width() {
-                let pcx_r = image[y as usize][(x as usize) * 3 + 0];
+                let pcx_r = image[y as usize][(x as usize) * 3];
                 let pcx_g = image[y as usize][(x as usize) * 3 + 1];
                 let pcx_b = image[y as usize][(x as usize) * 3 + 2];
 
-                let reference_pixel = reference_image.get_pixel(x as u32, y as u32);
+                let reference_pixel = reference_image.get_pixel(x, y);
                 let reference_r = reference_pixel.data[0];
                 let reference_g = reference_pixel.data[1];
                 let reference_b = reference_pixel.data[2];
@@ -80,9 +80,9 @@ fn test_file(path: &Path, interleaved: bool) {
         let mut image_g = Vec::new();
         let mut image_b = Vec::new();
         for _ in 0..pcx.height() {
-            let mut r: Vec<u8> = iter::repeat(0).take(pcx.width() as usize).collect();
-            let mut g: Vec<u8> = iter::repeat(0).take(pcx.width() as usize).collect();
-            let mut b: Vec<u8> = iter::repeat(0).take(pcx.width() as usize).collect();
+            let mut r: Vec<u8> = vec![0; pcx.width() as usize];
+            let mut g: Vec<u8> = vec![0; pcx.width() as usize];
+            let mut b: Vec<u8> = vec![0; pcx.width() as usize];
             pcx.next_row_rgb_separate(&mut r, &mut g, &mut b).unwrap();
             image_r.push(r);
             image_g.push(g);
@@ -95,7 +95,7 @@ fn test_file(path: &Path, interleaved: bool) {
                 let pcx_g = image_g[y as usize][x as usize];
                 let pcx_b = image_b[y as usize][x as usize];
 
-                let reference_pixel = reference_image.get_pixel(x as u32, y as u32);
+                let reference_pixel = reference_image.get_pixel(x, y);
                 let reference_r = reference_pixel.data[0];
                 let reference_g = reference_pixel.data[1];
                 let reference_b = reference_pixel.data[2];
@@ -111,7 +111,8 @@ fn test_file(path: &Path, interleaved: bool) {
 
 fn test_files(path: &str) {
     println!("Testing samples at {}", path);
-    for entry in WalkDir::new(path) {
+    // Golden corpus has references in a different format and is checked by `golden::tests`.
+    for entry in WalkDir::new(path).into_iter().filter_entry(|entry| entry.file_name() != "golden") {
         let entry = entry.unwrap();
 
         if let Some(ext) = entry.path().extension() {
@@ -0,0 +1,170 @@
+//! Transforms applied to rows while decoding.
+use std::borrow::Cow;
+use std::fmt;
+use std::sync::Arc;
+
+use pixels::PixelFormat;
+
+type RowFunction = dyn Fn(&mut [u8], PixelFormat) + Send + Sync;
+
+/// Transform applied to each row read by `Reader::next_row_into()`, `Reader::read_rows_into()` and `Reader::row()`, see
+/// `Reader::add_row_transform()`, or to each row written by `write_row()` of the writers, e.g. `WriterRgb::add_row_transform()`.
+#[derive(Clone)]
+pub enum RowTransform {
+    /// Replace each color value `v` with `table[v]`, alpha is left unchanged. Rows in `PixelFormat::Indexed` are remapped
+    /// index by index, so a table with the new index of each palette entry remaps the image to a reordered palette.
+    Lookup(Box<[u8; 256]>),
+
+    /// Swap the two channels with the given positions in each pixel, e.g. `SwapChannels(0, 2)` to turn RGB into BGR. Positions
+    /// past the number of channels of the pixel format leave rows unchanged.
+    SwapChannels(usize, usize),
+
+    /// Call the function with each row and its pixel format.
+    Custom(Arc<RowFunction>),
+}
+
+impl RowTransform {
+    /// Gamma correction of color values: `v` becomes `255 * (v / 255) ^ (1 / gamma)`.
+    pub fn gamma(gamma: f64) -> Self {
+        let mut table = [0; 256];
+        for (value, entry) in table.iter_mut().enumerate() {
+            *entry = (255.0 * (value as f64 / 255.0).powf(1.0 / gamma)).round() as u8;
+        }
+        RowTransform::Lookup(Box::new(table))
+    }
+
+    /// Transform with a custom function called with each row and its pixel format.
+    pub fn custom<F: Fn(&mut [u8], PixelFormat) + Send + Sync + 'static>(function: F) -> Self {
+        RowTransform::Custom(Arc::new(function))
+    }
+
+    /// Apply the transform to `row` in the given pixel format.
+    pub fn apply(&self, row: &mut [u8], format: PixelFormat) {
+        let channels = format.bytes_per_pixel();
+        match self {
+            RowTransform::Lookup(table) => {
+                let colors = match format {
+                    PixelFormat::Rgba | PixelFormat::Bgra => 3,
+                    _ => channels,
+                };
+                for pixel in row.chunks_mut(channels) {
+                    for value in &mut pixel[..colors] {
+                        *value = table[*value as usize];
+                    }
+                }
+            }
+            &RowTransform::SwapChannels(a, b) => {
+                if a < channels && b < channels {
+                    for pixel in row.chunks_mut(channels) {
+                        pixel.swap(a, b);
+                    }
+                }
+            }
+            RowTransform::Custom(function) => function(row, format),
+        }
+    }
+}
+
+// Apply `transforms` to a copy of `row`, borrows `row` if there are none.
+pub(crate) fn transform_row<'a>(transforms: &[RowTransform], row: &'a [u8], format: PixelFormat) -> Cow<'a, [u8]> {
+    if transforms.is_empty() {
+        return Cow::Borrowed(row);
+    }
+
+    let mut row = row.to_vec();
+    for transform in transforms {
+        transform.apply(&mut row, format);
+    }
+    Cow::Owned(row)
+}
+
+impl fmt::Debug for RowTransform {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        match self {
+            RowTransform::Lookup(table) => f.debug_tuple("Lookup").field(&&table[..]).finish(),
+            RowTransform::SwapChannels(a, b) => f.debug_tuple("SwapChannels").field(a).field(b).finish(),
+            RowTransform::Custom(_) => f.write_str("Custom(..)"),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io;
+    use super::RowTransform;
+    use {ChannelOrder, PixelFormat, Reader, WriterPaletted, WriterRgb};
+
+    #[test]
+    fn transforms() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let mut reader = Reader::new(io::Cursor::new(&data[..])).unwrap();
+        let width = reader.width() as usize;
+        let mut expected = vec![0; width * 3];
+        reader.next_row_into(&mut expected, PixelFormat::Rgb).unwrap();
+
+        let mut inverted = [0; 256];
+        for (value, entry) in inverted.iter_mut().enumerate() {
+            *entry = 255 - value as u8;
+        }
+        reader.add_row_transform(RowTransform::SwapChannels(0, 2));
+        reader.add_row_transform(RowTransform::Lookup(Box::new(inverted)));
+        reader.add_row_transform(RowTransform::custom(|row, format| row[0] = format.bytes_per_pixel() as u8));
+        let mut row = vec![0; width * 3];
+        reader.row(0, &mut row, PixelFormat::Rgb).unwrap();
+        for (x, (pixel, original)) in row.chunks(3).zip(expected.chunks(3)).enumerate() {
+            let red = if x == 0 { 3 } else { 255 - original[2] };
+            assert_eq!(pixel, [red, 255 - original[1], 255 - original[0]]);
+        }
+
+        // Alpha is not looked up.
+        reader.clear_row_transforms();
+        reader.add_row_transform(RowTransform::gamma(1.0));
+        reader.add_row_transform(RowTransform::Lookup(Box::new([0; 256])));
+        let mut row = vec![0; width * 4];
+        reader.row(0, &mut row, PixelFormat::Rgba).unwrap();
+        assert!(row.chunks(4).all(|pixel| pixel == [0, 0, 0, 255]));
+        assert!(format!("{:?}", RowTransform::custom(|_, _| ())).starts_with("Custom"));
+    }
+
+    #[test]
+    fn writer_transforms() {
+        let mut data = Vec::new();
+        {
+            let mut writer = WriterRgb::new(&mut data, (2, 1), (300, 300)).unwrap();
+            writer.add_row_transform(RowTransform::SwapChannels(0, 2));
+            writer.add_row_transform(RowTransform::gamma(2.0));
+            let row = [0, 64, 255, 10, 20, 30];
+            writer.write_row(&row).unwrap();
+            assert_eq!(row, [0, 64, 255, 10, 20, 30]);
+            writer.finish().unwrap();
+        }
+        let rgb = Reader::new(&data[..]).unwrap().read_image_rgb().unwrap();
+        assert_eq!(rgb, [255, 128, 0, 87, 71, 50]);
+
+        // Separate and packed rows are transformed as well.
+        let mut data = Vec::new();
+        {
+            let mut writer = WriterRgb::new(&mut data, (2, 2), (300, 300)).unwrap();
+            writer.add_row_transform(RowTransform::SwapChannels(0, 2));
+            writer.write_row_from_separate(&[1, 4], &[2, 5], &[3, 6]).unwrap();
+            writer.write_row_packed(&[0x0001_0203, 0x0004_0506], ChannelOrder::Argb).unwrap();
+            writer.finish().unwrap();
+        }
+        let rgb = Reader::new(&data[..]).unwrap().read_image_rgb().unwrap();
+        assert_eq!(rgb, [3, 2, 1, 6, 5, 4, 3, 2, 1, 6, 5, 4]);
+
+        let mut data = Vec::new();
+        {
+            let mut remap = [0; 256];
+            for (index, entry) in remap.iter_mut().enumerate() {
+                *entry = (index as u8).wrapping_add(1);
+            }
+            let mut writer = WriterPaletted::new(&mut data, (3, 1), (300, 300)).unwrap();
+            writer.add_row_transform(RowTransform::Lookup(Box::new(remap)));
+            writer.write_row(&[0, 1, 2]).unwrap();
+            writer.write_palette(&[0; 4 * 3]).unwrap();
+        }
+        let image = Reader::new(&data[..]).unwrap().read_image().unwrap();
+        assert_eq!(image.into_raw_parts().0, [1, 2, 3]);
+    }
+}
@@ -0,0 +1,78 @@
+//! Building one palette shared by several images.
+use std::collections::HashSet;
+
+use {Error, Palette, Quantizer, Result};
+use pixels::{Image, Pixels};
+
+/// Build one palette shared by all `images` and remap them to it, e.g. to combine tiles from images with different palettes
+/// into one tileset.
+///
+/// Colors of all images together are reduced with `quantizer`, images of any color format are accepted and alpha is ignored.
+/// Each of the `reserved` entries (index and color) keeps its place in the palette in addition to the slots locked in
+/// `quantizer`, e.g. for a transparent color or colors used by the user interface of an engine, see `Quantizer::lock()`.
+/// Pixels are mapped to the nearest color of the whole palette, so pixels with the exact color of a reserved entry use it.
+///
+/// Returns the palette together with the remapped paletted images in the same order as `images`.
+pub fn unify_palettes(images: &[Image], quantizer: &Quantizer, reserved: &[(u8, [u8; 3])]) -> Result<(Palette, Vec<Image>)> {
+    let mut reserved_indices = HashSet::new();
+    if !reserved.iter().all(|&(index, _)| reserved_indices.insert(index)) {
+        return Err(Error::InvalidUsage("reserved palette indices must be unique"));
+    }
+
+    let mut quantizer = *quantizer;
+    for &(index, color) in reserved {
+        quantizer.lock(index, color);
+    }
+    let rgb: Vec<Vec<u8>> = images.iter().map(Image::to_rgb).collect();
+    let palette = quantizer.palette(&rgb.concat())?;
+
+    let rgb_palette = palette.to_rgb();
+    let images = images
+        .iter()
+        .zip(rgb)
+        .map(|(image, rgb)| {
+            Ok(Image {
+                width: image.width,
+                height: image.height,
+                pixels: Pixels::Paletted { indices: palette.map_rgb_in(&rgb, quantizer.color_space())?, palette: rgb_palette.clone() },
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    Ok((palette, images))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::unify_palettes;
+    use {Error, Image, Pixels, Quantizer};
+
+    fn paletted(indices: &[u8], palette: &[u8]) -> Image {
+        Image { width: indices.len() as u16, height: 1, pixels: Pixels::Paletted { indices: indices.to_vec(), palette: palette.to_vec() } }
+    }
+
+    #[test]
+    fn unify() {
+        let red_green = paletted(&[0, 1, 1], &[255, 0, 0, 0, 255, 0]);
+        let blue_red = paletted(&[1, 0], &[0, 0, 255, 255, 0, 0]);
+        let rgb = Image { width: 1, height: 1, pixels: Pixels::Rgb(vec![0, 255, 0]) };
+        let images = [red_green, blue_red, rgb];
+
+        let (palette, unified) = unify_palettes(&images, &Quantizer::default(), &[]).unwrap();
+        assert_eq!(palette.to_rgb(), [0, 0, 255, 0, 255, 0, 255, 0, 0]);
+        for (original, unified) in images.iter().zip(&unified) {
+            assert_eq!(unified.to_rgb(), original.to_rgb());
+        }
+
+        // Reserved entries keep their place, other colors fill the free slots.
+        let (palette, unified) = unify_palettes(&images, &Quantizer::default(), &[(0, [255, 0, 255]), (5, [255; 3])]).unwrap();
+        assert_eq!(palette.to_rgb(), [255, 0, 255, 0, 0, 255, 0, 255, 0, 255, 0, 0, 0, 0, 0, 255, 255, 255]);
+        assert_eq!(unified[1].pixels, Pixels::Paletted { indices: vec![3, 1], palette: palette.to_rgb() });
+
+        // Fewer colors than needed.
+        let (palette, unified) = unify_palettes(&images, &Quantizer::new(2).unwrap(), &[(0, [0; 3])]).unwrap();
+        assert_eq!(palette.len(), 2);
+        assert_eq!(unified[2].pixels, Pixels::Paletted { indices: vec![1], palette: palette.to_rgb() });
+
+        assert!(matches!(unify_palettes(&images, &Quantizer::default(), &[(1, [0; 3]), (1, [1; 3])]), Err(Error::InvalidUsage(_))));
+    }
+}
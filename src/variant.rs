@@ -0,0 +1,112 @@
+//! Extension point for near-PCX variants, e.g. files written by specific games with nonstandard headers.
+use std::fmt;
+use std::io;
+
+use {Options, Reader, Result};
+use low_level::{Header, RawHeader, HEADER_LENGTH};
+
+/// Quirks handling of a format variant which differs from PCX only in its header, registered in `VariantRegistry`.
+///
+/// Variants are recognized by the raw bytes of the header, which they rewrite into a standard PCX header before it is parsed
+/// and validated as usual. Pixel data must follow the header as in PCX files.
+pub trait Variant: fmt::Debug + Send + Sync {
+    /// Name of the variant, e.g. of the game writing it.
+    fn name(&self) -> &str;
+
+    /// Whether the raw header belongs to this variant.
+    fn matches(&self, header: &[u8; HEADER_LENGTH]) -> bool;
+
+    /// Rewrite the raw header into a standard PCX header, or return an error if the file can not be read.
+    fn fix_header(&self, header: &mut [u8; HEADER_LENGTH]) -> Result<()>;
+}
+
+/// Collection of variants tried in the order of registration when opening files with `VariantRegistry::reader()`.
+#[derive(Debug, Default)]
+pub struct VariantRegistry {
+    variants: Vec<Box<dyn Variant>>,
+}
+
+impl VariantRegistry {
+    /// Create empty registry, which reads only standard PCX files.
+    pub fn new() -> Self {
+        VariantRegistry::default()
+    }
+
+    /// Register variant, it is tried after the ones registered before.
+    pub fn register<V: Variant + 'static>(&mut self, variant: V) {
+        self.variants.push(Box::new(variant));
+    }
+
+    /// Names of registered variants in the order of registration.
+    pub fn names(&self) -> impl Iterator<Item = &str> + '_ {
+        self.variants.iter().map(|variant| variant.name())
+    }
+
+    /// First registered variant matching the raw header, `None` for headers of no variant.
+    pub fn detect(&self, header: &[u8; HEADER_LENGTH]) -> Option<&dyn Variant> {
+        self.variants.iter().find(|variant| variant.matches(header)).map(|variant| &**variant)
+    }
+
+    /// Start reading PCX file or a file of a registered variant with the given options. Files matching no variant are read
+    /// as PCX files.
+    pub fn reader<R: io::Read>(&self, mut stream: R, options: Options) -> Result<Reader<R>> {
+        let mut bytes = [0; HEADER_LENGTH];
+        stream.read_exact(&mut bytes)?;
+        let raw_header = RawHeader::new(bytes);
+        if let Some(variant) = self.detect(&bytes) {
+            variant.fix_header(&mut bytes)?;
+        }
+
+        let header = Header::load_with_options(&mut &bytes[..], &options)?;
+        Reader::with_header(stream, header, raw_header, options)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::{Variant, VariantRegistry};
+    use {decode, Error, Options, Result};
+    use low_level::HEADER_LENGTH;
+
+    // Variant storing magic byte 0x42 and big-endian dimensions.
+    #[derive(Debug)]
+    struct Swapped;
+
+    impl Variant for Swapped {
+        fn name(&self) -> &str {
+            "swapped"
+        }
+
+        fn matches(&self, header: &[u8; HEADER_LENGTH]) -> bool {
+            header[0] == 0x42
+        }
+
+        fn fix_header(&self, header: &mut [u8; HEADER_LENGTH]) -> Result<()> {
+            header[0] = 0x0A;
+            for field in header[4..12].chunks_mut(2) {
+                field.swap(0, 1);
+            }
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn variants() {
+        let data = include_bytes!("../test-data/gmarbles.pcx");
+        let mut variant = data.to_vec();
+        variant[0] = 0x42;
+        for field in variant[4..12].chunks_mut(2) {
+            field.swap(0, 1);
+        }
+
+        let mut registry = VariantRegistry::new();
+        assert!(matches!(registry.reader(&variant[..], Options::default()), Err(Error::NotPcx)));
+        registry.register(Swapped);
+        assert_eq!(registry.names().collect::<Vec<_>>(), ["swapped"]);
+
+        let image = registry.reader(&variant[..], Options::default()).unwrap().read_image().unwrap();
+        assert_eq!(image, decode(&data[..]).unwrap());
+        let image = registry.reader(&data[..], Options::default()).unwrap().read_image().unwrap();
+        assert_eq!(image, decode(&data[..]).unwrap());
+    }
+}
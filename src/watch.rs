@@ -0,0 +1,99 @@
+//! Watchpoints for debugging decoding of malformed files.
+use std::fmt;
+use std::sync::Arc;
+
+type WatchFunction = dyn Fn(&DecoderState) + Send + Sync;
+
+/// Position in the file at which the callback registered with `Reader::add_watchpoint()` is called.
+///
+/// Watchpoints are checked at the start of each lane, so the callback sees the state between two lanes.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum Watchpoint {
+    /// Start of the first lane beginning at or after the byte with this offset from the start of file.
+    Offset(u64),
+
+    /// Start of the first lane of the row with this number.
+    Row(u16),
+}
+
+/// State of the decoder passed to watchpoint callbacks.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub struct DecoderState {
+    /// Row of the lane about to be read.
+    pub row: u16,
+
+    /// Color plane of the lane about to be read.
+    pub plane: u8,
+
+    /// Offset of the next stored byte from the start of file.
+    pub offset: u64,
+
+    /// Number of decoded bytes of pixel data before the lane, including padding of lanes.
+    pub decoded: u64,
+
+    /// Count and value of the RLE run in progress which continues into the lane, count is 0 if there is none. A run crossing
+    /// the end of a lane usually means that the writer or this file is broken.
+    pub pending_run: (u8, u8),
+}
+
+// Registered watchpoint with its callback.
+#[derive(Clone)]
+pub(crate) struct Watch {
+    pub(crate) watchpoint: Watchpoint,
+    pub(crate) callback: Arc<WatchFunction>,
+    pub(crate) triggered: bool,
+}
+
+impl Watch {
+    pub(crate) fn new<F: Fn(&DecoderState) + Send + Sync + 'static>(watchpoint: Watchpoint, callback: F) -> Self {
+        Watch { watchpoint, callback: Arc::new(callback), triggered: false }
+    }
+
+    // Call the callback if the lane described by `state` reaches the watchpoint for the first time.
+    pub(crate) fn check(&mut self, state: &DecoderState) {
+        let reached = match self.watchpoint {
+            Watchpoint::Offset(offset) => state.offset >= offset,
+            Watchpoint::Row(row) => state.row == row,
+        };
+        if reached && !self.triggered {
+            self.triggered = true;
+            (self.callback)(state);
+        }
+    }
+}
+
+impl fmt::Debug for Watch {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        f.debug_struct("Watch").field("watchpoint", &self.watchpoint).field("triggered", &self.triggered).finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::{Arc, Mutex};
+    use super::{DecoderState, Watchpoint};
+    use Reader;
+
+    #[test]
+    fn watchpoints() {
+        let data = include_bytes!("../test-data/marbles.pcx");
+        let states = Arc::new(Mutex::new(Vec::new()));
+        let mut reader = Reader::new(&data[..]).unwrap();
+        for &watchpoint in &[Watchpoint::Row(2), Watchpoint::Offset(128), Watchpoint::Offset(1 << 40)] {
+            let states = states.clone();
+            reader.add_watchpoint(watchpoint, move |state: &DecoderState| states.lock().unwrap().push((watchpoint, *state)));
+        }
+        let image = reader.read_image().unwrap();
+        assert_eq!(image, Reader::new(&data[..]).unwrap().read_image().unwrap());
+
+        let states = states.lock().unwrap();
+        assert_eq!(states.len(), 2);
+        let (watchpoint, start) = states[0];
+        assert_eq!(watchpoint, Watchpoint::Offset(128));
+        assert_eq!((start.row, start.plane, start.offset, start.decoded, start.pending_run), (0, 0, 128, 0, (0, 0)));
+        let (watchpoint, row) = states[1];
+        assert_eq!(watchpoint, Watchpoint::Row(2));
+        assert_eq!((row.row, row.plane, row.decoded), (2, 0, 2 * 3 * 144));
+        assert!(row.offset > 128);
+    }
+}
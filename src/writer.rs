@@ -1,13 +1,31 @@
-use std::io;
+use std::{cmp, io};
+use std::collections::HashMap;
 use std::io::Write;
 use std::fs::File;
 use std::path::Path;
 use byteorder::WriteBytesExt;
 
-use user_error;
-use low_level::header;
+use {user_error, ChannelOrder, ColorError, ColorHistogram, ColorSpace, Dithering, Palette, PixelFormat, Profile, Quantizer, Reader, Result, RowTransform, WriterOptions};
+use low_level::{header, Header};
+use low_level::header::Version;
 use low_level::rle::Compressor;
 use low_level::PALETTE_START;
+use transform::transform_row;
+
+// Memory used by one color counted in a histogram of `Quantizer` including the overhead of the hash map, an upper estimate.
+const HISTOGRAM_ENTRY_MEMORY: u64 = 32;
+
+// Number of k-means iterations refining palettes of `WriterQuantized` with `Profile::Small`.
+const SMALL_PROFILE_REFINEMENT: u8 = 4;
+
+// Dithering of quantized images with `Profile::Small`. Ordered dithering gives regular patterns which RLE compresses better
+// than those of the other methods, at half strength it only breaks up bands between close colors, which costs little size.
+const SMALL_PROFILE_DITHERING: Dithering = Dithering::Ordered { size: 4 };
+const SMALL_PROFILE_DITHERING_STRENGTH: i32 = 50;
+
+// Largest difference of a channel by which the color of a run may be farther from a pixel than the color of the pixel for the
+// pixel to be merged into the run with `Profile::Small`.
+const SMALL_PROFILE_MERGE_TOLERANCE: i32 = 24;
 
 /// Create 24-bit RGB PCX image.
 #[derive(Clone, Debug)]
@@ -15,6 +33,7 @@ pub struct WriterRgb<W: io::Write> {
     compressor: Compressor<W>,
     num_rows_left: u16,
     width: u16,
+    row_transforms: Vec<RowTransform>,
 }
 
 /// Create paletted PCX image.
@@ -23,6 +42,58 @@ pub struct WriterPaletted<W: io::Write> {
     compressor: Compressor<W>,
     num_rows_left: u16,
     width: u16,
+    height: u16,
+    palette_length: Option<(u16, IndexPolicy)>,
+    row_transforms: Vec<RowTransform>,
+}
+
+/// What `WriterPaletted` does with pixels referring to colors beyond the end of the palette.
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Hash)]
+pub enum IndexPolicy {
+    /// Return an error from `write_row`.
+    Error,
+    /// Replace such indices with the index of the last palette color.
+    Clamp,
+}
+
+/// Create paletted PCX image with 2, 4, 8 or 16 colors stored in 1, 2, 3 or 4 bit planes. Palette is stored in the header.
+///
+/// Single plane gives 1-bit monochrome image as used by fax and scanned document archives, readers display such images in black
+/// (index 0) and white (index 1) regardless of the palette.
+#[derive(Clone, Debug)]
+pub struct WriterPlanar<W: io::Write> {
+    compressor: Compressor<W>,
+    num_rows_left: u16,
+    width: u16,
+    number_of_color_planes: u8,
+    lane: Vec<u8>,
+    row_transforms: Vec<RowTransform>,
+}
+
+/// Create 256-color paletted PCX image from RGB pixels, building the palette with `Quantizer`.
+///
+/// The palette can only be built once all pixels are known, so rows are kept in memory until `finish()` is called. Nothing
+/// is written if the writer is dropped without calling `finish()`.
+#[derive(Clone, Debug)]
+pub struct WriterQuantized<W: io::Write> {
+    stream: W,
+    image_size: (u16, u16),
+    dpi: (u16, u16),
+    options: WriterOptions,
+    quantizer: Quantizer,
+    rgb: Vec<u8>,
+    row_transforms: Vec<RowTransform>,
+}
+
+/// Create PCX image from stored pixel data as returned by `Reader::next_lane_raw()`, without compressing it again.
+///
+/// The header is written as given, so metadata of an existing image can be changed while its pixel data is copied
+/// byte-identically.
+#[derive(Clone, Debug)]
+pub struct WriterRaw<W: io::Write> {
+    stream: W,
+    header: Header,
+    num_lanes_left: u32,
 }
 
 impl WriterRgb<io::BufWriter<File>> {
@@ -45,22 +116,52 @@ impl WriterPaletted<io::BufWriter<File>> {
     }
 }
 
+impl WriterPlanar<io::BufWriter<File>> {
+    /// Start writing PCX file. This function will create a file if it does not exist, and will overwrite it if it does.
+    ///
+    /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
+    pub fn create_file<P: AsRef<Path>>(path: P, image_size: (u16, u16), dpi: (u16, u16), number_of_color_planes: u8, palette: &[u8]) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Self::new(io::BufWriter::new(file), image_size, dpi, number_of_color_planes, palette)
+    }
+}
+
 impl<W: io::Write> WriterRgb<W> {
     /// Create new PCX writer.
     ///
     /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
-    pub fn new(mut stream: W, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
-        header::write(&mut stream, false, image_size, dpi)?;
-
-        let lane_length = image_size.0 + (image_size.0 & 1); // width rounded up to even
+    pub fn new(stream: W, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
+        Self::new_with_options(stream, image_size, dpi, WriterOptions::default())
+    }
 
+    /// Create new PCX writer with the given options.
+    pub fn new_with_options(stream: W, image_size: (u16, u16), dpi: (u16, u16), options: WriterOptions) -> io::Result<Self> {
         Ok(WriterRgb {
-            compressor: Compressor::new(stream, lane_length),
+            compressor: start(stream, 8, 3, image_size, dpi, &[[0; 3]; 16], options)?,
             width: image_size.0,
             num_rows_left: image_size.1,
+            row_transforms: Vec::new(),
         })
     }
 
+    /// Estimated peak of memory allocated by the writer in bytes, not counting the underlying stream.
+    pub fn estimated_peak_memory(&self) -> u64 {
+        // Separate and packed rows are interleaved before they are transformed.
+        transform_memory(&self.row_transforms, (self.width as u64) * 3 * 2)
+    }
+
+    /// Append `transform` to the transforms applied to rows passed to `write_row()`, `write_row_from_separate()` and
+    /// `write_row_packed()` before they are encoded, in the order they were added. Rows are transformed as `PixelFormat::Rgb`,
+    /// the buffers passed by the caller are not modified.
+    pub fn add_row_transform(&mut self, transform: RowTransform) {
+        self.row_transforms.push(transform);
+    }
+
+    /// Remove all transforms added with `add_row_transform()`.
+    pub fn clear_row_transforms(&mut self) {
+        self.row_transforms.clear();
+    }
+
     /// Write next row of pixels from separate buffers for R, G and B channels.
     ///
     /// Length of each of `r`, `g` and `b` must be equal to the width of the image passed to `new`.
@@ -77,11 +178,16 @@ impl<W: io::Write> WriterRgb<W> {
             return user_error("pcx::WriterRgb::write_row_from_separate: buffer lengths must be equal to the width of the image");
         }
 
-        self.compressor.write(r)?;
+        if !self.row_transforms.is_empty() {
+            let rgb: Vec<u8> = r.iter().zip(g).zip(b).flat_map(|((&r, &g), &b)| [r, g, b]).collect();
+            return self.write_row(&rgb);
+        }
+
+        self.compressor.write_all(r)?;
         self.compressor.pad()?;
-        self.compressor.write(g)?;
+        self.compressor.write_all(g)?;
         self.compressor.pad()?;
-        self.compressor.write(b)?;
+        self.compressor.write_all(b)?;
         self.compressor.pad()?;
 
         self.num_rows_left -= 1;
@@ -103,6 +209,9 @@ impl<W: io::Write> WriterRgb<W> {
             return user_error("pcx::WriterRgb::write_row: buffer length must be equal to the width of the image multiplied by 3");
         }
 
+        let transformed = transform_row(&self.row_transforms, rgb, PixelFormat::Rgb);
+        let rgb = &transformed[..];
+
         for color in 0..3 {
             for x in 0..(self.width as usize) {
                 self.compressor.write_u8(rgb[x * 3 + color])?;
@@ -114,6 +223,40 @@ impl<W: io::Write> WriterRgb<W> {
         Ok(())
     }
 
+    /// Write next row of pixels from words with channels packed in the given order. Alpha channel is ignored.
+    ///
+    /// Length of the `pixels` buffer must be equal to the width of the image passed to `new`.
+    /// This function must be called number of times equal to the height of the image.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn write_row_packed(&mut self, pixels: &[u32], order: ChannelOrder) -> io::Result<()> {
+        if self.num_rows_left == 0 {
+            return user_error("pcx::WriterRgb::write_row_packed: all rows were already written");
+        }
+
+        if pixels.len() != self.width as usize {
+            return user_error("pcx::WriterRgb::write_row_packed: buffer length must be equal to the width of the image");
+        }
+
+        if !self.row_transforms.is_empty() {
+            let rgb: Vec<u8> = pixels.iter().flat_map(|&pixel| {
+                let [r, g, b, _] = order.unpack(pixel);
+                [r, g, b]
+            }).collect();
+            return self.write_row(&rgb);
+        }
+
+        for color in 0..3 {
+            for &pixel in pixels {
+                self.compressor.write_u8(order.unpack(pixel)[color])?;
+            }
+            self.compressor.pad()?;
+        }
+
+        self.num_rows_left -= 1;
+        Ok(())
+    }
+
     /// Flush all data and finish writing.
     ///
     /// If you simply drop `WriterRgb` it will also flush everything but this function is preferable because errors won't be ignored.
@@ -136,18 +279,57 @@ impl<W: io::Write> WriterPaletted<W> {
     /// Create new PCX writer.
     ///
     /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
-    pub fn new(mut stream: W, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
-        header::write(&mut stream, true, image_size, dpi)?;
-
-        let lane_length = image_size.0 + (image_size.0 & 1); // width rounded up to even
+    pub fn new(stream: W, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
+        Self::new_with_options(stream, image_size, dpi, WriterOptions::default())
+    }
 
+    /// Create new PCX writer with the given options.
+    pub fn new_with_options(stream: W, image_size: (u16, u16), dpi: (u16, u16), options: WriterOptions) -> io::Result<Self> {
+        // 16-color palette is not used as we will use 256-color palette instead.
         Ok(WriterPaletted {
-            compressor: Compressor::new(stream, lane_length),
+            compressor: start(stream, 8, 1, image_size, dpi, &[[0; 3]; 16], options)?,
             width: image_size.0,
             num_rows_left: image_size.1,
+            height: image_size.1,
+            palette_length: None,
+            row_transforms: Vec::new(),
         })
     }
 
+    /// Estimated peak of memory allocated by the writer in bytes, not counting the underlying stream.
+    pub fn estimated_peak_memory(&self) -> u64 {
+        transform_memory(&self.row_transforms, self.width as u64)
+    }
+
+    /// Append `transform` to the transforms applied to rows passed to `write_row()` before they are encoded, in the order they
+    /// were added. Rows are transformed as `PixelFormat::Indexed`, so `RowTransform::Lookup` remaps palette indices.
+    pub fn add_row_transform(&mut self, transform: RowTransform) {
+        self.row_transforms.push(transform);
+    }
+
+    /// Remove all transforms added with `add_row_transform()`.
+    pub fn clear_row_transforms(&mut self) {
+        self.row_transforms.clear();
+    }
+
+    /// Declare the number of colors in the palette in advance so that pixels can be validated as rows are written.
+    ///
+    /// Must be called before writing any rows. `policy` defines what happens to pixels with index not less than `palette_length`.
+    /// Without calling this function pixels are not validated, and colors missing from a short palette passed to
+    /// `write_palette()` are black.
+    pub fn set_palette_length(&mut self, palette_length: u16, policy: IndexPolicy) -> io::Result<()> {
+        if self.num_rows_left != self.height {
+            return user_error("pcx::WriterPaletted::set_palette_length: must be called before writing rows");
+        }
+
+        if palette_length == 0 || palette_length > 256 {
+            return user_error("pcx::WriterPaletted::set_palette_length: palette length must be between 1 and 256");
+        }
+
+        self.palette_length = Some((palette_length, policy));
+        Ok(())
+    }
+
     /// Write next row of pixels.
     ///
     /// Row length must be equal to the width of the image passed to `new`.
@@ -163,7 +345,22 @@ impl<W: io::Write> WriterPaletted<W> {
             return user_error("pcx::WriterPaletted::write_row: buffer length must be equal to the width of the image");
         }
 
-        self.compressor.write(row)?;
+        let transformed = transform_row(&self.row_transforms, row, PixelFormat::Indexed);
+        let row = &transformed[..];
+
+        let max_index = row.iter().cloned().max().unwrap_or(0);
+        match self.palette_length {
+            Some((palette_length, IndexPolicy::Error)) if (max_index as u16) >= palette_length => {
+                return user_error("pcx::WriterPaletted::write_row: pixel refers to color beyond the end of the palette");
+            }
+            Some((palette_length, IndexPolicy::Clamp)) if (max_index as u16) >= palette_length => {
+                let last_index = (palette_length - 1) as u8;
+                for &index in row {
+                    self.compressor.write_u8(cmp::min(index, last_index))?;
+                }
+            }
+            _ => self.compressor.write_all(row)?,
+        }
         self.compressor.pad()?;
 
         self.num_rows_left -= 1;
@@ -173,6 +370,7 @@ impl<W: io::Write> WriterPaletted<W> {
     /// Since palette is written to the end of PCX file this function must be called only after writing all the pixels.
     ///
     /// Palette length must be not larger than 256*3 = 768 bytes and be divisible by 3. Format is R, G, B, R, G, B, ...
+    /// Shorter palettes are padded with black. If `set_palette_length()` was called the palette must have the declared length.
     pub fn write_palette(self, palette: &[u8]) -> io::Result<()> {
         if self.num_rows_left != 0 {
             return user_error("pcx::WriterPaletted::write_palette: not all rows written");
@@ -182,9 +380,16 @@ impl<W: io::Write> WriterPaletted<W> {
             return user_error("pcx::WriterPaletted::write_palette: incorrect palette length");
         }
 
+        let palette_length = (palette.len() / 3) as u16;
+        if let Some((declared_length, _)) = self.palette_length {
+            if palette_length != declared_length {
+                return user_error("pcx::WriterPaletted::write_palette: palette length differs from the one passed to set_palette_length");
+            }
+        }
+
         let mut stream = self.compressor.finish()?;
         stream.write_u8(PALETTE_START)?;
-        stream.write(palette)?;
+        stream.write_all(palette)?;
         for _ in 0..(256 * 3 - palette.len()) {
             stream.write_u8(0)?;
         }
@@ -192,3 +397,470 @@ impl<W: io::Write> WriterPaletted<W> {
         Ok(())
     }
 }
+
+impl WriterQuantized<io::BufWriter<File>> {
+    /// Start writing PCX file. This function will create a file if it does not exist, and will overwrite it if it does.
+    ///
+    /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
+    pub fn create_file<P: AsRef<Path>>(path: P, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
+        let file = File::create(path)?;
+        Self::new(io::BufWriter::new(file), image_size, dpi)
+    }
+}
+
+impl<W: io::Write> WriterQuantized<W> {
+    /// Create new PCX writer.
+    ///
+    /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
+    pub fn new(stream: W, image_size: (u16, u16), dpi: (u16, u16)) -> io::Result<Self> {
+        Self::new_with_options(stream, image_size, dpi, WriterOptions::default())
+    }
+
+    /// Create new PCX writer with the given options.
+    pub fn new_with_options(stream: W, image_size: (u16, u16), dpi: (u16, u16), options: WriterOptions) -> io::Result<Self> {
+        // Validate the size now rather than after all rows were written.
+        header::writer_header(8, 1, image_size, dpi, &[[0; 3]; 16])?;
+
+        Ok(WriterQuantized {
+            stream,
+            image_size,
+            dpi,
+            options,
+            quantizer: Quantizer::default(),
+            rgb: Vec::new(),
+            row_transforms: Vec::new(),
+        })
+    }
+
+    /// Estimated peak of memory allocated by the writer in bytes, reached in `finish()`, not counting the underlying stream.
+    ///
+    /// All rows are kept until the palette is built, then the histogram of colors and the palette indices are allocated. The
+    /// histogram is estimated for every pixel having a distinct color, so the estimate is an upper bound.
+    pub fn estimated_peak_memory(&self) -> u64 {
+        let pixels = (self.image_size.0 as u64) * (self.image_size.1 as u64);
+        let histogram = cmp::min(pixels, 1 << 24) * HISTOGRAM_ENTRY_MEMORY;
+        let row = transform_memory(&self.row_transforms, (self.image_size.0 as u64) * 3);
+        pixels * 3 + cmp::max(row, histogram + pixels + 256 * 3)
+    }
+
+    /// Append `transform` to the transforms applied to rows passed to `write_row()`, in the order they were added. Rows are
+    /// transformed as `PixelFormat::Rgb` before the palette is built.
+    pub fn add_row_transform(&mut self, transform: RowTransform) {
+        self.row_transforms.push(transform);
+    }
+
+    /// Remove all transforms added with `add_row_transform()`.
+    pub fn clear_row_transforms(&mut self) {
+        self.row_transforms.clear();
+    }
+
+    /// Set the quantizer used to build the palette. Default quantizer produces palettes of up to 256 colors.
+    pub fn set_quantizer(&mut self, quantizer: Quantizer) {
+        self.quantizer = quantizer;
+    }
+
+    /// Write next row of pixels from buffer which contains RGB values interleaved (i.e. R, G, B, R, G, B, ...).
+    ///
+    /// Length of the `rgb` buffer must be equal to the width of the image passed to `new` multiplied by 3.
+    /// This function must be called number of times equal to the height of the image.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn write_row(&mut self, rgb: &[u8]) -> io::Result<()> {
+        let row_length = (self.image_size.0 as usize) * 3;
+        if self.rgb.len() == row_length * (self.image_size.1 as usize) {
+            return user_error("pcx::WriterQuantized::write_row: all rows were already written");
+        }
+
+        if rgb.len() != row_length {
+            return user_error("pcx::WriterQuantized::write_row: buffer length must be equal to the width of the image multiplied by 3");
+        }
+
+        self.rgb.extend_from_slice(&transform_row(&self.row_transforms, rgb, PixelFormat::Rgb));
+        Ok(())
+    }
+
+    /// Write next row of pixels from words with channels packed in the given order. Alpha channel is ignored.
+    ///
+    /// Length of the `pixels` buffer must be equal to the width of the image passed to `new`.
+    /// This function must be called number of times equal to the height of the image.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn write_row_packed(&mut self, pixels: &[u32], order: ChannelOrder) -> io::Result<()> {
+        if self.rgb.len() == (self.image_size.0 as usize) * 3 * (self.image_size.1 as usize) {
+            return user_error("pcx::WriterQuantized::write_row_packed: all rows were already written");
+        }
+
+        if pixels.len() != self.image_size.0 as usize {
+            return user_error("pcx::WriterQuantized::write_row_packed: buffer length must be equal to the width of the image");
+        }
+
+        let rgb: Vec<u8> = pixels.iter().flat_map(|&pixel| {
+            let [r, g, b, _] = order.unpack(pixel);
+            [r, g, b]
+        }).collect();
+        self.write_row(&rgb)
+    }
+
+    /// Build the palette, write the image and finish writing. Returns the palette of the image.
+    pub fn finish(self) -> io::Result<Palette> {
+        if self.rgb.len() != (self.image_size.0 as usize) * 3 * (self.image_size.1 as usize) {
+            return user_error("pcx::WriterQuantized::finish: not all rows written");
+        }
+
+        encode_quantized(self.stream, self.image_size, self.dpi, self.options, self.quantizer, &self.rgb)
+    }
+
+    /// Like `finish()` but the encoded image is decoded again in memory and compared with the written rows before it is
+    /// written to the stream, e.g. so that pipelines can reject conversions losing too much color. Returns the palette together
+    /// with the color error.
+    pub fn finish_verified(mut self) -> io::Result<(Palette, ColorError)> {
+        if self.rgb.len() != (self.image_size.0 as usize) * 3 * (self.image_size.1 as usize) {
+            return user_error("pcx::WriterQuantized::finish_verified: not all rows written");
+        }
+
+        let mut encoded = Vec::new();
+        let palette = encode_quantized(&mut encoded, self.image_size, self.dpi, self.options, self.quantizer, &self.rgb)?;
+        let decoded = Reader::new(&encoded[..])?.read_image_rgb()?;
+        let error = ColorError::between(&self.rgb, &decoded);
+
+        self.stream.write_all(&encoded)?;
+        self.stream.flush()?;
+        Ok((palette, error))
+    }
+}
+
+/// Create 256-color paletted PCX image from RGB rows like `WriterQuantized` without holding the image in memory, e.g. for
+/// huge sources rendered or read from disk row by row.
+///
+/// `read_row(y, rgb)` must fill `rgb` with the interleaved RGB values of row `y`. Rows are requested from top to bottom twice:
+/// the first pass collects the colors to build the palette with `quantizer`, the second one encodes the image, so the source
+/// must produce the same rows both times. Returns the palette of the image.
+pub fn write_quantized_two_pass<W, F>(stream: W, image_size: (u16, u16), dpi: (u16, u16), options: WriterOptions, quantizer: &Quantizer, mut read_row: F) -> io::Result<Palette>
+where
+    W: io::Write,
+    F: FnMut(u16, &mut [u8]) -> io::Result<()>,
+{
+    // Validate the size before the first pass.
+    header::writer_header(8, 1, image_size, dpi, &[[0; 3]; 16])?;
+
+    let mut rgb = vec![0; (image_size.0 as usize) * 3];
+    let mut histogram = ColorHistogram::new();
+    for y in 0..image_size.1 {
+        read_row(y, &mut rgb)?;
+        histogram.add_rgb(&rgb)?;
+    }
+    let palette = profile_quantizer(*quantizer, options).palette_from_histogram(&histogram);
+
+    let mut writer = WriterPaletted::new_with_options(stream, image_size, dpi, options)?;
+    let mut indices = vec![0; image_size.0 as usize];
+    let mut cache = HashMap::new();
+    for y in 0..image_size.1 {
+        read_row(y, &mut rgb)?;
+        if options.profile == Profile::Small {
+            indices = small_profile_indices(&palette, &rgb, image_size.0 as usize, y as usize, quantizer.color_space())?;
+        } else {
+            for (index, color) in indices.iter_mut().zip(rgb.chunks(3)) {
+                let color = [color[0], color[1], color[2]];
+                *index = *cache.entry(color).or_insert_with(|| palette.nearest_in(color, quantizer.color_space()).unwrap());
+            }
+        }
+        writer.write_row(&indices)?;
+    }
+    writer.write_palette(&palette.to_rgb())?;
+    Ok(palette)
+}
+
+// Quantizer with the refinement implied by the profile of `options`.
+fn profile_quantizer(mut quantizer: Quantizer, options: WriterOptions) -> Quantizer {
+    if options.profile == Profile::Small && quantizer.refinement() == 0 {
+        quantizer.set_refinement(SMALL_PROFILE_REFINEMENT);
+    }
+    quantizer
+}
+
+// Palette indices of the RGB rows of `width` pixels starting at row `first_row` with `Profile::Small`: the colors are dithered,
+// then single pixels interrupting a run are merged into it.
+fn small_profile_indices(palette: &Palette, rgb: &[u8], width: usize, first_row: usize, color_space: ColorSpace) -> Result<Vec<u8>> {
+    let mut indices = palette.dither_rows(rgb, width, first_row, SMALL_PROFILE_DITHERING, SMALL_PROFILE_DITHERING_STRENGTH, color_space)?;
+    if width > 0 {
+        for (row, rgb) in indices.chunks_mut(width).zip(rgb.chunks(width * 3)) {
+            merge_into_runs(row, rgb, palette, color_space);
+        }
+    }
+    Ok(indices)
+}
+
+// Look one pixel ahead and give a single pixel between two pixels of the same color that color if it is almost as close to
+// the original color, which saves the codes interrupting the run. Pixels shifted to another color by dithering are kept, so
+// dithering patterns survive.
+fn merge_into_runs(indices: &mut [u8], rgb: &[u8], palette: &Palette, color_space: ColorSpace) {
+    let colors = palette.colors();
+    let error = |index: u8, color: &[u8]| (0..3).map(|channel| (colors[index as usize][channel] as i32 - color[channel] as i32).abs()).max().unwrap();
+    for x in 1..indices.len().saturating_sub(1) {
+        let (run, index, color) = (indices[x - 1], indices[x], &rgb[(x * 3)..(x * 3 + 3)]);
+        if index == run || indices[x + 1] != run || error(run, color) > error(index, color) + SMALL_PROFILE_MERGE_TOLERANCE {
+            continue;
+        }
+        if palette.nearest_in([color[0], color[1], color[2]], color_space) == Some(index) {
+            indices[x] = run;
+        }
+    }
+}
+
+// Memory of the copy of a row of `row_length` bytes made when transforms are applied.
+fn transform_memory(transforms: &[RowTransform], row_length: u64) -> u64 {
+    if transforms.is_empty() {
+        0
+    } else {
+        row_length
+    }
+}
+
+// Build the palette of the RGB pixels and write them as 256-color image.
+fn encode_quantized<W: io::Write>(stream: W, image_size: (u16, u16), dpi: (u16, u16), options: WriterOptions, quantizer: Quantizer, rgb: &[u8]) -> io::Result<Palette> {
+    let palette = profile_quantizer(quantizer, options).palette(rgb)?;
+    let indices = if options.profile == Profile::Small {
+        small_profile_indices(&palette, rgb, image_size.0 as usize, 0, quantizer.color_space())?
+    } else {
+        palette.map_rgb_in(rgb, quantizer.color_space())?
+    };
+
+    let mut writer = WriterPaletted::new_with_options(stream, image_size, dpi, options)?;
+    for row in indices.chunks(image_size.0 as usize) {
+        writer.write_row(row)?;
+    }
+    writer.write_palette(&palette.to_rgb())?;
+    Ok(palette)
+}
+
+impl<W: io::Write> WriterPlanar<W> {
+    /// Create new PCX writer.
+    ///
+    /// `number_of_color_planes` must be 1, 2, 3 or 4 giving 2, 4, 8 or 16 colors respectively. `palette` is written to the header,
+    /// it must contain at most `2^number_of_color_planes` colors in R, G, B, R, G, B, ... format. Empty palette of a monochrome
+    /// image is written as black and white.
+    ///
+    /// If you are not sure what to pass to `dpi` value just use something like `(100, 100)` or `(300, 300)`.
+    pub fn new(stream: W, image_size: (u16, u16), dpi: (u16, u16), number_of_color_planes: u8, palette: &[u8]) -> io::Result<Self> {
+        Self::new_with_options(stream, image_size, dpi, number_of_color_planes, palette, WriterOptions::default())
+    }
+
+    /// Create new PCX writer with the given options.
+    pub fn new_with_options(stream: W, image_size: (u16, u16), dpi: (u16, u16), number_of_color_planes: u8, palette: &[u8], options: WriterOptions) -> io::Result<Self> {
+        if !(1..=4).contains(&number_of_color_planes) {
+            return user_error("pcx::WriterPlanar::new: number of color planes must be 1, 2, 3 or 4");
+        }
+
+        if palette.len() > (3 << number_of_color_planes) || palette.len() % 3 != 0 {
+            return user_error("pcx::WriterPlanar::new: incorrect palette length");
+        }
+
+        let mut header_palette = [[0; 3]; 16];
+        if number_of_color_planes == 1 && palette.is_empty() {
+            header_palette[1] = [255; 3];
+        }
+        for (color, rgb) in header_palette.iter_mut().zip(palette.chunks(3)) {
+            color.copy_from_slice(rgb);
+        }
+
+        Ok(WriterPlanar {
+            compressor: start(stream, 1, number_of_color_planes, image_size, dpi, &header_palette, options)?,
+            num_rows_left: image_size.1,
+            width: image_size.0,
+            number_of_color_planes,
+            lane: vec![0; header::lane_proper_length(image_size.0, 1) as usize],
+            row_transforms: Vec::new(),
+        })
+    }
+
+    /// Estimated peak of memory allocated by the writer in bytes, not counting the underlying stream.
+    pub fn estimated_peak_memory(&self) -> u64 {
+        (self.lane.len() as u64) + transform_memory(&self.row_transforms, self.width as u64)
+    }
+
+    /// Append `transform` to the transforms applied to rows passed to `write_row()` before they are encoded, in the order they
+    /// were added. Rows are transformed as `PixelFormat::Indexed`, so `RowTransform::Lookup` remaps palette indices.
+    pub fn add_row_transform(&mut self, transform: RowTransform) {
+        self.row_transforms.push(transform);
+    }
+
+    /// Remove all transforms added with `add_row_transform()`.
+    pub fn clear_row_transforms(&mut self) {
+        self.row_transforms.clear();
+    }
+
+    /// Write next row of pixels. Each pixel is an index into the palette and must be less than `2^number_of_color_planes`.
+    ///
+    /// Row length must be equal to the width of the image passed to `new`.
+    /// This function must be called number of times equal to the height of the image.
+    ///
+    /// Order of rows is from top to bottom, order of pixels is from left to right.
+    pub fn write_row(&mut self, row: &[u8]) -> io::Result<()> {
+        if self.num_rows_left == 0 {
+            return user_error("pcx::WriterPlanar::write_row: all rows were already written");
+        }
+
+        if row.len() != self.width as usize {
+            return user_error("pcx::WriterPlanar::write_row: buffer length must be equal to the width of the image");
+        }
+
+        let transformed = transform_row(&self.row_transforms, row, PixelFormat::Indexed);
+        let row = &transformed[..];
+
+        if row.iter().any(|&index| index >> self.number_of_color_planes != 0) {
+            return user_error("pcx::WriterPlanar::write_row: pixel value does not fit into the number of color planes");
+        }
+
+        for plane in 0..self.number_of_color_planes {
+            for byte in self.lane.iter_mut() {
+                *byte = 0;
+            }
+
+            for (x, &index) in row.iter().enumerate() {
+                if (index >> plane) & 1 != 0 {
+                    self.lane[x >> 3] |= 0x80 >> (x & 7);
+                }
+            }
+
+            self.compressor.write_all(&self.lane)?;
+            self.compressor.pad()?;
+        }
+
+        self.num_rows_left -= 1;
+        Ok(())
+    }
+
+    /// Flush all data and finish writing.
+    ///
+    /// If you simply drop `WriterPlanar` it will also flush everything but this function is preferable because errors won't be ignored.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.num_rows_left != 0 {
+            return user_error("pcx::WriterPlanar::finish: not all rows written");
+        }
+
+        self.compressor.flush()
+    }
+}
+
+impl<W: io::Write> Drop for WriterPlanar<W> {
+    fn drop(&mut self) {
+        let _r = self.compressor.flush();
+    }
+}
+
+impl<W: io::Write> WriterRaw<W> {
+    /// Create new PCX writer and write `header`, which must describe the stored pixel data.
+    pub fn new(mut stream: W, header: &Header) -> io::Result<Self> {
+        header.save(&mut stream)?;
+        Ok(WriterRaw {
+            stream,
+            header: *header,
+            num_lanes_left: (header.size.1 as u32) * (header.number_of_color_planes as u32),
+        })
+    }
+
+    /// Write stored data of the next lane.
+    ///
+    /// For compressed images data must consist of RLE codes decoding to exactly `lane_length` bytes, otherwise it must be
+    /// `lane_length` bytes long. Padding of the very last lane may be missing, so its data may cover just
+    /// `lane_proper_length()` bytes or anything in between.
+    /// This function must be called for each plane of each row, in the order of `Reader::next_lane_raw()`.
+    pub fn write_lane_raw(&mut self, data: &[u8]) -> io::Result<()> {
+        if self.num_lanes_left == 0 {
+            return user_error("pcx::WriterRaw::write_lane_raw: all lanes were already written");
+        }
+
+        let covered = match decoded_length(data, self.header.is_compressed) {
+            Some(covered) => covered,
+            None => return user_error("pcx::WriterRaw::write_lane_raw: data ends within a code"),
+        };
+        let lane_length = self.header.lane_length as usize;
+        let minimum = if self.num_lanes_left == 1 { self.header.lane_proper_length() as usize } else { lane_length };
+        if covered < minimum || covered > lane_length {
+            return user_error("pcx::WriterRaw::write_lane_raw: data does not match the lane length");
+        }
+
+        self.stream.write_all(data)?;
+        self.num_lanes_left -= 1;
+        Ok(())
+    }
+
+    /// Write 256-color palette and finish writing, must be called only after writing all the lanes.
+    ///
+    /// Palette length must be 256*3 = 768 bytes. Format is R, G, B, R, G, B, ...
+    pub fn write_palette(mut self, palette: &[u8]) -> io::Result<()> {
+        if self.num_lanes_left != 0 {
+            return user_error("pcx::WriterRaw::write_palette: not all lanes written");
+        }
+
+        if self.header.palette_length() != Some(256) {
+            return user_error("pcx::WriterRaw::write_palette: image does not have a 256-color palette");
+        }
+
+        if palette.len() != 256 * 3 {
+            return user_error("pcx::WriterRaw::write_palette: incorrect palette length");
+        }
+
+        self.stream.write_u8(PALETTE_START)?;
+        self.stream.write_all(palette)?;
+        self.stream.flush()
+    }
+
+    /// Flush all data and finish writing, for images without a palette at the end of file.
+    pub fn finish(mut self) -> io::Result<()> {
+        if self.num_lanes_left != 0 {
+            return user_error("pcx::WriterRaw::finish: not all lanes written");
+        }
+
+        self.stream.flush()
+    }
+}
+
+// Number of bytes stored pixel data decodes to, `None` if it ends within a code.
+fn decoded_length(data: &[u8], compressed: bool) -> Option<usize> {
+    if !compressed {
+        return Some(data.len());
+    }
+
+    let mut length = 0;
+    let mut codes = data.iter();
+    while let Some(&code) = codes.next() {
+        if (code & 0xC0) == 0xC0 {
+            codes.next()?;
+            length += (code & 0x3F) as usize;
+        } else {
+            length += 1;
+        }
+    }
+    Some(length)
+}
+
+// Write the header and create compressor for the pixel data.
+fn start<W: io::Write>(mut stream: W, bit_depth: u8, number_of_color_planes: u8, size: (u16, u16), dpi: (u16, u16), palette: &[[u8; 3]; 16], options: WriterOptions) -> io::Result<Compressor<W>> {
+    let mut header = header::writer_header(bit_depth, number_of_color_planes, size, dpi, palette)?;
+    header.is_compressed = options.compress;
+    header.start = options.start;
+    if let Some(version) = options.version {
+        header.version = version;
+    }
+    header.screen_size = match options.screen_size {
+        Some(screen_size) => screen_size,
+        None if header.version == Version::V5 => size,
+        None => (0, 0),
+    };
+    if let Some(lane_length) = options.lane_length {
+        if options.strict && lane_length % 2 != 0 {
+            return user_error("pcx: lane length must be even, disable WriterOptions::strict to write odd lane length");
+        }
+        header.lane_length = lane_length;
+    }
+    header.save_with_options(&mut stream, &options)?;
+
+    let mut compressor = if options.compress {
+        Compressor::new(stream, header.lane_length)
+    } else {
+        Compressor::new_uncompressed(stream, header.lane_length)
+    };
+    compressor.set_extend_runs(options.profile == Profile::Small);
+    Ok(compressor)
+}